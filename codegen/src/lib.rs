@@ -0,0 +1,138 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, AttributeArgs, Data, DeriveInput, Fields, ItemFn, LitStr,
+};
+
+mod test;
+
+/// Declares a named database, see `rocket_sqlxpostgres::SqlxPostgresDatabase`.
+#[proc_macro_derive(SqlxPostgresDatabase, attributes(database))]
+pub fn derive_database(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Runs an async test against a fresh database, see `rocket_sqlxpostgres::test`.
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let input = parse_macro_input!(input as ItemFn);
+
+    match test::expand(args, input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = database_name(&input)?;
+
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {}
+            fields => {
+                return Err(syn::Error::new(
+                    fields.span(),
+                    "SqlxPostgresDatabase needs a tuple struct with one field, e.g. `struct Main(PgPool);`",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "SqlxPostgresDatabase can only be derived for a tuple struct, e.g. `struct Main(PgPool);`",
+            ))
+        }
+    }
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "SqlxPostgresDatabase cannot be derived for a generic struct",
+        ));
+    }
+
+    let ident = &input.ident;
+
+    Ok(quote! {
+        impl ::rocket_sqlxpostgres::DatabaseMarker for #ident {
+            const NAME: &'static str = #name;
+        }
+
+        impl #ident {
+            /// Fairing connecting this database with `config`, pass it to `rocket.attach()`.
+            pub fn init(
+                config: ::rocket_sqlxpostgres::SqlxPostgresConfig,
+            ) -> ::rocket_sqlxpostgres::SqlxPostgresFairing {
+                ::rocket_sqlxpostgres::SqlxPostgresFairing::named(#name, config)
+            }
+        }
+
+        impl ::std::ops::Deref for #ident {
+            type Target = ::rocket_sqlxpostgres::__private::sqlx::postgres::PgPool;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        #[::rocket_sqlxpostgres::__private::rocket::async_trait]
+        impl<'r> ::rocket_sqlxpostgres::__private::rocket::request::FromRequest<'r> for #ident {
+            type Error = ::rocket_sqlxpostgres::PoolGuardError;
+
+            async fn from_request(
+                request: &'r ::rocket_sqlxpostgres::__private::rocket::request::Request<'_>,
+            ) -> ::rocket_sqlxpostgres::__private::rocket::request::Outcome<Self, Self::Error> {
+                request
+                    .guard::<::rocket_sqlxpostgres::SQLxPostgres<#ident>>()
+                    .await
+                    .map(|db| #ident(db.pool().clone()))
+            }
+        }
+
+        impl ::rocket_sqlxpostgres::__private::rocket::Sentinel for #ident {
+            fn abort(
+                rocket: &::rocket_sqlxpostgres::__private::rocket::Rocket<
+                    ::rocket_sqlxpostgres::__private::rocket::Ignite,
+                >,
+            ) -> bool {
+                <::rocket_sqlxpostgres::SQLxPostgres<#ident> as ::rocket_sqlxpostgres::__private::rocket::Sentinel>::abort(rocket)
+            }
+        }
+    })
+}
+
+/// Reads the name of `#[database("name")]`.
+fn database_name(input: &DeriveInput) -> syn::Result<LitStr> {
+    let attr = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("database"))
+        .ok_or_else(|| {
+            syn::Error::new(
+                input.ident.span(),
+                "missing `#[database(\"name\")]` attribute naming the database",
+            )
+        })?;
+
+    let name: LitStr = attr.parse_args().map_err(|_| {
+        syn::Error::new(
+            attr.span(),
+            "expected `#[database(\"name\")]` with a string literal",
+        )
+    })?;
+
+    if name.value().is_empty() {
+        return Err(syn::Error::new(
+            name.span(),
+            "the database name cannot be empty",
+        ));
+    }
+
+    Ok(name)
+}
@@ -0,0 +1,211 @@
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{spanned::Spanned, AttributeArgs, ItemFn, Lit, Meta, NestedMeta, Path};
+
+/// Arguments of `#[rocket_sqlxpostgres::test(...)]`.
+struct Args {
+    rocket: Option<Path>,
+    config: Option<Path>,
+    migrations: Option<String>,
+    fixtures: Option<String>,
+    only: Option<Vec<syn::LitStr>>,
+    transaction: bool,
+}
+
+/// Directory fixtures are loaded from when only `fixtures(...)` is given.
+const DEFAULT_FIXTURES: &str = "tests/fixtures";
+
+impl Args {
+    fn parse(args: AttributeArgs) -> syn::Result<Self> {
+        let mut parsed = Args {
+            rocket: None,
+            config: None,
+            migrations: None,
+            fixtures: None,
+            only: None,
+            transaction: false,
+        };
+
+        for arg in args {
+            let pair = match arg {
+                NestedMeta::Meta(Meta::NameValue(pair)) => pair,
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("fixtures") => {
+                    let names = list
+                        .nested
+                        .iter()
+                        .map(|name| match name {
+                            NestedMeta::Lit(Lit::Str(name)) => Ok(name.clone()),
+                            name => Err(syn::Error::new(name.span(), "expected a fixture name string")),
+                        })
+                        .collect::<syn::Result<Vec<_>>>()?;
+                    parsed.only = Some(names);
+                    continue;
+                }
+                arg => {
+                    return Err(syn::Error::new(
+                        arg.span(),
+                        "expected `rocket = \"path\"`, `config = \"path\"`, `migrations = \"dir\"`, `fixtures = \"dir\"`, `fixtures(\"name\", ...)` or `mode = \"...\"`",
+                    ))
+                }
+            };
+            let value = match &pair.lit {
+                Lit::Str(value) => value,
+                lit => return Err(syn::Error::new(lit.span(), "expected a string literal")),
+            };
+            let key = pair
+                .path
+                .get_ident()
+                .map(|ident| ident.to_string())
+                .unwrap_or_default();
+
+            match key.as_str() {
+                "rocket" => parsed.rocket = Some(value.parse()?),
+                "config" => parsed.config = Some(value.parse()?),
+                "migrations" => parsed.migrations = Some(value.value()),
+                "fixtures" => parsed.fixtures = Some(value.value()),
+                "mode" => match value.value().as_str() {
+                    "database" => parsed.transaction = false,
+                    "transaction" => parsed.transaction = true,
+                    _ => {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected `mode = \"database\"` or `mode = \"transaction\"`",
+                        ))
+                    }
+                },
+                _ => return Err(syn::Error::new(pair.path.span(), "unknown argument")),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+pub(crate) fn expand(args: AttributeArgs, input: ItemFn) -> syn::Result<TokenStream> {
+    let args = Args::parse(args)?;
+
+    if input.sig.asyncness.is_none() {
+        return Err(syn::Error::new(
+            input.sig.fn_token.span(),
+            "#[rocket_sqlxpostgres::test] needs an `async fn`",
+        ));
+    }
+
+    if input.sig.inputs.len() != 2 {
+        return Err(syn::Error::new(
+            input.sig.inputs.span(),
+            "expected two arguments, the local `Client` and the `PgPool` or `TestTransaction`",
+        ));
+    }
+
+    let krate = quote!(::rocket_sqlxpostgres);
+    let private = quote!(#krate::__private);
+    let attrs = &input.attrs;
+    let vis = &input.vis;
+    let name = &input.sig.ident;
+    let output = &input.sig.output;
+    let inputs = &input.sig.inputs;
+    let body = &input.block;
+
+    let rocket = match &args.rocket {
+        Some(path) => quote!(#path()),
+        None => quote!(#private::rocket::build()),
+    };
+    let config = match &args.config {
+        Some(path) => quote!(#path()),
+        None => quote! {
+            #krate::SqlxPostgresConfig::from_env()
+                .expect("reading the test database config from the environment")
+        },
+    };
+    let migrate = match &args.migrations {
+        Some(dir) => {
+            let dir = syn::LitStr::new(dir, Span::call_site());
+            quote! {
+                #private::sqlx::migrate!(#dir)
+                    .run(&pool)
+                    .await
+                    .expect("running the test migrations");
+            }
+        }
+        None => quote!(),
+    };
+    let fixtures = match (&args.fixtures, &args.only) {
+        (None, None) => None,
+        (dir, only) => {
+            let dir = dir.as_deref().unwrap_or(DEFAULT_FIXTURES);
+            let dir = syn::LitStr::new(&format!("/{}", dir), Span::call_site());
+            let only = only.as_ref().map(|names| quote!(.only(&[#(#names),*])));
+
+            Some(quote! {
+                #krate::Fixtures::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), #dir))
+                    .expect("loading the test fixtures")
+                    #only
+            })
+        }
+    };
+    let apply_pool = match &fixtures {
+        Some(fixtures) => quote! {
+            #fixtures.apply_pool(&pool).await.expect("applying the test fixtures");
+        },
+        None => quote!(),
+    };
+    let apply_tx = match &fixtures {
+        Some(fixtures) => quote! {
+            #fixtures
+                .apply(&mut *tx.acquire().await)
+                .await
+                .expect("applying the test fixtures");
+        },
+        None => quote!(),
+    };
+
+    // The database handle lives in the outer future, so a panic in the test still drops
+    // it and the database is removed.
+    let setup = if args.transaction {
+        quote! {
+            let config = #config;
+            let pool = #private::connect(&config).await.expect("connecting the test pool");
+            #migrate
+            let tx = #krate::TestTransaction::begin(&pool)
+                .await
+                .expect("beginning the test transaction");
+            #apply_tx
+            let rocket = #rocket
+                .manage(tx.clone())
+                .attach(#krate::SqlxPostgresFairing::new(config, Some(pool)));
+            let client = #private::rocket::local::asynchronous::Client::tracked(rocket)
+                .await
+                .expect("launching the test rocket");
+            let result = test(client, tx.clone()).await;
+            tx.rollback().await.expect("rolling back the test transaction");
+            result
+        }
+    } else {
+        quote! {
+            let db = #krate::TestDatabase::create(#config)
+                .await
+                .expect("creating the test database");
+            let pool = db.pool().clone();
+            #migrate
+            #apply_pool
+            let rocket = #rocket.attach(db.fairing());
+            let client = #private::rocket::local::asynchronous::Client::tracked(rocket)
+                .await
+                .expect("launching the test rocket");
+            let result = test(client, pool).await;
+            db.cleanup().await.expect("dropping the test database");
+            result
+        }
+    };
+
+    Ok(quote! {
+        #[::core::prelude::v1::test]
+        #(#attrs)*
+        #vis fn #name() #output {
+            async fn test(#inputs) #output #body
+
+            #private::rocket::async_test(async move { #setup })
+        }
+    })
+}
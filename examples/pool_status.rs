@@ -0,0 +1,24 @@
+#[macro_use]
+extern crate rocket;
+
+use rocket::{serde::json::Json, State};
+use rocket_sqlxpostgres::{PoolStatus, SQLxPostgresPool, SqlxPostgresConfig, SqlxPostgresFairing};
+
+#[get("/pool")]
+fn pool_status(pool: &State<SQLxPostgresPool>) -> Json<PoolStatus> {
+    Json(pool.status())
+}
+
+#[launch]
+fn rocket() -> _ {
+    let config = SqlxPostgresConfig::default()
+        .with_database("databasename")
+        .with_username("username")
+        .with_password("password")
+        .with_host("localhost")
+        .with_port(5432);
+
+    rocket::build()
+        .attach(SqlxPostgresFairing::from_config(config))
+        .mount("/", routes![pool_status])
+}
@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate rocket;
+
+use log::LevelFilter;
+use rocket::{http::Status, State};
+use rocket_sqlxpostgres::{SqlxPostgresConfig, SqlxPostgresFairing, StatementLogControl};
+use std::str::FromStr;
+
+/// Put this behind authentication in a real deployment.
+#[post("/admin/statement-log/<level>")]
+fn set_statement_log(control: &State<StatementLogControl>, level: &str) -> Result<String, Status> {
+    let level = LevelFilter::from_str(level).map_err(|_| Status::BadRequest)?;
+    control.set_level(level);
+    Ok(format!("statements are logged at {}", control.current()))
+}
+
+#[get("/admin/statement-log")]
+fn statement_log(control: &State<StatementLogControl>) -> String {
+    control.current().to_string()
+}
+
+#[launch]
+fn rocket() -> _ {
+    let config = SqlxPostgresConfig::default()
+        .with_database("databasename")
+        .with_username("username")
+        .with_password("password")
+        .with_host("localhost")
+        .with_port(5432)
+        .with_loglevel(LevelFilter::Off);
+
+    rocket::build()
+        .attach(SqlxPostgresFairing::from_config(config))
+        .mount("/", routes![statement_log, set_statement_log])
+}
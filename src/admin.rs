@@ -0,0 +1,209 @@
+use crate::{
+    connect, health,
+    migrate::{self, MigrationStatus},
+    named::{DatabaseMarker, DefaultDatabase, SQLxPostgresPools},
+    session::SQLxPostgresPool,
+    status::PoolStatus,
+};
+use rocket::{
+    http::Status,
+    serde::{json::Json, Serialize},
+    Route, State,
+};
+use std::{ops::Deref, sync::Arc};
+
+/// Introspection routes for debugging deployed instances, mount them under an
+/// authenticated prefix.
+///
+/// Nothing is mounted unless you mount these. `<database>` is the name a fairing was
+/// attached with, or `default` for the unnamed one; unknown names answer 404. No route
+/// returns credentials.
+///
+/// | route | answer |
+/// |---|---|
+/// | `GET /<database>/pool` | [`PoolStatus`] |
+/// | `GET /<database>/migrations` | [`MigrationStatus`] against the migrations the fairing ran or checked |
+/// | `GET /<database>/server` | server version and `postgres://user@host:port/database` summary |
+/// | `GET /<database>/probe` | runs the health query now |
+///
+/// ```rust,ignore
+/// rocket::build()
+///     .attach(SqlxPostgresFairing::from_config(config))
+///     .mount("/admin/db", rocket_sqlxpostgres::admin_routes())
+/// ```
+pub fn admin_routes() -> Vec<Route> {
+    rocket::routes![pool, migrations, server, probe]
+}
+
+/// Route resizing a pool at runtime through its [`crate::PoolHandle`], kept apart from
+/// [`admin_routes`] since it changes the pool; mount it under an authenticated prefix.
+///
+/// | route | answer |
+/// |---|---|
+/// | `POST /<database>/pool/resize/<max_connections>` | [`PoolStatus`] of the new pool |
+///
+/// Pools without a handle answer 409 and a new pool that fails to connect answers 503,
+/// keeping the current pool.
+///
+/// ```rust,ignore
+/// rocket::build()
+///     .attach(SqlxPostgresFairing::from_config(config))
+///     .mount("/admin/db", rocket_sqlxpostgres::admin_resize_routes())
+/// ```
+pub fn admin_resize_routes() -> Vec<Route> {
+    rocket::routes![resize]
+}
+
+/// The store of `database`, borrowed from state or shared with [`SQLxPostgresPools`].
+enum Store<'r> {
+    Unnamed(&'r SQLxPostgresPool),
+    Named(Arc<SQLxPostgresPool>),
+}
+
+impl Deref for Store<'_> {
+    type Target = SQLxPostgresPool;
+
+    fn deref(&self) -> &SQLxPostgresPool {
+        match self {
+            Store::Unnamed(store) => store,
+            Store::Named(store) => store,
+        }
+    }
+}
+
+fn store<'r>(
+    database: &str,
+    unnamed: Option<&'r State<SQLxPostgresPool>>,
+    named: Option<&'r State<SQLxPostgresPools>>,
+) -> Result<Store<'r>, Status> {
+    if database == DefaultDatabase::NAME {
+        if let Some(store) = unnamed {
+            return Ok(Store::Unnamed(store.inner()));
+        }
+    }
+
+    named
+        .and_then(|pools| pools.pool(database))
+        .map(Store::Named)
+        .ok_or(Status::NotFound)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ServerInfo {
+    database: String,
+    version: String,
+    connection: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ProbeResult {
+    healthy: bool,
+    latency_ms: Option<f64>,
+    error: Option<String>,
+}
+
+#[rocket::get("/<database>/pool")]
+fn pool(
+    database: &str,
+    unnamed: Option<&State<SQLxPostgresPool>>,
+    named: Option<&State<SQLxPostgresPools>>,
+) -> Result<Json<PoolStatus>, Status> {
+    Ok(Json(store(database, unnamed, named)?.status()))
+}
+
+#[rocket::get("/<database>/migrations")]
+async fn migrations(
+    database: &str,
+    unnamed: Option<&State<SQLxPostgresPool>>,
+    named: Option<&State<SQLxPostgresPools>>,
+) -> Result<Json<MigrationStatus>, Status> {
+    let store = store(database, unnamed, named)?;
+
+    match migrate::status(&store.client(), &store.migrations.0).await {
+        Ok(status) => Ok(Json(status)),
+        Err(e) => {
+            log::warn!(
+                "SQLxPostgres: reading the migrations of `{}` failed: {}",
+                database,
+                e
+            );
+            Err(Status::ServiceUnavailable)
+        }
+    }
+}
+
+#[rocket::get("/<database>/server")]
+async fn server(
+    database: &str,
+    unnamed: Option<&State<SQLxPostgresPool>>,
+    named: Option<&State<SQLxPostgresPools>>,
+) -> Result<Json<ServerInfo>, Status> {
+    let store = store(database, unnamed, named)?;
+
+    match connect::server_version(&store.client()).await {
+        Ok(version) => Ok(Json(ServerInfo {
+            database: database.to_owned(),
+            version: version.to_string(),
+            // The summary never includes the password.
+            connection: store.config.summary(),
+        })),
+        Err(e) => {
+            log::warn!(
+                "SQLxPostgres: reading the server version of `{}` failed: {}",
+                database,
+                e
+            );
+            Err(Status::ServiceUnavailable)
+        }
+    }
+}
+
+#[rocket::post("/<database>/pool/resize/<max_connections>")]
+async fn resize(
+    database: &str,
+    max_connections: u32,
+    unnamed: Option<&State<SQLxPostgresPool>>,
+    named: Option<&State<SQLxPostgresPools>>,
+) -> Result<Json<PoolStatus>, Status> {
+    if max_connections == 0 {
+        return Err(Status::BadRequest);
+    }
+
+    let store = store(database, unnamed, named)?;
+    let handle = store.rotation.as_ref().ok_or(Status::Conflict)?;
+
+    match handle.resize(max_connections).await {
+        Ok(()) => Ok(Json(store.status())),
+        Err(_) => Err(Status::ServiceUnavailable),
+    }
+}
+
+#[rocket::get("/<database>/probe")]
+async fn probe(
+    database: &str,
+    unnamed: Option<&State<SQLxPostgresPool>>,
+    named: Option<&State<SQLxPostgresPools>>,
+) -> Result<(Status, Json<ProbeResult>), Status> {
+    let store = store(database, unnamed, named)?;
+
+    Ok(match health::probe(&store.client(), &store.config).await {
+        Ok(latency) => (
+            Status::Ok,
+            Json(ProbeResult {
+                healthy: true,
+                latency_ms: Some(latency.as_secs_f64() * 1000.0),
+                error: None,
+            }),
+        ),
+        Err(e) => (
+            Status::ServiceUnavailable,
+            Json(ProbeResult {
+                healthy: false,
+                latency_ms: None,
+                error: Some(e.message()),
+            }),
+        ),
+    })
+}
@@ -0,0 +1,168 @@
+use crate::{named::DatabaseMarker, session::SQLxPostgres};
+use sqlx::{pool::PoolConnection, postgres::PgConnection, Postgres};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+/// SQLSTATE `query_canceled`, raised when `statement_timeout` expires.
+const QUERY_CANCELED: &str = "57014";
+
+/// A session level advisory lock, held until the guard is dropped or released.
+///
+/// Advisory locks belong to the connection that took them, so the guard keeps that
+/// connection out of the pool for as long as it lives and derefs to it. On drop the lock is
+/// released in a spawned task before the connection goes back; if that fails the connection
+/// is closed instead, which releases the lock server side.
+///
+/// ```rust,ignore
+/// if let Some(_lock) = db.try_advisory_lock(42).await? {
+///     run_nightly_job(db.pool()).await?;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AdvisoryLockGuard {
+    key: i64,
+    conn: Option<PoolConnection<Postgres>>,
+}
+
+impl AdvisoryLockGuard {
+    /// Key the lock was taken on.
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+
+    /// Releases the lock and returns the connection to the pool, reporting unlock errors.
+    pub async fn release(mut self) -> Result<(), sqlx::Error> {
+        let conn = self.conn.take().expect("advisory lock connection taken");
+        unlock(self.key, conn).await
+    }
+}
+
+impl Deref for AdvisoryLockGuard {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("advisory lock connection taken")
+    }
+}
+
+impl DerefMut for AdvisoryLockGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().expect("advisory lock connection taken")
+    }
+}
+
+impl Drop for AdvisoryLockGuard {
+    fn drop(&mut self) {
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let key = self.key;
+
+        match rocket::tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = unlock(key, conn).await {
+                        log::warn!(
+                            "SQLxPostgres: failed to release advisory lock {}: {}",
+                            key,
+                            e
+                        );
+                    }
+                });
+            }
+            // Without a runtime the unlock can't run, closing the connection releases it.
+            Err(_) => drop(conn.detach()),
+        }
+    }
+}
+
+/// Runs `pg_advisory_unlock`, closing the connection rather than pooling it when that fails.
+async fn unlock(key: i64, mut conn: PoolConnection<Postgres>) -> Result<(), sqlx::Error> {
+    let released = sqlx::query_scalar::<_, bool>("SELECT pg_advisory_unlock($1)")
+        .bind(key)
+        .fetch_one(&mut *conn)
+        .await;
+
+    match released {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            log::warn!(
+                "SQLxPostgres: advisory lock {} was not held when released",
+                key
+            );
+            Ok(())
+        }
+        Err(e) => {
+            drop(conn.detach());
+            Err(e)
+        }
+    }
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Takes the advisory lock `key` with `pg_try_advisory_lock`, `None` when another
+    /// session holds it.
+    pub async fn try_advisory_lock(
+        &self,
+        key: i64,
+    ) -> Result<Option<AdvisoryLockGuard>, sqlx::Error> {
+        let mut conn = self.pool().acquire().await?;
+        let locked = sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+            .bind(key)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        Ok(locked.then(|| AdvisoryLockGuard {
+            key,
+            conn: Some(conn),
+        }))
+    }
+
+    /// Waits up to `timeout` for the advisory lock `key` with `pg_advisory_lock`, `None`
+    /// when it is still held elsewhere.
+    ///
+    /// The wait is bounded server side with `statement_timeout`, which is reset before the
+    /// guard is returned.
+    pub async fn advisory_lock(
+        &self,
+        key: i64,
+        timeout: Duration,
+    ) -> Result<Option<AdvisoryLockGuard>, sqlx::Error> {
+        let mut conn = self.pool().acquire().await?;
+        let millis = timeout.as_millis().clamp(1, i32::MAX as u128).to_string();
+
+        sqlx::query("SELECT set_config('statement_timeout', $1, false)")
+            .bind(millis)
+            .execute(&mut *conn)
+            .await?;
+
+        let locked = sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(key)
+            .execute(&mut *conn)
+            .await;
+
+        let reset = sqlx::query("RESET statement_timeout")
+            .execute(&mut *conn)
+            .await;
+
+        match (locked, reset) {
+            (Ok(_), Ok(_)) => Ok(Some(AdvisoryLockGuard {
+                key,
+                conn: Some(conn),
+            })),
+            (Err(sqlx::Error::Database(e)), Ok(_)) if is_canceled(&*e) => Ok(None),
+            (Err(e), _) | (Ok(_), Err(e)) => {
+                // Closing the connection drops both the lock and the timeout setting.
+                drop(conn.detach());
+                Err(e)
+            }
+        }
+    }
+}
+
+fn is_canceled(error: &dyn sqlx::error::DatabaseError) -> bool {
+    error.code().as_deref() == Some(QUERY_CANCELED)
+}
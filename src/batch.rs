@@ -0,0 +1,128 @@
+use crate::{named::DatabaseMarker, session::SQLxPostgres};
+use futures::TryStreamExt;
+use sqlx::{postgres::PgConnection, Connection, Executor};
+use std::fmt;
+
+/// A failed [`execute_batch`].
+#[derive(Debug)]
+pub enum BatchError {
+    /// The batch held no statement, nothing was sent.
+    Empty,
+    /// Statement `index` of the batch, starting at 0, failed.
+    Statement {
+        /// Index of the failed statement.
+        index: usize,
+        /// The error of the server.
+        source: sqlx::Error,
+    },
+    /// Acquiring the connection or ending the transaction failed.
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Empty => f.write_str("the SQL batch is empty"),
+            BatchError::Statement { index, source } => {
+                write!(f, "statement {} of the SQL batch failed: {}", index, source)
+            }
+            BatchError::Database(e) => write!(f, "running the SQL batch failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatchError::Empty => None,
+            BatchError::Statement { source, .. } => Some(source),
+            BatchError::Database(e) => Some(e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for BatchError {
+    fn from(error: sqlx::Error) -> Self {
+        BatchError::Database(error)
+    }
+}
+
+/// Whether `sql` holds nothing but whitespace and separators.
+fn is_empty(sql: &str) -> bool {
+    sql.trim_matches(|c: char| c.is_whitespace() || c == ';')
+        .is_empty()
+}
+
+/// Runs `sql`, any number of `;` separated statements, on `conn` in one round trip,
+/// returning the rows affected by each statement in order.
+///
+/// The batch goes through the simple query protocol, so it takes no bind parameters and
+/// may hold statements the extended protocol refuses, such as several DDL statements from
+/// a seed or fixture file. Rows returned by a statement are discarded, its count is the
+/// number of rows. Postgres runs a batch without its own `BEGIN` and `COMMIT` as one
+/// implicit transaction, a failing statement rolls back those before it too.
+///
+/// Pass `&mut **tx` to run the batch as part of a transaction guard.
+pub async fn execute_batch(conn: &mut PgConnection, sql: &str) -> Result<Vec<u64>, BatchError> {
+    if is_empty(sql) {
+        return Err(BatchError::Empty);
+    }
+
+    let mut results = conn.execute_many(sql);
+    let mut affected = Vec::new();
+
+    loop {
+        match results.try_next().await {
+            Ok(Some(result)) => affected.push(result.rows_affected()),
+            Ok(None) => return Ok(affected),
+            // Every statement before the failed one reported its count.
+            Err(source) => {
+                return Err(BatchError::Statement {
+                    index: affected.len(),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Runs [`execute_batch`] on a connection of its own, within a transaction when
+    /// `transactional` is set.
+    ///
+    /// Without `transactional` the batch runs as written, so it may hold its own `BEGIN`
+    /// and `COMMIT`; with it the whole batch is committed only when every statement
+    /// succeeded.
+    ///
+    /// ```rust,ignore
+    /// #[post("/admin/seed")]
+    /// async fn seed(db: SQLxPostgres) -> Result<String, Status> {
+    ///     let counts = db
+    ///         .execute_batch(include_str!("../seed.sql"), true)
+    ///         .await
+    ///         .map_err(|_| Status::InternalServerError)?;
+    ///     Ok(format!("ran {} statements", counts.len()))
+    /// }
+    /// ```
+    pub async fn execute_batch(
+        &self,
+        sql: &str,
+        transactional: bool,
+    ) -> Result<Vec<u64>, BatchError> {
+        let mut conn = self.pool().acquire().await?;
+
+        if !transactional {
+            return execute_batch(&mut conn, sql).await;
+        }
+
+        // Checked before `BEGIN` so an empty batch sends nothing at all.
+        if is_empty(sql) {
+            return Err(BatchError::Empty);
+        }
+
+        let mut tx = conn.begin().await?;
+        let affected = execute_batch(&mut tx, sql).await?;
+        tx.commit().await?;
+        Ok(affected)
+    }
+}
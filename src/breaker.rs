@@ -0,0 +1,239 @@
+use crate::PoolGuardError;
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// When the circuit breaker of [`crate::SqlxPostgresConfig::with_circuit_breaker`] opens and
+/// how long it stays open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerPolicy {
+    failure_threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    trip_on_query_errors: bool,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            trip_on_query_errors: false,
+        }
+    }
+}
+
+impl CircuitBreakerPolicy {
+    /// Opens after 5 consecutive acquisition failures within 10 seconds, for 30 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Consecutive failures opening the breaker, at least one.
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// Time the consecutive failures must fall within, counting starts over after it.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Time the breaker stays open before letting a probe request through.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Whether errors returned by the server for a query count as failures too.
+    ///
+    /// Off by default: only connection errors and acquire timeouts count, and a query the
+    /// server answered with an error shows the database is up.
+    pub fn with_trip_on_query_errors(mut self, trip_on_query_errors: bool) -> Self {
+        self.trip_on_query_errors = trip_on_query_errors;
+        self
+    }
+}
+
+/// State of a circuit breaker, in [`crate::PoolStatus::circuit_breaker`].
+///
+/// Serializable with the `serde` feature, as `closed`, `open` or `half_open`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(crate = "rocket::serde", rename_all = "snake_case")
+)]
+pub enum BreakerState {
+    /// Guards acquire as usual.
+    Closed,
+    /// Guards fail right away with 503.
+    Open,
+    /// One probe request is let through, its outcome closes or reopens the breaker.
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    failures: u32,
+    first_failure: Option<Instant>,
+    /// When the breaker opened, or when the probe of a half open breaker started.
+    since: Instant,
+}
+
+/// Circuit breaker shared by the clones of a config.
+#[derive(Debug, Clone)]
+pub(crate) struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(policy: CircuitBreakerPolicy) -> Self {
+        Self {
+            policy,
+            inner: Arc::new(Mutex::new(Inner {
+                state: BreakerState::Closed,
+                failures: 0,
+                first_failure: None,
+                since: Instant::now(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn state(&self) -> BreakerState {
+        self.lock().state
+    }
+
+    /// Fails with [`PoolGuardError::CircuitOpen`] while the breaker is open, or half open
+    /// with its probe still running.
+    ///
+    /// A probe that neither succeeds nor fails within the cooldown, e.g. because its request
+    /// was dropped, is replaced by the next request.
+    pub(crate) fn check(&self) -> Result<(), PoolGuardError> {
+        let mut inner = self.lock();
+
+        match inner.state {
+            BreakerState::Closed => Ok(()),
+            _ if inner.since.elapsed() < self.policy.cooldown => Err(PoolGuardError::CircuitOpen),
+            BreakerState::Open => {
+                log::info!(
+                    "SQLxPostgres: circuit breaker half open, letting a probe request through"
+                );
+                inner.state = BreakerState::HalfOpen;
+                inner.since = Instant::now();
+                Ok(())
+            }
+            BreakerState::HalfOpen => {
+                inner.since = Instant::now();
+                Ok(())
+            }
+        }
+    }
+
+    /// Counts an acquire or query that went through.
+    pub(crate) fn success(&self) {
+        let mut inner = self.lock();
+
+        match inner.state {
+            BreakerState::Closed => inner.failures = 0,
+            BreakerState::HalfOpen => {
+                log::info!("SQLxPostgres: circuit breaker closed, the database is reachable again");
+                inner.state = BreakerState::Closed;
+                inner.failures = 0;
+                inner.first_failure = None;
+            }
+            // Requests that started before the breaker opened say nothing of now.
+            BreakerState::Open => {}
+        }
+    }
+
+    /// Counts a failed acquire or query, errors that don't mean the database is down count
+    /// as a success.
+    pub(crate) fn failure(&self, error: &sqlx::Error) {
+        match error {
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::Protocol(_)
+            | sqlx::Error::WorkerCrashed => {}
+            sqlx::Error::Database(_) if self.policy.trip_on_query_errors => {}
+            // Closed for shutdown or a rebuild, not an outage.
+            sqlx::Error::PoolClosed => return,
+            _ => return self.success(),
+        }
+
+        let mut inner = self.lock();
+        let now = Instant::now();
+
+        match inner.state {
+            BreakerState::Closed => {
+                let in_window = inner.failures > 0
+                    && inner.first_failure.map_or(false, |first| {
+                        now.duration_since(first) <= self.policy.window
+                    });
+
+                match in_window {
+                    true => inner.failures += 1,
+                    false => {
+                        inner.failures = 1;
+                        inner.first_failure = Some(now);
+                    }
+                }
+
+                if inner.failures >= self.policy.failure_threshold {
+                    log::error!(
+                        "SQLxPostgres: circuit breaker open after {} consecutive failures, failing fast for {:?}: {}",
+                        inner.failures,
+                        self.policy.cooldown,
+                        error
+                    );
+                    inner.state = BreakerState::Open;
+                    inner.since = now;
+                }
+            }
+            BreakerState::HalfOpen => {
+                log::warn!(
+                    "SQLxPostgres: circuit breaker probe failed, open again for {:?}: {}",
+                    self.policy.cooldown,
+                    error
+                );
+                inner.state = BreakerState::Open;
+                inner.since = now;
+            }
+            BreakerState::Open => {}
+        }
+    }
+
+    /// Counts the outcome of a guard's acquire.
+    pub(crate) fn record<T>(&self, result: &Result<T, PoolGuardError>) {
+        match result {
+            Ok(_) => self.success(),
+            Err(PoolGuardError::AcquireTimeout) => self.failure(&sqlx::Error::PoolTimedOut),
+            Err(PoolGuardError::Database(e)) => self.failure(e),
+            Err(_) => {}
+        }
+    }
+}
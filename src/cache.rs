@@ -0,0 +1,238 @@
+#[cfg(feature = "metrics")]
+use crate::metrics::DatabaseMetrics;
+use crate::{named::DatabaseMarker, session::SQLxPostgres};
+use rocket::tokio::sync::Mutex as AsyncMutex;
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires: Instant,
+    /// Tick of the last read, the smallest is evicted first.
+    used: u64,
+}
+
+/// Bounded in-process cache of query results, see [`SQLxPostgres::cached`].
+pub(crate) struct QueryCache {
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// One lock per key being computed, so concurrent misses run the query once.
+    flights: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+    tick: AtomicU64,
+    #[cfg(feature = "metrics")]
+    metrics: Option<DatabaseMetrics>,
+}
+
+impl QueryCache {
+    pub(crate) fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(HashMap::new()),
+            flights: Mutex::new(HashMap::new()),
+            tick: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics(mut self, metrics: Option<DatabaseMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn get<T: Clone + 'static>(&self, key: &str) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if entries.get(key)?.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+
+        let entry = entries.get_mut(key)?;
+        entry.used = self.tick.fetch_add(1, Ordering::Relaxed);
+        entry.value.downcast_ref::<T>().cloned()
+    }
+
+    fn put<T: Send + Sync + 'static>(&self, key: &str, value: T, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires > now);
+        }
+
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            let least_recent = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+
+            if let Some(least_recent) = least_recent {
+                entries.remove(&least_recent);
+            }
+        }
+
+        entries.insert(
+            key.to_owned(),
+            Entry {
+                value: Arc::new(value),
+                expires: Instant::now() + ttl,
+                used: self.tick.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+
+    fn record(&self, hit: bool) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.query_cache(hit);
+        }
+
+        #[cfg(not(feature = "metrics"))]
+        let _ = hit;
+    }
+
+    async fn get_or_run<T, E, F, Fut>(&self, key: &str, ttl: Duration, f: F) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(value) = self.get(key) {
+            self.record(true);
+            return Ok(value);
+        }
+
+        let flight = Flight::join(self, key);
+        let _running = flight.lock.lock().await;
+
+        // Whoever held the lock before may have filled the entry meanwhile.
+        if let Some(value) = self.get(key) {
+            self.record(true);
+            return Ok(value);
+        }
+
+        self.record(false);
+        let value = f().await?;
+        self.put(key, value.clone(), ttl);
+        Ok(value)
+    }
+
+    fn invalidate(&self, key: &str) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key);
+    }
+
+    fn invalidate_prefix(&self, prefix: &str) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !key.starts_with(prefix));
+    }
+}
+
+impl fmt::Debug for QueryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner()).len();
+
+        f.debug_struct("QueryCache")
+            .field("max_entries", &self.max_entries)
+            .field("entries", &entries)
+            .finish()
+    }
+}
+
+/// A caller's share of the lock of one key, removed once the last caller is done with it.
+struct Flight<'c> {
+    cache: &'c QueryCache,
+    key: &'c str,
+    lock: Arc<AsyncMutex<()>>,
+}
+
+impl<'c> Flight<'c> {
+    fn join(cache: &'c QueryCache, key: &'c str) -> Self {
+        let lock = cache
+            .flights
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_owned())
+            .or_default()
+            .clone();
+
+        Self { cache, key, lock }
+    }
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.cache.flights.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Callers only clone the lock with the map locked, so the count can't grow here.
+        if Arc::strong_count(&self.lock) <= 2 {
+            flights.remove(self.key);
+        }
+    }
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Returns the result of `f` cached under `key` for `ttl`, running it on a miss.
+    ///
+    /// Concurrent misses of one key wait for a single run instead of all querying. Errors
+    /// are not cached, the next caller runs `f` again. The cache is per process, enabled with
+    /// [`crate::SqlxPostgresConfig::query_cache`] and bounded by
+    /// [`crate::SqlxPostgresConfig::with_query_cache_max_entries`], evicting the least
+    /// recently used entry. While it is disabled `f` simply runs every time.
+    ///
+    /// Values are cloned out of the cache, so wrap large ones in an `Arc`. A cached value of
+    /// another type than `T` counts as a miss and is replaced.
+    ///
+    /// ```rust,ignore
+    /// #[get("/stats")]
+    /// async fn stats(db: SQLxPostgres) -> Result<String, Status> {
+    ///     let (orders,): (i64,) = db
+    ///         .cached("stats:orders", Duration::from_secs(5), || {
+    ///             sqlx::query_as("SELECT COUNT(*) FROM orders").fetch_one(&db)
+    ///         })
+    ///         .await
+    ///         .map_err(|_| Status::InternalServerError)?;
+    ///     Ok(format!("{} orders", orders))
+    /// }
+    /// ```
+    pub async fn cached<T, E, F, Fut>(&self, key: &str, ttl: Duration, f: F) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        match self.query_cache() {
+            Some(cache) => cache.get_or_run(key, ttl, f).await,
+            None => f().await,
+        }
+    }
+
+    /// Drops the cached result under `key`, e.g. after writing what it was computed from.
+    pub fn invalidate(&self, key: &str) {
+        if let Some(cache) = self.query_cache() {
+            cache.invalidate(key);
+        }
+    }
+
+    /// Drops every cached result whose key starts with `prefix`.
+    pub fn invalidate_prefix(&self, prefix: &str) {
+        if let Some(cache) = self.query_cache() {
+            cache.invalidate_prefix(prefix);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{guards, session::SqlxPostgresConfig, PoolGuardError};
+use rocket::{request::Request, tokio};
+use sqlx::{postgres::PgConnection, Connection};
+use std::{
+    borrow::Cow,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+/// The backend a guard's connection runs on, captured when the guard was created.
+///
+/// The guard's connection keeps the backend alive until the cancel is sent, so the pid can't
+/// have been reused by another session in the meantime. The host is captured too, as a pool
+/// failing over to another host of the list runs its queries on a different server.
+struct Armed {
+    pid: i32,
+    host: Option<(Cow<'static, str>, u16)>,
+    config: Arc<SqlxPostgresConfig>,
+    grace: Duration,
+}
+
+/// Connection of a guard, cancelling the query left running on it when the guard is dropped
+/// with the request, see [`crate::SqlxPostgresConfig::with_cancel_on_disconnect`].
+pub(crate) struct CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    conn: Option<C>,
+    armed: Option<Armed>,
+}
+
+/// Wraps the connection of a guard, capturing its backend when the config of the managed pool
+/// cancels queries of dropped requests.
+pub(crate) async fn arm<C>(
+    request: &Request<'_>,
+    mut conn: C,
+) -> Result<CancelOnDrop<C>, PoolGuardError>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    let store = guards::managed_store(request)?;
+
+    let (grace, config) = match (store.config.cancel_on_disconnect, &store.cancel_config) {
+        (Some(grace), Some(config)) => (grace, config.clone()),
+        _ => {
+            return Ok(CancelOnDrop {
+                conn: Some(conn),
+                armed: None,
+            })
+        }
+    };
+
+    let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(CancelOnDrop {
+        conn: Some(conn),
+        armed: Some(Armed {
+            pid,
+            host: config.failover_host(),
+            config,
+            grace,
+        }),
+    })
+}
+
+impl<C> CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    /// Returns the connection, which is no longer cancelled on drop.
+    pub(crate) fn into_inner(mut self) -> C {
+        self.armed = None;
+        self.conn.take().expect("connection taken before drop")
+    }
+}
+
+impl<C> Deref for CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.conn.as_ref().expect("connection taken before drop")
+    }
+}
+
+impl<C> DerefMut for CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    fn deref_mut(&mut self) -> &mut C {
+        self.conn.as_mut().expect("connection taken before drop")
+    }
+}
+
+impl<C> fmt::Debug for CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + fmt::Debug + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.conn.fmt(f)
+    }
+}
+
+impl<C> Drop for CancelOnDrop<C>
+where
+    C: DerefMut<Target = PgConnection> + Send + 'static,
+{
+    fn drop(&mut self) {
+        let (conn, armed) = match (self.conn.take(), self.armed.take()) {
+            (Some(conn), Some(armed)) => (conn, armed),
+            _ => return,
+        };
+
+        // Outside a runtime the connection just goes back to the pool.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(armed.release(conn));
+        }
+    }
+}
+
+impl Armed {
+    /// Pings the connection, which answers once no query is left running on it, and cancels
+    /// the query when that takes longer than the grace.
+    ///
+    /// The connection is held until then, so it can't go back to the pool and run the query of
+    /// another request while the cancel is sent.
+    async fn release<C>(self, mut conn: C)
+    where
+        C: DerefMut<Target = PgConnection> + Send + 'static,
+    {
+        let ping = Connection::ping(&mut *conn);
+        tokio::pin!(ping);
+
+        tokio::select! {
+            _ = &mut ping => return,
+            _ = tokio::time::sleep(self.grace) => {}
+        }
+
+        self.cancel().await;
+        let _ = ping.await;
+    }
+
+    /// Sends `pg_cancel_backend` from a connection of its own, since the pool may be exhausted
+    /// by the very queries to cancel.
+    async fn cancel(&self) {
+        let result = match self.connect().await {
+            Ok(mut conn) => {
+                let cancelled = sqlx::query_scalar::<_, bool>("SELECT pg_cancel_backend($1)")
+                    .bind(self.pid)
+                    .fetch_one(&mut conn)
+                    .await;
+                let _ = conn.close().await;
+                cancelled.map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(true) => log::info!(
+                "SQLxPostgres: cancelled the query of a dropped request on backend {}",
+                self.pid
+            ),
+            Ok(false) => {}
+            Err(e) => log::warn!(
+                "SQLxPostgres: could not cancel the query of a dropped request on backend {}: {}",
+                self.pid,
+                e
+            ),
+        }
+    }
+
+    async fn connect(&self) -> Result<PgConnection, String> {
+        let mut options = self.config.connect_options().map_err(|e| e.to_string())?;
+
+        if let Some((host, port)) = &self.host {
+            options = options.host(host).port(*port);
+        }
+
+        let connect = PgConnection::connect_with(&options);
+
+        match tokio::time::timeout(self.config.connect_timeout, connect).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(_) => Err("timed out".to_owned()),
+        }
+    }
+}
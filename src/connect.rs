@@ -0,0 +1,352 @@
+use crate::sql::quote_ident;
+use futures::future::try_join_all;
+use rand::Rng;
+use sqlx::{
+    postgres::{PgConnectOptions, PgConnection, PgPool},
+    ConnectOptions, Executor, Row,
+};
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Longest delay between two reconnect attempts.
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Which servers a new connection is accepted on, mirroring libpq's `target_session_attrs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetSessionAttrs {
+    /// Any server is accepted.
+    Any,
+    /// Only servers accepting writes are accepted, connecting to a read-only replica fails.
+    ReadWrite,
+}
+
+impl Default for TargetSessionAttrs {
+    fn default() -> Self {
+        TargetSessionAttrs::Any
+    }
+}
+
+impl std::str::FromStr for TargetSessionAttrs {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether SCRAM authentication binds to the TLS channel, mirroring libpq's `channel_binding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelBinding {
+    /// Never use channel binding.
+    Disable,
+    /// Use channel binding when available.
+    Prefer,
+    /// Refuse to connect without channel binding.
+    Require,
+}
+
+impl Default for ChannelBinding {
+    fn default() -> Self {
+        ChannelBinding::Prefer
+    }
+}
+
+impl std::str::FromStr for ChannelBinding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(ChannelBinding::Disable),
+            "prefer" => Ok(ChannelBinding::Prefer),
+            "require" => Ok(ChannelBinding::Require),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Fails when the connected server only accepts reads, e.g. a replica after a failover.
+pub(crate) async fn check_read_write(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let row = sqlx::query("SHOW transaction_read_only")
+        .fetch_one(&mut *conn)
+        .await?;
+    let read_only: String = row.try_get(0)?;
+
+    if read_only == "on" {
+        return Err(sqlx::Error::Configuration(
+            "server is read-only but target_session_attrs is read-write".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Exponential backoff for the given zero based attempt, capped at [`MAX_BACKOFF`] and
+/// jittered to between half and all of the delay so instances don't retry in lockstep.
+pub(crate) fn backoff(initial: Duration, attempt: u32) -> Duration {
+    let delay = initial
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF);
+    let half = delay / 2;
+
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// Logs once per process how to fix prepared statement errors caused by PgBouncer.
+///
+/// SQLSTATE 26000 (invalid_sql_statement_name) and 42P05 (duplicate_prepared_statement)
+/// on sqlx's named statements mean transaction pooling moved them between server sessions.
+pub(crate) fn hint_pgbouncer(error: &sqlx::Error) {
+    static HINTED: AtomicBool = AtomicBool::new(false);
+
+    let code = match error {
+        sqlx::Error::Database(e) => e.code(),
+        _ => return,
+    };
+
+    if matches!(code.as_deref(), Some("26000" | "42P05")) && !HINTED.swap(true, Ordering::Relaxed) {
+        log::warn!(
+            "SQLxPostgres: prepared statement error ({}), if connecting through PgBouncer in \
+             transaction mode enable `SqlxPostgresConfig::pgbouncer_mode`",
+            error
+        );
+    }
+}
+
+/// Runs `SELECT 1` on the pool within `timeout`, returning the round trip time.
+pub(crate) async fn ping(pool: &PgPool, timeout: Duration) -> Result<Duration, sqlx::Error> {
+    let start = Instant::now();
+    let query = sqlx::query("SELECT 1").execute(pool);
+
+    match rocket::tokio::time::timeout(timeout, query).await {
+        Ok(result) => result.map(|_| start.elapsed()),
+        Err(_) => Err(sqlx::Error::PoolTimedOut),
+    }
+}
+
+/// Runs `SELECT pg_is_in_recovery()` within `timeout`, a ping that also tells a standby,
+/// returning the round trip time and whether the server is in recovery.
+pub(crate) async fn ping_recovery(
+    pool: &PgPool,
+    timeout: Duration,
+) -> Result<(Duration, bool), sqlx::Error> {
+    let start = Instant::now();
+    let query = sqlx::query_scalar("SELECT pg_is_in_recovery()").fetch_one(pool);
+
+    match rocket::tokio::time::timeout(timeout, query).await {
+        Ok(result) => result.map(|in_recovery| (start.elapsed(), in_recovery)),
+        Err(_) => Err(sqlx::Error::PoolTimedOut),
+    }
+}
+
+/// Opens `count` connections at once and runs `SELECT 1` on each within `timeout`.
+///
+/// Every connection is held until all are ready so the pool keeps `count` of them.
+pub(crate) async fn warm_up(
+    pool: &PgPool,
+    count: u32,
+    timeout: Duration,
+) -> Result<Duration, sqlx::Error> {
+    let start = Instant::now();
+    let connections = try_join_all((0..count).map(|_| async move {
+        let mut conn = pool.acquire().await?;
+        sqlx::query("SELECT 1").execute(&mut conn).await?;
+        Ok::<_, sqlx::Error>(conn)
+    }));
+
+    match rocket::tokio::time::timeout(timeout, connections).await {
+        Ok(result) => result.map(|_| start.elapsed()),
+        Err(_) => Err(sqlx::Error::PoolTimedOut),
+    }
+}
+
+/// Whether `error` is the server rejecting an unknown database, SQLSTATE 3D000.
+pub(crate) fn is_missing_database(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Database(e) if e.code().as_deref() == Some("3D000"))
+}
+
+/// Creates `database` through the `postgres` maintenance database.
+///
+/// A database created concurrently by another instance is not an error.
+pub(crate) async fn create_database(
+    opts: &PgConnectOptions,
+    database: &str,
+) -> Result<(), sqlx::Error> {
+    let mut conn = opts.clone().database("postgres").connect().await?;
+    let sql = format!("CREATE DATABASE {}", quote_ident(database));
+
+    match conn.execute(&*sql).await {
+        Ok(_) => Ok(()),
+        // duplicate_database, or unique_violation when both creates race on pg_database
+        Err(sqlx::Error::Database(e)) if matches!(e.code().as_deref(), Some("42P04" | "23505")) => {
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates `schema` unless it exists, returning whether it was created.
+///
+/// Existence is checked first because `CREATE SCHEMA IF NOT EXISTS` still requires
+/// the `CREATE` privilege on the database.
+pub(crate) async fn ensure_schema(pool: &PgPool, schema: &str) -> Result<bool, sqlx::Error> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)")
+            .bind(schema)
+            .fetch_one(pool)
+            .await?;
+
+    if exists {
+        return Ok(false);
+    }
+
+    let sql = format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema));
+    pool.execute(&*sql).await?;
+    Ok(true)
+}
+
+/// Major and minor version of the server, `9.6` for the 9.x series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ServerVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ServerVersion {
+    /// Parses `server_version_num`, e.g. `140005` or `90624`.
+    fn from_num(num: &str) -> Option<Self> {
+        let num: u32 = num.trim().parse().ok()?;
+
+        Some(if num >= 100_000 {
+            Self {
+                major: num / 10_000,
+                minor: num % 10_000,
+            }
+        } else {
+            Self {
+                major: num / 10_000,
+                minor: num / 100 % 100,
+            }
+        })
+    }
+
+    /// Parses the leading version of `server_version`, e.g. `14.5 (Ubuntu 14.5-1)`.
+    fn from_version(version: &str) -> Option<Self> {
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        let mut parts = version[..end].split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts
+            .next()
+            .and_then(|minor| minor.parse().ok())
+            .unwrap_or(0);
+
+        Some(Self { major, minor })
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Reads the server version, falling back to `server_version` for forks reporting
+/// an unusual `server_version_num`.
+pub(crate) async fn server_version(pool: &PgPool) -> Result<ServerVersion, sqlx::Error> {
+    let num: String = sqlx::query_scalar("SHOW server_version_num")
+        .fetch_one(pool)
+        .await?;
+
+    if let Some(version) = ServerVersion::from_num(&num) {
+        return Ok(version);
+    }
+
+    let version: String = sqlx::query_scalar("SHOW server_version")
+        .fetch_one(pool)
+        .await?;
+
+    ServerVersion::from_version(&version)
+        .ok_or_else(|| sqlx::Error::Protocol(format!("unrecognized server version `{}`", version)))
+}
+
+/// The server's connection limit and how much of it other clients use.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConnectionLimit {
+    /// `max_connections` of the server.
+    pub max: u32,
+    /// `superuser_reserved_connections`, slots ordinary roles can't take.
+    pub reserved: u32,
+    /// Client connections open on the server, this pool's excluded.
+    pub others: u32,
+}
+
+impl ConnectionLimit {
+    /// Connections left for this pool.
+    pub fn headroom(&self) -> u32 {
+        self.max
+            .saturating_sub(self.reserved)
+            .saturating_sub(self.others)
+    }
+}
+
+/// Reads the server's connection limit, counting only client backends of
+/// `pg_stat_activity`, which needs Postgres 10, since background workers have their own slots.
+pub(crate) async fn connection_limit(pool: &PgPool) -> Result<ConnectionLimit, sqlx::Error> {
+    let max: String = sqlx::query_scalar("SHOW max_connections")
+        .fetch_one(pool)
+        .await?;
+    let reserved: String = sqlx::query_scalar("SHOW superuser_reserved_connections")
+        .fetch_one(pool)
+        .await?;
+    let clients: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM pg_stat_activity WHERE backend_type = 'client backend'",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let parse = |name: &str, value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| sqlx::Error::Protocol(format!("unrecognized {} `{}`", name, value)))
+    };
+    let own = pool.size() as i64;
+
+    Ok(ConnectionLimit {
+        max: parse("max_connections", &max)?,
+        reserved: parse("superuser_reserved_connections", &reserved)?,
+        others: (clients - own).max(0) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let initial = Duration::from_millis(100);
+
+        for attempt in 0..4 {
+            let delay = initial * 2u32.pow(attempt);
+            let jittered = backoff(initial, attempt);
+
+            assert!(jittered >= delay / 2 && jittered <= delay, "{:?}", jittered);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert!(backoff(Duration::from_secs(1), 10) <= MAX_BACKOFF);
+        assert!(backoff(Duration::from_secs(1), u32::MAX) <= MAX_BACKOFF);
+    }
+}
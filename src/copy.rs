@@ -0,0 +1,136 @@
+use crate::{named::DatabaseMarker, session::SQLxPostgres};
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use sqlx::postgres::PgConnection;
+use std::{fmt, io};
+
+/// Size of the chunks sent to the server.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A failed [`copy_in`], after the `COPY` was aborted.
+#[derive(Debug)]
+pub enum CopyError {
+    /// The server rejected the `COPY` or the data.
+    Database(sqlx::Error),
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The input was longer than the limit, in bytes.
+    TooLarge(u64),
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CopyError::Database(e) => write!(f, "COPY failed: {}", e),
+            CopyError::Io(e) => write!(f, "reading the COPY input failed: {}", e),
+            CopyError::TooLarge(limit) => write!(f, "COPY input is larger than {} bytes", limit),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CopyError::Database(e) => Some(e),
+            CopyError::Io(e) => Some(e),
+            CopyError::TooLarge(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for CopyError {
+    fn from(error: sqlx::Error) -> Self {
+        CopyError::Database(error)
+    }
+}
+
+/// The `COPY` statement for `target`: run as is when it is one, CSV from stdin otherwise.
+fn statement(target: &str) -> String {
+    let is_copy = target
+        .trim_start()
+        .get(..5)
+        .map_or(false, |start| start.eq_ignore_ascii_case("copy "));
+
+    match is_copy {
+        true => target.to_owned(),
+        false => format!("COPY {} FROM STDIN (FORMAT csv)", target),
+    }
+}
+
+/// Streams `data` into `COPY ... FROM STDIN` on `conn`, returning the number of rows copied.
+///
+/// `target` is either a whole `COPY ... FROM STDIN` statement or a table, optionally with a
+/// column list such as `users (name, email)`, loaded as CSV. It is SQL, never pass user input.
+/// Rocket's `DataStream` can be passed as `data` directly. At most `limit` bytes are read
+/// when set.
+///
+/// On any error the `COPY` is aborted so the connection stays usable; inside a transaction
+/// the transaction is failed and must be rolled back. Pass `&mut **tx` to import as part of
+/// a transaction guard.
+pub async fn copy_in<R>(
+    conn: &mut PgConnection,
+    target: &str,
+    mut data: R,
+    limit: Option<u64>,
+) -> Result<u64, CopyError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut copy = conn.copy_in_raw(&statement(target)).await?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut total = 0u64;
+
+    let error = loop {
+        let read = match data.read(&mut buffer).await {
+            Ok(0) => return Ok(copy.finish().await?),
+            Ok(read) => read,
+            Err(e) => break CopyError::Io(e),
+        };
+
+        total += read as u64;
+
+        if let Some(limit) = limit {
+            if total > limit {
+                break CopyError::TooLarge(limit);
+            }
+        }
+
+        // A rejected chunk fails the COPY server side, the error surfaces on abort below.
+        if let Err(e) = copy.send(&buffer[..read]).await {
+            break CopyError::Database(e);
+        }
+    };
+
+    if let Err(e) = copy.abort(error.to_string()).await {
+        log::warn!("SQLxPostgres: aborting COPY failed: {}", e);
+    }
+
+    Err(error)
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Runs [`copy_in`] on a connection of the pool.
+    ///
+    /// ```rust,ignore
+    /// #[post("/users/import", data = "<csv>")]
+    /// async fn import(db: SQLxPostgres, csv: Data<'_>) -> Result<String, Status> {
+    ///     let stream = csv.open(100.mebibytes());
+    ///     let rows = db
+    ///         .copy_in("users (name, email)", stream, None)
+    ///         .await
+    ///         .map_err(|_| Status::BadRequest)?;
+    ///     Ok(format!("imported {} users", rows))
+    /// }
+    /// ```
+    pub async fn copy_in<R>(
+        &self,
+        target: &str,
+        data: R,
+        limit: Option<u64>,
+    ) -> Result<u64, CopyError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut conn = self.pool().acquire().await?;
+        copy_in(&mut conn, target, data, limit).await
+    }
+}
@@ -0,0 +1,442 @@
+#[cfg(feature = "sessions")]
+use crate::SessionError;
+use crate::{BatchError, CopyError, MigrationError, MigrationStatus, PageError};
+use std::{fmt, path::PathBuf};
+
+/// Result of the fallible operations of this crate, see [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Any failure of this crate, to `?` its operations in your own setup code and match on the
+/// category of what went wrong.
+///
+/// The specific errors returned by individual operations, such as [`ConfigError`] or
+/// [`PoolGuardError`], convert into it.
+#[derive(Debug)]
+pub enum Error {
+    /// The config is invalid.
+    Config(ConfigError),
+    /// Reading the config from Rocket's figment failed.
+    Figment(rocket::figment::Error),
+    /// Connecting to the server failed.
+    Connect(sqlx::Error),
+    /// Running or loading migrations failed.
+    Migrate(MigrationError),
+    /// The database does not match the migrations required to be applied.
+    MigrationsNotApplied(MigrationStatus),
+    /// Creating the configured schema failed.
+    Schema { schema: String, source: sqlx::Error },
+    /// Init SQL statement `index`, starting at 0, failed.
+    InitSql { index: usize, source: sqlx::Error },
+    /// Preparing a statement of [`crate::SqlxPostgresConfig::with_prepared_warmup`] failed.
+    Prepare { sql: String, source: sqlx::Error },
+    /// No connection became available in time.
+    Timeout,
+    /// The pool awaited through [`crate::DbReady`] never became ready, for this reason.
+    NotReady(String),
+    /// No pool is managed by Rocket.
+    NotManaged,
+    /// No pool was attached under this name.
+    NotAttached(&'static str),
+    /// A request guard failed for another reason, such as a missing row level security context.
+    Guard(PoolGuardError),
+    /// A query failed.
+    Database(sqlx::Error),
+    /// A `COPY` failed.
+    Copy(CopyError),
+    /// An SQL batch failed.
+    Batch(BatchError),
+    /// Fetching a page failed.
+    Page(PageError),
+    /// Reading or writing a session value failed.
+    #[cfg(feature = "sessions")]
+    Session(SessionError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "invalid configuration: {}", e),
+            Error::Figment(e) => write!(f, "invalid configuration: {}", e),
+            Error::Connect(e) => write!(f, "connecting to the database failed: {}", e),
+            Error::Migrate(e) => e.fmt(f),
+            Error::MigrationsNotApplied(status) => write!(
+                f,
+                "database schema does not match the required migrations, {} pending, {} edited",
+                status.pending.len(),
+                status.mismatched.len()
+            ),
+            Error::Schema { schema, source } => {
+                write!(f, "creating schema `{}` failed: {}", schema, source)
+            }
+            Error::InitSql { index, source } => {
+                write!(f, "init sql statement {} failed: {}", index, source)
+            }
+            Error::Prepare { sql, source } => {
+                write!(
+                    f,
+                    "preparing the warm up statement `{}` failed: {}",
+                    sql, source
+                )
+            }
+            Error::Timeout => write!(f, "timed out waiting for a database connection"),
+            Error::NotReady(reason) => write!(f, "the database pool is not available: {}", reason),
+            Error::NotManaged => PoolGuardError::NotManaged.fmt(f),
+            Error::NotAttached(name) => PoolGuardError::NotAttached(name).fmt(f),
+            Error::Guard(e) => e.fmt(f),
+            Error::Database(e) => write!(f, "database error: {}", e),
+            Error::Copy(e) => e.fmt(f),
+            Error::Batch(e) => e.fmt(f),
+            Error::Page(e) => e.fmt(f),
+            #[cfg(feature = "sessions")]
+            Error::Session(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::Figment(e) => Some(e),
+            Error::Connect(e) | Error::Database(e) => Some(e),
+            Error::Migrate(e) => Some(e),
+            Error::Schema { source, .. }
+            | Error::InitSql { source, .. }
+            | Error::Prepare { source, .. } => Some(source),
+            Error::Guard(e) => Some(e),
+            Error::Copy(e) => Some(e),
+            Error::Batch(e) => Some(e),
+            Error::Page(e) => Some(e),
+            #[cfg(feature = "sessions")]
+            Error::Session(e) => Some(e),
+            Error::MigrationsNotApplied(_)
+            | Error::Timeout
+            | Error::NotReady(_)
+            | Error::NotManaged
+            | Error::NotAttached(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::PoolTimedOut => Error::Timeout,
+            error => Error::Database(error),
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        Error::Config(error)
+    }
+}
+
+impl From<rocket::figment::Error> for Error {
+    fn from(error: rocket::figment::Error) -> Self {
+        Error::Figment(error)
+    }
+}
+
+impl From<MigrationError> for Error {
+    fn from(error: MigrationError) -> Self {
+        Error::Migrate(error)
+    }
+}
+
+impl From<PoolGuardError> for Error {
+    fn from(error: PoolGuardError) -> Self {
+        match error {
+            PoolGuardError::NotManaged => Error::NotManaged,
+            PoolGuardError::NotAttached(name) => Error::NotAttached(name),
+            PoolGuardError::AcquireTimeout => Error::Timeout,
+            PoolGuardError::PoolClosed => Error::Database(sqlx::Error::PoolClosed),
+            PoolGuardError::Database(e) => Error::Database(e),
+            error => Error::Guard(error),
+        }
+    }
+}
+
+impl From<CopyError> for Error {
+    fn from(error: CopyError) -> Self {
+        Error::Copy(error)
+    }
+}
+
+impl From<BatchError> for Error {
+    fn from(error: BatchError) -> Self {
+        Error::Batch(error)
+    }
+}
+
+impl From<PageError> for Error {
+    fn from(error: PageError) -> Self {
+        Error::Page(error)
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl From<SessionError> for Error {
+    fn from(error: SessionError) -> Self {
+        Error::Session(error)
+    }
+}
+
+/// Errors produced while building or validating a
+/// [`SqlxPostgresConfig`](crate::SqlxPostgresConfig).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// No database name was set.
+    EmptyDatabase,
+    /// No username was set.
+    EmptyUsername,
+    /// The port was set to 0.
+    ZeroPort,
+    /// max_connections was set to 0.
+    ZeroMaxConnections,
+    /// The connection URL could not be parsed.
+    InvalidUrl(String),
+    /// An environment variable held a value that could not be used.
+    InvalidEnv { name: String, reason: String },
+    /// No service file defines the service, see [`crate::SqlxPostgresConfig::from_service`].
+    UnknownService {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+    /// A setting of a service held a value that could not be used.
+    InvalidService {
+        name: String,
+        key: String,
+        reason: String,
+    },
+    /// A file referenced by the config does not exist.
+    MissingFile { option: &'static str, path: PathBuf },
+    /// A file referenced by the config could not be read.
+    UnreadableFile {
+        option: &'static str,
+        path: PathBuf,
+        reason: String,
+    },
+    /// An option was set without another option it depends on.
+    MissingOption {
+        option: &'static str,
+        requires: &'static str,
+    },
+    /// A timeout that must be positive was set to zero.
+    ZeroDuration(&'static str),
+    /// An option was set to a value the connection driver cannot honor.
+    Unsupported {
+        option: &'static str,
+        reason: &'static str,
+    },
+    /// Two options that cannot be used together were both set.
+    Conflict {
+        option: &'static str,
+        conflicts_with: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyDatabase => write!(
+                f,
+                "database name is empty, set it with `SqlxPostgresConfig::with_database`"
+            ),
+            ConfigError::EmptyUsername => write!(
+                f,
+                "username is empty, set it with `SqlxPostgresConfig::with_username`"
+            ),
+            ConfigError::ZeroPort => write!(f, "port must not be 0, postgres defaults to 5432"),
+            ConfigError::ZeroMaxConnections => write!(
+                f,
+                "max_connections must be at least 1, set it with `SqlxPostgresConfig::set_max_connections`"
+            ),
+            ConfigError::InvalidUrl(reason) => write!(f, "invalid postgres url: {}", reason),
+            ConfigError::InvalidEnv { name, reason } => {
+                write!(f, "invalid environment variable {}: {}", name, reason)
+            }
+            ConfigError::UnknownService { name, searched } if searched.is_empty() => write!(
+                f,
+                "service `{}` not found, set PGSERVICEFILE or PGSYSCONFDIR to locate pg_service.conf",
+                name
+            ),
+            ConfigError::UnknownService { name, searched } => {
+                let searched = searched
+                    .iter()
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "service `{}` not found in {}", name, searched)
+            }
+            ConfigError::InvalidService { name, key, reason } => {
+                write!(f, "invalid setting {} of service `{}`: {}", key, name, reason)
+            }
+            ConfigError::MissingFile { option, path } => {
+                write!(f, "{} file `{}` does not exist", option, path.display())
+            }
+            ConfigError::UnreadableFile {
+                option,
+                path,
+                reason,
+            } => write!(f, "{} file `{}` could not be read: {}", option, path.display(), reason),
+            ConfigError::MissingOption { option, requires } => {
+                write!(f, "{} is set but {} is missing", option, requires)
+            }
+            ConfigError::ZeroDuration(option) => write!(f, "{} must be greater than zero", option),
+            ConfigError::Unsupported { option, reason } => {
+                write!(f, "{} is not supported: {}", option, reason)
+            }
+            ConfigError::Conflict {
+                option,
+                conflicts_with,
+            } => write!(f, "{} cannot be used together with {}", option, conflicts_with),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Errors returned by the request guards when no connection can be handed out.
+#[derive(Debug)]
+pub enum PoolGuardError {
+    /// No pool is managed by Rocket.
+    NotManaged,
+    /// No pool was attached under this name.
+    NotAttached(&'static str),
+    /// The pool was closed.
+    PoolClosed,
+    /// No connection became available in time.
+    AcquireTimeout,
+    /// Too many guards were waiting for a connection already, see
+    /// [`crate::SqlxPostgresConfig::with_max_waiters`].
+    TooManyWaiters,
+    /// The request guard providing the row level security context failed.
+    MissingContext,
+    /// Database work is refused, see [`crate::MaintenanceMode`].
+    Maintenance,
+    /// Recent acquisitions failed, see [`crate::SqlxPostgresConfig::with_circuit_breaker`].
+    CircuitOpen,
+    /// No [`crate::ShardRouter`] with the guard's key type was registered.
+    NoShardRouter,
+    /// The shard of the request could not be resolved, failing with the status.
+    ShardUnresolved(rocket::http::Status),
+    /// The shard key mapped to a database that isn't attached.
+    ShardNotAttached(&'static str),
+    /// The config has no [`crate::TenantSchemas`] for the [`crate::TenantDb`] guard.
+    NoTenantSchemas,
+    /// The request has no valid tenant, failing with the configured status.
+    TenantUnresolved(rocket::http::Status),
+    /// Opening or preparing a connection failed.
+    Database(sqlx::Error),
+}
+
+impl PoolGuardError {
+    /// Status the guard fails with.
+    pub fn status(&self) -> rocket::http::Status {
+        match self {
+            PoolGuardError::NotManaged
+            | PoolGuardError::NotAttached(_)
+            | PoolGuardError::NoShardRouter
+            | PoolGuardError::ShardNotAttached(_)
+            | PoolGuardError::NoTenantSchemas => rocket::http::Status::InternalServerError,
+            PoolGuardError::ShardUnresolved(status) | PoolGuardError::TenantUnresolved(status) => {
+                *status
+            }
+            PoolGuardError::MissingContext => rocket::http::Status::Unauthorized,
+            _ => rocket::http::Status::ServiceUnavailable,
+        }
+    }
+
+    /// A copy of the error, keeping only the message of a database error.
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            PoolGuardError::NotManaged => PoolGuardError::NotManaged,
+            PoolGuardError::NotAttached(name) => PoolGuardError::NotAttached(name),
+            PoolGuardError::PoolClosed => PoolGuardError::PoolClosed,
+            PoolGuardError::AcquireTimeout => PoolGuardError::AcquireTimeout,
+            PoolGuardError::TooManyWaiters => PoolGuardError::TooManyWaiters,
+            PoolGuardError::MissingContext => PoolGuardError::MissingContext,
+            PoolGuardError::Maintenance => PoolGuardError::Maintenance,
+            PoolGuardError::CircuitOpen => PoolGuardError::CircuitOpen,
+            PoolGuardError::NoShardRouter => PoolGuardError::NoShardRouter,
+            PoolGuardError::ShardUnresolved(status) => PoolGuardError::ShardUnresolved(*status),
+            PoolGuardError::ShardNotAttached(name) => PoolGuardError::ShardNotAttached(name),
+            PoolGuardError::NoTenantSchemas => PoolGuardError::NoTenantSchemas,
+            PoolGuardError::TenantUnresolved(status) => PoolGuardError::TenantUnresolved(*status),
+            PoolGuardError::Database(e) => {
+                PoolGuardError::Database(sqlx::Error::Protocol(e.to_string()))
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for PoolGuardError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::PoolClosed => PoolGuardError::PoolClosed,
+            sqlx::Error::PoolTimedOut => PoolGuardError::AcquireTimeout,
+            error => PoolGuardError::Database(error),
+        }
+    }
+}
+
+impl fmt::Display for PoolGuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolGuardError::NotManaged => write!(
+                f,
+                "no database pool is managed, attach SqlxPostgresFairing or check that it connected"
+            ),
+            PoolGuardError::NotAttached(name) => write!(
+                f,
+                "database '{}' not attached, attach SqlxPostgresFairing::named(\"{}\", ...)",
+                name, name
+            ),
+            PoolGuardError::PoolClosed => write!(f, "the database pool is closed"),
+            PoolGuardError::MissingContext => {
+                write!(f, "no row level security context for this request")
+            }
+            PoolGuardError::Maintenance => write!(f, "the database is in maintenance mode"),
+            PoolGuardError::CircuitOpen => {
+                write!(f, "the database circuit breaker is open, failing fast")
+            }
+            PoolGuardError::NoShardRouter => write!(
+                f,
+                "no shard router is registered for this key type, register one with SqlxPostgresFairing::with_shards"
+            ),
+            PoolGuardError::ShardUnresolved(status) => {
+                write!(f, "could not resolve the shard of the request: {}", status)
+            }
+            PoolGuardError::ShardNotAttached(name) => write!(
+                f,
+                "the shard key maps to database '{}', which is not attached, attach SqlxPostgresFairing::named(\"{}\", ...)",
+                name, name
+            ),
+            PoolGuardError::NoTenantSchemas => write!(
+                f,
+                "no tenant schemas are configured, set them with SqlxPostgresConfig::with_tenant_schemas"
+            ),
+            PoolGuardError::TenantUnresolved(status) => {
+                write!(f, "could not resolve the tenant of the request: {}", status)
+            }
+            PoolGuardError::AcquireTimeout => {
+                write!(f, "timed out waiting for a database connection")
+            }
+            PoolGuardError::TooManyWaiters => {
+                write!(f, "too many requests waiting for a database connection")
+            }
+            PoolGuardError::Database(e) => write!(f, "database connection failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PoolGuardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolGuardError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
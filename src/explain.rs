@@ -0,0 +1,265 @@
+use crate::observe::rid_prefix;
+use rocket::tokio;
+use sqlx::{postgres::PgPool, types::JsonValue, Connection, Executor};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Window the explain budget of [`ExplainPolicy::with_max_per_minute`] is counted over.
+const BUDGET_WINDOW: Duration = Duration::from_secs(60);
+
+/// When [`crate::SqlxPostgresConfig::with_slow_query_explain`] explains a slow query and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExplainPolicy {
+    threshold: Duration,
+    max_per_minute: u32,
+    analyze: bool,
+}
+
+impl ExplainPolicy {
+    /// Explains queries taking longer than `threshold`, at most 6 per minute, without
+    /// `ANALYZE`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            max_per_minute: 6,
+            analyze: false,
+        }
+    }
+
+    /// Plans logged per minute at most, later slow queries of the minute are not explained.
+    pub fn with_max_per_minute(mut self, max_per_minute: u32) -> Self {
+        self.max_per_minute = max_per_minute;
+        self
+    }
+
+    /// Whether to run `EXPLAIN ANALYZE`, which executes the query again to time each node.
+    ///
+    /// Only read queries (`SELECT`, `WITH`, `VALUES` and `TABLE`) without bind parameters are
+    /// analyzed, in a read only transaction that is rolled back, so a data modifying query
+    /// is never run twice. Other queries get a plain `EXPLAIN`. Off by default: the query
+    /// being slow, analyzing it costs as much again.
+    pub fn with_analyze(mut self, analyze: bool) -> Self {
+        self.analyze = analyze;
+        self
+    }
+}
+
+/// What a statement is, as far as explaining it goes.
+#[derive(Debug, PartialEq, Eq)]
+enum Statement {
+    /// Can't be explained, e.g. DDL or several statements.
+    Unexplainable,
+    /// Can be explained, `read` when it only reads.
+    Explainable { read: bool, parameters: bool },
+}
+
+/// Explains slow queries, with the budget shared by the clones of a config.
+#[derive(Debug, Clone)]
+pub(crate) struct Explainer {
+    policy: ExplainPolicy,
+    /// Start of the current window and the plans explained in it.
+    budget: Arc<Mutex<(Instant, u32)>>,
+    /// Set once the server rejected `GENERIC_PLAN`, from then on queries with bind
+    /// parameters are not explained.
+    generic_plan_unsupported: Arc<AtomicBool>,
+}
+
+impl Explainer {
+    pub(crate) fn new(policy: ExplainPolicy) -> Self {
+        Self {
+            policy,
+            budget: Arc::new(Mutex::new((Instant::now(), 0))),
+            generic_plan_unsupported: Arc::default(),
+        }
+    }
+
+    /// Logs the plan of `sql` when it took longer than the threshold, in the background on
+    /// another connection of `pool`.
+    ///
+    /// Best effort: nothing is logged when the budget is spent, the statement can't be
+    /// explained, the pool has no idle connection or `EXPLAIN` fails.
+    ///
+    /// The plan is prefixed with `rid`, the request id when it is logged.
+    pub(crate) fn observe(&self, pool: &PgPool, sql: &str, elapsed: Duration, rid: Option<&str>) {
+        if elapsed < self.policy.threshold {
+            return;
+        }
+
+        let (read, parameters) = match classify(sql) {
+            Statement::Explainable { read, parameters } => (read, parameters),
+            Statement::Unexplainable => return,
+        };
+
+        if parameters && self.generic_plan_unsupported.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if !self.take_budget() {
+            return;
+        }
+
+        // Never wait for a connection, the pool may be what made the query slow.
+        let mut conn = match pool.try_acquire() {
+            Some(conn) => conn,
+            None => {
+                log::debug!("SQLxPostgres: no idle connection to explain a slow query");
+                return;
+            }
+        };
+
+        let analyze = self.policy.analyze && read && !parameters;
+        // sqlx can't copy the bind parameters of a query, so those get the generic plan.
+        let options = match (analyze, parameters) {
+            (true, _) => "ANALYZE, FORMAT JSON",
+            (false, true) => "FORMAT JSON, GENERIC_PLAN",
+            (false, false) => "FORMAT JSON",
+        };
+        let explain = format!("EXPLAIN ({}) {}", options, sql);
+        let sql = sql.to_owned();
+        let rid = rid_prefix(rid);
+
+        let generic_plan_unsupported = self.generic_plan_unsupported.clone();
+        let note = match parameters {
+            true => " (generic, without the bind parameters)",
+            false => "",
+        };
+
+        tokio::spawn(async move {
+            let plan = match analyze {
+                true => {
+                    let mut tx = match conn.begin().await {
+                        Ok(tx) => tx,
+                        Err(e) => {
+                            return log::debug!(
+                                "SQLxPostgres: could not explain a slow query: {}",
+                                e
+                            )
+                        }
+                    };
+                    let plan = match tx.execute("SET TRANSACTION READ ONLY").await {
+                        Ok(_) => {
+                            sqlx::query_scalar::<_, JsonValue>(&explain)
+                                .fetch_one(&mut tx)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    let _ = tx.rollback().await;
+                    plan
+                }
+                false => {
+                    sqlx::query_scalar::<_, JsonValue>(&explain)
+                        .fetch_one(&mut conn)
+                        .await
+                }
+            };
+
+            match plan {
+                Ok(plan) => log::warn!(
+                    "SQLxPostgres: {}slow query took {:?}, plan{}: {}\n\n{}",
+                    rid,
+                    elapsed,
+                    note,
+                    plan,
+                    sql
+                ),
+                // GENERIC_PLAN needs Postgres 16, older servers reject it as an unknown option.
+                Err(e) if parameters && e.to_string().contains("generic_plan") => {
+                    if !generic_plan_unsupported.swap(true, Ordering::Relaxed) {
+                        log::warn!(
+                            "SQLxPostgres: the server does not support EXPLAIN (GENERIC_PLAN), which \
+                             needs Postgres 16, slow queries with bind parameters are not explained: {}",
+                            e
+                        );
+                    }
+                }
+                Err(e) => log::debug!("SQLxPostgres: could not explain a slow query: {}", e),
+            }
+        });
+    }
+
+    /// Counts a plan against the budget, `false` when it is spent for this minute.
+    fn take_budget(&self) -> bool {
+        let mut budget = self.budget.lock().unwrap_or_else(|e| e.into_inner());
+
+        if budget.0.elapsed() >= BUDGET_WINDOW {
+            *budget = (Instant::now(), 0);
+        }
+
+        match budget.1 < self.policy.max_per_minute {
+            true => {
+                budget.1 += 1;
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+/// Classifies a statement by its first keyword, also checking whether it holds bind
+/// parameters and more than one statement, outside quotes and comments.
+fn classify(sql: &str) -> Statement {
+    let mut chars = sql.chars().peekable();
+    let mut keyword = String::new();
+    let mut parameters = false;
+    let mut ended = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for commented in chars.by_ref() {
+                    if commented == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                let mut last = ' ';
+
+                for commented in chars.by_ref() {
+                    if last == '*' && commented == '/' {
+                        break;
+                    }
+
+                    last = commented;
+                }
+            }
+            ';' => ended = true,
+            c if c.is_whitespace() => {}
+            _ if ended => return Statement::Unexplainable,
+            '\'' | '"' => {
+                for quoted in chars.by_ref() {
+                    if quoted == c {
+                        break;
+                    }
+                }
+            }
+            '$' if chars.peek().map_or(false, char::is_ascii_digit) => parameters = true,
+            c if c.is_ascii_alphabetic() && keyword.is_empty() => {
+                keyword.push(c);
+
+                while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                    keyword.push(c);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match &*keyword.to_ascii_uppercase() {
+        "SELECT" | "WITH" | "VALUES" | "TABLE" => Statement::Explainable {
+            read: true,
+            parameters,
+        },
+        "INSERT" | "UPDATE" | "DELETE" | "MERGE" => Statement::Explainable {
+            read: false,
+            parameters,
+        },
+        _ => Statement::Unexplainable,
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{named::SQLxPostgresPools, session::SQLxPostgresPool, slot::PgPoolSlot};
+use rocket::{Phase, Rocket};
+use sqlx::postgres::PgPool;
+
+/// Pool lookups on a Rocket instance, for fairings, background tasks and composition code.
+///
+/// The pools are returned by value, cloning one only bumps a reference count. A clone
+/// keeps using the pool it was taken from after the supervisor or a [`crate::PoolHandle`]
+/// swaps in a new one, so long-running workers should hold a [`PgPoolSlot`] instead.
+///
+/// ```rust,ignore
+/// .attach(AdHoc::on_liftoff("worker", |rocket| Box::pin(async move {
+///     let slot = rocket.pg_pool_slot().expect("database attached");
+///     rocket::tokio::spawn(run_jobs(slot));
+/// })))
+/// ```
+pub trait SqlxPostgresRocketExt {
+    /// The pool of the unnamed fairing, `None` when it is not attached or did not connect.
+    fn pg_pool(&self) -> Option<PgPool>;
+
+    /// The pool of the database attached under `name`.
+    fn pg_named_pool(&self, name: &str) -> Option<PgPool>;
+
+    /// The swappable pool of the unnamed fairing, following the pools swapped in later.
+    fn pg_pool_slot(&self) -> Option<PgPoolSlot>;
+
+    /// The swappable pool of the database attached under `name`.
+    fn pg_named_pool_slot(&self, name: &str) -> Option<PgPoolSlot>;
+
+    /// The pool of the unnamed fairing.
+    ///
+    /// # Panics
+    ///
+    /// When no pool is managed.
+    fn pg_pool_or_panic(&self) -> PgPool {
+        self.pg_pool().expect(
+            "no database pool is managed, attach SqlxPostgresFairing or check that it connected",
+        )
+    }
+}
+
+impl<P: Phase> SqlxPostgresRocketExt for Rocket<P> {
+    fn pg_pool(&self) -> Option<PgPool> {
+        self.state::<SQLxPostgresPool>().map(|store| store.client())
+    }
+
+    fn pg_named_pool(&self, name: &str) -> Option<PgPool> {
+        self.state::<SQLxPostgresPools>()
+            .and_then(|pools| pools.pool(name))
+            .map(|store| store.client())
+    }
+
+    fn pg_pool_slot(&self) -> Option<PgPoolSlot> {
+        self.state::<SQLxPostgresPool>().map(|store| store.slot())
+    }
+
+    fn pg_named_pool_slot(&self, name: &str) -> Option<PgPoolSlot> {
+        self.state::<SQLxPostgresPools>()
+            .and_then(|pools| pools.pool(name))
+            .map(|store| store.slot())
+    }
+}
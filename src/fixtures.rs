@@ -0,0 +1,361 @@
+use crate::{
+    batch::{self, BatchError},
+    sql::{quote_ident, quote_table},
+};
+use rocket::serde::Serialize;
+use serde_json::Value;
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    types::Json,
+    Connection,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// A failed [`Fixtures`] load or apply.
+#[derive(Debug)]
+pub enum FixtureError {
+    /// The fixture directory or one of its files could not be read.
+    Io { path: PathBuf, reason: String },
+    /// The rows for `table` could not be serialized, or weren't JSON objects.
+    Serialize { table: String, reason: String },
+    /// [`Fixtures::only`] named a fixture that wasn't loaded.
+    Unknown(String),
+    /// Statement `index`, starting at 1, of the SQL file `fixture` failed.
+    Statement {
+        fixture: String,
+        index: usize,
+        source: sqlx::Error,
+    },
+    /// Inserting the rows of `fixture` failed.
+    Rows {
+        fixture: String,
+        source: sqlx::Error,
+    },
+    /// Beginning or committing the transaction fixtures are applied in failed.
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io { path, reason } => {
+                write!(
+                    f,
+                    "reading fixtures at `{}` failed: {}",
+                    path.display(),
+                    reason
+                )
+            }
+            FixtureError::Serialize { table, reason } => {
+                write!(
+                    f,
+                    "serializing the fixture rows of `{}` failed: {}",
+                    table, reason
+                )
+            }
+            FixtureError::Unknown(name) => write!(f, "no fixture named `{}` is loaded", name),
+            FixtureError::Statement {
+                fixture,
+                index,
+                source,
+            } => write!(
+                f,
+                "statement {} of fixture `{}` failed: {}",
+                index, fixture, source
+            ),
+            FixtureError::Rows { fixture, source } => {
+                write!(
+                    f,
+                    "inserting the rows of fixture `{}` failed: {}",
+                    fixture, source
+                )
+            }
+            FixtureError::Database(e) => write!(f, "applying the fixtures failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FixtureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FixtureError::Statement { source, .. } | FixtureError::Rows { source, .. } => {
+                Some(source)
+            }
+            FixtureError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for FixtureError {
+    fn from(error: sqlx::Error) -> Self {
+        FixtureError::Database(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Fixture {
+    /// A `.sql` file, run as a batch.
+    Sql { name: String, sql: String },
+    /// Rows serialized to JSON objects, inserted into `table` of `schema`.
+    Rows {
+        schema: Option<String>,
+        table: String,
+        rows: Vec<Value>,
+    },
+}
+
+impl Fixture {
+    fn name(&self) -> &str {
+        match self {
+            Fixture::Sql { name, .. } => name,
+            Fixture::Rows { table, .. } => table,
+        }
+    }
+
+    /// Whether [`Fixtures::only`] selects this fixture by `name`: its file stem with or
+    /// without an ordering prefix like `01_`, or its table.
+    fn is_named(&self, name: &str) -> bool {
+        let own = self.name();
+        let unprefixed = own
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .trim_start_matches(|c: char| c == '_' || c == '-');
+
+        own == name || unprefixed == name
+    }
+
+    async fn apply(&self, conn: &mut PgConnection) -> Result<(), FixtureError> {
+        match self {
+            Fixture::Sql { name, sql } => match batch::execute_batch(conn, sql).await {
+                Ok(_) | Err(BatchError::Empty) => Ok(()),
+                Err(BatchError::Statement { index, source }) => Err(FixtureError::Statement {
+                    fixture: name.clone(),
+                    index: index + 1,
+                    source,
+                }),
+                Err(BatchError::Database(source)) => Err(FixtureError::Statement {
+                    fixture: name.clone(),
+                    index: 1,
+                    source,
+                }),
+            },
+            Fixture::Rows { rows, .. } if rows.is_empty() => Ok(()),
+            Fixture::Rows {
+                schema,
+                table,
+                rows,
+            } => {
+                let sql = rows_statement(schema.as_deref(), table, rows);
+
+                sqlx::query(&sql)
+                    .bind(Json(rows))
+                    .execute(conn)
+                    .await
+                    .map(|_| ())
+                    .map_err(|source| FixtureError::Rows {
+                        fixture: table.clone(),
+                        source,
+                    })
+            }
+        }
+    }
+}
+
+/// Inserts the JSON objects of `$1` into `table` of `schema`, setting only the columns some
+/// row has so the others keep their defaults.
+fn rows_statement(schema: Option<&str>, table: &str, rows: &[Value]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+
+    for key in rows
+        .iter()
+        .filter_map(Value::as_object)
+        .flat_map(|row| row.keys())
+    {
+        if !columns.contains(&key.as_str()) {
+            columns.push(key.as_str());
+        }
+    }
+
+    let columns = columns
+        .into_iter()
+        .map(quote_ident)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let table = quote_table(schema, table);
+
+    format!(
+        "INSERT INTO {table} ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::{table}, $1)",
+        table = table,
+        columns = columns
+    )
+}
+
+/// Seed data for tests, from `.sql` files and serialized rows, applied after the migrations.
+///
+/// Fixtures are applied in the order they were added, files of a directory in lexicographic
+/// order of their names, so prefixes like `01_users.sql` order them. Tests pick the ones they
+/// need with [`Fixtures::only`]. On a [`crate::TestTransaction`] apply them to its connection,
+/// so they are rolled back with the test.
+///
+/// ```rust,ignore
+/// let fixtures = Fixtures::from_dir("tests/fixtures")?
+///     .with_rows("orders", &[Order { id: 1, user_id: 1, total: 100 }])?
+///     .only(&["users", "orders"]);
+///
+/// fixtures.apply_pool(db.pool()).await?;
+/// // or, in transaction mode
+/// fixtures.apply(&mut *tx.acquire().await).await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Fixtures {
+    fixtures: Vec<Fixture>,
+    selected: Option<Vec<String>>,
+}
+
+impl Fixtures {
+    /// No fixtures, to add rows to.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every `.sql` file of `dir`, named by their file stem.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, FixtureError> {
+        Self::new().with_dir(dir)
+    }
+
+    /// Adds every `.sql` file of `dir`, in lexicographic order, named by their file stem.
+    pub fn with_dir(mut self, dir: impl AsRef<Path>) -> Result<Self, FixtureError> {
+        let dir = dir.as_ref();
+        let io_error = |path: &Path, e: std::io::Error| FixtureError::Io {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        };
+
+        let mut paths = Vec::new();
+
+        for entry in std::fs::read_dir(dir).map_err(|e| io_error(dir, e))? {
+            let path = entry.map_err(|e| io_error(dir, e))?.path();
+
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "sql") {
+                paths.push(path);
+            }
+        }
+
+        paths.sort();
+
+        for path in paths {
+            let sql = std::fs::read_to_string(&path).map_err(|e| io_error(&path, e))?;
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            self.fixtures.push(Fixture::Sql { name, sql });
+        }
+
+        Ok(self)
+    }
+
+    /// Adds `rows` to insert into `table` of the connection's `search_path`, named after the
+    /// table.
+    ///
+    /// Each row must serialize to an object whose fields are columns of the table; columns no
+    /// row sets keep their defaults. The name is quoted as a whole, so a dot is part of it;
+    /// use [`Fixtures::with_rows_in`] for a table of another schema.
+    pub fn with_rows<T: Serialize>(self, table: &str, rows: &[T]) -> Result<Self, FixtureError> {
+        self.rows(None, table, rows)
+    }
+
+    /// Adds `rows` to insert into `table` of `schema`, named after the table like
+    /// [`Fixtures::with_rows`].
+    pub fn with_rows_in<T: Serialize>(
+        self,
+        schema: &str,
+        table: &str,
+        rows: &[T],
+    ) -> Result<Self, FixtureError> {
+        self.rows(Some(schema), table, rows)
+    }
+
+    fn rows<T: Serialize>(
+        mut self,
+        schema: Option<&str>,
+        table: &str,
+        rows: &[T],
+    ) -> Result<Self, FixtureError> {
+        let serialize_error = |reason: String| FixtureError::Serialize {
+            table: table.to_owned(),
+            reason,
+        };
+
+        let rows = rows
+            .iter()
+            .map(|row| match serde_json::to_value(row) {
+                Ok(row @ Value::Object(_)) => Ok(row),
+                Ok(_) => Err(serialize_error("a row is not a struct or map".to_owned())),
+                Err(e) => Err(serialize_error(e.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.fixtures.push(Fixture::Rows {
+            schema: schema.map(str::to_owned),
+            table: table.to_owned(),
+            rows,
+        });
+        Ok(self)
+    }
+
+    /// Applies only the fixtures named in `names`, still in the order they were added.
+    ///
+    /// A name matches a file stem, the stem without an ordering prefix like `01_`, or the
+    /// table of rows. Names that match nothing fail [`Fixtures::apply`].
+    pub fn only(mut self, names: &[&str]) -> Self {
+        self.selected = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// The fixtures to apply, in order.
+    fn selected(&self) -> Result<Vec<&Fixture>, FixtureError> {
+        let names = match &self.selected {
+            Some(names) => names,
+            None => return Ok(self.fixtures.iter().collect()),
+        };
+
+        if let Some(unknown) = names
+            .iter()
+            .find(|name| !self.fixtures.iter().any(|fixture| fixture.is_named(name)))
+        {
+            return Err(FixtureError::Unknown(unknown.clone()));
+        }
+
+        Ok(self
+            .fixtures
+            .iter()
+            .filter(|fixture| names.iter().any(|name| fixture.is_named(name)))
+            .collect())
+    }
+
+    /// Applies the fixtures on `conn`, stopping at the first failure.
+    ///
+    /// Nothing is undone on failure unless `conn` is in a transaction, as it is for a
+    /// [`crate::TestTransaction`].
+    pub async fn apply(&self, conn: &mut PgConnection) -> Result<(), FixtureError> {
+        for fixture in self.selected()? {
+            fixture.apply(conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the fixtures on a connection of `pool`, in one transaction.
+    pub async fn apply_pool(&self, pool: &PgPool) -> Result<(), FixtureError> {
+        let mut conn = pool.acquire().await?;
+        let mut tx = conn.begin().await?;
+        self.apply(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
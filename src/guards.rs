@@ -0,0 +1,974 @@
+use self::isolation::{Isolation, IsolationLevel};
+#[cfg(feature = "sticky-reads")]
+use crate::sticky;
+use crate::{
+    cancel::{self, CancelOnDrop},
+    connect, maintenance,
+    named::{self, DatabaseMarker, DefaultDatabase},
+    observe::{self, RequestDbTime},
+    session::SQLxPostgresPool,
+    PoolGuardError,
+};
+use futures::future::BoxFuture;
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{FromRequest, Request},
+    Ignite, Rocket, Sentinel,
+};
+use sqlx::{
+    pool::PoolConnection,
+    postgres::{PgConnection, PgPool},
+    Connection, Executor, Postgres, Transaction,
+};
+use std::{
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The pool managed by the unnamed fairing.
+pub(crate) fn managed_store<'r>(
+    request: &'r Request<'_>,
+) -> Result<&'r SQLxPostgresPool, PoolGuardError> {
+    request
+        .rocket()
+        .state::<SQLxPostgresPool>()
+        .ok_or(PoolGuardError::NotManaged)
+}
+
+/// Runs `f` inside a savepoint of the transaction open on `conn`.
+///
+/// `SAVEPOINT` is issued first, then `RELEASE SAVEPOINT` when `f` returns `Ok` and
+/// `ROLLBACK TO SAVEPOINT` when it returns `Err`, undoing only what `f` did so the
+/// transaction stays usable even after a failed statement. Savepoints are named by their
+/// depth, so `savepoint` nests on the connection handed to `f`. When the returned future is
+/// dropped before completing, the rollback to the savepoint runs before the next statement
+/// on the connection.
+///
+/// ```rust,ignore
+/// let inserted = savepoint(&mut **tx, |sp| Box::pin(async move {
+///     sqlx::query("INSERT INTO tags (name) VALUES ($1)")
+///         .bind(&name)
+///         .execute(&mut *sp)
+///         .await
+/// }))
+/// .await
+/// .is_ok();
+/// ```
+pub async fn savepoint<T, E, F>(conn: &mut PgConnection, f: F) -> Result<T, E>
+where
+    F: for<'t> FnOnce(&'t mut PgConnection) -> BoxFuture<'t, Result<T, E>> + Send,
+    T: Send,
+    E: From<sqlx::Error> + Send,
+{
+    // Inside a transaction sqlx begins a savepoint named after the nesting depth.
+    let mut sp = conn.begin().await?;
+
+    match f(&mut *sp).await {
+        Ok(value) => {
+            sp.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            if let Err(rollback) = sp.rollback().await {
+                log::warn!("SQLxPostgres: rollback to savepoint failed: {}", rollback);
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// Set in the request local cache when a guard gave up acquiring, so the fairing
+/// can add a `Retry-After` header.
+pub(crate) struct AcquireTimedOut(pub(crate) bool);
+
+/// Runs `acquire` on the managed pool, bounded by the configured guard acquire timeout.
+async fn acquire<'r, T, F, Fut>(request: &'r Request<'_>, acquire: F) -> Result<T, PoolGuardError>
+where
+    F: FnOnce(PgPool) -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    maintenance::check(request)?;
+    let store = managed_store(request)?;
+    check_breaker(store)?;
+    #[cfg(feature = "sticky-reads")]
+    sticky::mark(request);
+    let _waiting = match store.waiters.enter(store.config.max_waiters) {
+        Some(waiting) => waiting,
+        None => {
+            request.local_cache(|| AcquireTimedOut(true));
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &store.metrics {
+                metrics.waiter_rejected();
+            }
+
+            return Err(PoolGuardError::TooManyWaiters);
+        }
+    };
+    let rid = store.config.log_request_id(Some(request));
+    let start = Instant::now();
+    let future = acquire(store.client());
+    #[cfg(feature = "tracing")]
+    let future = tracing::Instrument::instrument(future, store.config.acquire_span(rid.as_deref()));
+
+    let result = match store.config.guard_acquire_timeout {
+        Some(timeout) => match rocket::tokio::time::timeout(timeout, future).await {
+            Ok(result) => result.map_err(PoolGuardError::from),
+            Err(_) => Err(PoolGuardError::AcquireTimeout),
+        },
+        None => future.await.map_err(PoolGuardError::from),
+    };
+
+    if let Err(PoolGuardError::AcquireTimeout) = result {
+        request.local_cache(|| AcquireTimedOut(true));
+    }
+
+    let elapsed = start.elapsed();
+
+    if let Some(request_time) = RequestDbTime::for_request(&store.config, request) {
+        request_time.add(elapsed, false);
+    }
+
+    if let Some(threshold) = store.config.slow_acquire_threshold {
+        if elapsed > threshold {
+            warn_slow_acquire(request, store, elapsed, rid.as_deref());
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = &store.metrics {
+        metrics.record_acquire(elapsed, &result);
+    }
+
+    if let Some(breaker) = &store.config.circuit_breaker {
+        breaker.record(&result);
+    }
+
+    result
+}
+
+/// Fails with [`PoolGuardError::CircuitOpen`] while the circuit breaker of `store` is open.
+pub(crate) fn check_breaker(store: &SQLxPostgresPool) -> Result<(), PoolGuardError> {
+    match &store.config.circuit_breaker {
+        Some(breaker) => breaker.check(),
+        None => Ok(()),
+    }
+}
+
+/// Minimum time between two slow acquire warnings.
+const SLOW_ACQUIRE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+fn warn_slow_acquire(
+    request: &Request<'_>,
+    store: &SQLxPostgresPool,
+    elapsed: Duration,
+    rid: Option<&str>,
+) {
+    let suppressed = match store.slow_acquire_log.check(SLOW_ACQUIRE_LOG_INTERVAL) {
+        Some(suppressed) => suppressed,
+        None => return,
+    };
+
+    let route = match request.route() {
+        Some(route) => route.uri.to_string(),
+        None => request.uri().path().to_string(),
+    };
+
+    log::warn!(
+        "SQLxPostgres: {}{} waited {:?} for a connection, pool: {} ({} similar warnings suppressed)",
+        observe::rid_prefix(rid),
+        route,
+        elapsed,
+        store.status(),
+        suppressed
+    );
+}
+
+/// Turns a guard result into an outcome failing with the status of the error.
+pub(crate) fn outcome<T>(
+    result: Result<T, PoolGuardError>,
+) -> Outcome<T, (Status, PoolGuardError), ()> {
+    match result {
+        Ok(value) => Outcome::Success(value),
+        Err(e) => {
+            if let PoolGuardError::Database(e) = &e {
+                connect::hint_pgbouncer(e);
+            }
+
+            log::warn!("SQLxPostgres: {}", e);
+            Outcome::Failure((e.status(), e))
+        }
+    }
+}
+
+/// Request guard holding one connection for the whole request.
+///
+/// For session state like temporary tables, advisory locks or `SET` that must stay on the
+/// same connection. Derefs to `PgConnection`, so `&mut *conn` can be used as an executor.
+/// The connection goes back to the pool when the guard is dropped at the end of the request.
+#[derive(Debug)]
+pub struct SQLxPostgresConnection {
+    conn: CancelOnDrop<PoolConnection<Postgres>>,
+}
+
+impl SQLxPostgresConnection {
+    /// Returns the pooled connection, which still goes back to the pool on drop but is no
+    /// longer cancelled with the request.
+    pub fn into_inner(self) -> PoolConnection<Postgres> {
+        self.conn.into_inner()
+    }
+}
+
+impl Deref for SQLxPostgresConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl DerefMut for SQLxPostgresConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresConnection {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let result = async {
+            let conn = acquire(request, |pool| async move { pool.acquire().await }).await?;
+            cancel::arm(request, conn).await
+        };
+
+        outcome(result.await.map(|conn| SQLxPostgresConnection { conn }))
+    }
+}
+
+impl Sentinel for SQLxPostgresConnection {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresConnection")
+    }
+}
+
+/// Connection shared by every [`SQLxPostgresShared`] guard of a request.
+type SharedSlot = Result<rocket::tokio::sync::Mutex<PoolConnection<Postgres>>, PoolGuardError>;
+
+/// Request guard sharing a single connection between all its users in a request.
+///
+/// The first `SQLxPostgresShared` resolved in a request acquires the connection and every
+/// later one, e.g. in a custom authentication guard and then in the handler, gets the same
+/// connection, so the request holds one connection and session settings are seen by all.
+/// Other guards of this crate keep acquiring their own connections.
+///
+/// Access goes through [`SQLxPostgresShared::lock`]. Hold the lock only while querying: a
+/// guard or handler waiting on the lock while another part of the same request holds it
+/// deadlocks the request.
+#[derive(Debug)]
+pub struct SQLxPostgresShared<'r> {
+    conn: &'r rocket::tokio::sync::Mutex<PoolConnection<Postgres>>,
+}
+
+impl<'r> SQLxPostgresShared<'r> {
+    /// Locks the shared connection, `&mut *conn` can be used as an executor.
+    pub async fn lock(&self) -> rocket::tokio::sync::MutexGuard<'r, PoolConnection<Postgres>> {
+        self.conn.lock().await
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresShared<'r> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let slot: &'r SharedSlot = request
+            .local_cache_async(async {
+                acquire(request, |pool| async move { pool.acquire().await })
+                    .await
+                    .map(rocket::tokio::sync::Mutex::new)
+            })
+            .await;
+
+        match slot {
+            Ok(conn) => Outcome::Success(SQLxPostgresShared { conn }),
+            // The first guard of the request failed, later ones report the same error.
+            Err(e) => outcome(Err(e.duplicate())),
+        }
+    }
+}
+
+impl Sentinel for SQLxPostgresShared<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresShared")
+    }
+}
+
+/// Transaction isolation levels, used as the type parameter of [`SQLxPostgresTransaction`].
+pub mod isolation {
+    /// Isolation level set on a transaction.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum IsolationLevel {
+        ReadCommitted,
+        RepeatableRead,
+        Serializable,
+    }
+
+    impl IsolationLevel {
+        pub(crate) fn set_sql(self) -> &'static str {
+            match self {
+                IsolationLevel::ReadCommitted => "SET TRANSACTION ISOLATION LEVEL READ COMMITTED",
+                IsolationLevel::RepeatableRead => "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ",
+                IsolationLevel::Serializable => "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+            }
+        }
+    }
+
+    /// Marker types selecting the isolation level of a transaction guard.
+    pub trait Isolation: Send + Sync + 'static {
+        /// Level set after `BEGIN`, `None` keeps the server's `default_transaction_isolation`.
+        const LEVEL: Option<IsolationLevel>;
+    }
+
+    /// Keeps the server's default isolation level.
+    #[derive(Debug)]
+    pub struct ServerDefault;
+
+    /// `READ COMMITTED`.
+    #[derive(Debug)]
+    pub struct ReadCommitted;
+
+    /// `REPEATABLE READ`.
+    #[derive(Debug)]
+    pub struct RepeatableRead;
+
+    /// `SERIALIZABLE`, expect serialization failures (SQLSTATE 40001) and retry them.
+    #[derive(Debug)]
+    pub struct Serializable;
+
+    impl Isolation for ServerDefault {
+        const LEVEL: Option<IsolationLevel> = None;
+    }
+
+    impl Isolation for ReadCommitted {
+        const LEVEL: Option<IsolationLevel> = Some(IsolationLevel::ReadCommitted);
+    }
+
+    impl Isolation for RepeatableRead {
+        const LEVEL: Option<IsolationLevel> = Some(IsolationLevel::RepeatableRead);
+    }
+
+    impl Isolation for Serializable {
+        const LEVEL: Option<IsolationLevel> = Some(IsolationLevel::Serializable);
+    }
+}
+
+/// Request guard running the handler inside a transaction.
+///
+/// The transaction begins before the handler runs and derefs to sqlx's `Transaction`, so
+/// `&mut *tx` can be used as an executor. It must be ended with
+/// [`SQLxPostgresTransaction::commit`]; when the guard is dropped without committing,
+/// e.g. because the handler returned early with `?`, the transaction is rolled back.
+///
+/// The isolation level is picked by the type parameter, e.g.
+/// `SQLxPostgresTransaction<isolation::Serializable>`, and defaults to the server's.
+///
+/// Every guard begins its own transaction on its own connection, so taking two in the
+/// same handler gives two independent transactions.
+#[derive(Debug)]
+pub struct SQLxPostgresTransaction<L: Isolation = isolation::ServerDefault> {
+    tx: CancelOnDrop<Transaction<'static, Postgres>>,
+    level: PhantomData<L>,
+}
+
+impl<L: Isolation> SQLxPostgresTransaction<L> {
+    /// Isolation level set on the transaction, `None` when it is the server's default.
+    pub fn isolation_level(&self) -> Option<IsolationLevel> {
+        L::LEVEL
+    }
+
+    /// Commits the transaction.
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await
+    }
+
+    /// Rolls the transaction back right away instead of when the connection is reused.
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+
+    /// Runs `f` inside a savepoint, see [`savepoint`].
+    ///
+    /// ```rust,no_run
+    /// # use rocket::{http::Status, post};
+    /// # use rocket_sqlxpostgres::SQLxPostgresTransaction;
+    /// #[post("/orders")]
+    /// async fn create(mut tx: SQLxPostgresTransaction) -> Result<(), Status> {
+    ///     sqlx::query("INSERT INTO orders DEFAULT VALUES")
+    ///         .execute(&mut *tx)
+    ///         .await
+    ///         .map_err(|_| Status::InternalServerError)?;
+    ///
+    ///     // A duplicate coupon only undoes the coupon insert, not the order.
+    ///     let _ = tx.savepoint(|sp| Box::pin(async move {
+    ///         sqlx::query("INSERT INTO coupons (code) VALUES ('WELCOME')")
+    ///             .execute(&mut *sp)
+    ///             .await
+    ///     }))
+    ///     .await;
+    ///
+    ///     tx.commit().await.map_err(|_| Status::InternalServerError)
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub async fn savepoint<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: for<'t> FnOnce(&'t mut PgConnection) -> BoxFuture<'t, Result<T, E>> + Send,
+        T: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        savepoint(&mut self.tx, f).await
+    }
+
+    /// Returns the sqlx transaction, which still rolls back when dropped uncommitted but is
+    /// no longer cancelled with the request.
+    pub fn into_inner(self) -> Transaction<'static, Postgres> {
+        self.tx.into_inner()
+    }
+}
+
+impl<L: Isolation> Deref for SQLxPostgresTransaction<L> {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl<L: Isolation> DerefMut for SQLxPostgresTransaction<L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, L: Isolation> FromRequest<'r> for SQLxPostgresTransaction<L> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let result = async {
+            let tx = begin(request, L::LEVEL.map(IsolationLevel::set_sql)).await?;
+            cancel::arm(request, tx).await
+        };
+
+        outcome(result.await.map(|tx| SQLxPostgresTransaction {
+            tx,
+            level: PhantomData,
+        }))
+    }
+}
+
+impl<L: Isolation> Sentinel for SQLxPostgresTransaction<L> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresTransaction")
+    }
+}
+
+/// Begins a transaction and runs `setup` in it, e.g. `SET TRANSACTION READ ONLY`, then
+/// labels it with the request application name when one is configured.
+pub(crate) async fn begin(
+    request: &Request<'_>,
+    setup: Option<&'static str>,
+) -> Result<Transaction<'static, Postgres>, PoolGuardError> {
+    let mut tx = acquire(request, |pool| async move { pool.begin().await }).await?;
+
+    if let Some(setup) = setup {
+        tx.execute(setup).await?;
+    }
+
+    if let Some(timeout) = request_statement_timeout(request) {
+        let sql = format!("SET LOCAL statement_timeout = {}", timeout.as_millis());
+        tx.execute(&*sql).await?;
+    }
+
+    if let Some(name) = request_application_name(request) {
+        sqlx::query("SELECT set_config('application_name', $1, true)")
+            .bind(name)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    Ok(tx)
+}
+
+/// The remaining budget of `request`, raised to the configured floor.
+fn request_statement_timeout(request: &Request<'_>) -> Option<Duration> {
+    let config = &managed_store(request).ok()?.config;
+    let budget = (config.request_deadline.as_ref()?)(request)?;
+
+    // A `statement_timeout` of 0 disables the timeout instead.
+    Some(
+        budget
+            .max(config.request_deadline_floor)
+            .max(Duration::from_millis(1)),
+    )
+}
+
+/// Longest `application_name` Postgres keeps, longer values are truncated by the server.
+const MAX_APPLICATION_NAME: usize = 63;
+
+/// Renders the configured request application name template for `request`.
+fn request_application_name(request: &Request<'_>) -> Option<String> {
+    let config = &managed_store(request).ok()?.config;
+    let template = config.request_application_name.as_ref()?;
+    let route = request.route();
+
+    let mut name = template
+        .replace("{method}", request.method().as_str())
+        .replace(
+            "{route}",
+            &route.map(|route| route.uri.to_string()).unwrap_or_default(),
+        )
+        .replace(
+            "{name}",
+            route.and_then(|route| route.name.as_deref()).unwrap_or(""),
+        )
+        .replace(
+            "{request_id}",
+            config.request_id(request).as_deref().unwrap_or("-"),
+        );
+
+    if name.len() > MAX_APPLICATION_NAME {
+        let mut end = MAX_APPLICATION_NAME;
+
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        name.truncate(end);
+    }
+
+    Some(name)
+}
+
+/// Request guard running the handler inside a read only transaction.
+///
+/// The transaction is set `READ ONLY` before the handler runs, so any write fails with
+/// SQLSTATE 25006. Meant as defense in depth for public endpoints, it uses the primary pool
+/// like [`SQLxPostgresTransaction`] and ends the same way: rolled back on drop, while
+/// [`SQLxPostgresReadOnly::commit`] just ends it early.
+#[derive(Debug)]
+pub struct SQLxPostgresReadOnly {
+    tx: CancelOnDrop<Transaction<'static, Postgres>>,
+}
+
+impl SQLxPostgresReadOnly {
+    /// Ends the transaction.
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await
+    }
+
+    /// Rolls the transaction back right away instead of when the connection is reused.
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+}
+
+impl Deref for SQLxPostgresReadOnly {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for SQLxPostgresReadOnly {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresReadOnly {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let result = async {
+            let tx = begin(request, Some("SET TRANSACTION READ ONLY")).await?;
+            cancel::arm(request, tx).await
+        };
+
+        outcome(result.await.map(|tx| SQLxPostgresReadOnly { tx }))
+    }
+}
+
+impl Sentinel for SQLxPostgresReadOnly {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresReadOnly")
+    }
+}
+
+/// Session settings applied by [`SQLxPostgresRls`], usually from the authenticated user.
+///
+/// ```rust,ignore
+/// impl RlsContext for User {
+///     fn settings(&self) -> Vec<(String, String)> {
+///         vec![("app.current_user_id".into(), self.id.to_string())]
+///     }
+/// }
+/// ```
+pub trait RlsContext: Send + Sync + 'static {
+    /// Pairs of setting name and value, set with `set_config(name, value, true)`.
+    fn settings(&self) -> Vec<(String, String)>;
+}
+
+/// Request guard running the handler inside a transaction carrying row level security settings.
+///
+/// The context `C` is taken from its own request guard, then every setting of
+/// [`RlsContext::settings`] is applied with `SELECT set_config($1, $2, true)` using bind
+/// parameters. The settings are transaction local, so nothing leaks into the pool. When the
+/// context guard fails the request fails with its status, or 401 when it forwarded, and no
+/// transaction is started. Ends like [`SQLxPostgresTransaction`].
+#[derive(Debug)]
+pub struct SQLxPostgresRls<C: RlsContext> {
+    tx: CancelOnDrop<Transaction<'static, Postgres>>,
+    context: C,
+}
+
+impl<C: RlsContext> SQLxPostgresRls<C> {
+    /// The context the settings came from.
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    /// Commits the transaction.
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await
+    }
+
+    /// Rolls the transaction back right away instead of when the connection is reused.
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+}
+
+impl<C: RlsContext> Deref for SQLxPostgresRls<C> {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl<C: RlsContext> DerefMut for SQLxPostgresRls<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, C> FromRequest<'r> for SQLxPostgresRls<C>
+where
+    C: RlsContext + FromRequest<'r>,
+{
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let context = match request.guard::<C>().await {
+            Outcome::Success(context) => context,
+            Outcome::Failure((status, _)) => {
+                return Outcome::Failure((status, PoolGuardError::MissingContext))
+            }
+            Outcome::Forward(()) => {
+                return Outcome::Failure((Status::Unauthorized, PoolGuardError::MissingContext))
+            }
+        };
+
+        let result = async {
+            let mut tx = begin(request, None).await?;
+
+            for (name, value) in context.settings() {
+                sqlx::query("SELECT set_config($1, $2, true)")
+                    .bind(name)
+                    .bind(value)
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            cancel::arm(request, tx).await
+        };
+
+        outcome(result.await.map(|tx| SQLxPostgresRls { tx, context }))
+    }
+}
+
+impl<C: RlsContext> Sentinel for SQLxPostgresRls<C> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresRls")
+    }
+}
+
+/// What to do with an automatic transaction once the response status is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionAction {
+    Commit,
+    Rollback,
+}
+
+impl TransactionAction {
+    /// The default policy, committing for 1xx to 3xx and rolling back otherwise.
+    pub fn for_status(status: Status) -> Self {
+        if status.code < 400 {
+            TransactionAction::Commit
+        } else {
+            TransactionAction::Rollback
+        }
+    }
+}
+
+/// Transaction of [`SQLxPostgresAutoTransaction`], kept in the request local cache
+/// until the fairing ends it.
+#[derive(Debug, Default)]
+pub(crate) struct AutoTransactionSlot(Mutex<Option<Transaction<'static, Postgres>>>);
+
+impl AutoTransactionSlot {
+    pub(crate) fn take(&self) -> Option<Transaction<'static, Postgres>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    fn put(&self, tx: Transaction<'static, Postgres>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+    }
+}
+
+/// Request guard running the handler inside a transaction ended by the fairing.
+///
+/// Unlike [`SQLxPostgresTransaction`] there is no commit to call: once the handler returns,
+/// the fairing commits when the response status is 1xx to 3xx and rolls back otherwise,
+/// including when the handler panicked. The policy can be changed with
+/// [`crate::SqlxPostgresFairing::with_transaction_policy`].
+///
+/// The decision is made when the response is created, before a streamed body is sent, so
+/// an error while streaming cannot undo the commit. Use one guard per handler.
+#[derive(Debug)]
+pub struct SQLxPostgresAutoTransaction<'r> {
+    tx: Option<Transaction<'static, Postgres>>,
+    slot: &'r AutoTransactionSlot,
+}
+
+impl SQLxPostgresAutoTransaction<'_> {
+    /// Runs `f` inside a savepoint, see [`savepoint`].
+    pub async fn savepoint<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: for<'t> FnOnce(&'t mut PgConnection) -> BoxFuture<'t, Result<T, E>> + Send,
+        T: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        savepoint(self, f).await
+    }
+}
+
+impl Deref for SQLxPostgresAutoTransaction<'_> {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tx.as_ref().expect("transaction taken before drop")
+    }
+}
+
+impl DerefMut for SQLxPostgresAutoTransaction<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tx.as_mut().expect("transaction taken before drop")
+    }
+}
+
+impl Drop for SQLxPostgresAutoTransaction<'_> {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            self.slot.put(tx);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresAutoTransaction<'r> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let result = begin(request, None).await;
+        outcome(result.map(|tx| SQLxPostgresAutoTransaction {
+            tx: Some(tx),
+            slot: request.local_cache(AutoTransactionSlot::default),
+        }))
+    }
+}
+
+impl Sentinel for SQLxPostgresAutoTransaction<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresAutoTransaction")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SqlxPostgresConfig, SqlxPostgresFairing};
+    use rocket::local::asynchronous::Client;
+
+    /// A custom guard built on the shared connection, e.g. one authenticating the user.
+    struct Auth<'r>(SQLxPostgresShared<'r>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Auth<'r> {
+        type Error = PoolGuardError;
+
+        async fn from_request(
+            request: &'r Request<'_>,
+        ) -> Outcome<Self, (Status, Self::Error), ()> {
+            request.guard::<SQLxPostgresShared<'r>>().await.map(Auth)
+        }
+    }
+
+    async fn backend_pid(shared: &SQLxPostgresShared<'_>) -> i32 {
+        let mut conn = shared.lock().await;
+
+        sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap()
+    }
+
+    #[rocket::get("/")]
+    async fn same_connection(auth: Auth<'_>, shared: SQLxPostgresShared<'_>) -> String {
+        (backend_pid(&auth.0).await == backend_pid(&shared).await).to_string()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn shared_guards_of_a_request_use_one_connection() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        // A second connection could not be acquired while the first guard holds one.
+        let config = SqlxPostgresConfig::from_url(&url)
+            .unwrap()
+            .set_max_connections(1)
+            .with_acquire_timeout(Duration::from_secs(2));
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(config, None))
+            .mount("/", rocket::routes![same_connection]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.get("/").dispatch().await;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.as_deref(), Some("true"));
+    }
+
+    /// A transaction with an empty temporary `tags` table, on a pool of `DATABASE_URL`.
+    async fn tags_transaction() -> Transaction<'static, Postgres> {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let pool = SqlxPostgresConfig::from_url(&url)
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut tx = pool.begin().await.unwrap();
+
+        sqlx::query("CREATE TEMPORARY TABLE tags (name text UNIQUE) ON COMMIT DROP")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx
+    }
+
+    async fn insert_tag(conn: &mut PgConnection, name: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO tags (name) VALUES ($1)")
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+
+    async fn tags(conn: &mut PgConnection) -> Vec<String> {
+        sqlx::query_scalar("SELECT name FROM tags ORDER BY name")
+            .fetch_all(conn)
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn savepoint_releases_on_ok_and_rolls_back_on_err() {
+        let mut tx = tags_transaction().await;
+
+        savepoint(&mut *tx, |sp| {
+            Box::pin(async move { insert_tag(sp, "a").await })
+        })
+        .await
+        .unwrap();
+        let duplicate = savepoint(&mut *tx, |sp| {
+            Box::pin(async move {
+                insert_tag(sp, "b").await?;
+                insert_tag(sp, "a").await
+            })
+        })
+        .await;
+
+        assert!(duplicate.is_err());
+        assert_eq!(tags(&mut *tx).await, ["a"]);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn nested_savepoints_roll_back_only_the_inner_one() {
+        let mut tx = tags_transaction().await;
+
+        savepoint(&mut *tx, |outer| {
+            Box::pin(async move {
+                insert_tag(outer, "a").await?;
+
+                let inner = savepoint(outer, |inner| {
+                    Box::pin(async move {
+                        insert_tag(inner, "b").await?;
+                        Err::<(), _>(sqlx::Error::RowNotFound)
+                    })
+                })
+                .await;
+                assert!(inner.is_err());
+
+                insert_tag(outer, "c").await
+            })
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(tags(&mut *tx).await, ["a", "c"]);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn a_savepoint_dropped_midway_leaves_the_transaction_usable() {
+        let mut tx = tags_transaction().await;
+
+        let dropped = rocket::tokio::time::timeout(
+            Duration::from_millis(100),
+            savepoint(&mut *tx, |sp| {
+                Box::pin(async move {
+                    insert_tag(sp, "a").await?;
+                    sqlx::query("SELECT pg_sleep(1)").execute(&mut *sp).await?;
+                    Ok::<_, sqlx::Error>(())
+                })
+            }),
+        )
+        .await;
+        assert!(dropped.is_err());
+
+        insert_tag(&mut *tx, "b").await.unwrap();
+        assert_eq!(tags(&mut *tx).await, ["b"]);
+        tx.commit().await.unwrap();
+    }
+}
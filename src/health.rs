@@ -0,0 +1,235 @@
+use crate::{
+    breaker::BreakerState,
+    observe::rid_prefix,
+    session::{SQLxPostgresPool, SqlxPostgresConfig},
+};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use rocket::{
+    http::{ContentType, Status},
+    tokio::{self, sync::watch},
+    Route, Shutdown, State,
+};
+use sqlx::postgres::PgPool;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Liveness and readiness routes, mount them at any prefix.
+///
+/// `GET /healthz` answers 200 while the process is up. `GET /readyz` runs the configured
+/// health query on the managed pool and answers 200, or 503 when it fails, times out, the
+/// circuit breaker is open or no pool is managed, with a small JSON body:
+///
+/// ```rust,ignore
+/// rocket::build()
+///     .attach(SqlxPostgresFairing::from_config(config))
+///     .mount("/", rocket_sqlxpostgres::health_routes())
+/// ```
+///
+/// ```json
+/// {"status":"ok","latency_ms":1.204}
+/// ```
+pub fn health_routes() -> Vec<Route> {
+    rocket::routes![liveness, readiness]
+}
+
+#[rocket::get("/healthz")]
+fn liveness() -> Status {
+    Status::Ok
+}
+
+#[rocket::get("/readyz")]
+async fn readiness(store: Option<&State<SQLxPostgresPool>>) -> (Status, (ContentType, String)) {
+    let store = match store {
+        Some(store) => store,
+        None => return not_ready("no database pool is managed"),
+    };
+
+    let breaker = store
+        .config
+        .circuit_breaker
+        .as_ref()
+        .map(|breaker| breaker.state());
+
+    if breaker == Some(BreakerState::Open) {
+        return not_ready("circuit breaker open");
+    }
+
+    match probe(&store.client(), &store.config).await {
+        Ok(latency) => {
+            let latency = latency.as_secs_f64() * 1000.0;
+            let body = match breaker {
+                Some(state) => format!(
+                    r#"{{"status":"ok","latency_ms":{:.3},"circuit_breaker":"{}"}}"#,
+                    latency, state
+                ),
+                None => format!(r#"{{"status":"ok","latency_ms":{:.3}}}"#, latency),
+            };
+            (Status::Ok, (ContentType::JSON, body))
+        }
+        Err(ProbeError::Query(e)) => {
+            log::warn!("SQLxPostgres: readiness query failed: {}", e);
+            not_ready("query failed")
+        }
+        Err(ProbeError::Timeout) => not_ready("query timed out"),
+    }
+}
+
+pub(crate) enum ProbeError {
+    Query(sqlx::Error),
+    Timeout,
+}
+
+impl ProbeError {
+    pub(crate) fn message(&self) -> String {
+        match self {
+            ProbeError::Query(e) => e.to_string(),
+            ProbeError::Timeout => "health query timed out".to_owned(),
+        }
+    }
+}
+
+/// Runs the health query of `config` on `pool`, returning its latency.
+pub(crate) async fn probe(
+    pool: &PgPool,
+    config: &SqlxPostgresConfig,
+) -> Result<Duration, ProbeError> {
+    let start = Instant::now();
+    let query = sqlx::query(&*config.health_query).execute(pool);
+
+    match tokio::time::timeout(config.health_timeout, query).await {
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(e)) => Err(ProbeError::Query(e)),
+        Err(_) => Err(ProbeError::Timeout),
+    }
+}
+
+/// Result of the last background health probe, see [`DbHealth`].
+///
+/// Serializable with the `serde` feature.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "rocket::serde"))]
+pub struct HealthStatus {
+    /// Whether the last probe succeeded, `false` before the first one completed.
+    pub healthy: bool,
+    /// Error of the last probe, `None` when it succeeded.
+    pub last_error: Option<String>,
+    /// When a probe last succeeded.
+    pub last_success: Option<DateTime<Utc>>,
+    /// Duration of the last successful probe.
+    pub latency: Option<Duration>,
+}
+
+/// Managed health of the pool, kept up to date by a background probe.
+///
+/// With [`SqlxPostgresConfig::with_health_probe_interval`] the health query runs on that
+/// interval from liftoff until shutdown, bounded by the health timeout, so fairings and
+/// background jobs can check the database without querying it themselves. The unnamed
+/// database's is managed as `DbHealth`, named ones are returned by
+/// [`crate::SQLxPostgresPools::health`].
+///
+/// ```rust,ignore
+/// let health = rocket.state::<DbHealth>().unwrap();
+///
+/// if !health.wait_until_healthy(Duration::from_secs(30)).await {
+///     log::warn!("database still down: {:?}", health.status().last_error);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DbHealth {
+    sender: Arc<watch::Sender<HealthStatus>>,
+    receiver: watch::Receiver<HealthStatus>,
+}
+
+impl DbHealth {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = watch::channel(HealthStatus::default());
+
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Whether the last probe succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.receiver.borrow().healthy
+    }
+
+    /// The result of the last probe.
+    pub fn status(&self) -> HealthStatus {
+        self.receiver.borrow().clone()
+    }
+
+    /// Waits until a probe succeeds, returning `false` when `timeout` passes first.
+    ///
+    /// Returns right away when the last probe succeeded.
+    pub async fn wait_until_healthy(&self, timeout: Duration) -> bool {
+        let mut receiver = self.receiver.clone();
+
+        let wait = async move {
+            while !receiver.borrow().healthy {
+                // The sender lives as long as `self`, so this only fails after shutdown.
+                if receiver.changed().await.is_err() {
+                    return false;
+                }
+            }
+
+            true
+        };
+
+        tokio::time::timeout(timeout, wait).await.unwrap_or(false)
+    }
+
+    /// Probes the pool in `slot` every `interval` until shutdown.
+    pub(crate) async fn run(
+        self,
+        slot: Arc<RwLock<PgPool>>,
+        config: SqlxPostgresConfig,
+        interval: Duration,
+        mut shutdown: Shutdown,
+    ) {
+        let rid = rid_prefix(config.log_request_id(None).as_deref());
+
+        loop {
+            let pool = slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+            let result = probe(&pool, &config).await;
+            let previous = self.status();
+
+            let status = match result {
+                Ok(latency) => HealthStatus {
+                    healthy: true,
+                    last_error: None,
+                    last_success: Some(Utc::now()),
+                    latency: Some(latency),
+                },
+                Err(e) => HealthStatus {
+                    healthy: false,
+                    last_error: Some(e.message()),
+                    ..previous.clone()
+                },
+            };
+
+            match (previous.healthy, &status.last_error) {
+                (true, Some(e)) => log::warn!("SQLxPostgres: {}health probe failed: {}", rid, e),
+                (false, None) => log::info!("SQLxPostgres: {}health probe succeeded", rid),
+                _ => {}
+            }
+
+            let _ = self.sender.send(status);
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = &mut shutdown => return,
+            }
+        }
+    }
+}
+
+fn not_ready(reason: &str) -> (Status, (ContentType, String)) {
+    let body = format!(r#"{{"status":"unavailable","reason":"{}"}}"#, reason);
+    (Status::ServiceUnavailable, (ContentType::JSON, body))
+}
@@ -0,0 +1,459 @@
+use crate::{
+    named::DatabaseMarker,
+    session::SQLxPostgres,
+    sql::{quote_ident, quote_table},
+};
+use sqlx::{
+    postgres::{PgArguments, PgConnection, PgHasArrayType},
+    query::Query,
+    Connection, Encode, Postgres, Type,
+};
+use std::{any::Any, borrow::Cow, fmt};
+
+/// Rows sent per statement by default, see [`InsertOptions::with_chunk_size`].
+const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// A failed [`insert_many`].
+#[derive(Debug)]
+pub enum InsertError {
+    /// No column was given.
+    NoColumns,
+    /// Row `row`, starting at 0, pushed `found` values for `expected` columns.
+    ColumnCount {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// Row `row` pushed a value of another type than the rows before it in `column`.
+    MixedTypes { row: usize, column: String },
+    /// The server rejected a chunk, or acquiring the connection or ending the transaction
+    /// failed.
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertError::NoColumns => f.write_str("insert_many needs at least one column"),
+            InsertError::ColumnCount {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has {} values for {} columns",
+                row, found, expected
+            ),
+            InsertError::MixedTypes { row, column } => write!(
+                f,
+                "row {} has a value of another type than the rows before it in column `{}`",
+                row, column
+            ),
+            InsertError::Database(e) => write!(f, "inserting the rows failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InsertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InsertError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for InsertError {
+    fn from(error: sqlx::Error) -> Self {
+        InsertError::Database(error)
+    }
+}
+
+/// What [`insert_many`] does with rows conflicting with existing ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Fails the statement, no `ON CONFLICT` clause.
+    Error,
+    /// `ON CONFLICT DO NOTHING`, conflicting rows are skipped and not counted.
+    DoNothing,
+    /// `ON CONFLICT (target) DO UPDATE SET column = EXCLUDED.column` for each of `update`.
+    DoUpdate {
+        target: Vec<String>,
+        update: Vec<String>,
+    },
+}
+
+impl Default for OnConflict {
+    fn default() -> Self {
+        OnConflict::Error
+    }
+}
+
+impl OnConflict {
+    /// Updates the `update` columns of the rows conflicting on the `target` columns.
+    pub fn update(target: &[&str], update: &[&str]) -> Self {
+        OnConflict::DoUpdate {
+            target: target.iter().map(|column| column.to_string()).collect(),
+            update: update.iter().map(|column| column.to_string()).collect(),
+        }
+    }
+
+    fn clause(&self) -> String {
+        match self {
+            OnConflict::Error => String::new(),
+            OnConflict::DoNothing => " ON CONFLICT DO NOTHING".to_owned(),
+            OnConflict::DoUpdate { target, update } => format!(
+                " ON CONFLICT ({}) DO UPDATE SET {}",
+                target
+                    .iter()
+                    .map(|column| quote_ident(column))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                update
+                    .iter()
+                    .map(|column| format!("{0} = EXCLUDED.{0}", quote_ident(column)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Schema, conflict handling and chunking of [`insert_many`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertOptions {
+    schema: Option<Cow<'static, str>>,
+    on_conflict: OnConflict,
+    chunk_size: usize,
+}
+
+impl Default for InsertOptions {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            on_conflict: OnConflict::Error,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl InsertOptions {
+    /// Fails on conflicts, 10 000 rows per statement.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the schema of the table, the connection's `search_path` by default.
+    ///
+    /// Quoted on its own, so a dot in the schema or table name is part of that name.
+    pub fn with_schema(mut self, schema: impl Into<Cow<'static, str>>) -> Self {
+        self.schema = Some(schema.into());
+        self
+    }
+
+    /// Sets what happens to rows conflicting with existing ones.
+    pub fn with_on_conflict(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Sets the rows sent per statement, at least 1, to keep each message and the arrays the
+    /// server builds from it reasonably sized.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+/// A value [`insert_many`] can send in an array.
+pub trait InsertValue:
+    for<'q> Encode<'q, Postgres> + Type<Postgres> + PgHasArrayType + Send + 'static
+{
+}
+
+impl<T> InsertValue for T where
+    T: for<'q> Encode<'q, Postgres> + Type<Postgres> + PgHasArrayType + Send + 'static
+{
+}
+
+/// A row of [`insert_many`], pushing one value per column in the order of the columns.
+///
+/// Implemented for tuples of up to 8 values. `Option` values insert `NULL`.
+///
+/// ```rust,ignore
+/// impl InsertRow for User {
+///     fn push_values(self, values: &mut InsertValues) {
+///         values.push(self.id).push(self.name).push(self.email);
+///     }
+/// }
+/// ```
+pub trait InsertRow {
+    fn push_values(self, values: &mut InsertValues);
+}
+
+/// The values of one column of a chunk.
+trait ColumnArray: Send {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn bind<'q>(
+        self: Box<Self>,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments>;
+}
+
+struct Array<T>(Vec<T>);
+
+impl<T: InsertValue> ColumnArray for Array<T> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn bind<'q>(
+        self: Box<Self>,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        query.bind(self.0)
+    }
+}
+
+/// The columns of a chunk as parallel arrays, filled by [`InsertRow::push_values`].
+pub struct InsertValues {
+    columns: Vec<Option<Box<dyn ColumnArray>>>,
+    names: Vec<String>,
+    /// Column the next value goes to.
+    next: usize,
+    row: usize,
+    error: Option<InsertError>,
+}
+
+impl InsertValues {
+    fn new(names: &[&str]) -> Self {
+        Self {
+            columns: names.iter().map(|_| None).collect(),
+            names: names.iter().map(|name| name.to_string()).collect(),
+            next: 0,
+            row: 0,
+            error: None,
+        }
+    }
+
+    /// Appends `value` to the next column of the row.
+    pub fn push<T: InsertValue>(&mut self, value: T) -> &mut Self {
+        let row = self.row;
+        let index = self.next;
+        self.next += 1;
+
+        if self.error.is_some() {
+            return self;
+        }
+
+        let column = match self.columns.get_mut(index) {
+            Some(column) => column,
+            // Counted in `next`, reported once the row is done.
+            None => return self,
+        };
+
+        match column {
+            None => *column = Some(Box::new(Array(vec![value]))),
+            Some(array) => match array.as_any_mut().downcast_mut::<Array<T>>() {
+                Some(array) => array.0.push(value),
+                None => {
+                    self.error = Some(InsertError::MixedTypes {
+                        row,
+                        column: self.names[index].clone(),
+                    })
+                }
+            },
+        }
+
+        self
+    }
+
+    /// Checks the row just pushed had one value per column.
+    fn end_row(&mut self) -> Result<(), InsertError> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        if self.next != self.columns.len() {
+            return Err(InsertError::ColumnCount {
+                row: self.row,
+                expected: self.columns.len(),
+                found: self.next,
+            });
+        }
+
+        self.next = 0;
+        self.row += 1;
+        Ok(())
+    }
+
+    /// Takes the arrays of the chunk, leaving the columns empty for the next one.
+    fn take(&mut self) -> Vec<Box<dyn ColumnArray>> {
+        self.columns.iter_mut().filter_map(Option::take).collect()
+    }
+}
+
+macro_rules! impl_insert_row {
+    ($($name:ident),+) => {
+        impl<$($name: InsertValue),+> InsertRow for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn push_values(self, values: &mut InsertValues) {
+                let ($($name,)+) = self;
+                $(values.push($name);)+
+            }
+        }
+    };
+}
+
+impl_insert_row!(A);
+impl_insert_row!(A, B);
+impl_insert_row!(A, B, C);
+impl_insert_row!(A, B, C, D);
+impl_insert_row!(A, B, C, D, E);
+impl_insert_row!(A, B, C, D, E, F);
+impl_insert_row!(A, B, C, D, E, F, G);
+impl_insert_row!(A, B, C, D, E, F, G, H);
+
+/// `INSERT INTO table (columns) SELECT * FROM UNNEST($1, $2, ...)` with the conflict clause.
+fn statement(
+    schema: Option<&str>,
+    table: &str,
+    columns: &[&str],
+    on_conflict: &OnConflict,
+) -> String {
+    let names = columns
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let parameters = (1..=columns.len())
+        .map(|index| format!("${}", index))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "INSERT INTO {} ({}) SELECT * FROM UNNEST({}){}",
+        quote_table(schema, table),
+        names,
+        parameters,
+        on_conflict.clause()
+    )
+}
+
+async fn insert_chunk(
+    conn: &mut PgConnection,
+    sql: &str,
+    arrays: Vec<Box<dyn ColumnArray>>,
+) -> Result<u64, sqlx::Error> {
+    let query = arrays
+        .into_iter()
+        .fold(sqlx::query(sql), |query, array| array.bind(query));
+
+    Ok(query.execute(conn).await?.rows_affected())
+}
+
+/// Inserts `rows` into `table` on `conn`, each chunk of rows with one
+/// `INSERT ... SELECT * FROM UNNEST(...)` statement binding one array per column, returning
+/// the number of rows inserted or updated.
+///
+/// The table and column names are quoted, so they are matched case sensitively; set the
+/// table's schema with [`InsertOptions::with_schema`]. The column types are those of the
+/// bound arrays, e.g. `int8[]` for `i64` and `text[]` for `String`. A row with a wrong number
+/// of values, or a value of another type than the rows before it, fails before its chunk is
+/// sent.
+///
+/// Chunks are sent one after the other, so a failing chunk leaves those before it inserted
+/// unless `conn` is in a transaction. Pass `&mut **tx` to insert as part of a transaction
+/// guard.
+///
+/// ```rust,ignore
+/// let rows = users.into_iter().map(|user| (user.id, user.name, user.email));
+/// let inserted = insert_many(
+///     &mut **tx,
+///     "users",
+///     &["id", "name", "email"],
+///     rows,
+///     InsertOptions::new().with_on_conflict(OnConflict::DoNothing),
+/// )
+/// .await?;
+/// ```
+pub async fn insert_many<R, I>(
+    conn: &mut PgConnection,
+    table: &str,
+    columns: &[&str],
+    rows: I,
+    options: InsertOptions,
+) -> Result<u64, InsertError>
+where
+    R: InsertRow,
+    I: IntoIterator<Item = R>,
+{
+    if columns.is_empty() {
+        return Err(InsertError::NoColumns);
+    }
+
+    let sql = statement(
+        options.schema.as_deref(),
+        table,
+        columns,
+        &options.on_conflict,
+    );
+    let mut values = InsertValues::new(columns);
+    let mut affected = 0;
+    let mut in_chunk = 0;
+
+    for row in rows {
+        row.push_values(&mut values);
+        values.end_row()?;
+        in_chunk += 1;
+
+        if in_chunk == options.chunk_size {
+            affected += insert_chunk(conn, &sql, values.take()).await?;
+            in_chunk = 0;
+        }
+    }
+
+    if in_chunk > 0 {
+        affected += insert_chunk(conn, &sql, values.take()).await?;
+    }
+
+    Ok(affected)
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Runs [`insert_many`] on a connection of the pool, in a transaction so a failing chunk
+    /// inserts nothing.
+    ///
+    /// ```rust,ignore
+    /// #[post("/tags", data = "<tags>")]
+    /// async fn tags(db: SQLxPostgres, tags: Json<Vec<String>>) -> Result<String, Status> {
+    ///     let rows = tags.into_inner().into_iter().map(|tag| (tag,));
+    ///     let options = InsertOptions::new()
+    ///         .with_schema("app")
+    ///         .with_on_conflict(OnConflict::DoNothing);
+    ///     let inserted = db
+    ///         .insert_many("tags", &["name"], rows, options)
+    ///         .await
+    ///         .map_err(|_| Status::BadRequest)?;
+    ///     Ok(format!("added {} tags", inserted))
+    /// }
+    /// ```
+    pub async fn insert_many<R, I>(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: I,
+        options: InsertOptions,
+    ) -> Result<u64, InsertError>
+    where
+        R: InsertRow,
+        I: IntoIterator<Item = R>,
+    {
+        let mut conn = self.pool().acquire().await?;
+        let mut tx = conn.begin().await?;
+        let affected = insert_many(&mut tx, table, columns, rows, options).await?;
+        tx.commit().await?;
+        Ok(affected)
+    }
+}
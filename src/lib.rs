@@ -1,2 +1,174 @@
+#[cfg(all(feature = "tls-rustls", feature = "tls-native-tls"))]
+compile_error!(
+    "features `tls-rustls` and `tls-native-tls` are exclusive, set `default-features = false` \
+     to use `tls-native-tls`"
+);
+
+#[cfg(not(any(feature = "tls-rustls", feature = "tls-native-tls")))]
+compile_error!("enable one of the features `tls-rustls` or `tls-native-tls`");
+
+#[cfg(feature = "admin")]
+mod admin;
+mod advisory;
+mod batch;
+mod breaker;
+mod cache;
+mod cancel;
+mod connect;
+mod copy;
+mod error;
+mod explain;
+mod ext;
+#[cfg(feature = "test-util")]
+mod fixtures;
+mod guards;
+mod health;
+mod insert;
+mod listen;
+mod maintenance;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod migrate;
+#[cfg(feature = "test-util")]
+mod mock;
+mod named;
+mod observe;
+mod page;
+mod pgpass;
+mod provider;
+#[cfg(feature = "rds-iam")]
+mod rds;
+mod ready;
+mod replica;
+mod retry;
+mod rotate;
+mod service;
 mod session;
-pub use session::{SQLxPostgres, SqlxPostgresFairing, SqlxPostgresConfig};
+#[cfg(feature = "sessions")]
+mod sessions;
+mod shard;
+mod slot;
+mod sql;
+mod statement_log;
+mod status;
+#[cfg(feature = "sticky-reads")]
+mod sticky;
+mod stream;
+mod supervisor;
+mod tasks;
+mod tenant;
+#[cfg(feature = "test-util")]
+mod testing;
+mod watchdog;
+#[cfg(feature = "admin")]
+pub use admin::{admin_resize_routes, admin_routes};
+pub use advisory::AdvisoryLockGuard;
+pub use batch::{execute_batch, BatchError};
+pub use breaker::{BreakerState, CircuitBreakerPolicy};
+pub use connect::{ChannelBinding, TargetSessionAttrs};
+pub use copy::{copy_in, CopyError};
+pub use error::{ConfigError, Error, PoolGuardError, Result};
+pub use explain::ExplainPolicy;
+pub use ext::SqlxPostgresRocketExt;
+#[cfg(feature = "test-util")]
+pub use fixtures::{FixtureError, Fixtures};
+pub use guards::{
+    isolation, savepoint, RlsContext, SQLxPostgresAutoTransaction, SQLxPostgresConnection,
+    SQLxPostgresReadOnly, SQLxPostgresRls, SQLxPostgresShared, SQLxPostgresTransaction,
+    TransactionAction,
+};
+pub use health::{health_routes, DbHealth, HealthStatus};
+pub use insert::{
+    insert_many, InsertError, InsertOptions, InsertRow, InsertValue, InsertValues, OnConflict,
+};
+pub use listen::{Notification, PgNotifications};
+pub use maintenance::MaintenanceMode;
+#[cfg(feature = "metrics")]
+pub use metrics::PoolMetrics;
+pub use migrate::{revert_last, MigrationError, MigrationStatus};
+#[cfg(feature = "test-util")]
+pub use mock::{MockDb, MockQuery};
+pub use named::{DatabaseMarker, DefaultDatabase, SQLxPostgresPools};
+pub use page::{Page, PageError, PageParams};
+pub use provider::{DbProvider, DbQuery, DbValue, PgExecutorProvider};
+pub use ready::DbReady;
+pub use replica::{ReplicaStatus, ReplicaStrategy, SQLxPostgresReplicas};
+pub use retry::RetryPolicy;
+pub use rotate::PoolHandle;
+#[cfg(feature = "serde")]
+pub use session::SerializeWithPassword;
+pub use session::{
+    reset_session_state, AfterConnectHook, ConnectFailedCallback, ConnectedCallback,
+    DeadlineExtractor, PoolConnectionHook, PoolCustomizer, PoolSettings, RequestIdExtractor,
+    ResyncCallback, SQLxPostgres, SQLxPostgresOptional, SQLxPostgresPool, SQLxPostgresRef,
+    SQLxPostgresReplica, SqlxPostgresConfig, SqlxPostgresFairing, TransactionPolicy,
+};
+#[cfg(feature = "sessions")]
+pub use sessions::{Session, SessionConfig, SessionError, SessionStore, SqlxSessionFairing};
+pub use shard::{ShardRouter, Sharded};
+pub use slot::PgPoolSlot;
+pub use sqlx::postgres::PgSslMode;
+pub use statement_log::StatementLogControl;
+pub use status::PoolStatus;
+pub use stream::PgStreamResponse;
+pub use tasks::DbTasks;
+pub use tenant::{TenantDb, TenantSchemas};
+#[cfg(feature = "test-util")]
+pub use testing::{TestConnection, TestDatabase, TestTransaction};
+pub use watchdog::TransactionWatchdog;
+
+/// Derives a named database from `#[database("name")] struct Main(PgPool);`.
+///
+/// The struct becomes a [`DatabaseMarker`] and a request guard handing out the pool
+/// attached under that name, derefs to `PgPool`, and `Main::init(config)` returns the
+/// fairing to attach.
+///
+/// ```rust,ignore
+/// #[derive(SqlxPostgresDatabase)]
+/// #[database("main")]
+/// struct Main(PgPool);
+///
+/// rocket::build().attach(Main::init(config))
+/// ```
+#[cfg(feature = "derive")]
+pub use rocket_sqlxpostgres_codegen::SqlxPostgresDatabase;
+
+/// Runs an async test against a fresh database with a Rocket local client.
+///
+/// The test receives the client of a Rocket instance with the fairing attached to the
+/// database, and the database's pool. By default each test gets a [`TestDatabase`] whose
+/// config is read with [`SqlxPostgresConfig::from_env`]; it is dropped even when the test
+/// panics.
+///
+/// ```rust,ignore
+/// #[rocket_sqlxpostgres::test(rocket = "crate::rocket", migrations = "./migrations")]
+/// async fn lists_users(client: Client, pool: PgPool) {
+///     let response = client.get("/users").dispatch().await;
+///     assert_eq!(response.status(), Status::Ok);
+/// }
+/// ```
+///
+/// Arguments, all optional:
+///
+/// * `rocket = "path"`: function returning the `Rocket<Build>` to attach the fairing to.
+/// * `config = "path"`: function returning the admin [`SqlxPostgresConfig`].
+/// * `migrations = "dir"`: migrations run before the test.
+/// * `fixtures = "dir"`: [`Fixtures`] applied after the migrations, inside the test
+///   transaction in transaction mode.
+/// * `fixtures("users", "orders")`: applies only these fixtures, from `tests/fixtures` unless
+///   `fixtures = "dir"` is given too.
+/// * `mode = "transaction"`: uses the configured database as is and runs the test in a
+///   [`TestTransaction`], passed instead of the pool and managed as state; only queries
+///   run through it are rolled back.
+#[cfg(feature = "test-util")]
+pub use rocket_sqlxpostgres_codegen::test;
+
+#[cfg(any(feature = "derive", feature = "test-util"))]
+#[doc(hidden)]
+pub mod __private {
+    pub use rocket;
+    pub use sqlx;
+
+    #[cfg(feature = "test-util")]
+    pub use crate::testing::connect;
+}
@@ -0,0 +1,304 @@
+#[cfg(feature = "metrics")]
+use crate::metrics::DatabaseMetrics;
+use crate::{
+    connect,
+    session::{ResyncCallback, SQLxPostgresPool},
+};
+use futures::stream::{self, Stream};
+use rocket::{
+    response::stream::Event,
+    tokio::{self, sync::broadcast},
+    Shutdown,
+};
+use sqlx::postgres::{PgListener, PgPool};
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Notifications buffered for slow subscribers before they start missing some.
+const CAPACITY: usize = 1024;
+
+/// Delay before the second reconnect attempt, doubled up to [`connect::MAX_BACKOFF`].
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// What a [`PgNotifications::subscribe_to`] stream yields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// A `NOTIFY` on the subscribed channel.
+    Message { channel: String, payload: String },
+    /// Notifications may have been missed because the subscriber fell too far behind.
+    /// Reload whatever the notifications keep up to date.
+    Gap,
+    /// The listener lost its connection and is listening again after `downtime`, so the
+    /// notifications sent meanwhile are gone. Run a catch-up query.
+    Reconnected { downtime: Duration },
+}
+
+impl Notification {
+    /// The notification as a server-sent event: its payload as data, an empty event named
+    /// `gap`, or an event named `reconnected` with the downtime in milliseconds as data.
+    pub fn into_event(self) -> Event {
+        match self {
+            Notification::Message { payload, .. } => Event::data(payload),
+            Notification::Gap => Event::empty().event("gap"),
+            Notification::Reconnected { downtime } => {
+                Event::data(downtime.as_millis().to_string()).event("reconnected")
+            }
+        }
+    }
+}
+
+/// Managed state relaying the notifications of the channels given to
+/// [`crate::SqlxPostgresFairing::listen`].
+///
+/// ```rust,ignore
+/// let mut notifications = rocket.state::<PgNotifications>().unwrap().subscribe();
+///
+/// while let Ok((channel, payload)) = notifications.recv().await {
+///     ...
+/// }
+/// ```
+///
+/// [`PgNotifications::subscribe_to`] streams one channel, e.g. to browsers:
+///
+/// ```rust,ignore
+/// #[get("/dashboard/events")]
+/// fn events(notifications: &State<PgNotifications>) -> EventStream![] {
+///     let mut notifications = notifications.subscribe_to("dashboard");
+///
+///     EventStream! {
+///         while let Some(notification) = notifications.next().await {
+///             yield notification.into_event();
+///         }
+///     }
+/// }
+/// ```
+pub struct PgNotifications {
+    sender: broadcast::Sender<(String, String)>,
+    events: broadcast::Sender<Notification>,
+    relay: Mutex<Option<Relay>>,
+}
+
+impl PgNotifications {
+    /// Connects a listener with the connect options of the pool of `store` and listens on
+    /// `channels`, running `resync` after each reconnect.
+    pub(crate) async fn connect(
+        store: &SQLxPostgresPool,
+        channels: &[String],
+        resync: Vec<(String, ResyncCallback)>,
+    ) -> Result<Self, sqlx::Error> {
+        let listener = listen(&store.client(), channels).await?;
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &store.metrics {
+            metrics.listener_connected(true);
+        }
+
+        Ok(Self {
+            sender: broadcast::channel(CAPACITY).0,
+            events: broadcast::channel(CAPACITY).0,
+            relay: Mutex::new(Some(Relay {
+                listener,
+                pool: store.handle(),
+                channels: channels.to_vec(),
+                resync,
+                #[cfg(feature = "metrics")]
+                metrics: store.metrics.clone(),
+            })),
+        })
+    }
+
+    /// Receives the `(channel, payload)` of every notification sent from now on.
+    ///
+    /// A subscriber falling more than 1024 notifications behind skips the oldest ones.
+    /// Reconnects of the listener are only reported by [`PgNotifications::subscribe_to`].
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, String)> {
+        self.sender.subscribe()
+    }
+
+    /// Streams the notifications sent on `channel` from now on, which must be one of the
+    /// channels listened on.
+    ///
+    /// A subscriber falling more than 1024 notifications behind skips the oldest ones, so
+    /// slow clients never hold up the listener. Skipped notifications yield
+    /// [`Notification::Gap`], and reconnects of the listener [`Notification::Reconnected`].
+    pub fn subscribe_to(&self, channel: &str) -> impl Stream<Item = Notification> + Send + 'static {
+        let channel = channel.to_owned();
+
+        stream::unfold(self.events.subscribe(), move |mut receiver| {
+            let channel = channel.clone();
+
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(Notification::Message {
+                            channel: sent,
+                            payload,
+                        }) if sent == channel => {
+                            let notification = Notification::Message { channel, payload };
+                            return Some((notification, receiver));
+                        }
+                        Ok(Notification::Message { .. }) => continue,
+                        Ok(notification @ Notification::Reconnected { .. }) => {
+                            return Some((notification, receiver))
+                        }
+                        Ok(Notification::Gap) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                            return Some((Notification::Gap, receiver))
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        })
+    }
+
+    /// Starts relaying notifications until Rocket shuts down.
+    pub(crate) fn spawn(&self, mut shutdown: Shutdown) {
+        let mut relay = match self.relay.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(relay) => relay,
+            None => return,
+        };
+        let sender = self.sender.clone();
+        let events = self.events.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    // `try_recv` reports a lost connection, the relay reconnects with backoff.
+                    notification = relay.listener.try_recv() => match notification {
+                        Ok(Some(notification)) => {
+                            let channel = notification.channel().to_owned();
+                            let payload = notification.payload().to_owned();
+
+                            // No subscribers is not an error, the notification is just dropped.
+                            if events.receiver_count() > 0 {
+                                let _ = events.send(Notification::Message {
+                                    channel: channel.clone(),
+                                    payload: payload.clone(),
+                                });
+                            }
+
+                            let _ = sender.send((channel, payload));
+                        }
+                        Ok(None) => {
+                            log::warn!("SQLxPostgres: listener lost its connection, reconnecting");
+
+                            if !relay.reconnect(&events, &mut shutdown).await {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("SQLxPostgres: listener failed: {}, reconnecting", e);
+
+                            if !relay.reconnect(&events, &mut shutdown).await {
+                                break;
+                            }
+                        }
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+
+            log::info!("SQLxPostgres: listener stopped");
+        });
+    }
+}
+
+/// The listener and what it needs to reconnect, moved into the relay task at liftoff.
+struct Relay {
+    listener: PgListener,
+    /// The current pool, which credential rotations replace.
+    pool: Arc<RwLock<PgPool>>,
+    channels: Vec<String>,
+    resync: Vec<(String, ResyncCallback)>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<DatabaseMetrics>,
+}
+
+impl Relay {
+    /// Connects a new listener, retrying with backoff, then runs the resync callbacks and
+    /// tells the subscribers. Returns `false` when Rocket shuts down first.
+    async fn reconnect(
+        &mut self,
+        events: &broadcast::Sender<Notification>,
+        shutdown: &mut Shutdown,
+    ) -> bool {
+        let lost = Instant::now();
+        let mut attempt = 0;
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.listener_connected(false);
+        }
+
+        let listener = loop {
+            attempt += 1;
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.listener_reconnect_attempt();
+            }
+
+            let pool = self.pool.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+            match listen(&pool, &self.channels).await {
+                Ok(listener) => break listener,
+                Err(e) => {
+                    let delay = connect::backoff(INITIAL_BACKOFF, attempt - 1);
+                    log::warn!(
+                        "SQLxPostgres: listener reconnect attempt {} failed: {}, retrying in {:?}",
+                        attempt,
+                        e,
+                        delay
+                    );
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = &mut *shutdown => return false,
+                    }
+                }
+            }
+        };
+
+        self.listener = listener;
+        let downtime = lost.elapsed();
+        log::info!(
+            "SQLxPostgres: listener reconnected after {} attempts and {:?}, listening on {:?}",
+            attempt,
+            downtime,
+            self.channels
+        );
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.listener_reconnected();
+        }
+
+        // Resyncing before the new notifications are relayed keeps them ordered after it.
+        let pool = self.pool.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+        for (channel, resync) in &self.resync {
+            if let Err(e) = resync(&pool).await {
+                log::warn!(
+                    "SQLxPostgres: resyncing channel `{}` after the listener reconnected failed: {}",
+                    channel,
+                    e
+                );
+            }
+        }
+
+        let _ = events.send(Notification::Reconnected { downtime });
+        true
+    }
+}
+
+/// Connects a listener with the connect options of `pool` and listens on `channels`.
+async fn listen(pool: &PgPool, channels: &[String]) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener
+        .listen_all(channels.iter().map(|channel| channel.as_str()))
+        .await?;
+
+    Ok(listener)
+}
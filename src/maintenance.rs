@@ -0,0 +1,117 @@
+use crate::{session::SqlxPostgresConfig, PoolGuardError};
+use rocket::{request::Request, response::Response, Build, Rocket};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Set in the request local cache when a guard was refused for maintenance, so the fairing
+/// can add a `Retry-After` header.
+struct RefusedForMaintenance(bool);
+
+/// Managed switch refusing database work while the app stays up, e.g. during a risky
+/// migration.
+///
+/// While enabled every database guard of this crate fails with 503 before touching a pool,
+/// except [`crate::SQLxPostgresOptional`] which forwards, and the response is sent with
+/// `Retry-After`. Routes without database guards keep working. Changes apply to the next
+/// guard resolved, without a restart. Start enabled with
+/// [`SqlxPostgresConfig::with_maintenance_mode`].
+///
+/// ```rust,ignore
+/// #[post("/admin/maintenance/<enabled>")]
+/// fn maintenance(mode: &State<MaintenanceMode>, enabled: bool) {
+///     mode.set_enabled(enabled);
+/// }
+///
+/// #[get("/")]
+/// fn index(mode: &State<MaintenanceMode>) -> Template {
+///     Template::render("index", context! { maintenance: mode.is_enabled() })
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MaintenanceMode {
+    enabled: Arc<AtomicBool>,
+    retry_after: Duration,
+}
+
+impl MaintenanceMode {
+    fn new(enabled: bool, retry_after: Duration) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            retry_after,
+        }
+    }
+
+    /// Whether database work is refused right now.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops refusing database work.
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            match enabled {
+                true => {
+                    log::warn!("SQLxPostgres: maintenance mode enabled, refusing database work")
+                }
+                false => log::info!("SQLxPostgres: maintenance mode disabled"),
+            }
+        }
+    }
+
+    /// Time clients are told to wait in `Retry-After`.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+/// Manages the switch with the settings of `config` unless one is managed already, in
+/// which case `config` can only enable it.
+pub(crate) fn register(rocket: Rocket<Build>, config: &SqlxPostgresConfig) -> Rocket<Build> {
+    match rocket.state::<MaintenanceMode>() {
+        Some(mode) => {
+            if config.maintenance_mode {
+                mode.set_enabled(true);
+            }
+
+            rocket
+        }
+        None => {
+            let mode =
+                MaintenanceMode::new(config.maintenance_mode, config.maintenance_retry_after);
+
+            if mode.is_enabled() {
+                log::warn!("SQLxPostgres: starting in maintenance mode, refusing database work");
+            }
+
+            rocket.manage(mode)
+        }
+    }
+}
+
+/// Fails with [`PoolGuardError::Maintenance`] while maintenance mode is enabled.
+pub(crate) fn check(request: &Request<'_>) -> Result<(), PoolGuardError> {
+    match request.rocket().state::<MaintenanceMode>() {
+        Some(mode) if mode.is_enabled() => {
+            request.local_cache(|| RefusedForMaintenance(true));
+            Err(PoolGuardError::Maintenance)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Adds `Retry-After` when a guard of the request was refused for maintenance.
+pub(crate) fn report(request: &Request<'_>, response: &mut Response<'_>) {
+    if !request.local_cache(|| RefusedForMaintenance(false)).0 {
+        return;
+    }
+
+    if let Some(mode) = request.rocket().state::<MaintenanceMode>() {
+        let secs = mode.retry_after.as_secs().max(1);
+        response.set_raw_header("Retry-After", secs.to_string());
+    }
+}
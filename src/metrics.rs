@@ -0,0 +1,278 @@
+use crate::{breaker::BreakerState, status::PoolStatus, PoolGuardError};
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
+};
+use rocket::Shutdown;
+use std::{fmt, time::Duration};
+
+/// How often the pool gauges are refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Buckets of the acquire latency histogram, in seconds.
+const ACQUIRE_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Prometheus metrics of the pools, labelled by `database`.
+///
+/// Register them into your own registry and hand them to every fairing with
+/// [`crate::SqlxPostgresFairing::with_metrics`]; the unnamed fairing is labelled `default`
+/// and its replicas `replica:<host>:<port>`.
+///
+/// | metric | type |
+/// |---|---|
+/// | `sqlxpostgres_pool_connections` | gauge of open connections |
+/// | `sqlxpostgres_pool_idle_connections` | gauge |
+/// | `sqlxpostgres_pool_in_use_connections` | gauge |
+/// | `sqlxpostgres_pool_waiting` | gauge of guards waiting for a connection |
+/// | `sqlxpostgres_circuit_breaker_state` | gauge, 0 closed, 1 half open, 2 open |
+/// | `sqlxpostgres_acquire_seconds` | histogram of guard acquire latency |
+/// | `sqlxpostgres_acquire_timeouts_total` | counter |
+/// | `sqlxpostgres_waiter_rejections_total` | counter of guards refused by `with_max_waiters` |
+/// | `sqlxpostgres_connection_errors_total` | counter of failed connects and acquires |
+/// | `sqlxpostgres_query_cache_hits_total` | counter of [`crate::SQLxPostgres::cached`] hits |
+/// | `sqlxpostgres_query_cache_misses_total` | counter |
+/// | `sqlxpostgres_listener_connected` | gauge, 1 while the `listen` listener is connected |
+/// | `sqlxpostgres_listener_reconnect_attempts_total` | counter |
+/// | `sqlxpostgres_listener_reconnects_total` | counter of successful reconnects |
+/// | `sqlxpostgres_replica_lag_seconds` | gauge of replica replay lag, with `with_replica_max_lag` |
+///
+/// ```rust,ignore
+/// let registry = prometheus::Registry::new();
+/// let metrics = PoolMetrics::register(&registry)?;
+///
+/// rocket::build().attach(SqlxPostgresFairing::from_config(config).with_metrics(metrics))
+/// ```
+#[derive(Clone)]
+pub struct PoolMetrics {
+    size: IntGaugeVec,
+    idle: IntGaugeVec,
+    in_use: IntGaugeVec,
+    waiting: IntGaugeVec,
+    circuit_breaker: IntGaugeVec,
+    acquire_seconds: HistogramVec,
+    acquire_timeouts: IntCounterVec,
+    waiter_rejections: IntCounterVec,
+    connection_errors: IntCounterVec,
+    query_cache_hits: IntCounterVec,
+    query_cache_misses: IntCounterVec,
+    listener_connected: IntGaugeVec,
+    listener_reconnect_attempts: IntCounterVec,
+    listener_reconnects: IntCounterVec,
+    replica_lag_seconds: GaugeVec,
+}
+
+impl PoolMetrics {
+    /// Creates the metrics and registers them into `registry`.
+    pub fn register(registry: &Registry) -> prometheus::Result<Self> {
+        let gauge = |name: &str, help: &str| -> prometheus::Result<IntGaugeVec> {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), &["database"])?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let counter = |name: &str, help: &str| -> prometheus::Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help), &["database"])?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+
+        let acquire_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "sqlxpostgres_acquire_seconds",
+                "Time request guards waited for a connection",
+            )
+            .buckets(ACQUIRE_BUCKETS.to_vec()),
+            &["database"],
+        )?;
+        registry.register(Box::new(acquire_seconds.clone()))?;
+
+        let replica_lag_seconds = GaugeVec::new(
+            Opts::new(
+                "sqlxpostgres_replica_lag_seconds",
+                "How far the replay of a replica is behind its primary",
+            ),
+            &["database"],
+        )?;
+        registry.register(Box::new(replica_lag_seconds.clone()))?;
+
+        Ok(Self {
+            size: gauge("sqlxpostgres_pool_connections", "Open connections")?,
+            idle: gauge("sqlxpostgres_pool_idle_connections", "Idle connections")?,
+            in_use: gauge(
+                "sqlxpostgres_pool_in_use_connections",
+                "Checked out connections",
+            )?,
+            waiting: gauge(
+                "sqlxpostgres_pool_waiting",
+                "Request guards waiting for a connection",
+            )?,
+            circuit_breaker: gauge(
+                "sqlxpostgres_circuit_breaker_state",
+                "Circuit breaker state, 0 closed, 1 half open, 2 open",
+            )?,
+            acquire_seconds,
+            acquire_timeouts: counter(
+                "sqlxpostgres_acquire_timeouts_total",
+                "Request guards that gave up waiting for a connection",
+            )?,
+            waiter_rejections: counter(
+                "sqlxpostgres_waiter_rejections_total",
+                "Request guards refused because too many were waiting for a connection",
+            )?,
+            connection_errors: counter(
+                "sqlxpostgres_connection_errors_total",
+                "Failed connects and acquires",
+            )?,
+            query_cache_hits: counter(
+                "sqlxpostgres_query_cache_hits_total",
+                "Query cache lookups answered from the cache",
+            )?,
+            query_cache_misses: counter(
+                "sqlxpostgres_query_cache_misses_total",
+                "Query cache lookups that ran the query",
+            )?,
+            listener_connected: gauge(
+                "sqlxpostgres_listener_connected",
+                "Whether the notification listener is connected, 1 or 0",
+            )?,
+            listener_reconnect_attempts: counter(
+                "sqlxpostgres_listener_reconnect_attempts_total",
+                "Attempts of the notification listener to reconnect",
+            )?,
+            listener_reconnects: counter(
+                "sqlxpostgres_listener_reconnects_total",
+                "Times the notification listener reconnected",
+            )?,
+            replica_lag_seconds,
+        })
+    }
+
+    pub(crate) fn database(&self, name: &str) -> DatabaseMetrics {
+        DatabaseMetrics {
+            size: self.size.with_label_values(&[name]),
+            idle: self.idle.with_label_values(&[name]),
+            in_use: self.in_use.with_label_values(&[name]),
+            waiting: self.waiting.with_label_values(&[name]),
+            circuit_breaker: self.circuit_breaker.with_label_values(&[name]),
+            acquire_seconds: self.acquire_seconds.with_label_values(&[name]),
+            acquire_timeouts: self.acquire_timeouts.with_label_values(&[name]),
+            waiter_rejections: self.waiter_rejections.with_label_values(&[name]),
+            connection_errors: self.connection_errors.with_label_values(&[name]),
+            query_cache_hits: self.query_cache_hits.with_label_values(&[name]),
+            query_cache_misses: self.query_cache_misses.with_label_values(&[name]),
+            listener_connected: self.listener_connected.with_label_values(&[name]),
+            listener_reconnect_attempts: self
+                .listener_reconnect_attempts
+                .with_label_values(&[name]),
+            listener_reconnects: self.listener_reconnects.with_label_values(&[name]),
+            replica_lag_seconds: self.replica_lag_seconds.with_label_values(&[name]),
+        }
+    }
+}
+
+impl fmt::Debug for PoolMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolMetrics").finish()
+    }
+}
+
+/// The metrics of one database.
+#[derive(Clone)]
+pub(crate) struct DatabaseMetrics {
+    size: IntGauge,
+    idle: IntGauge,
+    in_use: IntGauge,
+    waiting: IntGauge,
+    circuit_breaker: IntGauge,
+    acquire_seconds: Histogram,
+    acquire_timeouts: IntCounter,
+    waiter_rejections: IntCounter,
+    connection_errors: IntCounter,
+    query_cache_hits: IntCounter,
+    query_cache_misses: IntCounter,
+    listener_connected: IntGauge,
+    listener_reconnect_attempts: IntCounter,
+    listener_reconnects: IntCounter,
+    replica_lag_seconds: Gauge,
+}
+
+impl DatabaseMetrics {
+    pub(crate) fn record_status(&self, status: &PoolStatus) {
+        self.size.set(i64::from(status.size));
+        self.idle.set(status.idle as i64);
+        self.in_use.set(status.in_use as i64);
+        self.waiting.set(status.waiting as i64);
+
+        if let Some(state) = status.circuit_breaker {
+            self.circuit_breaker.set(match state {
+                BreakerState::Closed => 0,
+                BreakerState::HalfOpen => 1,
+                BreakerState::Open => 2,
+            });
+        }
+    }
+
+    pub(crate) fn record_acquire<T>(&self, elapsed: Duration, result: &Result<T, PoolGuardError>) {
+        self.acquire_seconds.observe(elapsed.as_secs_f64());
+
+        match result {
+            Ok(_) => {}
+            Err(PoolGuardError::AcquireTimeout) => self.acquire_timeouts.inc(),
+            Err(_) => self.connection_errors.inc(),
+        }
+    }
+
+    pub(crate) fn waiter_rejected(&self) {
+        self.waiter_rejections.inc();
+    }
+
+    pub(crate) fn connection_error(&self) {
+        self.connection_errors.inc();
+    }
+
+    pub(crate) fn query_cache(&self, hit: bool) {
+        match hit {
+            true => self.query_cache_hits.inc(),
+            false => self.query_cache_misses.inc(),
+        }
+    }
+
+    pub(crate) fn listener_connected(&self, connected: bool) {
+        self.listener_connected.set(i64::from(connected));
+    }
+
+    pub(crate) fn listener_reconnect_attempt(&self) {
+        self.listener_reconnect_attempts.inc();
+    }
+
+    pub(crate) fn listener_reconnected(&self) {
+        self.listener_reconnects.inc();
+        self.listener_connected.set(1);
+    }
+
+    pub(crate) fn record_replica_lag(&self, lag: Duration) {
+        self.replica_lag_seconds.set(lag.as_secs_f64());
+    }
+
+    /// Refreshes the gauges from `status` until shutdown or the pool is gone.
+    pub(crate) async fn refresh<F>(self, status: F, mut shutdown: Shutdown)
+    where
+        F: Fn() -> Option<PoolStatus>,
+    {
+        while let Some(status) = status() {
+            self.record_status(&status);
+
+            rocket::tokio::select! {
+                _ = rocket::tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = &mut shutdown => return,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DatabaseMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseMetrics").finish()
+    }
+}
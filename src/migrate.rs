@@ -0,0 +1,369 @@
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migration, Migrator},
+    postgres::PgPool,
+};
+use std::{collections::HashMap, fmt, path::Path};
+
+/// A migration failure, naming the migration when one was being applied.
+#[derive(Debug)]
+pub struct MigrationError {
+    /// Version and description of the failed migration.
+    pub migration: Option<(i64, String)>,
+    /// The underlying sqlx error.
+    pub source: MigrateError,
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.migration {
+            Some((version, description)) => write!(
+                f,
+                "migration {} ({}) failed: {}",
+                version, description, self.source
+            ),
+            None => write!(f, "migrations failed: {}", self.source),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<MigrateError> for MigrationError {
+    fn from(source: MigrateError) -> Self {
+        Self {
+            migration: None,
+            source,
+        }
+    }
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrateError::Execute(e).into()
+    }
+}
+
+/// How the database compares to a migrator, as checked by
+/// [`require_migrations_applied`](crate::SqlxPostgresFairing::require_migrations_applied).
+///
+/// Serializable with the `serde` feature.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "rocket::serde"))]
+pub struct MigrationStatus {
+    /// Versions applied to the database and known to the migrator.
+    pub applied: Vec<i64>,
+    /// Versions known to the migrator but not applied yet.
+    pub pending: Vec<i64>,
+    /// Versions applied with a different checksum, i.e. edited after being applied.
+    pub mismatched: Vec<i64>,
+    /// Version of a migration that failed part way and left the database dirty.
+    pub dirty: Option<i64>,
+}
+
+impl MigrationStatus {
+    /// Whether the database matches the migrator exactly.
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty() && self.mismatched.is_empty() && self.dirty.is_none()
+    }
+}
+
+/// The migrations a fairing ran or checked at ignite, kept for [`crate::admin_routes`].
+#[derive(Default)]
+pub(crate) struct KnownMigrations(pub(crate) Vec<Migration>);
+
+impl fmt::Debug for KnownMigrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|migration| migration.version))
+            .finish()
+    }
+}
+
+/// Compares the database against `migrations` without running any DDL.
+///
+/// Without any migrations every applied version is listed as applied.
+pub(crate) async fn status(
+    pool: &PgPool,
+    migrations: &[Migration],
+) -> Result<MigrationStatus, MigrationError> {
+    let mut conn = pool.acquire().await?;
+    let (exists,): (bool,) = sqlx::query_as("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(&mut conn)
+        .await?;
+    let mut status = MigrationStatus::default();
+    let applied: HashMap<_, _> = if exists {
+        status.dirty = conn.dirty_version().await?;
+
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|migration| (migration.version, migration.checksum))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    if migrations.is_empty() {
+        status.applied = applied.into_keys().collect();
+        status.applied.sort_unstable();
+        return Ok(status);
+    }
+
+    for migration in migrations {
+        if migration.migration_type.is_down_migration() {
+            continue;
+        }
+
+        match applied.get(&migration.version) {
+            Some(checksum) if *checksum != migration.checksum => {
+                status.mismatched.push(migration.version)
+            }
+            Some(_) => status.applied.push(migration.version),
+            None => status.pending.push(migration.version),
+        }
+    }
+
+    Ok(status)
+}
+
+/// Loads the migrations in `path` at runtime, failing when the directory is missing or empty.
+pub(crate) async fn from_path(path: &Path) -> Result<Migrator, MigrationError> {
+    if !path.is_dir() {
+        return Err(MigrateError::Source(
+            format!("migrations directory `{}` does not exist", path.display()).into(),
+        )
+        .into());
+    }
+
+    let migrator = Migrator::new(path).await?;
+
+    if migrator.iter().next().is_none() {
+        return Err(MigrateError::Source(
+            format!(
+                "migrations directory `{}` contains no migrations",
+                path.display()
+            )
+            .into(),
+        )
+        .into());
+    }
+
+    Ok(migrator)
+}
+
+/// The version [`run`] brings the database to, set with
+/// [`crate::SqlxPostgresFairing::with_migration_target`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Target {
+    pub version: i64,
+    /// Whether migrations newer than `version` may be reverted.
+    pub allow_revert: bool,
+}
+
+/// Applies the pending migrations of `migrator` one at a time, returning how many were
+/// reverted and how many applied.
+///
+/// Mirrors `Migrator::run` but reports which migration failed. With a `target`, applied
+/// migrations newer than it are reverted first and newer pending ones are left pending.
+pub(crate) async fn run(
+    pool: &PgPool,
+    migrator: &Migrator,
+    target: Option<Target>,
+) -> Result<(usize, usize), MigrationError> {
+    if let Some(target) = target {
+        let known = migrator
+            .iter()
+            .any(|migration| migration.version == target.version);
+
+        if target.version != 0 && !known {
+            return Err(MigrateError::VersionMissing(target.version).into());
+        }
+    }
+
+    let mut conn = pool.acquire().await?;
+
+    conn.lock().await?;
+    let result = async {
+        let reverted = match target {
+            Some(target) => revert_newer(
+                &mut conn,
+                migrator,
+                Some(target.version),
+                target.allow_revert,
+            )
+            .await?
+            .len(),
+            None => 0,
+        };
+        let applied =
+            apply_pending(&mut conn, migrator, target.map(|target| target.version)).await?;
+
+        Ok::<_, MigrationError>((reverted, applied))
+    }
+    .await;
+    conn.unlock().await?;
+
+    result
+}
+
+/// Reverts the latest applied migration of `migrator`, returning its version, or `None`
+/// when none is applied.
+///
+/// For an admin route or an ops binary; calling it is the explicit go ahead, so unlike
+/// [`crate::SqlxPostgresFairing::with_migration_target`] it needs no `allow_revert`. Fails
+/// without running any DDL when the migration is irreversible, i.e. `migrator` has no down
+/// migration for it, or isn't known to `migrator` at all. The revert is logged with the
+/// version and description of the migration.
+///
+/// ```rust,ignore
+/// let reverted = revert_last(db.pool(), &sqlx::migrate!("./migrations")).await?;
+/// ```
+pub async fn revert_last(
+    pool: &PgPool,
+    migrator: &Migrator,
+) -> Result<Option<i64>, MigrationError> {
+    let mut conn = pool.acquire().await?;
+
+    conn.lock().await?;
+    let result = revert_newer(&mut conn, migrator, None, true).await;
+    conn.unlock().await?;
+
+    result.map(|reverted| reverted.first().copied())
+}
+
+/// Reverts the applied migrations newer than `target`, or only the latest one without a
+/// target, newest first, returning the reverted versions.
+///
+/// Every migration to revert is checked to be reversible, and reverting to be allowed,
+/// before the first one runs.
+async fn revert_newer(
+    conn: &mut sqlx::pool::PoolConnection<sqlx::Postgres>,
+    migrator: &Migrator,
+    target: Option<i64>,
+    allowed: bool,
+) -> Result<Vec<i64>, MigrationError> {
+    conn.ensure_migrations_table().await?;
+
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version).into());
+    }
+
+    let mut applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .filter(|version| target.map_or(true, |target| *version > target))
+        .collect();
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    if target.is_none() {
+        applied.truncate(1);
+    }
+
+    let mut downs = Vec::with_capacity(applied.len());
+
+    for version in applied {
+        let mut migrations = migrator
+            .iter()
+            .filter(|migration| migration.version == version);
+        let up = migrations
+            .clone()
+            .find(|migration| !migration.migration_type.is_down_migration())
+            .ok_or(MigrateError::VersionMissing(version))?;
+
+        match migrations.find(|migration| migration.migration_type.is_down_migration()) {
+            Some(down) => downs.push(down),
+            None => {
+                return Err(MigrationError {
+                    migration: Some((version, up.description.to_string())),
+                    source: MigrateError::Source(
+                        "it is irreversible, it has no down migration".into(),
+                    ),
+                })
+            }
+        }
+    }
+
+    if let (false, Some(down)) = (allowed, downs.first()) {
+        return Err(MigrationError {
+            migration: Some((down.version, down.description.to_string())),
+            source: MigrateError::Source(
+                "reverting migrations at ignite needs `allow_revert(true)`".into(),
+            ),
+        });
+    }
+
+    for down in &downs {
+        let elapsed = conn.revert(down).await.map_err(|source| MigrationError {
+            migration: Some((down.version, down.description.to_string())),
+            source,
+        })?;
+        log::info!(
+            "SQLxPostgres: reverted migration {} ({}) in {:?}",
+            down.version,
+            down.description,
+            elapsed
+        );
+    }
+
+    Ok(downs.iter().map(|down| down.version).collect())
+}
+
+/// Applies the pending migrations up to `target`, or all of them.
+async fn apply_pending(
+    conn: &mut sqlx::pool::PoolConnection<sqlx::Postgres>,
+    migrator: &Migrator,
+    target: Option<i64>,
+) -> Result<usize, MigrationError> {
+    conn.ensure_migrations_table().await?;
+
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version).into());
+    }
+
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+    let mut count = 0;
+
+    for migration in migrator.iter() {
+        if migration.migration_type.is_down_migration()
+            || target.map_or(false, |target| migration.version > target)
+        {
+            continue;
+        }
+
+        match applied.get(&migration.version) {
+            Some(checksum) if *checksum != migration.checksum => {
+                return Err(MigrateError::VersionMismatch(migration.version).into());
+            }
+            Some(_) => {}
+            None => {
+                conn.apply(migration)
+                    .await
+                    .map_err(|source| MigrationError {
+                        migration: Some((migration.version, migration.description.to_string())),
+                        source,
+                    })?;
+                log::info!(
+                    "SQLxPostgres: applied migration {} ({})",
+                    migration.version,
+                    migration.description
+                );
+                count += 1;
+            }
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,265 @@
+use crate::provider::{DbQuery, DbValue, PgExecutorProvider};
+use futures::future::{self, BoxFuture};
+use rocket::serde::Serialize;
+use serde_json::Value;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+type MockError = Arc<dyn Fn() -> sqlx::Error + Send + Sync>;
+
+/// What a [`MockQuery`] answers.
+#[derive(Clone)]
+enum Response {
+    Rows(Vec<Value>),
+    RowsAffected(u64),
+    Error(MockError),
+}
+
+/// An expected query of a [`MockDb`] and its canned answer.
+///
+/// The query matches when its SQL contains `pattern`, both with their whitespace collapsed,
+/// and, with [`MockQuery::with_binds`], when its parameters are equal. It answers once
+/// unless [`MockQuery::times`] says otherwise, and with no rows by default.
+#[derive(Clone)]
+pub struct MockQuery {
+    pattern: String,
+    binds: Option<Vec<DbValue>>,
+    response: Response,
+    times: usize,
+    used: usize,
+}
+
+impl MockQuery {
+    /// Expects a query containing `pattern`.
+    pub fn new(pattern: impl AsRef<str>) -> Self {
+        Self {
+            pattern: normalize(pattern.as_ref()),
+            binds: None,
+            response: Response::Rows(Vec::new()),
+            times: 1,
+            used: 0,
+        }
+    }
+
+    /// Only matches queries bound with exactly `binds`.
+    pub fn with_binds(mut self, binds: Vec<DbValue>) -> Self {
+        self.binds = Some(binds);
+        self
+    }
+
+    /// Answers with `rows`, serialized to JSON objects keyed by column name, which is also
+    /// the number of rows affected.
+    ///
+    /// # Panics
+    ///
+    /// When a row doesn't serialize to a JSON object.
+    pub fn with_rows<T: Serialize>(mut self, rows: &[T]) -> Self {
+        let rows = rows
+            .iter()
+            .map(|row| match serde_json::to_value(row) {
+                Ok(row @ Value::Object(_)) => row,
+                Ok(_) => panic!("MockQuery::with_rows: a row is not a struct or map"),
+                Err(e) => panic!("MockQuery::with_rows: serializing a row failed: {}", e),
+            })
+            .collect();
+
+        self.response = Response::Rows(rows);
+        self
+    }
+
+    /// Answers a statement without rows, like an `UPDATE`, affecting `rows` rows.
+    pub fn with_rows_affected(mut self, rows: u64) -> Self {
+        self.response = Response::RowsAffected(rows);
+        self
+    }
+
+    /// Fails with the error `error` returns, e.g. `|| sqlx::Error::PoolTimedOut`.
+    pub fn with_error<F>(mut self, error: F) -> Self
+    where
+        F: Fn() -> sqlx::Error + Send + Sync + 'static,
+    {
+        self.response = Response::Error(Arc::new(error));
+        self
+    }
+
+    /// Answers `times` matching queries instead of one.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, sql: &str, binds: &[DbValue]) -> bool {
+        self.used < self.times
+            && sql.contains(&self.pattern)
+            && self
+                .binds
+                .as_deref()
+                .map_or(true, |expected| expected == binds)
+    }
+}
+
+impl fmt::Debug for MockQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockQuery")
+            .field("pattern", &self.pattern)
+            .field("binds", &self.binds)
+            .field("times", &self.times)
+            .field("used", &self.used)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expected: Vec<MockQuery>,
+    /// Every query run, in order.
+    queries: Vec<DbQuery>,
+    /// The SQL of the queries no expectation matched.
+    unexpected: Vec<String>,
+}
+
+/// A [`PgExecutorProvider`] answering queries from expectations instead of a database, for
+/// unit tests of handler logic.
+///
+/// Run code written against the provider on it directly, or manage it in a test Rocket,
+/// where the [`crate::DbProvider`] guard hands it out in place of the pool. A query no
+/// expectation matches fails with an error naming its SQL, and
+/// [`MockDb::assert_all_used`] fails the test on it. Clones share the expectations, so keep
+/// one to assert on after managing another.
+///
+/// ```rust,ignore
+/// let ann = User { id: 1, name: "ann".into() };
+/// let mock = MockDb::new()
+///     .expect(MockQuery::new("SELECT id, name FROM users").with_rows(&[ann]))
+///     .expect(
+///         MockQuery::new("DELETE FROM sessions")
+///             .with_binds(vec![1.into()])
+///             .with_rows_affected(2),
+///     );
+///
+/// let users = active_users(&mock).await?;
+/// mock.assert_all_used();
+/// ```
+#[derive(Clone, Default)]
+pub struct MockDb {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockDb {
+    /// A mock without expectations, failing every query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an expectation, tried in the order they were added.
+    pub fn expect(self, query: MockQuery) -> Self {
+        self.lock().expected.push(query);
+        self
+    }
+
+    /// The queries run so far, in order.
+    pub fn queries(&self) -> Vec<DbQuery> {
+        self.lock().queries.clone()
+    }
+
+    /// Fails when an expectation answered fewer queries than expected or a query matched no
+    /// expectation.
+    ///
+    /// # Panics
+    ///
+    /// Listing the unused expectations and the SQL of the unexpected queries.
+    pub fn assert_all_used(&self) {
+        let state = self.lock();
+        let unused: Vec<String> = state
+            .expected
+            .iter()
+            .filter(|query| query.used < query.times)
+            .map(|query| {
+                format!(
+                    "`{}` answered {} of {} times",
+                    query.pattern, query.used, query.times
+                )
+            })
+            .collect();
+
+        if !unused.is_empty() || !state.unexpected.is_empty() {
+            panic!(
+                "MockDb: unused expectations: [{}], unexpected queries: [{}]",
+                unused.join(", "),
+                state.unexpected.join(", ")
+            );
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The answer of the first expectation matching `query`.
+    fn answer(&self, query: DbQuery) -> Result<Response, sqlx::Error> {
+        let mut state = self.lock();
+        let sql = normalize(query.sql());
+        let found = state
+            .expected
+            .iter_mut()
+            .find(|expected| expected.matches(&sql, query.binds()));
+
+        let response = match found {
+            Some(expected) => {
+                expected.used += 1;
+                Ok(expected.response.clone())
+            }
+            None => {
+                log::error!("MockDb: no expectation matches the query: {}", query.sql());
+                state.unexpected.push(query.sql().to_owned());
+                Err(sqlx::Error::Protocol(format!(
+                    "MockDb: no expectation matches the query: {}",
+                    query.sql()
+                )))
+            }
+        };
+
+        state.queries.push(query);
+        response
+    }
+}
+
+impl fmt::Debug for MockDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+
+        f.debug_struct("MockDb")
+            .field("expected", &state.expected)
+            .field("unexpected", &state.unexpected)
+            .finish()
+    }
+}
+
+impl PgExecutorProvider for MockDb {
+    fn execute_query(&self, query: DbQuery) -> BoxFuture<'_, Result<u64, sqlx::Error>> {
+        let result = self.answer(query).and_then(|response| match response {
+            Response::Rows(rows) => Ok(rows.len() as u64),
+            Response::RowsAffected(rows) => Ok(rows),
+            Response::Error(error) => Err(error()),
+        });
+
+        Box::pin(future::ready(result))
+    }
+
+    fn fetch_rows(&self, query: DbQuery) -> BoxFuture<'_, Result<Vec<Value>, sqlx::Error>> {
+        let result = self.answer(query).and_then(|response| match response {
+            Response::Rows(rows) => Ok(rows),
+            Response::RowsAffected(_) => Ok(Vec::new()),
+            Response::Error(error) => Err(error()),
+        });
+
+        Box::pin(future::ready(result))
+    }
+}
+
+/// `sql` with runs of whitespace collapsed to one space and trimmed.
+fn normalize(sql: &str) -> String {
+    sql.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,229 @@
+use crate::{
+    guards,
+    health::DbHealth,
+    rotate::PoolHandle,
+    session::{SQLxPostgres, SQLxPostgresPool},
+    PoolGuardError,
+};
+use rocket::{request::Request, Build, Ignite, Phase, Rocket};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// Names of the databases read from the figment, see [`intern`].
+static INTERNED_NAMES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+/// Returns a `'static` copy of a database name only known at runtime.
+///
+/// Named pools are keyed by `&'static str`, so each distinct name is leaked once per process
+/// and reused when a Rocket instance with the same config is ignited again, e.g. in tests.
+pub(crate) fn intern(name: String) -> &'static str {
+    let mut names = INTERNED_NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    let names = names.get_or_insert_with(HashSet::new);
+
+    match names.get(name.as_str()).copied() {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+/// Managed state holding the pools attached with [`crate::SqlxPostgresFairing::named`].
+///
+/// Request it with `&State<SQLxPostgresPools>` and look pools up by name:
+///
+/// ```rust,ignore
+/// #[get("/")]
+/// async fn index(pools: &State<SQLxPostgresPools>) -> Option<String> {
+///     let analytics = pools.get("analytics")?;
+///     ...
+/// }
+/// ```
+#[derive(Default)]
+pub struct SQLxPostgresPools {
+    pools: RwLock<HashMap<&'static str, Arc<SQLxPostgresPool>>>,
+}
+
+impl SQLxPostgresPools {
+    /// Returns the guard for the pool named `name`, if one was attached and connected.
+    pub fn get(&self, name: &str) -> Option<SQLxPostgres> {
+        self.pool(name)
+            .map(|store| SQLxPostgres::from_store(&store))
+    }
+
+    /// Returns the background health of the pool named `name`, if it has a health probe.
+    pub fn health(&self, name: &str) -> Option<DbHealth> {
+        self.pool(name)?.health.clone()
+    }
+
+    /// Returns the handle rotating the credentials of the pool named `name`, if it was
+    /// connected from its config.
+    pub fn rotation(&self, name: &str) -> Option<PoolHandle> {
+        self.pool(name)?.rotation.clone()
+    }
+
+    /// Names of the pools currently registered.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.read().keys().copied().collect()
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.read().contains_key(name)
+    }
+
+    pub(crate) fn pool(&self, name: &str) -> Option<Arc<SQLxPostgresPool>> {
+        self.read().get(name).cloned()
+    }
+
+    /// Registers `store` under `name`, returning `false` when the name is taken.
+    pub(crate) fn insert(&self, name: &'static str, store: SQLxPostgresPool) -> bool {
+        let mut pools = self.pools.write().unwrap_or_else(|e| e.into_inner());
+
+        if pools.contains_key(name) {
+            return false;
+        }
+
+        pools.insert(name, Arc::new(store));
+        true
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<&'static str, Arc<SQLxPostgresPool>>> {
+        self.pools.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for SQLxPostgresPools {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SQLxPostgresPools")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+/// Names a database attached with [`crate::SqlxPostgresFairing::named`], for `SQLxPostgres<D>`.
+///
+/// ```rust,ignore
+/// struct Analytics;
+///
+/// impl DatabaseMarker for Analytics {
+///     const NAME: &'static str = "analytics";
+/// }
+///
+/// #[get("/report")]
+/// async fn report(db: SQLxPostgres<Analytics>) { ... }
+/// ```
+pub trait DatabaseMarker: Send + Sync + 'static {
+    /// Name the database was attached under.
+    const NAME: &'static str;
+
+    #[doc(hidden)]
+    fn with_store<T, F>(request: &Request<'_>, f: F) -> Result<T, PoolGuardError>
+    where
+        F: FnOnce(&SQLxPostgresPool) -> T,
+    {
+        request
+            .rocket()
+            .state::<SQLxPostgresPools>()
+            .and_then(|pools| pools.pool(Self::NAME))
+            .map(|store| f(&store))
+            .ok_or(PoolGuardError::NotAttached(Self::NAME))
+    }
+
+    #[doc(hidden)]
+    fn with_rocket_store<P, T, F>(rocket: &Rocket<P>, f: F) -> Option<T>
+    where
+        P: Phase,
+        F: FnOnce(&SQLxPostgresPool) -> T,
+    {
+        rocket
+            .state::<SQLxPostgresPools>()
+            .and_then(|pools| pools.pool(Self::NAME))
+            .map(|store| f(&store))
+    }
+}
+
+/// The database of the unnamed fairing, what plain `SQLxPostgres` hands out.
+#[derive(Debug)]
+pub struct DefaultDatabase;
+
+impl DatabaseMarker for DefaultDatabase {
+    const NAME: &'static str = "default";
+
+    fn with_store<T, F>(request: &Request<'_>, f: F) -> Result<T, PoolGuardError>
+    where
+        F: FnOnce(&SQLxPostgresPool) -> T,
+    {
+        guards::managed_store(request).map(f)
+    }
+
+    fn with_rocket_store<P, T, F>(rocket: &Rocket<P>, f: F) -> Option<T>
+    where
+        P: Phase,
+        F: FnOnce(&SQLxPostgresPool) -> T,
+    {
+        rocket.state::<SQLxPostgresPool>().map(f)
+    }
+}
+
+/// Databases whose fairing ran at ignite, connected or not, for the sentinels of the guards.
+#[derive(Debug, Default)]
+pub(crate) struct AttachedDatabases(Mutex<Vec<&'static str>>);
+
+/// Records that the fairing of database `name` was attached.
+pub(crate) fn register_attached(rocket: Rocket<Build>, name: &'static str) -> Rocket<Build> {
+    let rocket = match rocket.state::<AttachedDatabases>() {
+        Some(_) => rocket,
+        None => rocket.manage(AttachedDatabases::default()),
+    };
+
+    if let Some(attached) = rocket.state::<AttachedDatabases>() {
+        attached
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(name);
+    }
+
+    rocket
+}
+
+/// Sentinel check of a guard of database `name`: aborts launch with an error naming the
+/// fairing to attach when none was attached for it.
+///
+/// A fairing that failed to connect with [`crate::SqlxPostgresConfig::ignore_connect_errors`]
+/// still counts as attached, its guards fail at request time as configured.
+pub(crate) fn abort_unattached(rocket: &Rocket<Ignite>, name: &'static str, guard: &str) -> bool {
+    let attached = rocket
+        .state::<AttachedDatabases>()
+        .map_or(false, |attached| {
+            attached
+                .0
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&name)
+        });
+
+    if attached {
+        return false;
+    }
+
+    match name == DefaultDatabase::NAME {
+        true => log::error!(
+            "SQLxPostgres: a route uses `{}` but no SqlxPostgresFairing is attached, add `.attach(SqlxPostgresFairing::new(config, None))`",
+            guard
+        ),
+        false => log::error!(
+            "SQLxPostgres: a route uses `{}` but database '{}' is not attached, add `.attach(SqlxPostgresFairing::named(\"{}\", config))`",
+            guard,
+            name,
+            name
+        ),
+    }
+
+    true
+}
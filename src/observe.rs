@@ -0,0 +1,511 @@
+use crate::{breaker::CircuitBreaker, connect, explain::Explainer, session::SqlxPostgresConfig};
+use futures::{
+    future::BoxFuture,
+    stream::{BoxStream, Stream},
+};
+use rocket::{http::Header, request::Request, response::Response};
+use sqlx::{
+    postgres::{PgPool, PgQueryResult, PgRow},
+    Either,
+};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+type Item = Result<Either<PgQueryResult, PgRow>, sqlx::Error>;
+
+/// Statements kept per request by [`QueryCapture`], later ones are only counted.
+const MAX_CAPTURED: usize = 1000;
+
+/// `rid=<id> ` prefix of a plain log event, empty unless the request id is logged, see
+/// [`SqlxPostgresConfig::with_log_request_id`].
+pub(crate) fn rid_prefix(rid: Option<&str>) -> String {
+    match rid {
+        Some(rid) => format!("rid={} ", rid),
+        None => String::new(),
+    }
+}
+
+/// Database time of one request, see [`SqlxPostgresConfig::with_request_db_time`].
+#[derive(Debug, Default)]
+pub(crate) struct RequestDbTime {
+    nanos: AtomicU64,
+    queries: AtomicU64,
+    headers: AtomicBool,
+    reported: AtomicBool,
+}
+
+/// Request local cache slot of [`RequestDbTime`], empty when no guard enabled it.
+pub(crate) struct RequestDbTimeSlot(Option<Arc<RequestDbTime>>);
+
+impl RequestDbTime {
+    /// The request's counters, created by the first guard whose config enables them.
+    pub(crate) fn for_request(
+        config: &SqlxPostgresConfig,
+        request: &Request<'_>,
+    ) -> Option<Arc<Self>> {
+        let headers = config.request_db_time?;
+        let slot = request.local_cache(|| RequestDbTimeSlot(Some(Arc::default())));
+        let timing = slot.0.clone()?;
+
+        if headers {
+            timing.headers.store(true, Ordering::Relaxed);
+        }
+
+        Some(timing)
+    }
+
+    /// Adds time spent waiting on the database, counting a query when `query` is set.
+    pub(crate) fn add(&self, elapsed: Duration, query: bool) {
+        let nanos = elapsed.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+
+        if query {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Logs the request's totals and adds the headers if enabled, once per request.
+    pub(crate) fn report(request: &Request<'_>, response: &mut Response<'_>) {
+        let timing = match &request.local_cache(|| RequestDbTimeSlot(None)).0 {
+            Some(timing) => timing,
+            None => return,
+        };
+
+        if timing.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let millis = timing.nanos.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let queries = timing.queries.load(Ordering::Relaxed);
+
+        log::info!(
+            "SQLxPostgres: {} {} spent {:.3}ms in {} queries",
+            request.method(),
+            request.uri(),
+            millis,
+            queries
+        );
+
+        if timing.headers.load(Ordering::Relaxed) {
+            response.set_header(Header::new("X-DB-Time-Ms", format!("{:.3}", millis)));
+            response.set_header(Header::new("X-DB-Queries", queries.to_string()));
+        }
+    }
+}
+
+/// One statement run by a request, without its bind values.
+struct CapturedQuery {
+    sql: String,
+    elapsed: Duration,
+    rows: u64,
+    rows_affected: u64,
+    failed: bool,
+}
+
+/// Statements of one request, see [`SqlxPostgresConfig::with_query_capture`].
+#[derive(Default)]
+pub(crate) struct QueryCapture {
+    queries: Mutex<Vec<CapturedQuery>>,
+    dropped: AtomicU64,
+    header: AtomicBool,
+    reported: AtomicBool,
+}
+
+/// Request local cache slot of [`QueryCapture`], empty unless the request asked for it.
+pub(crate) struct QueryCaptureSlot(Option<Arc<QueryCapture>>);
+
+impl QueryCapture {
+    /// The request's capture, when the config enables it and the request sent the header.
+    pub(crate) fn for_request(
+        config: &SqlxPostgresConfig,
+        request: &Request<'_>,
+    ) -> Option<Arc<Self>> {
+        let (name, header) = config.query_capture.as_ref()?;
+
+        match request.headers().get_one(name)? {
+            "" | "0" => return None,
+            _ => {}
+        }
+
+        let slot = request.local_cache(|| QueryCaptureSlot(Some(Arc::default())));
+        let capture = slot.0.clone()?;
+
+        if *header {
+            capture.header.store(true, Ordering::Relaxed);
+        }
+
+        Some(capture)
+    }
+
+    fn push(&self, query: CapturedQuery) {
+        let mut queries = self.queries.lock().unwrap_or_else(|e| e.into_inner());
+
+        match queries.len() < MAX_CAPTURED {
+            true => queries.push(query),
+            false => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Logs the request's statements in order and adds the count header if enabled, once
+    /// per request.
+    pub(crate) fn report(request: &Request<'_>, response: &mut Response<'_>) {
+        let capture = match &request.local_cache(|| QueryCaptureSlot(None)).0 {
+            Some(capture) => capture,
+            None => return,
+        };
+
+        if capture.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let queries = capture.queries.lock().unwrap_or_else(|e| e.into_inner());
+        let dropped = capture.dropped.load(Ordering::Relaxed);
+        let count = queries.len() as u64 + dropped;
+
+        log::info!(
+            "SQLxPostgres: {} {} ran {} statements",
+            request.method(),
+            request.uri(),
+            count
+        );
+
+        for (index, query) in queries.iter().enumerate() {
+            log::info!(
+                "SQLxPostgres:   #{} {:.3}ms rows={} rows_affected={}{} {}",
+                index + 1,
+                query.elapsed.as_secs_f64() * 1000.0,
+                query.rows,
+                query.rows_affected,
+                if query.failed { " failed" } else { "" },
+                query.sql
+            );
+        }
+
+        if dropped > 0 {
+            log::info!("SQLxPostgres:   {} more statements not captured", dropped);
+        }
+
+        if capture.header.load(Ordering::Relaxed) {
+            response.set_header(Header::new("X-DB-Captured-Queries", count.to_string()));
+        }
+    }
+}
+
+/// Hooks around the queries run through a guard's executor.
+///
+/// Does nothing unless a feature or option needs it, so the guards then hand the pool's
+/// streams and futures through untouched.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QueryObserver {
+    #[cfg(feature = "tracing")]
+    statement_level: Option<tracing::Level>,
+    /// See [`SqlxPostgresConfig::statement_log_sample`].
+    sampled_log: Option<(f64, log::Level, Option<Duration>)>,
+    request_time: Option<Arc<RequestDbTime>>,
+    capture: Option<Arc<QueryCapture>>,
+    /// Counts the queries run on a pool, which acquire a connection of their own.
+    breaker: Option<CircuitBreaker>,
+    /// See [`SqlxPostgresConfig::with_slow_query_explain`].
+    explain: Option<Explainer>,
+    /// See [`SqlxPostgresConfig::with_log_request_id`].
+    rid: Option<Arc<str>>,
+}
+
+impl QueryObserver {
+    pub(crate) fn new(config: &SqlxPostgresConfig) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            statement_level: Some(config.tracing_statement_level),
+            sampled_log: config.statement_log_sample(),
+            request_time: None,
+            capture: None,
+            breaker: config.circuit_breaker.clone(),
+            explain: config.slow_query_explain.clone(),
+            rid: config.log_request_id(None),
+        }
+    }
+
+    /// Also adds the queries' durations to the request's database time and capture.
+    pub(crate) fn for_request(config: &SqlxPostgresConfig, request: &Request<'_>) -> Self {
+        Self {
+            request_time: RequestDbTime::for_request(config, request),
+            capture: QueryCapture::for_request(config, request),
+            rid: config.log_request_id(Some(request)),
+            ..Self::new(config)
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        #[cfg(feature = "tracing")]
+        if self.statement_level.is_some() {
+            return true;
+        }
+
+        self.request_time.is_some()
+            || self.sampled_log.is_some()
+            || self.capture.is_some()
+            || self.breaker.is_some()
+            || self.explain.is_some()
+    }
+
+    /// Observes a query run on `pool`, which also explains it when it is slow.
+    pub(crate) fn fetch_many<'e>(
+        &self,
+        sql: &'e str,
+        pool: &PgPool,
+        stream: BoxStream<'e, Item>,
+    ) -> BoxStream<'e, Item> {
+        if !self.is_active() {
+            return stream;
+        }
+
+        Box::pin(Observed {
+            inner: stream,
+            observer: self.clone(),
+            pool: pool.clone(),
+            sql,
+            start: Instant::now(),
+            rows: 0,
+            rows_affected: 0,
+            failed: false,
+        })
+    }
+
+    pub(crate) fn fetch_optional<'e>(
+        &self,
+        sql: &'e str,
+        pool: &PgPool,
+        future: BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>,
+    ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>> {
+        if !self.is_active() {
+            return future;
+        }
+
+        let observer = self.clone();
+        let pool = pool.clone();
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = future.await;
+
+            if let Err(e) = &result {
+                connect::hint_pgbouncer(e);
+                observer.failed(e);
+            }
+            let rows = match &result {
+                Ok(Some(_)) => 1,
+                _ => 0,
+            };
+
+            let elapsed = start.elapsed();
+            observer.record(sql, elapsed, rows, 0, result.is_err());
+            observer.explain(&pool, sql, elapsed);
+            result
+        })
+    }
+
+    fn explain(&self, pool: &PgPool, sql: &str, elapsed: Duration) {
+        if let Some(explain) = &self.explain {
+            explain.observe(pool, sql, elapsed, self.rid.as_deref());
+        }
+    }
+
+    /// Counts a failed query in the circuit breaker, once per query.
+    fn failed(&self, error: &sqlx::Error) {
+        if let Some(breaker) = &self.breaker {
+            breaker.failure(error);
+        }
+    }
+
+    fn record(&self, sql: &str, elapsed: Duration, rows: u64, rows_affected: u64, failed: bool) {
+        if let Some(request_time) = &self.request_time {
+            request_time.add(elapsed, true);
+        }
+
+        if let (Some(breaker), false) = (&self.breaker, failed) {
+            breaker.success();
+        }
+
+        if let Some(capture) = &self.capture {
+            capture.push(CapturedQuery {
+                sql: sql.to_owned(),
+                elapsed,
+                rows,
+                rows_affected,
+                failed,
+            });
+        }
+
+        if let Some((rate, level, slow)) = self.sampled_log {
+            let logged_by_sqlx = slow.map_or(false, |threshold| elapsed >= threshold);
+
+            if !logged_by_sqlx && rand::random::<f64>() < rate {
+                log::log!(
+                    target: "sqlx::query",
+                    level,
+                    "{}rows affected: {}, rows returned: {}, elapsed: {:.3?}, sampled\n\n{}",
+                    rid_prefix(self.rid.as_deref()),
+                    rows_affected,
+                    rows,
+                    elapsed,
+                    sql
+                );
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        if let Some(level) = self.statement_level {
+            trace::statement(
+                level,
+                sql,
+                elapsed,
+                rows,
+                rows_affected,
+                failed,
+                self.rid.as_deref(),
+            );
+        }
+    }
+}
+
+/// Stream of a query's results, recorded once it is dropped.
+struct Observed<'e> {
+    inner: BoxStream<'e, Item>,
+    observer: QueryObserver,
+    pool: PgPool,
+    sql: &'e str,
+    start: Instant,
+    rows: u64,
+    rows_affected: u64,
+    failed: bool,
+}
+
+impl Stream for Observed<'_> {
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Item>> {
+        let this = self.get_mut();
+        let item = this.inner.as_mut().poll_next(cx);
+
+        match &item {
+            Poll::Ready(Some(Ok(Either::Left(done)))) => this.rows_affected += done.rows_affected(),
+            Poll::Ready(Some(Ok(Either::Right(_)))) => this.rows += 1,
+            Poll::Ready(Some(Err(e))) => {
+                connect::hint_pgbouncer(e);
+
+                if !this.failed {
+                    this.observer.failed(e);
+                }
+
+                this.failed = true
+            }
+            _ => {}
+        }
+
+        item
+    }
+}
+
+impl Drop for Observed<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+
+        self.observer.record(
+            self.sql,
+            elapsed,
+            self.rows,
+            self.rows_affected,
+            self.failed,
+        );
+        self.observer.explain(&self.pool, self.sql, elapsed);
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) mod trace {
+    use std::time::Duration;
+    use tracing::{Level, Span};
+
+    /// `tracing` needs the level of a callsite at compile time, so pick the callsite by level.
+    macro_rules! at_level {
+        ($level:expr, $macro:ident!($($args:tt)*)) => {
+            match $level {
+                Level::ERROR => tracing::$macro!(Level::ERROR, $($args)*),
+                Level::WARN => tracing::$macro!(Level::WARN, $($args)*),
+                Level::INFO => tracing::$macro!(Level::INFO, $($args)*),
+                Level::DEBUG => tracing::$macro!(Level::DEBUG, $($args)*),
+                _ => tracing::$macro!(Level::TRACE, $($args)*),
+            }
+        };
+    }
+
+    /// Span around connecting the pool, never carrying the password.
+    ///
+    /// `rid` is the request id, `None` when it isn't logged, which leaves the field out.
+    pub(crate) fn connect(
+        level: Level,
+        host: &str,
+        port: u16,
+        database: &str,
+        user: &str,
+        rid: Option<&str>,
+    ) -> Span {
+        at_level!(
+            level,
+            span!(
+                "db.connect",
+                db.system = "postgresql",
+                db.name = database,
+                db.user = user,
+                net.peer.name = host,
+                net.peer.port = port,
+                rid,
+            )
+        )
+    }
+
+    /// Span around a guard acquiring a connection.
+    pub(crate) fn acquire(level: Level, database: &str, rid: Option<&str>) -> Span {
+        at_level!(
+            level,
+            span!(
+                "db.acquire",
+                db.system = "postgresql",
+                db.name = database,
+                rid
+            )
+        )
+    }
+
+    pub(crate) fn statement(
+        level: Level,
+        sql: &str,
+        elapsed: Duration,
+        rows: u64,
+        rows_affected: u64,
+        failed: bool,
+        rid: Option<&str>,
+    ) {
+        at_level!(
+            level,
+            event!(
+                db.statement = sql,
+                elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+                rows,
+                rows_affected,
+                failed,
+                rid,
+                "statement executed"
+            )
+        )
+    }
+}
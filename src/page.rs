@@ -0,0 +1,238 @@
+use crate::{named::DatabaseMarker, session::SQLxPostgres, sql::quote_ident};
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use rocket::FromForm;
+use sqlx::{
+    postgres::{PgRow, Postgres},
+    Decode, Encode, FromRow, Row, Type,
+};
+use std::{fmt, str::FromStr};
+
+/// `per_page` of [`PageParams`] when none is given.
+const DEFAULT_PER_PAGE: u32 = 20;
+
+/// Upper bound for `per_page` of [`PageParams`].
+const MAX_PER_PAGE: u32 = 100;
+
+/// One page of rows, see [`SQLxPostgres::fetch_page`] and [`SQLxPostgres::fetch_after`].
+///
+/// Serializable with the `serde` feature, to be returned as JSON directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "rocket::serde"))]
+pub struct Page<T> {
+    /// Rows of this page.
+    pub items: Vec<T>,
+    /// Rows across all pages, when counted.
+    pub total: Option<i64>,
+    /// Cursor of the next page in keyset mode, `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// Pagination query parameters, `?page=2&per_page=50`, `?cursor=...` or `?sort=name`.
+///
+/// ```rust,ignore
+/// #[get("/users?<params..>")]
+/// async fn users(db: SQLxPostgres, params: PageParams) -> Result<Json<Page<User>>, Status> {
+///     let sort = params.sort(&["name", "created_at"]).unwrap_or("id");
+///     let query = format!("SELECT id, name, created_at FROM users ORDER BY {}, id", sort);
+///     db.fetch_page(&query, params.page(), params.per_page(), true)
+///         .await
+///         .map(Json)
+///         .map_err(|_| Status::InternalServerError)
+/// }
+/// ```
+#[derive(Debug, Clone, Default, FromForm)]
+pub struct PageParams {
+    page: Option<u32>,
+    per_page: Option<u32>,
+    cursor: Option<String>,
+    sort: Option<String>,
+}
+
+impl PageParams {
+    /// The requested page, starting at 1.
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    /// The requested page size, 20 by default and clamped to 1 to 100.
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    /// The cursor returned with the previous page, for [`SQLxPostgres::fetch_after`].
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    /// The requested sort column, if it is one of `allowed`.
+    ///
+    /// The entry of `allowed` is returned, never the parameter itself, so it is safe to
+    /// embed in an `ORDER BY`.
+    pub fn sort<'a>(&self, allowed: &[&'a str]) -> Option<&'a str> {
+        let sort = self.sort.as_deref()?;
+        allowed.iter().copied().find(|column| *column == sort)
+    }
+}
+
+/// A failed [`SQLxPostgres::fetch_after`].
+#[derive(Debug)]
+pub enum PageError {
+    /// Running the query failed.
+    Database(sqlx::Error),
+    /// The cursor was not one returned by a previous page.
+    InvalidCursor,
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageError::Database(e) => write!(f, "fetching the page failed: {}", e),
+            PageError::InvalidCursor => f.write_str("invalid page cursor"),
+        }
+    }
+}
+
+impl std::error::Error for PageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PageError::Database(e) => Some(e),
+            PageError::InvalidCursor => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for PageError {
+    fn from(error: sqlx::Error) -> Self {
+        PageError::Database(error)
+    }
+}
+
+fn encode_cursor(value: &str) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor<C: FromStr>(cursor: &str) -> Result<C, PageError> {
+    let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| PageError::InvalidCursor)?;
+    let value = String::from_utf8(bytes).map_err(|_| PageError::InvalidCursor)?;
+    value.parse().map_err(|_| PageError::InvalidCursor)
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Fetches page `page`, starting at 1, of `per_page` rows of `query` with
+    /// `LIMIT` and `OFFSET`.
+    ///
+    /// `query` should end in an `ORDER BY` for stable pages. With `count` the rows of all
+    /// pages are counted too, in a second query.
+    pub async fn fetch_page<T>(
+        &self,
+        query: &str,
+        page: u32,
+        per_page: u32,
+        count: bool,
+    ) -> Result<Page<T>, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        let per_page = per_page.max(1);
+        let offset = i64::from(page.max(1) - 1) * i64::from(per_page);
+
+        let items = sqlx::query_as(&format!("SELECT * FROM ({}) q LIMIT $1 OFFSET $2", query))
+            .bind(i64::from(per_page))
+            .bind(offset)
+            .fetch_all(self.pool())
+            .await?;
+
+        let total = match count {
+            true => Some(
+                sqlx::query_scalar(&format!("SELECT COUNT(*) FROM ({}) q", query))
+                    .fetch_one(self.pool())
+                    .await?,
+            ),
+            false => None,
+        };
+
+        Ok(Page {
+            items,
+            total,
+            next_cursor: None,
+        })
+    }
+
+    /// Fetches up to `limit` rows of `query` after `cursor`, ordered by `cursor_column`.
+    ///
+    /// `cursor_column` must be a unique column of `query`, e.g. `id`, decoded as `C`. Pass the
+    /// [`Page::next_cursor`] of the previous page as `cursor`, or `None` for the first one.
+    /// Unlike offsets, pages stay consistent while rows are inserted and deep pages cost the
+    /// same as the first. The order of `query` itself is replaced.
+    ///
+    /// ```rust,ignore
+    /// let page: Page<User> = db
+    ///     .fetch_after::<User, i64>("SELECT id, name FROM users", "id", params.cursor(), 50)
+    ///     .await?;
+    /// ```
+    pub async fn fetch_after<T, C>(
+        &self,
+        query: &str,
+        cursor_column: &str,
+        cursor: Option<&str>,
+        limit: u32,
+    ) -> Result<Page<T>, PageError>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+        C: for<'q> Encode<'q, Postgres> + for<'r> Decode<'r, Postgres> + Type<Postgres>,
+        C: FromStr + ToString + Send,
+    {
+        let limit = limit.max(1);
+        let column = quote_ident(cursor_column);
+        let after = cursor.map(decode_cursor::<C>).transpose()?;
+
+        // One row more than asked tells whether there is a next page.
+        let rows = match after {
+            Some(after) => {
+                let sql = format!(
+                    "SELECT * FROM ({}) q WHERE q.{col} > $1 ORDER BY q.{col} LIMIT $2",
+                    query,
+                    col = column
+                );
+                sqlx::query(&sql)
+                    .bind(after)
+                    .bind(i64::from(limit) + 1)
+                    .fetch_all(self.pool())
+                    .await?
+            }
+            None => {
+                let sql = format!("SELECT * FROM ({}) q ORDER BY q.{} LIMIT $1", query, column);
+                sqlx::query(&sql)
+                    .bind(i64::from(limit) + 1)
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        let has_next = rows.len() > limit as usize;
+        let rows = &rows[..rows.len().min(limit as usize)];
+
+        let next_cursor = match (has_next, rows.last()) {
+            (true, Some(last)) => {
+                let value: C = last.try_get(cursor_column)?;
+                Some(encode_cursor(&value.to_string()))
+            }
+            _ => None,
+        };
+
+        let items = rows
+            .iter()
+            .map(T::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Page {
+            items,
+            total: None,
+            next_cursor,
+        })
+    }
+}
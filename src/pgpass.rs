@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+/// Location of the password file following libpq: `PGPASSFILE`, then `~/.pgpass`.
+pub(crate) fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PGPASSFILE") {
+        return Some(PathBuf::from(path));
+    }
+
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".pgpass"))
+}
+
+/// Finds the password of the first line matching the connection, `*` matching anything.
+///
+/// Malformed lines are skipped with a warning, a missing file or no match returns `None`.
+pub(crate) fn lookup(
+    path: &Path,
+    host: &str,
+    port: u16,
+    database: &str,
+    username: &str,
+) -> Option<String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            log::debug!(
+                "SQLxPostgres: could not read pgpass file `{}`: {}",
+                path.display(),
+                e
+            );
+            return None;
+        }
+    };
+
+    warn_on_loose_permissions(path);
+
+    let port = port.to_string();
+    let wanted = [host, &port[..], database, username];
+
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = split_line(line);
+
+        if fields.len() != 5 {
+            log::warn!(
+                "SQLxPostgres: skipping malformed line {} of pgpass file `{}`",
+                index + 1,
+                path.display()
+            );
+            continue;
+        }
+
+        let matches = fields
+            .iter()
+            .zip(wanted.iter())
+            .all(|(field, wanted)| field == "*" || field == wanted);
+
+        if matches {
+            return Some(fields[4].clone());
+        }
+    }
+
+    log::debug!(
+        "SQLxPostgres: no pgpass entry for {}:{}:{}:{}, using an empty password",
+        host,
+        port,
+        database,
+        username
+    );
+    None
+}
+
+/// Splits a line on `:`, where `\:` and `\\` escape a literal colon and backslash.
+fn split_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+
+    fields
+}
+
+#[cfg(unix)]
+fn warn_on_loose_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            log::warn!(
+                "SQLxPostgres: pgpass file `{}` has group or world access; permissions should be u=rw (0600) or less",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_on_loose_permissions(_path: &Path) {}
@@ -0,0 +1,337 @@
+use crate::{
+    named::{self, DefaultDatabase},
+    session::{SQLxPostgres, SQLxPostgresRef, SQLxPostgresReplica},
+    DatabaseMarker, PoolGuardError,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use futures::future::BoxFuture;
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{FromRequest, Request},
+    serde::de::DeserializeOwned,
+    Ignite, Rocket, Sentinel,
+};
+use sqlx::{
+    postgres::{PgArguments, PgPool, PgRow, Postgres},
+    query::Query,
+    types::JsonValue,
+    Column, Row, TypeInfo,
+};
+use std::{borrow::Cow, fmt, ops::Deref, sync::Arc};
+
+/// A bind parameter of a [`DbQuery`], typed so `None` binds a typed `NULL`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbValue {
+    /// Bound as `bool`.
+    Bool(Option<bool>),
+    /// Bound as `int8`, which Postgres compares with and assigns to the smaller integers.
+    Int(Option<i64>),
+    /// Bound as `float8`.
+    Float(Option<f64>),
+    /// Bound as `text`.
+    Text(Option<String>),
+    /// Bound as `jsonb`.
+    Json(Option<JsonValue>),
+    /// Bound as `timestamptz`.
+    Timestamp(Option<DateTime<Utc>>),
+}
+
+macro_rules! impl_db_value {
+    ($variant:ident, $ty:ty, $convert:expr) => {
+        impl From<$ty> for DbValue {
+            fn from(value: $ty) -> Self {
+                DbValue::$variant(Some($convert(value)))
+            }
+        }
+
+        impl From<Option<$ty>> for DbValue {
+            fn from(value: Option<$ty>) -> Self {
+                DbValue::$variant(value.map($convert))
+            }
+        }
+    };
+}
+
+impl_db_value!(Bool, bool, |value| value);
+impl_db_value!(Int, i16, i64::from);
+impl_db_value!(Int, i32, i64::from);
+impl_db_value!(Int, i64, |value| value);
+impl_db_value!(Float, f32, f64::from);
+impl_db_value!(Float, f64, |value| value);
+impl_db_value!(Text, String, |value| value);
+impl_db_value!(Text, &str, str::to_owned);
+impl_db_value!(Json, JsonValue, |value| value);
+impl_db_value!(Timestamp, DateTime<Utc>, |value| value);
+
+/// SQL text and bind parameters run through a [`PgExecutorProvider`], the provider's
+/// counterpart of `sqlx::query`.
+///
+/// ```rust,ignore
+/// let users: Vec<User> = DbQuery::new("SELECT id, name FROM users WHERE team = $1")
+///     .bind(team)
+///     .fetch_all(&db)
+///     .await?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbQuery {
+    sql: Cow<'static, str>,
+    binds: Vec<DbValue>,
+}
+
+impl DbQuery {
+    /// A query of `sql` without parameters yet.
+    pub fn new(sql: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            sql: sql.into(),
+            binds: Vec::new(),
+        }
+    }
+
+    /// Binds the next parameter, `$1` first.
+    pub fn bind(mut self, value: impl Into<DbValue>) -> Self {
+        self.binds.push(value.into());
+        self
+    }
+
+    /// The SQL text, as written.
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// The bound parameters, in order.
+    pub fn binds(&self) -> &[DbValue] {
+        &self.binds
+    }
+
+    /// Runs the query, returning the number of rows affected.
+    pub async fn execute<P>(self, db: &P) -> Result<u64, sqlx::Error>
+    where
+        P: PgExecutorProvider + ?Sized,
+    {
+        db.execute_query(self).await
+    }
+
+    /// Runs the query, deserializing every row into `T` by column name.
+    pub async fn fetch_all<T, P>(self, db: &P) -> Result<Vec<T>, sqlx::Error>
+    where
+        T: DeserializeOwned,
+        P: PgExecutorProvider + ?Sized,
+    {
+        db.fetch_rows(self)
+            .await?
+            .into_iter()
+            .map(deserialize_row)
+            .collect()
+    }
+
+    /// Runs the query, deserializing the first row, failing with
+    /// [`sqlx::Error::RowNotFound`] when there is none.
+    pub async fn fetch_one<T, P>(self, db: &P) -> Result<T, sqlx::Error>
+    where
+        T: DeserializeOwned,
+        P: PgExecutorProvider + ?Sized,
+    {
+        self.fetch_optional(db)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Runs the query, deserializing the first row if there is one.
+    pub async fn fetch_optional<T, P>(self, db: &P) -> Result<Option<T>, sqlx::Error>
+    where
+        T: DeserializeOwned,
+        P: PgExecutorProvider + ?Sized,
+    {
+        match db.fetch_rows(self).await?.into_iter().next() {
+            Some(row) => deserialize_row(row).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The sqlx query binding the parameters.
+    fn to_sqlx(&self) -> Query<'_, Postgres, PgArguments> {
+        let mut query = sqlx::query(&self.sql);
+
+        for value in &self.binds {
+            query = match value {
+                DbValue::Bool(value) => query.bind(*value),
+                DbValue::Int(value) => query.bind(*value),
+                DbValue::Float(value) => query.bind(*value),
+                DbValue::Text(value) => query.bind(value.as_deref()),
+                DbValue::Json(value) => query.bind(value.clone()),
+                DbValue::Timestamp(value) => query.bind(*value),
+            };
+        }
+
+        query
+    }
+}
+
+fn deserialize_row<T: DeserializeOwned>(row: JsonValue) -> Result<T, sqlx::Error> {
+    T::deserialize(row).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+/// Something [`DbQuery`] runs on, so handler logic can be unit tested against
+/// [`crate::MockDb`] instead of a running Postgres.
+///
+/// Implemented by the pool guards, [`SQLxPostgres`], [`SQLxPostgresReplica`] and
+/// [`SQLxPostgresRef`], and by `PgPool`, all running the query on the pool. Rows travel as
+/// JSON objects keyed by column name; columns of types other than booleans, integers, floats,
+/// text, JSON and the date and time types fail to decode, cast them to `text` in the query.
+/// The transaction guards need `&mut` to run a query and don't implement it.
+///
+/// ```rust,ignore
+/// async fn active_users(db: &dyn PgExecutorProvider) -> Result<Vec<User>, sqlx::Error> {
+///     DbQuery::new("SELECT id, name FROM users WHERE active").fetch_all(db).await
+/// }
+/// ```
+pub trait PgExecutorProvider: Send + Sync {
+    /// Runs `query`, returning the number of rows affected.
+    fn execute_query(&self, query: DbQuery) -> BoxFuture<'_, Result<u64, sqlx::Error>>;
+
+    /// Runs `query`, returning its rows as JSON objects keyed by column name.
+    fn fetch_rows(&self, query: DbQuery) -> BoxFuture<'_, Result<Vec<JsonValue>, sqlx::Error>>;
+}
+
+/// Implements [`PgExecutorProvider`] for a type whose reference is an sqlx executor.
+macro_rules! impl_provider {
+    ([$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> PgExecutorProvider for $ty {
+            fn execute_query(&self, query: DbQuery) -> BoxFuture<'_, Result<u64, sqlx::Error>> {
+                Box::pin(async move {
+                    let done = query.to_sqlx().execute(self).await?;
+                    Ok(done.rows_affected())
+                })
+            }
+
+            fn fetch_rows(
+                &self,
+                query: DbQuery,
+            ) -> BoxFuture<'_, Result<Vec<JsonValue>, sqlx::Error>> {
+                Box::pin(async move {
+                    let rows = query.to_sqlx().fetch_all(self).await?;
+                    rows.iter().map(row_to_json).collect()
+                })
+            }
+        }
+    };
+}
+
+impl_provider!([D: DatabaseMarker] SQLxPostgres<D>);
+impl_provider!([] SQLxPostgresReplica);
+impl_provider!(['r] SQLxPostgresRef<'r>);
+impl_provider!([] PgPool);
+
+/// Decodes a row into a JSON object keyed by column name.
+fn row_to_json(row: &PgRow) -> Result<JsonValue, sqlx::Error> {
+    row.columns()
+        .iter()
+        .map(|column| {
+            let index = column.ordinal();
+
+            let value = match column.type_info().name() {
+                "BOOL" => row.try_get::<Option<bool>, _>(index)?.map(JsonValue::from),
+                "INT2" => row.try_get::<Option<i16>, _>(index)?.map(JsonValue::from),
+                "INT4" => row.try_get::<Option<i32>, _>(index)?.map(JsonValue::from),
+                "INT8" => row.try_get::<Option<i64>, _>(index)?.map(JsonValue::from),
+                "FLOAT4" => row.try_get::<Option<f32>, _>(index)?.map(JsonValue::from),
+                "FLOAT8" => row.try_get::<Option<f64>, _>(index)?.map(JsonValue::from),
+                "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => row
+                    .try_get::<Option<String>, _>(index)?
+                    .map(JsonValue::from),
+                "JSON" | "JSONB" => row.try_get::<Option<JsonValue>, _>(index)?,
+                "TIMESTAMPTZ" => row
+                    .try_get::<Option<DateTime<Utc>>, _>(index)?
+                    .map(|value| JsonValue::from(value.to_rfc3339())),
+                "TIMESTAMP" => row
+                    .try_get::<Option<NaiveDateTime>, _>(index)?
+                    .map(|value| value.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+                    .map(JsonValue::from),
+                "DATE" => row
+                    .try_get::<Option<NaiveDate>, _>(index)?
+                    .map(|value| JsonValue::from(value.to_string())),
+                "TIME" => row
+                    .try_get::<Option<NaiveTime>, _>(index)?
+                    .map(|value| JsonValue::from(value.to_string())),
+                other => {
+                    return Err(sqlx::Error::ColumnDecode {
+                        index: column.name().to_owned(),
+                        source: format!("type {} is not supported, cast it to text", other).into(),
+                    })
+                }
+            };
+
+            Ok((column.name().to_owned(), value.unwrap_or(JsonValue::Null)))
+        })
+        .collect::<Result<_, sqlx::Error>>()
+        .map(JsonValue::Object)
+}
+
+/// Request guard handing out the provider of the request: the [`crate::MockDb`] managed by a
+/// test Rocket, otherwise the [`SQLxPostgres`] guard of the unnamed fairing.
+///
+/// Derefs to `dyn PgExecutorProvider`, so handlers written against it run unchanged on the
+/// mock. Fails like [`SQLxPostgres`].
+///
+/// ```rust,ignore
+/// #[get("/users")]
+/// async fn users(db: DbProvider) -> Result<Json<Vec<User>>, Status> {
+///     DbQuery::new("SELECT id, name FROM users")
+///         .fetch_all(&*db)
+///         .await
+///         .map(Json)
+///         .map_err(|_| Status::InternalServerError)
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DbProvider(Arc<dyn PgExecutorProvider>);
+
+impl DbProvider {
+    /// Wraps any provider, e.g. to hand a mock to code taking a `DbProvider`.
+    pub fn new(provider: impl PgExecutorProvider + 'static) -> Self {
+        Self(Arc::new(provider))
+    }
+}
+
+impl Deref for DbProvider {
+    type Target = dyn PgExecutorProvider;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for DbProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DbProvider").field(&"<provider>").finish()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DbProvider {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        #[cfg(feature = "test-util")]
+        if let Some(mock) = request.rocket().state::<crate::MockDb>() {
+            return Outcome::Success(DbProvider::new(mock.clone()));
+        }
+
+        <SQLxPostgres<DefaultDatabase>>::from_request(request)
+            .await
+            .map(DbProvider::new)
+    }
+}
+
+/// Aborts launch when neither the fairing is attached nor a [`crate::MockDb`] is managed.
+impl Sentinel for DbProvider {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        #[cfg(feature = "test-util")]
+        if rocket.state::<crate::MockDb>().is_some() {
+            return false;
+        }
+
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "DbProvider")
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{session::SqlxPostgresConfig, slot};
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sigv4::{
+    http_request::{
+        sign, SignableBody, SignableRequest, SignatureLocation, SigningParams, SigningSettings,
+    },
+    sign::v4,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rocket::{tokio, Shutdown};
+use sqlx::postgres::{PgConnectOptions, PgPool};
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+/// How long a token is valid, the most RDS accepts.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// How often the pool is rebuilt with a fresh token, well within [`TOKEN_LIFETIME`].
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Characters kept as is in query values, the unreserved set of RFC 3986.
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Region and credentials used to sign RDS IAM auth tokens.
+#[derive(Clone)]
+pub(crate) struct RdsIam {
+    region: String,
+    credentials: Option<SharedCredentialsProvider>,
+}
+
+impl RdsIam {
+    pub(crate) fn new(region: String, credentials: Option<SharedCredentialsProvider>) -> Self {
+        Self {
+            region,
+            credentials,
+        }
+    }
+
+    /// Replaces the password of `opts` with a fresh token for its host, port and user.
+    pub(crate) async fn authenticate(
+        &self,
+        opts: PgConnectOptions,
+    ) -> Result<PgConnectOptions, sqlx::Error> {
+        let token = self
+            .token(opts.get_host(), opts.get_port(), opts.get_username())
+            .await
+            .map_err(|e| {
+                sqlx::Error::Configuration(
+                    format!("generating the RDS IAM auth token failed: {}", e).into(),
+                )
+            })?;
+
+        Ok(opts.password(&token))
+    }
+
+    async fn token(&self, host: &str, port: u16, user: &str) -> Result<String, String> {
+        let provider = match &self.credentials {
+            Some(provider) => provider.clone(),
+            None => aws_config::load_defaults(aws_config::BehaviorVersion::latest())
+                .await
+                .credentials_provider()
+                .ok_or("no AWS credentials provider is configured")?,
+        };
+        let credentials = provider
+            .provide_credentials()
+            .await
+            .map_err(|e| e.to_string())?;
+        let identity = credentials.into();
+
+        let mut settings = SigningSettings::default();
+        settings.expires_in = Some(TOKEN_LIFETIME);
+        settings.signature_location = SignatureLocation::QueryParams;
+
+        let params: SigningParams<'_> = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name("rds-db")
+            .time(SystemTime::now())
+            .settings(settings)
+            .build()
+            .map_err(|e| e.to_string())?
+            .into();
+
+        let mut token = format!(
+            "{}:{}/?Action=connect&DBUser={}",
+            host,
+            port,
+            utf8_percent_encode(user, QUERY_VALUE)
+        );
+        let url = format!("https://{}", token);
+        let request =
+            SignableRequest::new("GET", &url, std::iter::empty(), SignableBody::Bytes(&[]))
+                .map_err(|e| e.to_string())?;
+        let (instructions, _) = sign(request, &params)
+            .map_err(|e| e.to_string())?
+            .into_parts();
+
+        for (name, value) in instructions.params() {
+            token.push('&');
+            token.push_str(name);
+            token.push('=');
+            token.extend(utf8_percent_encode(&value, QUERY_VALUE));
+        }
+
+        Ok(token)
+    }
+}
+
+impl fmt::Debug for RdsIam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RdsIam")
+            .field("region", &self.region)
+            .field("credentials", &self.credentials.is_some())
+            .finish()
+    }
+}
+
+/// Rebuilds the pool in `slot` with a fresh token until shutdown.
+///
+/// sqlx 0.6 reuses a pool's connect options for every new connection, so once the token
+/// expires the pool could no longer grow. Swapping in a new pool before that keeps new
+/// connections authenticating. The old pool is closed after its drain timeout, until then
+/// clones of it keep their open connections but can't open new ones once its token expired,
+/// see [`crate::PgPoolSlot`].
+pub(crate) async fn refresh(
+    slot: Arc<RwLock<PgPool>>,
+    config: SqlxPostgresConfig,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+            _ = &mut shutdown => return,
+        }
+
+        let result = match config.connect_options() {
+            Ok(opts) => config.connect_with(opts).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(new_pool) => {
+                slot::replace(&slot, new_pool, config.drain_timeout);
+                log::debug!("SQLxPostgres: pool rebuilt with a fresh RDS IAM token");
+            }
+            // The current pool keeps serving with its open connections, retried next tick.
+            Err(e) => log::warn!(
+                "SQLxPostgres: rebuilding the pool with a fresh RDS IAM token failed: {}",
+                e
+            ),
+        }
+    }
+}
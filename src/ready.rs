@@ -0,0 +1,140 @@
+use crate::{connect, Error};
+use rocket::tokio::{self, sync::watch};
+use sqlx::postgres::PgPool;
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Delay before the first connection attempt of a lazy pool is retried.
+const LAZY_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+enum State {
+    Pending,
+    Ready(Arc<RwLock<PgPool>>),
+    Failed(String),
+}
+
+/// Resolves once the pool of a fairing is connected and verified, for code that can't
+/// rely on fairing order.
+///
+/// Managed by the unnamed fairing, and returned by [`crate::SqlxPostgresFairing::ready`]
+/// before attaching, for any fairing, so fairings attached earlier can hold one too. A
+/// pool passed in with the fairing resolves right away, a lazy one once its first
+/// connection succeeded. When ignite gives up it resolves to an error instead.
+///
+/// Ignite fairings run one after the other: one attached before the database fairing
+/// must not wait for it during its own ignite, spawn a task or wait at liftoff instead.
+///
+/// ```rust,ignore
+/// let ready = rocket.state::<DbReady>().unwrap();
+/// let pool = ready.wait(Duration::from_secs(30)).await?;
+/// let flags = load_feature_flags(&pool).await?;
+/// ```
+#[derive(Clone)]
+pub struct DbReady {
+    sender: Arc<watch::Sender<State>>,
+    receiver: watch::Receiver<State>,
+}
+
+impl DbReady {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = watch::channel(State::Pending);
+
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Whether the pool is ready now.
+    pub fn is_ready(&self) -> bool {
+        matches!(*self.receiver.borrow(), State::Ready(_))
+    }
+
+    /// Waits until the pool is ready and returns it.
+    ///
+    /// Fails with [`Error::NotReady`] when ignite gave up, and with [`Error::Timeout`] when
+    /// `timeout` passes first.
+    pub async fn wait(&self, timeout: Duration) -> crate::Result<PgPool> {
+        tokio::time::timeout(timeout, self.pool())
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Waits until the pool is ready, however long it takes.
+    pub(crate) async fn pool(&self) -> crate::Result<PgPool> {
+        let mut receiver = self.receiver.clone();
+
+        loop {
+            match &*receiver.borrow() {
+                State::Ready(slot) => {
+                    return Ok(slot.read().unwrap_or_else(|e| e.into_inner()).clone())
+                }
+                State::Failed(reason) => return Err(Error::NotReady(reason.clone())),
+                State::Pending => {}
+            }
+
+            // The sender lives as long as `self`, so this only fails once it is gone.
+            if receiver.changed().await.is_err() {
+                return Err(Error::NotReady(
+                    "the database fairing was dropped".to_owned(),
+                ));
+            }
+        }
+    }
+
+    /// Resolves to the pool in `slot`, whose current pool is handed out from then on.
+    pub(crate) fn resolve(&self, slot: Arc<RwLock<PgPool>>) {
+        self.sender.send_replace(State::Ready(slot));
+    }
+
+    /// Resolves to an error, unless the pool is ready already.
+    pub(crate) fn fail(&self, reason: impl Into<String>) {
+        let reason = reason.into();
+
+        self.sender.send_if_modified(|state| match state {
+            State::Ready(_) => false,
+            _ => {
+                *state = State::Failed(reason);
+                true
+            }
+        });
+    }
+
+    /// Resolves once a connection of the lazy pool in `slot` succeeded, retrying with
+    /// backoff.
+    pub(crate) fn resolve_lazy(&self, slot: Arc<RwLock<PgPool>>, timeout: Duration) {
+        let ready = self.clone();
+
+        tokio::spawn(async move {
+            let mut attempt = 0;
+
+            loop {
+                let pool = slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+                if pool.is_closed() {
+                    return ready.fail("the database pool was closed");
+                }
+
+                match connect::ping(&pool, timeout).await {
+                    Ok(_) => return ready.resolve(slot),
+                    Err(e) => log::debug!("SQLxPostgres: lazy pool not ready yet: {}", e),
+                }
+
+                tokio::time::sleep(connect::backoff(LAZY_RETRY_BACKOFF, attempt)).await;
+                attempt += 1;
+            }
+        });
+    }
+}
+
+impl fmt::Debug for DbReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DbReady")
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{
+    health,
+    session::SQLxPostgresPool,
+    status::{self, PoolStatus, RateLimit},
+};
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use rocket::{tokio, Shutdown};
+use sqlx::postgres::PgPool;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How often replicas are probed when their config sets no health probe interval.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum time between two warnings about reads falling back to the primary.
+const FALLBACK_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether the server is a standby and how far its replay is behind, in seconds.
+///
+/// A standby that replayed everything it received is not behind, however old its last
+/// replayed transaction, so an idle primary doesn't make it look lagging.
+const LAG: &str = "\
+SELECT pg_is_in_recovery(), \
+    CASE WHEN pg_last_wal_receive_lsn() IS NOT DISTINCT FROM pg_last_wal_replay_lsn() THEN 0 \
+    ELSE EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp())::float8 END";
+
+/// Stored as the lag while it is unknown.
+const UNKNOWN_LAG: u64 = u64::MAX;
+
+/// How [`crate::SQLxPostgresReplica`] picks one of several replicas, see
+/// [`crate::SqlxPostgresFairing::with_replica_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicaStrategy {
+    /// Each request takes the next available replica in turn.
+    RoundRobin,
+    /// Each request takes the available replica with the fewest connections checked out.
+    FewestInUse,
+}
+
+impl Default for ReplicaStrategy {
+    fn default() -> Self {
+        ReplicaStrategy::RoundRobin
+    }
+}
+
+/// Snapshot of one replica, see [`SQLxPostgresReplicas::status`].
+///
+/// Serializable with the `serde` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "rocket::serde"))]
+pub struct ReplicaStatus {
+    /// Host and port of the replica.
+    pub name: String,
+    /// Whether the replica is handed out, `false` while its health probe fails or it lags
+    /// more than [`crate::SqlxPostgresFairing::with_replica_max_lag`].
+    pub available: bool,
+    /// Replay lag at the last probe, `None` unless a maximum lag is set, before the first
+    /// measurement, or when the replica replayed nothing yet.
+    pub lag: Option<Duration>,
+    /// Whether the replica is behind by more than the maximum lag.
+    pub lagging: bool,
+    /// Whether the server is not in recovery, i.e. a primary configured as a replica.
+    pub is_primary: bool,
+    /// Connections of its pool.
+    pub pool: PoolStatus,
+}
+
+/// Health of a replica, shared with its probe task.
+#[derive(Debug, Default)]
+struct ReplicaState {
+    healthy: AtomicBool,
+    lagging: AtomicBool,
+    primary: AtomicBool,
+    /// In milliseconds, [`UNKNOWN_LAG`] when unknown.
+    lag_ms: AtomicU64,
+}
+
+impl ReplicaState {
+    fn lag(&self) -> Option<Duration> {
+        match self.lag_ms.load(Ordering::Relaxed) {
+            UNKNOWN_LAG => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+}
+
+struct Replica {
+    name: String,
+    store: SQLxPostgresPool,
+    state: Arc<ReplicaState>,
+}
+
+impl Replica {
+    fn is_available(&self) -> bool {
+        self.state.healthy.load(Ordering::Relaxed)
+            && !self.state.lagging.load(Ordering::Relaxed)
+            && !self.store.client().is_closed()
+    }
+}
+
+/// Managed replicas of the unnamed database, attached with
+/// [`crate::SqlxPostgresFairing::with_replica`].
+///
+/// Each replica is probed in the background; one whose probe fails, or that lags too far
+/// behind with [`crate::SqlxPostgresFairing::with_replica_max_lag`], is skipped until a probe
+/// succeeds again. Request it with `&State<SQLxPostgresReplicas>` to report on them:
+///
+/// ```rust,ignore
+/// #[get("/replicas")]
+/// fn replicas(replicas: &State<SQLxPostgresReplicas>) -> Json<Vec<ReplicaStatus>> {
+///     Json(replicas.status())
+/// }
+/// ```
+pub struct SQLxPostgresReplicas {
+    replicas: Vec<Replica>,
+    strategy: ReplicaStrategy,
+    max_lag: Option<Duration>,
+    next: AtomicUsize,
+    fallback_log: RateLimit,
+}
+
+impl SQLxPostgresReplicas {
+    /// Takes the replica pools with whether they connected at ignite, those that did not are
+    /// skipped until their first successful probe.
+    pub(crate) fn new(
+        replicas: Vec<(String, SQLxPostgresPool, bool)>,
+        strategy: ReplicaStrategy,
+        max_lag: Option<Duration>,
+    ) -> Self {
+        Self {
+            replicas: replicas
+                .into_iter()
+                .map(|(name, store, connected)| Replica {
+                    name,
+                    store,
+                    state: Arc::new(ReplicaState {
+                        healthy: AtomicBool::new(connected),
+                        lag_ms: AtomicU64::new(UNKNOWN_LAG),
+                        ..ReplicaState::default()
+                    }),
+                })
+                .collect(),
+            strategy,
+            max_lag,
+            next: AtomicUsize::new(0),
+            fallback_log: RateLimit::default(),
+        }
+    }
+
+    /// Snapshot of every replica, in the order they were attached.
+    pub fn status(&self) -> Vec<ReplicaStatus> {
+        self.replicas
+            .iter()
+            .map(|replica| ReplicaStatus {
+                name: replica.name.clone(),
+                available: replica.is_available(),
+                lag: replica.state.lag(),
+                lagging: replica.state.lagging.load(Ordering::Relaxed),
+                is_primary: replica.state.primary.load(Ordering::Relaxed),
+                pool: replica.store.status(),
+            })
+            .collect()
+    }
+
+    /// The replica the next read goes to, `None` with a warning when every one is down.
+    pub(crate) fn pick(&self) -> Option<&SQLxPostgresPool> {
+        let picked = match self.strategy {
+            ReplicaStrategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let count = self.replicas.len();
+
+                (0..count)
+                    .map(|offset| &self.replicas[(start + offset) % count])
+                    .find(|replica| replica.is_available())
+            }
+            ReplicaStrategy::FewestInUse => self
+                .replicas
+                .iter()
+                .filter(|replica| replica.is_available())
+                .min_by_key(|replica| replica.store.status().in_use),
+        };
+
+        if picked.is_none() {
+            if let Some(suppressed) = self.fallback_log.check(FALLBACK_LOG_INTERVAL) {
+                log::warn!(
+                    "SQLxPostgres: no replica available, reading from the primary ({} similar warnings suppressed)",
+                    suppressed
+                );
+            }
+        }
+
+        picked.map(|replica| &replica.store)
+    }
+
+    /// Every replica pool, to close them at shutdown.
+    pub(crate) fn stores(&self) -> impl Iterator<Item = &SQLxPostgresPool> {
+        self.replicas.iter().map(|replica| &replica.store)
+    }
+
+    /// Starts probing the replicas, measuring their lag, and logging and exporting their
+    /// status when configured, until shutdown.
+    pub(crate) fn spawn(&self, shutdown: Shutdown) {
+        for replica in &self.replicas {
+            let config = replica.store.config.clone();
+            let interval = config.health_probe_interval.unwrap_or(PROBE_INTERVAL);
+            let slot = replica.store.handle();
+            let state = replica.state.clone();
+            let name = replica.name.clone();
+            let max_lag = self.max_lag;
+            #[cfg(feature = "metrics")]
+            let metrics = replica.store.metrics.clone();
+            let mut probe_shutdown = shutdown.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = &mut probe_shutdown => return,
+                    }
+
+                    let pool = slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+                    let result = health::probe(&pool, &config).await;
+
+                    if let (Ok(_), Some(max_lag)) = (&result, max_lag) {
+                        match tokio::time::timeout(config.health_timeout, lag(&pool)).await {
+                            Ok(Ok((in_recovery, lag))) => {
+                                #[cfg(feature = "metrics")]
+                                if let (Some(metrics), Some(lag)) = (&metrics, lag) {
+                                    metrics.record_replica_lag(lag);
+                                }
+
+                                state.record_lag(&name, in_recovery, lag, max_lag);
+                            }
+                            Ok(Err(e)) => log::warn!(
+                                "SQLxPostgres: measuring the lag of replica {} failed: {}",
+                                name,
+                                e
+                            ),
+                            Err(_) => log::warn!(
+                                "SQLxPostgres: measuring the lag of replica {} timed out",
+                                name
+                            ),
+                        }
+                    }
+
+                    match (
+                        state.healthy.swap(result.is_ok(), Ordering::Relaxed),
+                        result,
+                    ) {
+                        (true, Err(e)) => log::warn!(
+                            "SQLxPostgres: replica {} failed its health probe, skipping it: {}",
+                            name,
+                            e.message()
+                        ),
+                        (false, Ok(_)) => {
+                            log::info!("SQLxPostgres: replica {} available again", name)
+                        }
+                        _ => {}
+                    }
+                }
+            });
+
+            if let Some(interval) = replica.store.config.metrics_log_interval {
+                let name = format!("replica {}", replica.name);
+                let status = replica.store.status_source();
+                tokio::spawn(status::log_status(name, interval, status, shutdown.clone()));
+            }
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = replica.store.metrics.clone() {
+                tokio::spawn(metrics.refresh(replica.store.status_source(), shutdown.clone()));
+            }
+        }
+    }
+}
+
+impl ReplicaState {
+    /// Stores a lag measurement, logging when the replica crosses `max_lag` either way and
+    /// when the server turns out to be a primary.
+    fn record_lag(&self, name: &str, in_recovery: bool, lag: Option<Duration>, max_lag: Duration) {
+        let was_primary = self.primary.swap(!in_recovery, Ordering::Relaxed);
+
+        // A primary is never behind, its config is what is wrong.
+        if !in_recovery {
+            if !was_primary {
+                log::error!(
+                    "SQLxPostgres: replica {} is not in recovery, it is a primary; check the replica config",
+                    name
+                );
+            }
+
+            self.lag_ms.store(UNKNOWN_LAG, Ordering::Relaxed);
+            self.lagging.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        let lag_ms = lag.map_or(UNKNOWN_LAG, |lag| lag.as_millis() as u64);
+        self.lag_ms.store(lag_ms, Ordering::Relaxed);
+
+        // An unknown lag, before the first replayed transaction, keeps the replica as it was.
+        let lag = match lag {
+            Some(lag) => lag,
+            None => return,
+        };
+
+        let lagging = lag > max_lag;
+
+        match (self.lagging.swap(lagging, Ordering::Relaxed), lagging) {
+            (false, true) => log::warn!(
+                "SQLxPostgres: replica {} is {:?} behind, more than {:?}, skipping it",
+                name,
+                lag,
+                max_lag
+            ),
+            (true, false) => log::info!(
+                "SQLxPostgres: replica {} caught up to {:?} behind, available again",
+                name,
+                lag
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Whether the server behind `pool` is in recovery, and its replay lag.
+async fn lag(pool: &PgPool) -> Result<(bool, Option<Duration>), sqlx::Error> {
+    let (in_recovery, seconds): (bool, Option<f64>) = sqlx::query_as(LAG).fetch_one(pool).await?;
+    let lag = seconds.map(|seconds| Duration::from_secs_f64(seconds.max(0.0)));
+    Ok((in_recovery, lag))
+}
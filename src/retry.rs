@@ -0,0 +1,139 @@
+use crate::{
+    connect, guards::isolation::IsolationLevel, named::DatabaseMarker, session::SQLxPostgres,
+};
+use futures::future::BoxFuture;
+use sqlx::{Executor, Postgres, Transaction};
+use std::time::Duration;
+
+type TxFuture<'t, T> = BoxFuture<'t, Result<T, sqlx::Error>>;
+
+/// SQLSTATE `serialization_failure`.
+const SERIALIZATION_FAILURE: &str = "40001";
+/// SQLSTATE `deadlock_detected`.
+const DEADLOCK_DETECTED: &str = "40P01";
+
+/// How [`SQLxPostgres::retry`] retries serialization failures and deadlocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    isolation: Option<IsolationLevel>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            isolation: Some(IsolationLevel::Serializable),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Five attempts starting at a 10ms backoff, in `SERIALIZABLE` transactions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total attempts including the first one, at least one.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry, doubled for each one after it with jitter.
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Isolation level of each attempt's transaction, `None` keeps the server's default.
+    pub fn with_isolation(mut self, isolation: Option<IsolationLevel>) -> Self {
+        self.isolation = isolation;
+        self
+    }
+}
+
+/// Whether an error is a serialization failure or deadlock, worth running again.
+pub(crate) fn is_retryable(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(e) => matches!(
+            e.code().as_deref(),
+            Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED)
+        ),
+        _ => false,
+    }
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    /// Runs `f` in a fresh transaction per attempt, retrying serialization failures and
+    /// deadlocks.
+    ///
+    /// The transaction is committed once `f` returns `Ok`. A commit failing with one of
+    /// those codes is retried too, a successful commit never is. Any other error is returned
+    /// right away, and the last error once the attempts run out.
+    ///
+    /// ```rust,ignore
+    /// #[post("/transfer")]
+    /// async fn transfer(db: SQLxPostgres) -> Result<(), Status> {
+    ///     db.retry(RetryPolicy::new(), |tx| Box::pin(async move {
+    ///         sqlx::query("UPDATE accounts SET balance = balance - 10 WHERE id = 1")
+    ///             .execute(&mut **tx)
+    ///             .await?;
+    ///         sqlx::query("UPDATE accounts SET balance = balance + 10 WHERE id = 2")
+    ///             .execute(&mut **tx)
+    ///             .await?;
+    ///         Ok(())
+    ///     }))
+    ///     .await
+    ///     .map_err(|_| Status::InternalServerError)
+    /// }
+    /// ```
+    pub async fn retry<T, F>(&self, policy: RetryPolicy, mut f: F) -> Result<T, sqlx::Error>
+    where
+        F: for<'t> FnMut(&'t mut Transaction<'static, Postgres>) -> TxFuture<'t, T> + Send,
+        T: Send,
+    {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let error = match self.attempt(policy, &mut f).await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            if !is_retryable(&error) || attempt >= policy.max_attempts {
+                return Err(error);
+            }
+
+            let delay = connect::backoff(policy.initial_backoff, attempt - 1);
+            log::debug!(
+                "SQLxPostgres: transaction attempt {}/{} failed: {}, retrying in {:?}",
+                attempt,
+                policy.max_attempts,
+                error,
+                delay
+            );
+            rocket::tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn attempt<T, F>(&self, policy: RetryPolicy, f: &mut F) -> Result<T, sqlx::Error>
+    where
+        F: for<'t> FnMut(&'t mut Transaction<'static, Postgres>) -> TxFuture<'t, T>,
+    {
+        let mut tx = self.pool().begin().await?;
+
+        if let Some(level) = policy.isolation {
+            tx.execute(level.set_sql()).await?;
+        }
+
+        // Dropping `tx` on error rolls it back when the connection returns to the pool.
+        let value = f(&mut tx).await?;
+        tx.commit().await?;
+        Ok(value)
+    }
+}
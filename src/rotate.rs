@@ -0,0 +1,146 @@
+use crate::{connect, session::SqlxPostgresConfig, slot, ConfigError, Error};
+use rocket::tokio::sync::Mutex;
+use sqlx::postgres::PgPool;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, RwLock,
+};
+
+/// Handle replacing the pool of a fairing without a restart, e.g. when a secrets manager
+/// rotates the database password or to shrink the pool during quiet hours.
+///
+/// Managed for the unnamed fairing and returned by [`crate::SQLxPostgresPools::rotation`]
+/// for named ones. Pools passed in with `from_pool` or shared between fairings can't be
+/// rebuilt from the config and have no handle.
+///
+/// ```rust,ignore
+/// #[post("/admin/rotate", data = "<password>")]
+/// async fn rotate(handle: &State<PoolHandle>, password: String) -> Result<(), Status> {
+///     handle.rotate(password).await.map_err(|_| Status::BadGateway)
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PoolHandle {
+    name: &'static str,
+    slot: Arc<RwLock<PgPool>>,
+    config: SqlxPostgresConfig,
+    rebuilding: Arc<Mutex<()>>,
+}
+
+impl PoolHandle {
+    pub(crate) fn new(
+        name: &'static str,
+        slot: Arc<RwLock<PgPool>>,
+        config: SqlxPostgresConfig,
+    ) -> Self {
+        Self {
+            name,
+            slot,
+            config,
+            rebuilding: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Connects a new pool logging in with `password` and, once a ping went through it,
+    /// swaps it in for the current one.
+    ///
+    /// Guards resolved from then on use the new pool. The old pool is closed after
+    /// [`SqlxPostgresConfig::with_drain_timeout`], until then connections checked out of it
+    /// and `PgPool` clones held elsewhere stay usable. On error the current pool is kept as is.
+    ///
+    /// The password is also used by pools the supervisor or a statement log change rebuild
+    /// later, secondary pools included; those keep their current pool until rotated through
+    /// their own handle.
+    ///
+    /// Concurrent rotations and resizes of the same pool run one after the other.
+    pub async fn rotate(&self, password: impl Into<String>) -> crate::Result<()> {
+        let _rebuilding = self.rebuilding.lock().await;
+        let password = password.into();
+
+        let config = SqlxPostgresConfig {
+            rotated_password: Arc::new(RwLock::new(Some(password.clone()))),
+            ..self.config.clone()
+        };
+
+        self.replace(&config, "rotating the credentials of", || {
+            *self
+                .config
+                .rotated_password
+                .write()
+                .unwrap_or_else(|e| e.into_inner()) = Some(password);
+        })
+        .await?;
+
+        log::info!("SQLxPostgres: rotated the credentials of `{}`", self.name);
+        Ok(())
+    }
+
+    /// Connects a new pool of at most `max_connections` connections and, once a ping went
+    /// through it, swaps it in for the current one, since sqlx can't resize a pool in place.
+    ///
+    /// Like [`PoolHandle::rotate`], guards resolved from then on use the new pool and the old
+    /// one stays open until its drain timeout passed, so for a while both pools hold
+    /// connections. On error the current pool is kept as is. The size is kept by the pools
+    /// rebuilt later, like those of the supervisor, until resized again; `min_connections` is
+    /// clamped to it.
+    ///
+    /// Concurrent rotations and resizes of the same pool run one after the other.
+    ///
+    /// ```rust,ignore
+    /// handle.resize(4).await?;
+    /// ```
+    pub async fn resize(&self, max_connections: u32) -> crate::Result<()> {
+        if max_connections == 0 {
+            return Err(Error::Config(ConfigError::ZeroMaxConnections));
+        }
+
+        let _rebuilding = self.rebuilding.lock().await;
+        let previous = self.config.max_connections();
+
+        let config = SqlxPostgresConfig {
+            resized_max_connections: Arc::new(AtomicU32::new(max_connections)),
+            ..self.config.clone()
+        };
+
+        self.replace(&config, "resizing", || {
+            self.config
+                .resized_max_connections
+                .store(max_connections, Ordering::Relaxed);
+        })
+        .await?;
+
+        log::info!(
+            "SQLxPostgres: resized `{}` from {} to {} max connections",
+            self.name,
+            previous,
+            max_connections
+        );
+        Ok(())
+    }
+
+    /// Connects a pool with `config` and swaps it in once pinged, running `commit` right
+    /// before the swap so later rebuilds use the new settings too.
+    async fn replace(
+        &self,
+        config: &SqlxPostgresConfig,
+        action: &str,
+        commit: impl FnOnce(),
+    ) -> crate::Result<()> {
+        let new_pool = config.connect().await?;
+
+        // A lazy pool connects here, so the new settings are checked either way.
+        if let Err(e) = connect::ping(&new_pool, config.connect_timeout).await {
+            log::warn!("SQLxPostgres: {} `{}` failed: {}", action, self.name, e);
+            new_pool.close().await;
+            return Err(match e {
+                sqlx::Error::PoolTimedOut => Error::Timeout,
+                e => Error::Connect(e),
+            });
+        }
+
+        commit();
+
+        slot::replace(&self.slot, new_pool, config.drain_timeout);
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::ConfigError;
+use std::path::PathBuf;
+
+/// Service files in the order libpq searches them: `PGSERVICEFILE` or
+/// `~/.pg_service.conf`, then `pg_service.conf` in `PGSYSCONFDIR`.
+fn search_paths() -> Vec<PathBuf> {
+    let user = std::env::var_os("PGSERVICEFILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".pg_service.conf"))
+        });
+    let system =
+        std::env::var_os("PGSYSCONFDIR").map(|dir| PathBuf::from(dir).join("pg_service.conf"));
+
+    user.into_iter().chain(system).collect()
+}
+
+/// The `key=value` settings of the `[name]` section, from the first service file defining it.
+///
+/// Missing files are skipped as libpq does, unreadable or malformed ones return an error.
+/// A key given twice keeps its first value.
+pub(crate) fn lookup(name: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let searched = search_paths();
+
+    for path in &searched {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(ConfigError::UnreadableFile {
+                    option: "service",
+                    path: path.clone(),
+                    reason: e.to_string(),
+                })
+            }
+        };
+
+        let syntax_error = |line: usize| ConfigError::UnreadableFile {
+            option: "service",
+            path: path.clone(),
+            reason: format!("syntax error on line {}", line + 1),
+        };
+        let mut section: Option<Vec<(String, String)>> = None;
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| syntax_error(index))?;
+
+                match section {
+                    // The next section ends the one asked for.
+                    Some(settings) => return Ok(settings),
+                    None if header.trim() == name => section = Some(Vec::new()),
+                    None => {}
+                }
+
+                continue;
+            }
+
+            let settings = match section.as_mut() {
+                Some(settings) => settings,
+                None => continue,
+            };
+
+            let (key, value) = line.split_once('=').ok_or_else(|| syntax_error(index))?;
+            let key = key.trim();
+
+            if !settings.iter().any(|(seen, _)| seen == key) {
+                settings.push((key.to_owned(), value.trim().to_owned()));
+            }
+        }
+
+        if let Some(settings) = section {
+            return Ok(settings);
+        }
+    }
+
+    Err(ConfigError::UnknownService {
+        name: name.to_owned(),
+        searched,
+    })
+}
@@ -1,19 +1,223 @@
+use futures::{future::BoxFuture, stream::BoxStream};
 use log::LevelFilter;
 use rocket::{
     fairing::{self, Fairing, Info},
+    figment::{providers::Env, Figment},
     http::Status,
-    outcome::{try_outcome, Outcome},
+    outcome::Outcome,
     request::{FromRequest, Request},
-    Build, Rocket, State,
+    response::Response,
+    serde::{Deserialize, Serialize},
+    Build, Ignite, Orbit, Phase, Rocket, Sentinel,
 };
 use sqlx::{
-    postgres::{PgConnectOptions, PgPool, PgPoolOptions},
-    ConnectOptions,
+    migrate::{Migration, Migrator},
+    pool::PoolConnectionMetadata,
+    postgres::{
+        PgConnectOptions, PgConnection, PgPool, PgPoolOptions, PgQueryResult, PgRow, PgSslMode,
+        PgStatement, PgTypeInfo,
+    },
+    ConnectOptions, Describe, Either, Execute, Executor, Postgres, Transaction,
 };
 
-use std::borrow::Cow;
+#[cfg(feature = "metrics")]
+use crate::metrics::{DatabaseMetrics, PoolMetrics};
+#[cfg(feature = "rds-iam")]
+use crate::rds::{self, RdsIam};
+#[cfg(feature = "sticky-reads")]
+use crate::sticky;
+use crate::{
+    breaker::{CircuitBreaker, CircuitBreakerPolicy},
+    cache::QueryCache,
+    connect::{self, ChannelBinding, TargetSessionAttrs},
+    explain::{ExplainPolicy, Explainer},
+    guards::{self, AcquireTimedOut, AutoTransactionSlot, TransactionAction},
+    health::DbHealth,
+    listen::PgNotifications,
+    maintenance,
+    migrate::{self, KnownMigrations},
+    named::{self, DatabaseMarker, DefaultDatabase, SQLxPostgresPools},
+    observe::{self, QueryCapture, QueryObserver, RequestDbTime},
+    pgpass,
+    ready::DbReady,
+    replica::{ReplicaStrategy, SQLxPostgresReplicas},
+    rotate::PoolHandle,
+    shard::{ShardRouter, ShardRoutes},
+    slot::PgPoolSlot,
+    sql::{escape_startup_value, quote_ident},
+    statement_log,
+    status::{self, PoolStatus, RateLimit, Waiters},
+    supervisor,
+    tasks::DbTasks,
+    tenant::TenantSchemas,
+    watchdog::TransactionWatchdog,
+    ConfigError, Error, PoolGuardError,
+};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    marker::PhantomData,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, AtomicU8, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+mod sources;
+
+/// Sizing and timeouts of a secondary pool, see [`SqlxPostgresConfig::with_secondary_pool`].
+///
+/// Timeouts left unset keep those of the primary pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSettings {
+    /// Most connections the pool opens, 2 by default.
+    pub max_connections: u32,
+    /// Connections kept open while idle, none by default.
+    pub min_connections: u32,
+    /// Time acquiring a connection of the pool may take.
+    pub acquire_timeout: Option<Duration>,
+    /// Time an unused connection may sit idle before being closed.
+    pub idle_timeout: Option<Duration>,
+    /// Server side limit on statement duration.
+    pub statement_timeout: Option<Duration>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 2,
+            min_connections: 0,
+            acquire_timeout: None,
+            idle_timeout: None,
+            statement_timeout: None,
+        }
+    }
+}
+
+impl PoolSettings {
+    /// The config of the pool: `primary` resized, without secondary pools of its own.
+    fn apply(&self, primary: &SqlxPostgresConfig) -> SqlxPostgresConfig {
+        let mut config = SqlxPostgresConfig {
+            connections_per_worker: None,
+            secondary_pools: Vec::new(),
+            ..primary.clone()
+        }
+        .set_max_connections(self.max_connections)
+        .with_min_connections(self.min_connections);
+
+        if let Some(timeout) = self.acquire_timeout {
+            config = config.with_acquire_timeout(timeout);
+        }
+
+        if let Some(timeout) = self.idle_timeout {
+            config = config.with_idle_timeout(Some(timeout));
+        }
+
+        if let Some(timeout) = self.statement_timeout {
+            config = config.with_statement_timeout(timeout);
+        }
+
+        config
+    }
+}
+
+/// Key of the table read by [`SqlxPostgresConfig::from_figment`].
+const FIGMENT_KEY: &str = "sqlx_postgres";
+
+/// Figment table read by [`SqlxPostgresFairing::from_figment_databases`].
+const DATABASES_KEY: &str = "databases";
+
+/// Callback run on every new connection before the pool hands it out.
+pub type AfterConnectHook = Arc<
+    dyn for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<(), sqlx::Error>> + Send + Sync,
+>;
+
+/// Callback run on a pooled connection before it is handed out or after it is returned,
+/// `Ok(false)` or an error closes the connection instead.
+pub type PoolConnectionHook = Arc<
+    dyn for<'c> Fn(
+            &'c mut PgConnection,
+            PoolConnectionMetadata,
+        ) -> BoxFuture<'c, Result<bool, sqlx::Error>>
+        + Send
+        + Sync,
+>;
+
+/// Callback run by the fairing once the pool is created, an error aborts launch.
+pub type ConnectedCallback = Arc<
+    dyn for<'a> Fn(
+            &'a PgPool,
+        ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>>
+        + Send
+        + Sync,
+>;
+
+/// Callback run with the pool after the listener reconnected, see
+/// [`SqlxPostgresFairing::on_listener_resync`].
+pub type ResyncCallback = Arc<
+    dyn for<'a> Fn(
+            &'a PgPool,
+        ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>>
+        + Send
+        + Sync,
+>;
+
+/// Callback run by the fairing when connecting at ignite fails.
+pub type ConnectFailedCallback =
+    Arc<dyn for<'a> Fn(&'a sqlx::Error) -> BoxFuture<'a, ()> + Send + Sync>;
+
+/// Decides from the response status how an automatic transaction ends.
+pub type TransactionPolicy = Arc<dyn Fn(Status) -> TransactionAction + Send + Sync>;
+
+/// Reads the time a request has left, see [`SqlxPostgresConfig::with_request_deadline`].
+pub type DeadlineExtractor = Arc<dyn Fn(&Request<'_>) -> Option<Duration> + Send + Sync>;
+
+/// Reads the correlation id of a request, see [`SqlxPostgresConfig::with_request_id_extractor`].
+pub type RequestIdExtractor = Arc<dyn Fn(&Request<'_>) -> Option<String> + Send + Sync>;
 
-#[derive(Debug, Clone)]
+/// Callback adjusting the sqlx pool options before the pool is created.
+pub type PoolCustomizer = Arc<dyn Fn(PgPoolOptions) -> PgPoolOptions + Send + Sync>;
+
+/// Session state a request may leave behind, reset by [`reset_session_state`].
+///
+/// `DISCARD ALL` minus `DEALLOCATE ALL`, which would drop the statements sqlx prepared and
+/// cached on the connection.
+const RESET_SESSION_STATE: &str = "CLOSE ALL; SET SESSION AUTHORIZATION DEFAULT; RESET ALL; \
+UNLISTEN *; SELECT pg_advisory_unlock_all(); DISCARD TEMP; DISCARD SEQUENCES";
+
+/// An `after_release` hook resetting the session state a request may have left on its
+/// connection, for [`SqlxPostgresConfig::with_after_release`].
+///
+/// Closes cursors, resets the session user and every `SET` back to the connection's startup
+/// values, stops listening, releases advisory locks and drops temporary tables. Settings made
+/// with `SET` in [`SqlxPostgresConfig::with_after_connect`] are reset too, pass them as
+/// startup options instead. Prepared statements are kept. A connection that fails the reset
+/// is closed.
+pub fn reset_session_state() -> impl for<'c> Fn(
+    &'c mut PgConnection,
+    PoolConnectionMetadata,
+) -> BoxFuture<'c, Result<bool, sqlx::Error>>
+       + Send
+       + Sync
+       + 'static {
+    fn reset(
+        conn: &mut PgConnection,
+        _meta: PoolConnectionMetadata,
+    ) -> BoxFuture<'_, Result<bool, sqlx::Error>> {
+        Box::pin(async move {
+            conn.execute(RESET_SESSION_STATE).await?;
+            Ok(true)
+        })
+    }
+
+    reset
+}
+
+#[derive(Clone)]
 pub struct SqlxPostgresConfig {
     /// Database name
     database: Cow<'static, str>,
@@ -21,14 +225,430 @@ pub struct SqlxPostgresConfig {
     username: Cow<'static, str>,
     /// Database password for login
     password: Cow<'static, str>,
+    /// File the password is read from at ignite, wins over `password`.
+    password_file: Option<PathBuf>,
+    /// Look the password up in a pgpass file when none was set.
+    pgpass: bool,
+    /// pgpass file to use instead of libpq's default location.
+    pgpass_file: Option<PathBuf>,
     /// Database Host address
     host: Cow<'static, str>,
     /// Database Port address
     port: u16,
+    /// Hosts tried in order when connecting, replace `host`/`port` when not empty.
+    hosts: Vec<(Cow<'static, str>, u16)>,
+    /// Shuffle `hosts` before trying them.
+    randomize_hosts: bool,
+    /// The entry of `hosts` the pool last connected to, shared by the clones of the config.
+    connected_host: Arc<RwLock<Option<(Cow<'static, str>, u16)>>>,
+    /// Unix domain socket directory, takes precedence over `host`.
+    socket: Option<PathBuf>,
     /// Database Max Poll Connections.
     max_connections: u32,
+    /// Whether `max_connections` was set explicitly.
+    max_connections_set: bool,
+    /// Connections per Rocket worker and optional cap, replacing `max_connections` at ignite.
+    connections_per_worker: Option<(f32, Option<u32>)>,
+    /// Connections the pool keeps open even when idle.
+    min_connections: u32,
+    /// Time allowed for opening a new connection.
+    pub(crate) connect_timeout: Duration,
+    /// Extra connection attempts at ignite and the delay before the first one.
+    pub(crate) connect_retries: (u32, Duration),
+    /// Time a caller waits for a connection from the pool.
+    acquire_timeout: Option<Duration>,
+    /// Time an unused connection may sit idle before being closed.
+    idle_timeout: Option<Duration>,
+    /// Maximum age of a connection before it is replaced.
+    max_lifetime: Option<Duration>,
+    /// Ping connections before handing them out.
+    test_before_acquire: bool,
+    /// Which servers new connections are accepted on.
+    target_session_attrs: TargetSessionAttrs,
     /// Log Level for the database
     log_level: LevelFilter,
+    /// Level and threshold for logging slow statements.
+    slow_statement_log: (LevelFilter, Duration),
+    /// Fraction of statements logged, all when unset.
+    statement_log_sampling: Option<f64>,
+    /// Level set through [`crate::StatementLogControl`], shared by the clones of the config.
+    pub(crate) log_level_override: Arc<AtomicU8>,
+    /// Password set through [`crate::PoolHandle::rotate`], shared by the clones of the config.
+    pub(crate) rotated_password: Arc<RwLock<Option<String>>>,
+    /// Size set through [`crate::PoolHandle::resize`], 0 when unset, shared by the clones of
+    /// the config.
+    pub(crate) resized_max_connections: Arc<AtomicU32>,
+    /// Serve the replica guard from the primary shortly after the client used the primary.
+    #[cfg(feature = "sticky-reads")]
+    pub(crate) sticky_reads: bool,
+    /// How long replica reads stay on the primary.
+    #[cfg(feature = "sticky-reads")]
+    pub(crate) sticky_reads_window: Duration,
+    /// Disable what PgBouncer transaction pooling breaks.
+    pgbouncer_mode: bool,
+    /// Sign a fresh RDS IAM token as the password of each pool.
+    #[cfg(feature = "rds-iam")]
+    rds_iam: Option<RdsIam>,
+    /// Level of the connect and acquire spans.
+    #[cfg(feature = "tracing")]
+    tracing_span_level: tracing::Level,
+    /// Level of the statement events of the guards' executors.
+    #[cfg(feature = "tracing")]
+    pub(crate) tracing_statement_level: tracing::Level,
+    /// Prepared statements cached per connection.
+    statement_cache_capacity: usize,
+    /// Schemas set as the `search_path` of every connection.
+    search_path: Vec<Cow<'static, str>>,
+    /// Statements prepared on the warm connections at ignite.
+    prepared_warmup: Vec<Cow<'static, str>>,
+    /// Server side limit on statement duration.
+    statement_timeout: Option<Duration>,
+    /// Server side limit on waiting for locks.
+    lock_timeout: Option<Duration>,
+    /// Server side limit on sessions idling inside a transaction.
+    idle_in_transaction_timeout: Option<Duration>,
+    /// Idle time before the server sends TCP keepalive probes.
+    tcp_keepalive: Option<Duration>,
+    /// Time unacknowledged data may wait before the server drops the connection.
+    tcp_user_timeout: Option<Duration>,
+    /// Make every transaction read-only by default.
+    read_only: bool,
+    /// `TimeZone` of every connection.
+    timezone: Option<Cow<'static, str>>,
+    /// Role every connection switches to with `SET ROLE` after logging in.
+    role: Option<Cow<'static, str>>,
+    /// Template of the `application_name` set on request transactions.
+    pub(crate) request_application_name: Option<Cow<'static, str>>,
+    /// Header holding the request id used by the template.
+    pub(crate) request_id_header: Cow<'static, str>,
+    /// Reads the request id instead of the header.
+    request_id_extractor: Option<RequestIdExtractor>,
+    /// Whether the query log events carry the request id.
+    log_request_id: bool,
+    /// Budget of a request applied as the `statement_timeout` of its transactions.
+    pub(crate) request_deadline: Option<DeadlineExtractor>,
+    /// Lowest `statement_timeout` a request budget is applied as.
+    pub(crate) request_deadline_floor: Duration,
+    /// Time a query of a dropped request may keep running before it is cancelled.
+    pub(crate) cancel_on_disconnect: Option<Duration>,
+    /// How the tenant guard picks the schema of a request.
+    pub(crate) tenant_schemas: Option<TenantSchemas>,
+    /// Time the connection and transaction guards wait for a connection.
+    pub(crate) guard_acquire_timeout: Option<Duration>,
+    /// Guards allowed to wait for a connection at once.
+    pub(crate) max_waiters: Option<usize>,
+    /// Fails guards fast after repeated acquisition failures, shared by the clones of the config.
+    pub(crate) circuit_breaker: Option<CircuitBreaker>,
+    /// Explains slow queries run through the pool guards.
+    pub(crate) slow_query_explain: Option<Explainer>,
+    /// Watches for transactions held open too long.
+    pub(crate) transaction_watchdog: Option<TransactionWatchdog>,
+    /// Query run by the readiness route.
+    pub(crate) health_query: Cow<'static, str>,
+    /// Time the readiness route waits for the query.
+    pub(crate) health_timeout: Duration,
+    /// Interval of the background health probe, disabled when unset.
+    pub(crate) health_probe_interval: Option<Duration>,
+    /// How often the pool status is logged.
+    pub(crate) metrics_log_interval: Option<Duration>,
+    /// Cache results of [`SQLxPostgres::cached`].
+    query_cache: bool,
+    /// Bound of the query cache.
+    query_cache_max_entries: usize,
+    /// Pools to the same database registered under their own name.
+    pub(crate) secondary_pools: Vec<(&'static str, PoolSettings)>,
+    /// Whether request database time is logged, and also sent as headers when `true`.
+    pub(crate) request_db_time: Option<bool>,
+    /// Header asking to capture a request's statements, and whether their count is sent back.
+    pub(crate) query_capture: Option<(Cow<'static, str>, bool)>,
+    /// Start with [`crate::MaintenanceMode`] enabled.
+    pub(crate) maintenance_mode: bool,
+    /// `Retry-After` of responses refused for maintenance.
+    pub(crate) maintenance_retry_after: Duration,
+    /// Guard acquire time above which a warning is logged.
+    pub(crate) slow_acquire_threshold: Option<Duration>,
+    /// Lowest level of server notices forwarded to the log.
+    notice_log_level: Option<LevelFilter>,
+    /// Extra server settings sent as startup options.
+    options: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    /// Name reported in `pg_stat_activity`, defaults to the binary name.
+    application_name: Option<Cow<'static, str>>,
+    /// TLS mode, sqlx's default of `prefer` is used when unset.
+    ssl_mode: Option<PgSslMode>,
+    /// Root certificate bundle used to verify the server.
+    ssl_root_cert: Option<PathBuf>,
+    /// Client certificate presented to the server.
+    ssl_client_cert: Option<PathBuf>,
+    /// Private key for the client certificate.
+    ssl_client_key: Option<PathBuf>,
+    /// SCRAM channel binding requirement.
+    channel_binding: ChannelBinding,
+    /// User provided per-connection initialization.
+    after_connect: Option<AfterConnectHook>,
+    /// User provided check before a pooled connection is handed out.
+    before_acquire: Option<PoolConnectionHook>,
+    /// User provided cleanup after a connection is returned to the pool.
+    after_release: Option<PoolConnectionHook>,
+    /// User provided adjustments of the pool options.
+    pool_customizer: Option<PoolCustomizer>,
+    /// Prebuilt connect options replacing the connection fields above.
+    connect_options: Option<PgConnectOptions>,
+    /// Launch without a pool when connecting fails.
+    ignore_connect_errors: bool,
+    /// Defer connecting until a connection is first acquired.
+    lazy: bool,
+    /// Also manage the bare `PgPool`.
+    manage_raw_pool: bool,
+    /// Create the database at ignite when the server reports it missing.
+    create_database: bool,
+    /// Create the first `search_path` schema at ignite.
+    ensure_schema: bool,
+    /// Timeout of the `SELECT 1` run at ignite, skipped when unset.
+    startup_ping: Option<Duration>,
+    /// Check with `pg_is_in_recovery()` that a primary is one and a replica is a standby.
+    verify_server_role: bool,
+    /// Minimum server version as major and minor, checked at ignite.
+    server_version: Option<(u32, u32)>,
+    /// Compare `max_connections` with what the server has left at ignite.
+    check_connection_limit: bool,
+    /// Abort launch when `max_connections` exceeds what the server has left.
+    strict_connection_limit: bool,
+    /// Open the pool's connections at ignite.
+    warm_up: bool,
+    /// Bound of the warm up.
+    warm_up_timeout: Duration,
+    /// Abort launch when the warm up fails.
+    warm_up_required: bool,
+    /// Interval of the supervisor health checks, disabled when unset.
+    supervisor_interval: Option<Duration>,
+    /// Rebuilds in a row the supervisor attempts before giving up.
+    supervisor_attempts: u32,
+    /// Time allowed at shutdown for checked out connections to be returned.
+    shutdown_grace: Duration,
+    /// Time a replaced pool keeps serving its clones before it is closed.
+    pub(crate) drain_timeout: Duration,
+}
+
+impl fmt::Debug for SqlxPostgresConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("SqlxPostgresConfig");
+        debug
+            .field("database", &self.database)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("password_file", &self.password_file)
+            .field("pgpass", &self.pgpass)
+            .field("pgpass_file", &self.pgpass_file)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("hosts", &self.hosts)
+            .field("randomize_hosts", &self.randomize_hosts)
+            .field("socket", &self.socket)
+            .field("max_connections", &self.max_connections)
+            .field("connections_per_worker", &self.connections_per_worker)
+            .field("min_connections", &self.min_connections)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("connect_retries", &self.connect_retries)
+            .field("acquire_timeout", &self.acquire_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("max_lifetime", &self.max_lifetime)
+            .field("test_before_acquire", &self.test_before_acquire)
+            .field("target_session_attrs", &self.target_session_attrs)
+            .field("log_level", &self.log_level)
+            .field("slow_statement_log", &self.slow_statement_log)
+            .field("statement_log_sampling", &self.statement_log_sampling)
+            .field("log_level_override", &self.log_level_override())
+            .field("resized_max_connections", &self.resized_max_connections)
+            .field("pgbouncer_mode", &self.pgbouncer_mode);
+        #[cfg(feature = "rds-iam")]
+        debug.field("rds_iam", &self.rds_iam);
+        #[cfg(feature = "sticky-reads")]
+        debug
+            .field("sticky_reads", &self.sticky_reads)
+            .field("sticky_reads_window", &self.sticky_reads_window);
+        #[cfg(feature = "tracing")]
+        debug
+            .field("tracing_span_level", &self.tracing_span_level)
+            .field("tracing_statement_level", &self.tracing_statement_level);
+        debug
+            .field("statement_cache_capacity", &self.statement_cache_capacity)
+            .field("search_path", &self.search_path)
+            .field("prepared_warmup", &self.prepared_warmup)
+            .field("statement_timeout", &self.statement_timeout)
+            .field("lock_timeout", &self.lock_timeout)
+            .field(
+                "idle_in_transaction_timeout",
+                &self.idle_in_transaction_timeout,
+            )
+            .field("tcp_keepalive", &self.tcp_keepalive)
+            .field("tcp_user_timeout", &self.tcp_user_timeout)
+            .field("read_only", &self.read_only)
+            .field("timezone", &self.timezone)
+            .field("role", &self.role)
+            .field("request_application_name", &self.request_application_name)
+            .field("request_id_header", &self.request_id_header)
+            .field(
+                "request_id_extractor",
+                &self.request_id_extractor.as_ref().map(|_| "<fn>"),
+            )
+            .field("log_request_id", &self.log_request_id)
+            .field(
+                "request_deadline",
+                &self.request_deadline.as_ref().map(|_| "<fn>"),
+            )
+            .field("request_deadline_floor", &self.request_deadline_floor)
+            .field("cancel_on_disconnect", &self.cancel_on_disconnect)
+            .field("tenant_schemas", &self.tenant_schemas)
+            .field("guard_acquire_timeout", &self.guard_acquire_timeout)
+            .field("max_waiters", &self.max_waiters)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .field("slow_query_explain", &self.slow_query_explain)
+            .field("transaction_watchdog", &self.transaction_watchdog)
+            .field("health_query", &self.health_query)
+            .field("health_timeout", &self.health_timeout)
+            .field("health_probe_interval", &self.health_probe_interval)
+            .field("metrics_log_interval", &self.metrics_log_interval)
+            .field("query_cache", &self.query_cache)
+            .field("query_cache_max_entries", &self.query_cache_max_entries)
+            .field("secondary_pools", &self.secondary_pools)
+            .field("request_db_time", &self.request_db_time)
+            .field("query_capture", &self.query_capture)
+            .field("maintenance_mode", &self.maintenance_mode)
+            .field("maintenance_retry_after", &self.maintenance_retry_after)
+            .field("slow_acquire_threshold", &self.slow_acquire_threshold)
+            .field("notice_log_level", &self.notice_log_level)
+            .field("options", &self.options)
+            .field("application_name", &self.application_name)
+            .field("ssl_mode", &self.ssl_mode)
+            .field("ssl_root_cert", &self.ssl_root_cert)
+            .field("ssl_client_cert", &self.ssl_client_cert)
+            .field("ssl_client_key", &self.ssl_client_key)
+            .field("channel_binding", &self.channel_binding)
+            .field(
+                "after_connect",
+                &self.after_connect.as_ref().map(|_| "<fn>"),
+            )
+            .field(
+                "before_acquire",
+                &self.before_acquire.as_ref().map(|_| "<fn>"),
+            )
+            .field(
+                "after_release",
+                &self.after_release.as_ref().map(|_| "<fn>"),
+            )
+            .field(
+                "pool_customizer",
+                &self.pool_customizer.as_ref().map(|_| "<fn>"),
+            )
+            .field(
+                "connect_options",
+                &self.connect_options.as_ref().map(|_| "<redacted>"),
+            )
+            .field("ignore_connect_errors", &self.ignore_connect_errors)
+            .field("lazy", &self.lazy)
+            .field("manage_raw_pool", &self.manage_raw_pool)
+            .field("create_database", &self.create_database)
+            .field("ensure_schema", &self.ensure_schema)
+            .field("startup_ping", &self.startup_ping)
+            .field("verify_server_role", &self.verify_server_role)
+            .field("server_version", &self.server_version)
+            .field("check_connection_limit", &self.check_connection_limit)
+            .field("strict_connection_limit", &self.strict_connection_limit)
+            .field("warm_up", &self.warm_up)
+            .field("warm_up_timeout", &self.warm_up_timeout)
+            .field("warm_up_required", &self.warm_up_required)
+            .field("supervisor_interval", &self.supervisor_interval)
+            .field("supervisor_attempts", &self.supervisor_attempts)
+            .field("shutdown_grace", &self.shutdown_grace)
+            .field("drain_timeout", &self.drain_timeout)
+            .finish()
+    }
+}
+
+/// Prints a connection summary without the password, e.g. `user@localhost:5432/database`.
+impl fmt::Display for SqlxPostgresConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.socket {
+            Some(socket) => write!(
+                f,
+                "{}@{}:{}/{}",
+                self.username,
+                socket.display(),
+                self.port,
+                self.database
+            ),
+            None => {
+                let (host, port) = self.target();
+                write!(f, "{}@{}:{}/{}", self.username, host, port, self.database)
+            }
+        }
+    }
+}
+
+impl SqlxPostgresConfig {
+    /// Host and port, or socket, of the server, naming a replica in logs and metrics.
+    pub(crate) fn endpoint(&self) -> String {
+        match &self.socket {
+            Some(socket) => socket.display().to_string(),
+            None => {
+                let (host, port) = self.target();
+                format!("{}:{}", host, port)
+            }
+        }
+    }
+
+    /// The host and port connections go to: the entry of the host list the pool last
+    /// connected to or else its first one, `host` and `port` without a list.
+    pub(crate) fn target(&self) -> (Cow<'static, str>, u16) {
+        self.failover_host()
+            .unwrap_or_else(|| (self.host.clone(), self.port))
+    }
+
+    /// The entry of [`SqlxPostgresConfig::with_hosts`] connections go to, `None` without a
+    /// host list.
+    pub(crate) fn failover_host(&self) -> Option<(Cow<'static, str>, u16)> {
+        let first = self.hosts.first()?;
+        let connected = self
+            .connected_host
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+
+        match &*connected {
+            Some(host) if self.hosts.contains(host) => Some(host.clone()),
+            _ => Some(first.clone()),
+        }
+    }
+
+    /// One line description of what is connected to, everything but the password.
+    ///
+    /// e.g. `postgres://user@host:5432/dbname (max_connections=5, tls=require)`.
+    pub(crate) fn summary(&self) -> String {
+        let tls = ssl_mode_name(self.ssl_mode.unwrap_or(PgSslMode::Prefer));
+        let target = if self.connect_options.is_some() {
+            Cow::Borrowed("provided connect options")
+        } else {
+            Cow::Owned(format!("postgres://{}", self))
+        };
+
+        format!(
+            "{} (max_connections={}, tls={}{})",
+            target,
+            self.max_connections(),
+            tls,
+            match &self.role {
+                Some(role) => format!(", role={}", role),
+                None => String::new(),
+            }
+        )
+    }
+
+    /// The size of the pool, as last set with [`crate::PoolHandle::resize`] or else configured.
+    pub(crate) fn max_connections(&self) -> u32 {
+        match self.resized_max_connections.load(Ordering::Relaxed) {
+            0 => self.max_connections,
+            resized => resized,
+        }
+    }
 }
 
 impl Default for SqlxPostgresConfig {
@@ -37,21 +657,651 @@ impl Default for SqlxPostgresConfig {
             database: "".into(),
             username: "".into(),
             password: "".into(),
+            password_file: None,
+            pgpass: false,
+            pgpass_file: None,
             host: "localhost".into(),
             port: 5432,
+            hosts: Vec::new(),
+            randomize_hosts: false,
+            connected_host: Arc::default(),
+            socket: None,
             max_connections: 5,
+            max_connections_set: false,
+            connections_per_worker: None,
+            min_connections: 0,
+            connect_timeout: Duration::from_secs(30),
+            connect_retries: (0, Duration::from_millis(500)),
+            acquire_timeout: None,
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            test_before_acquire: true,
+            target_session_attrs: TargetSessionAttrs::Any,
             log_level: LevelFilter::Debug,
+            slow_statement_log: (LevelFilter::Warn, Duration::from_secs(1)),
+            statement_log_sampling: None,
+            log_level_override: statement_log::new_override(),
+            rotated_password: Arc::default(),
+            resized_max_connections: Arc::default(),
+            #[cfg(feature = "sticky-reads")]
+            sticky_reads: false,
+            #[cfg(feature = "sticky-reads")]
+            sticky_reads_window: Duration::from_secs(5),
+            pgbouncer_mode: false,
+            #[cfg(feature = "rds-iam")]
+            rds_iam: None,
+            #[cfg(feature = "tracing")]
+            tracing_span_level: tracing::Level::INFO,
+            #[cfg(feature = "tracing")]
+            tracing_statement_level: tracing::Level::DEBUG,
+            statement_cache_capacity: 100,
+            search_path: Vec::new(),
+            prepared_warmup: Vec::new(),
+            statement_timeout: None,
+            lock_timeout: None,
+            idle_in_transaction_timeout: None,
+            tcp_keepalive: None,
+            tcp_user_timeout: None,
+            read_only: false,
+            timezone: None,
+            role: None,
+            request_application_name: None,
+            request_id_header: Cow::Borrowed("X-Request-Id"),
+            request_id_extractor: None,
+            log_request_id: false,
+            request_deadline: None,
+            request_deadline_floor: Duration::from_millis(100),
+            cancel_on_disconnect: None,
+            tenant_schemas: None,
+            guard_acquire_timeout: None,
+            max_waiters: None,
+            circuit_breaker: None,
+            slow_query_explain: None,
+            transaction_watchdog: None,
+            health_query: Cow::Borrowed("SELECT 1"),
+            health_timeout: Duration::from_secs(2),
+            health_probe_interval: None,
+            metrics_log_interval: None,
+            query_cache: false,
+            query_cache_max_entries: 1000,
+            secondary_pools: Vec::new(),
+            request_db_time: None,
+            query_capture: None,
+            maintenance_mode: false,
+            maintenance_retry_after: Duration::from_secs(60),
+            slow_acquire_threshold: None,
+            notice_log_level: None,
+            options: Vec::new(),
+            application_name: None,
+            ssl_mode: None,
+            ssl_root_cert: None,
+            ssl_client_cert: None,
+            ssl_client_key: None,
+            channel_binding: ChannelBinding::Prefer,
+            after_connect: None,
+            before_acquire: None,
+            after_release: None,
+            pool_customizer: None,
+            connect_options: None,
+            ignore_connect_errors: false,
+            lazy: false,
+            manage_raw_pool: false,
+            create_database: false,
+            ensure_schema: false,
+            startup_ping: None,
+            verify_server_role: false,
+            server_version: None,
+            check_connection_limit: false,
+            strict_connection_limit: false,
+            warm_up: false,
+            warm_up_timeout: Duration::from_secs(30),
+            warm_up_required: false,
+            supervisor_interval: None,
+            supervisor_attempts: 10,
+            shutdown_grace: Duration::from_secs(5),
+            drain_timeout: Duration::from_secs(30),
         }
     }
 }
 
+/// Serializable form of [`SqlxPostgresConfig`], used for Rocket.toml and the `serde` feature.
+///
+/// Durations are whole seconds, `0` disables `idle_timeout` and `max_lifetime`.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+struct RawConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acquire_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_lifetime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    test_before_acquire: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_session_attrs: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statement_log_sampling: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statement_cache_capacity: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pgbouncer_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_path: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prepared_warmup: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statement_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_in_transaction_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_keepalive: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_user_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    read_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssl_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssl_root_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssl_client_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssl_client_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_binding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fail_fast: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query_cache: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query_cache_max_entries: Option<usize>,
+}
+
+/// Longest identifier Postgres keeps, longer ones are truncated by the server.
+const MAX_IDENTIFIER: usize = 63;
+
+/// Whether `role` can be quoted as an identifier naming exactly that role.
+fn is_role_name(role: &str) -> bool {
+    !role.is_empty() && role.len() <= MAX_IDENTIFIER && !role.contains('\0')
+}
+
+fn ssl_mode_name(mode: PgSslMode) -> &'static str {
+    match mode {
+        PgSslMode::Disable => "disable",
+        PgSslMode::Allow => "allow",
+        PgSslMode::Prefer => "prefer",
+        PgSslMode::Require => "require",
+        PgSslMode::VerifyCa => "verify-ca",
+        PgSslMode::VerifyFull => "verify-full",
+    }
+}
+
+fn seconds_or_disabled(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
 impl SqlxPostgresConfig {
+    /// Loads the config from the `sqlx_postgres` table of a Figment.
+    ///
+    /// Pass `rocket.figment()` so the selected Rocket profile is respected.
+    /// Keys may also be set as `ROCKET_SQLX_POSTGRES_<KEY>` environment variables,
+    /// which take precedence over Rocket.toml. Missing keys use the `Default` values.
+    pub fn from_figment(figment: &Figment) -> Result<Self, rocket::figment::Error> {
+        let figment = figment.clone().merge(
+            Env::prefixed("ROCKET_SQLX_POSTGRES_")
+                .map(|key| format!("{}.{}", FIGMENT_KEY, key).into())
+                .global(),
+        );
+        let raw: RawConfig = figment.extract_inner(FIGMENT_KEY)?;
+        Ok(Self::from_raw(raw)?)
+    }
+
+    /// Loads one config per sub-table of the `databases` table of a Figment.
+    ///
+    /// Each `[default.databases.<name>]` table takes the keys of `sqlx_postgres` and is
+    /// returned with its name, in name order. Errors name the offending database, and a
+    /// missing or empty `databases` table is an error too.
+    pub fn from_figment_databases(
+        figment: &Figment,
+    ) -> Result<Vec<(String, Self)>, rocket::figment::Error> {
+        let tables: BTreeMap<String, rocket::figment::value::Value> =
+            figment.extract_inner(DATABASES_KEY)?;
+
+        if tables.is_empty() {
+            return Err(format!("the `{}` table has no databases", DATABASES_KEY).into());
+        }
+
+        tables
+            .into_iter()
+            .map(|(name, table)| {
+                let config = table
+                    .deserialize::<RawConfig>()
+                    .map_err(|e| e.to_string())
+                    .and_then(Self::from_raw)
+                    .map_err(|e| format!("database `{}`: {}", name, e))?;
+
+                Ok((name, config))
+            })
+            .collect()
+    }
+
+    fn from_raw(raw: RawConfig) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        if let Some(database) = raw.database {
+            config = config.with_database(database);
+        }
+
+        if let Some(username) = raw.username {
+            config = config.with_username(username);
+        }
+
+        if let Some(password) = raw.password {
+            config = config.with_password(password);
+        }
+
+        if let Some(path) = raw.password_file {
+            config = config.with_password_file(path);
+        }
+
+        if let Some(host) = raw.host {
+            config = config.with_host(host);
+        }
+
+        if let Some(port) = raw.port {
+            config = config.with_port(port);
+        }
+
+        if let Some(socket) = raw.socket {
+            config = config.with_socket(socket);
+        }
+
+        if let Some(max) = raw.max_connections {
+            config = config.set_max_connections(max);
+        }
+
+        if let Some(min) = raw.min_connections {
+            config = config.with_min_connections(min);
+        }
+
+        if let Some(secs) = raw.connect_timeout {
+            config = config.with_connect_timeout(Duration::from_secs(secs));
+        }
+
+        if let Some(secs) = raw.acquire_timeout {
+            config = config.with_acquire_timeout(Duration::from_secs(secs));
+        }
+
+        if let Some(secs) = raw.idle_timeout {
+            config = config.with_idle_timeout(seconds_or_disabled(secs));
+        }
+
+        if let Some(secs) = raw.max_lifetime {
+            config = config.with_max_lifetime(seconds_or_disabled(secs));
+        }
+
+        if let Some(test) = raw.test_before_acquire {
+            config = config.with_test_before_acquire(test);
+        }
+
+        if let Some(attrs) = raw.target_session_attrs {
+            let attrs = TargetSessionAttrs::from_str(&attrs).map_err(|_| {
+                format!(
+                    "invalid target_session_attrs `{}`: expected any or read-write",
+                    attrs
+                )
+            })?;
+            config = config.with_target_session_attrs(attrs);
+        }
+
+        if let Some(level) = raw.log_level {
+            let level = LevelFilter::from_str(&level).map_err(|_| {
+                format!(
+                    "invalid log_level `{}`: expected off, error, warn, info, debug or trace",
+                    level
+                )
+            })?;
+            config = config.with_loglevel(level);
+        }
+
+        if let Some(rate) = raw.statement_log_sampling {
+            config = config.with_statement_log_sampling(rate);
+        }
+
+        if let Some(capacity) = raw.statement_cache_capacity {
+            config = config.with_statement_cache_capacity(capacity);
+        }
+
+        if let Some(search_path) = raw.search_path {
+            config = config.with_search_path(&search_path);
+        }
+
+        if let Some(statements) = raw.prepared_warmup {
+            config = config.with_prepared_warmup(statements.into_iter().map(Cow::Owned).collect());
+        }
+
+        if let Some(ms) = raw.statement_timeout_ms {
+            config = config.with_statement_timeout(Duration::from_millis(ms));
+        }
+
+        if let Some(ms) = raw.lock_timeout_ms {
+            config = config.with_lock_timeout(Duration::from_millis(ms));
+        }
+
+        if let Some(ms) = raw.idle_in_transaction_timeout_ms {
+            config = config.with_idle_in_transaction_timeout(Duration::from_millis(ms));
+        }
+
+        if let Some(secs) = raw.tcp_keepalive {
+            config = config.with_tcp_keepalive(Duration::from_secs(secs));
+        }
+
+        if let Some(ms) = raw.tcp_user_timeout_ms {
+            config = config.with_tcp_user_timeout(Duration::from_millis(ms));
+        }
+
+        if let Some(fail_fast) = raw.fail_fast {
+            config = config.ignore_connect_errors(!fail_fast);
+        }
+
+        if let Some(enabled) = raw.pgbouncer_mode {
+            config = config.pgbouncer_mode(enabled);
+        }
+
+        if let Some(enabled) = raw.query_cache {
+            config = config.query_cache(enabled);
+        }
+
+        if let Some(max_entries) = raw.query_cache_max_entries {
+            config = config.with_query_cache_max_entries(max_entries);
+        }
+
+        if let Some(read_only) = raw.read_only {
+            config = config.read_only(read_only);
+        }
+
+        if let Some(timezone) = raw.timezone {
+            config = config.with_timezone(timezone);
+        }
+
+        if let Some(role) = raw.role {
+            config = config.with_role(role);
+        }
+
+        for (key, value) in raw.options.unwrap_or_default() {
+            config = config.with_option(key, value);
+        }
+
+        if let Some(name) = raw.application_name {
+            config = config.with_application_name(name);
+        }
+
+        if let Some(mode) = raw.ssl_mode {
+            let mode = PgSslMode::from_str(&mode).map_err(|_| {
+                format!(
+                    "invalid ssl_mode `{}`: expected disable, allow, prefer, require, verify-ca or verify-full",
+                    mode
+                )
+            })?;
+            config = config.with_ssl_mode(mode);
+        }
+
+        if let Some(cert) = raw.ssl_root_cert {
+            config = config.with_ssl_root_cert(cert);
+        }
+
+        if let Some(cert) = raw.ssl_client_cert {
+            config = config.with_ssl_client_cert(cert);
+        }
+
+        if let Some(key) = raw.ssl_client_key {
+            config = config.with_ssl_client_key(key);
+        }
+
+        if let Some(binding) = raw.channel_binding {
+            let binding = ChannelBinding::from_str(&binding).map_err(|_| {
+                format!(
+                    "invalid channel_binding `{}`: expected disable, prefer or require",
+                    binding
+                )
+            })?;
+            config = config.with_channel_binding(binding);
+        }
+
+        Ok(config)
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_raw(&self, include_password: bool) -> RawConfig {
+        RawConfig {
+            database: Some(self.database.to_string()),
+            username: Some(self.username.to_string()),
+            password: if include_password {
+                Some(self.password.to_string())
+            } else {
+                None
+            },
+            password_file: self.password_file.clone(),
+            host: Some(self.host.to_string()),
+            port: Some(self.port),
+            socket: self.socket.clone(),
+            max_connections: Some(self.max_connections),
+            min_connections: Some(self.min_connections),
+            connect_timeout: Some(self.connect_timeout.as_secs()),
+            acquire_timeout: self.acquire_timeout.map(|timeout| timeout.as_secs()),
+            idle_timeout: Some(self.idle_timeout.map_or(0, |timeout| timeout.as_secs())),
+            max_lifetime: Some(self.max_lifetime.map_or(0, |lifetime| lifetime.as_secs())),
+            test_before_acquire: Some(self.test_before_acquire),
+            target_session_attrs: Some(
+                match self.target_session_attrs {
+                    TargetSessionAttrs::Any => "any",
+                    TargetSessionAttrs::ReadWrite => "read-write",
+                }
+                .to_owned(),
+            ),
+            log_level: Some(self.log_level.to_string().to_lowercase()),
+            statement_log_sampling: self.statement_log_sampling,
+            statement_cache_capacity: Some(self.statement_cache_capacity),
+            pgbouncer_mode: Some(self.pgbouncer_mode),
+            search_path: Some(self.search_path.iter().map(|s| s.to_string()).collect()),
+            prepared_warmup: Some(self.prepared_warmup.iter().map(|s| s.to_string()).collect()),
+            statement_timeout_ms: self.statement_timeout.map(|t| t.as_millis() as u64),
+            lock_timeout_ms: self.lock_timeout.map(|t| t.as_millis() as u64),
+            idle_in_transaction_timeout_ms: self
+                .idle_in_transaction_timeout
+                .map(|t| t.as_millis() as u64),
+            tcp_keepalive: self.tcp_keepalive.map(|t| t.as_secs()),
+            tcp_user_timeout_ms: self.tcp_user_timeout.map(|t| t.as_millis() as u64),
+            read_only: Some(self.read_only),
+            timezone: self.timezone.as_ref().map(|tz| tz.to_string()),
+            role: self.role.as_ref().map(|role| role.to_string()),
+            options: Some(
+                self.options
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            application_name: self.application_name.as_ref().map(|name| name.to_string()),
+            ssl_mode: self.ssl_mode.map(|mode| ssl_mode_name(mode).to_owned()),
+            ssl_root_cert: self.ssl_root_cert.clone(),
+            ssl_client_cert: self.ssl_client_cert.clone(),
+            ssl_client_key: self.ssl_client_key.clone(),
+            channel_binding: Some(
+                match self.channel_binding {
+                    ChannelBinding::Disable => "disable",
+                    ChannelBinding::Prefer => "prefer",
+                    ChannelBinding::Require => "require",
+                }
+                .to_owned(),
+            ),
+            fail_fast: Some(!self.ignore_connect_errors),
+            query_cache: Some(self.query_cache),
+            query_cache_max_entries: Some(self.query_cache_max_entries),
+        }
+    }
+
+    /// Wraps the config so serializing it includes the password.
+    ///
+    /// Plain serialization of [`SqlxPostgresConfig`] always leaves the password out.
+    #[cfg(feature = "serde")]
+    pub fn with_password_serialized(&self) -> SerializeWithPassword<'_> {
+        SerializeWithPassword(self)
+    }
+
     /// Set database pools max connections limit.
     ///
+    /// Zero fails [`SqlxPostgresConfig::validate`], which aborts launch at ignite.
+    ///
     /// Call on the fairing before passing it to `rocket.attach()`
     pub fn set_max_connections(mut self, max: u32) -> Self {
-        let max = std::cmp::max(max, 1);
         self.max_connections = max;
+        self.max_connections_set = true;
+        self
+    }
+
+    /// Size the pool relative to Rocket's worker count instead of a fixed limit.
+    ///
+    /// At ignite `max_connections` becomes `ceil(workers * factor)`, at least 1 and at most
+    /// `cap` when given, and the result is logged. Cannot be combined with
+    /// [`SqlxPostgresConfig::set_max_connections`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_connections_per_worker(mut self, factor: f32, cap: impl Into<Option<u32>>) -> Self {
+        self.connections_per_worker = Some((factor, cap.into()));
+        self
+    }
+
+    /// Set the number of connections the pool keeps open even when idle.
+    ///
+    /// Values above `max_connections` are clamped to `max_connections` when the pool is built.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_min_connections(mut self, min: u32) -> Self {
+        self.min_connections = min;
+        self
+    }
+
+    /// Set the time allowed for opening a new connection.
+    ///
+    /// Bounds the connection made at ignite. Later reconnects happen while acquiring, so
+    /// they are bounded by the acquire timeout, which defaults to this value.
+    /// Defaults to 30 seconds like sqlx, values below 100ms are raised to 100ms.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = std::cmp::max(timeout, Duration::from_millis(100));
+        self
+    }
+
+    /// Set how often connecting is retried at ignite before giving up.
+    ///
+    /// Useful when the database starts alongside the app, e.g. under docker-compose.
+    /// The delay doubles after every attempt starting at `initial_backoff`, with jitter and
+    /// capped at 30 seconds. Once the retries are exhausted the failure is handled as set by
+    /// [`SqlxPostgresConfig::ignore_connect_errors`]. Defaults to no retries.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_connect_retries(mut self, retries: u32, initial_backoff: Duration) -> Self {
+        self.connect_retries = (retries, initial_backoff);
+        self
+    }
+
+    /// Set how long a caller waits for a connection from the pool before erroring.
+    ///
+    /// Unlike the connect timeout this bounds waiting for a busy pool to free a connection,
+    /// and since sqlx opens new connections inside acquire it also bounds those. Defaults to
+    /// the connect timeout. Must not be zero.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how long an unused connection may sit idle before the pool closes it.
+    ///
+    /// `None` keeps idle connections open forever. Defaults to 10 minutes like sqlx.
+    /// Connections are never reaped below `min_connections`.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum age of a connection before the pool replaces it.
+    ///
+    /// `None` lets connections live forever. Defaults to 30 minutes like sqlx.
+    /// This applies independently of the idle timeout, whichever is reached first
+    /// closes the connection.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_max_lifetime(mut self, lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = lifetime;
+        self
+    }
+
+    /// Set whether connections are pinged before being handed out.
+    ///
+    /// Defaults to `true` like sqlx, which lets the pool discard connections that died
+    /// (e.g. after a failover) before a handler sees them, at the cost of a round trip.
+    /// Pools passed to [`SqlxPostgresFairing::new`] keep the setting they were built with.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_test_before_acquire(mut self, test: bool) -> Self {
+        self.test_before_acquire = test;
+        self
+    }
+
+    /// Set which servers new connections are accepted on
+    ///
+    /// With `TargetSessionAttrs::ReadWrite` every new connection runs `SHOW transaction_read_only`
+    /// and is dropped when the server is read-only, so the pool never hands out a connection
+    /// to a replica, including after a failover. Unlike libpq there is no next host to try,
+    /// since sqlx connects a pool to a single host: the connect fails instead, so point the
+    /// host at a name that follows the primary, like a DNS record or proxy updated on
+    /// failover. Defaults to `TargetSessionAttrs::Any`.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_target_session_attrs(mut self, attrs: TargetSessionAttrs) -> Self {
+        self.target_session_attrs = attrs;
         self
     }
 
@@ -79,6 +1329,40 @@ impl SqlxPostgresConfig {
         self
     }
 
+    /// Set a file the user password is read from
+    ///
+    /// e.g. a Docker or Kubernetes secret at `/run/secrets/db_password`. The file is only
+    /// read when the pool is built at ignite, with a trailing newline removed. When both
+    /// a password and a password file are set the file wins.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_password_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.password_file = Some(path.into());
+        self
+    }
+
+    /// Look the password up in a pgpass file when no password was set
+    ///
+    /// Uses `PGPASSFILE` or `~/.pgpass` like libpq, matching host, port, database and
+    /// username with `*` wildcards. Without a matching entry the password stays empty.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_pgpass(mut self, enabled: bool) -> Self {
+        self.pgpass = enabled;
+        self
+    }
+
+    /// Look the password up in the given pgpass file when no password was set
+    ///
+    /// See [`SqlxPostgresConfig::with_pgpass`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_pgpass_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pgpass = true;
+        self.pgpass_file = Some(path.into());
+        self
+    }
+
     /// Set database hostname
     ///
     /// Call on the fairing before passing it to `rocket.attach()`
@@ -95,95 +1379,5336 @@ impl SqlxPostgresConfig {
         self
     }
 
-    /// Set database logging level
+    /// Set a list of hosts to fail over between
+    ///
+    /// Every time a pool is built, at ignite and when the supervisor, a credential rotation or
+    /// a resize rebuilds it, the hosts are tried in order and the pool is built against the
+    /// first one accepting a connection, which is logged. Replaces `with_host`/`with_port`
+    /// when not empty. sqlx connects all connections of a pool to a single address, so the
+    /// connections the pool opens later go to the chosen host until it is rebuilt: combine
+    /// with [`SqlxPostgresConfig::supervise`] so a pool whose host went down moves to the
+    /// next one, and with `TargetSessionAttrs::ReadWrite` to refuse a host that became a
+    /// replica. A lazy pool uses the host last connected to, the first one at ignite.
     ///
     /// Call on the fairing before passing it to `rocket.attach()`
-    pub fn with_loglevel(mut self, level: LevelFilter) -> Self {
-        self.log_level = level;
+    pub fn with_hosts<H: AsRef<str>>(mut self, hosts: &[(H, u16)]) -> Self {
+        self.hosts = hosts
+            .iter()
+            .map(|(host, port)| (Cow::Owned(host.as_ref().to_owned()), *port))
+            .collect();
+        self.connected_host = Arc::default();
         self
     }
-}
-
-#[derive(Debug)]
-pub struct SQLxPostgresPool {
-    pub client: PgPool,
-}
+
+    /// Set whether the hosts given to [`SqlxPostgresConfig::with_hosts`] are tried in a
+    /// random order
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_random_host_order(mut self, randomize: bool) -> Self {
+        self.randomize_hosts = randomize;
+        self
+    }
+
+    /// Set the unix domain socket directory to connect through
+    ///
+    /// e.g. `/var/run/postgresql` or `/cloudsql/project:region:instance`. The port is still
+    /// used to pick the socket file. When a socket is set it takes precedence over the host,
+    /// and TLS options are rejected at ignite since they do not apply to socket connections.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket = Some(path.into());
+        self
+    }
+
+    /// Set database logging level
+    ///
+    /// It can be changed at runtime through the managed [`crate::StatementLogControl`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_loglevel(mut self, level: LevelFilter) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    /// Set how many prepared statements each connection caches
+    ///
+    /// Defaults to 100 like sqlx. `0` disables the cache, which PgBouncer transaction
+    /// pooling requires.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_statement_cache_capacity(mut self, capacity: usize) -> Self {
+        self.statement_cache_capacity = capacity;
+        self
+    }
+
+    /// Set whether connections go through PgBouncer in transaction pooling mode
+    ///
+    /// Disables the statement cache, so sqlx sends every query as an unnamed prepared
+    /// statement, including those of this crate's guards and checks. Also applies to
+    /// [`SqlxPostgresConfig::with_connect_options`].
+    ///
+    /// Consecutive transactions may run on different server connections, so avoid session
+    /// state: `LISTEN`, advisory locks, `SET` outside a transaction, temporary tables and
+    /// `PREPARE`. Settings sent as startup options, like `search_path`, `statement_timeout`
+    /// or [`SqlxPostgresConfig::with_option`], need `ignore_startup_parameters = options` in
+    /// PgBouncer.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn pgbouncer_mode(mut self, enabled: bool) -> Self {
+        self.pgbouncer_mode = enabled;
+        self
+    }
+
+    /// Authenticate with AWS RDS IAM auth tokens instead of a password
+    ///
+    /// Each connect signs a token for the host, port and username with the credentials of
+    /// `credentials`, or the default AWS provider chain when `None`. Tokens expire after 15
+    /// minutes, so the pool is rebuilt with a fresh one every 10 minutes while Rocket runs.
+    /// Any configured password is ignored.
+    ///
+    /// RDS only accepts tokens over TLS: `ssl_mode` defaults to `require` and
+    /// `disable`, `allow` and `prefer` fail validation.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    #[cfg(feature = "rds-iam")]
+    pub fn with_rds_iam(
+        mut self,
+        region: impl Into<String>,
+        credentials: Option<aws_credential_types::provider::SharedCredentialsProvider>,
+    ) -> Self {
+        self.rds_iam = Some(RdsIam::new(region.into(), credentials));
+        self
+    }
+
+    /// The RDS IAM settings, when enabled with `with_rds_iam`.
+    #[cfg(feature = "rds-iam")]
+    pub(crate) fn rds_iam(&self) -> Option<&RdsIam> {
+        self.rds_iam.as_ref()
+    }
+
+    /// Set statements to prepare on the pool's connections at ignite
+    ///
+    /// After the migrations and init SQL, `min_connections` connections, at least one, are
+    /// checked out at once and each statement is prepared on every one of them, so they are
+    /// in the statement caches before the first request. Write them exactly as the queries
+    /// run them, as the cache is keyed by the SQL text. A statement that fails to prepare,
+    /// likely a typo or schema drift, aborts launch naming it.
+    ///
+    /// Only the first `statement_cache_capacity` statements are prepared, since more would
+    /// evict the first ones, and none when the cache is disabled or in PgBouncer mode.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_prepared_warmup(mut self, statements: Vec<Cow<'static, str>>) -> Self {
+        self.prepared_warmup = statements;
+        self
+    }
+
+    /// Set the `search_path` of every pooled connection
+    ///
+    /// e.g. `with_search_path(&["app", "public"])`. Each schema is quoted, so names with
+    /// capital letters or spaces are used verbatim. It is sent as a startup parameter, so
+    /// connections the pool opens later get it too.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_search_path<S: AsRef<str>>(mut self, schemas: &[S]) -> Self {
+        self.search_path = schemas
+            .iter()
+            .map(|schema| Cow::Owned(schema.as_ref().to_owned()))
+            .collect();
+        self
+    }
+
+    /// Set a single schema as the `search_path` of every pooled connection
+    ///
+    /// Shorthand for `with_search_path(&[schema])`.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_schema(mut self, schema: impl Into<Cow<'static, str>>) -> Self {
+        self.search_path = vec![schema.into()];
+        self
+    }
+
+    /// Set the `statement_timeout` of every pooled connection
+    ///
+    /// Statements running longer are cancelled by the server with SQLSTATE 57014. The
+    /// value is sent in milliseconds as a startup parameter, so it also applies to
+    /// connections the pool opens later. `None` or a zero duration means no timeout.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_statement_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.statement_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Set the `lock_timeout` of every pooled connection
+    ///
+    /// Statements waiting longer than this for a lock fail with SQLSTATE 55P03 instead of
+    /// queueing. Like `statement_timeout` it is sent as a startup parameter, so it applies to
+    /// reconnects and is inherited by transactions, and the two are set independently.
+    /// `None` or a zero duration means no timeout.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_lock_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.lock_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Set the `idle_in_transaction_session_timeout` of every pooled connection
+    ///
+    /// Postgres terminates sessions that sit idle inside an open transaction for longer,
+    /// releasing their locks so a forgotten transaction cannot block autovacuum. The pool
+    /// notices the dead connection and replaces it, and with `test_before_acquire` enabled
+    /// it is discarded before any handler receives it. `None` or a zero duration means no
+    /// timeout.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_idle_in_transaction_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.idle_in_transaction_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Set the idle time after which TCP keepalive probes are sent on every connection
+    ///
+    /// sqlx doesn't expose client socket options, so this sets the server's
+    /// `tcp_keepalives_idle` and `tcp_keepalives_interval` as startup options. The server
+    /// probes idle connections every `idle`, whole seconds, which keeps NAT gateways and
+    /// firewalls from silently dropping them and lets the server notice dead clients. A
+    /// connection nobody answers for is still only noticed by the pool on its next use, so
+    /// keep `test_before_acquire` on. Applies to every new connection, reconnects included.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_tcp_keepalive(mut self, idle: Duration) -> Self {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// Set the server's `tcp_user_timeout` of every connection
+    ///
+    /// The server closes a connection once data sent on it stays unacknowledged for longer.
+    /// Needs Postgres 12 and a server platform with `TCP_USER_TIMEOUT`, such as Linux;
+    /// elsewhere the server rejects the connection. Like [`SqlxPostgresConfig::with_tcp_keepalive`]
+    /// it is a startup option, as sqlx doesn't expose client socket options.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_tcp_user_timeout(mut self, timeout: Duration) -> Self {
+        self.tcp_user_timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether every pooled connection defaults to read-only transactions
+    ///
+    /// Sets `default_transaction_read_only = on`, so an accidental `INSERT` or `UPDATE`
+    /// fails with SQLSTATE 25006 (read_only_sql_transaction) instead of writing. Sent as a
+    /// startup parameter, so connections the pool opens later are read-only too.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Set the lowest level of server notices, such as `RAISE NOTICE`, that reach the log
+    ///
+    /// sqlx logs the notices it receives with the target `sqlx::postgres::notice`, mapping
+    /// `WARNING` to warn, `NOTICE` and `INFO` to info and `DEBUG` to debug. This sets the
+    /// `client_min_messages` of every pooled connection so the server only sends notices at
+    /// or above `level`, and `LevelFilter::Off` silences all but `INFO`, which Postgres always
+    /// sends. sqlx does not expose notices, so they keep its target and carry no SQLSTATE.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_notice_log_level(mut self, level: LevelFilter) -> Self {
+        self.notice_log_level = Some(level);
+        self
+    }
+
+    /// Set the `TimeZone` of every pooled connection
+    ///
+    /// e.g. `with_timezone("UTC")`, so sessions behave the same regardless of the server
+    /// default. An unknown name makes the server refuse the connection.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_timezone(mut self, timezone: impl Into<Cow<'static, str>>) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    /// Switch every pooled connection to `role` with `SET ROLE` right after logging in
+    ///
+    /// For logging in with a login role that only holds the membership of the role granted
+    /// the tables, e.g. `with_role("app_rw")`. The name is quoted as an identifier, so it is
+    /// case sensitive. A connection that can't switch, because the role doesn't exist or the
+    /// login role isn't a member, is refused instead of running with the privileges of the
+    /// login role. `RESET ALL` and `SET SESSION AUTHORIZATION DEFAULT` switch back, so with
+    /// an [`SqlxPostgresConfig::with_after_release`] hook like [`crate::reset_session_state`]
+    /// the role is set again once the hook kept the connection. The ignite log line shows
+    /// the role.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_role(mut self, role: impl Into<Cow<'static, str>>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Set a server setting on every pooled connection
+    ///
+    /// Sent as a `-c key=value` startup option, e.g. `with_option("work_mem", "64MB")`.
+    /// Calls accumulate and a later value for the same key replaces the earlier one.
+    /// These are applied after the dedicated builder settings, so they win on conflicts.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_option(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let key = key.into();
+        self.options.retain(|(existing, _)| *existing != key);
+        self.options.push((key, value.into()));
+        self
+    }
+
+    /// Set the application name reported in `pg_stat_activity`
+    ///
+    /// Defaults to the name of the running binary.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_application_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.application_name = Some(name.into());
+        self
+    }
+
+    /// Set the level statements taking longer than `threshold` are logged at
+    ///
+    /// Independent of [`SqlxPostgresConfig::with_loglevel`], so normal statement logging can be
+    /// turned off while slow ones are still reported. Defaults to warn after 1 second like sqlx.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_slow_statement_log(mut self, level: LevelFilter, threshold: Duration) -> Self {
+        self.slow_statement_log = (level, threshold);
+        self
+    }
+
+    /// Log only about `rate`, from 0.0 to 1.0, of the statements at the configured level
+    ///
+    /// Statements over the [`SqlxPostgresConfig::with_slow_statement_log`] threshold are still
+    /// all logged. Sampling is decided per statement of the guards' executors, logged with the
+    /// `sqlx::query` target like unsampled ones; statements run on the raw `PgPool` are not
+    /// logged then. 0.0 turns statement logging off and 1.0 logs every statement as without
+    /// sampling.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_statement_log_sampling(mut self, rate: f64) -> Self {
+        // `max` turns NaN into 0.0.
+        self.statement_log_sampling = Some(rate.max(0.0).min(1.0));
+        self
+    }
+
+    /// Set the level of the `db.connect` and `db.acquire` tracing spans
+    ///
+    /// Defaults to `INFO`. The spans carry the host, port, database and user, never the
+    /// password.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    #[cfg(feature = "tracing")]
+    pub fn with_tracing_span_level(mut self, level: tracing::Level) -> Self {
+        self.tracing_span_level = level;
+        self
+    }
+
+    /// Set the level of the tracing events emitted for statements run through the guards
+    ///
+    /// Defaults to `DEBUG`. Each event carries the statement, its duration and the rows
+    /// returned or affected. Queries run on a transaction or connection guard are only
+    /// logged by sqlx, see [`SqlxPostgresConfig::with_loglevel`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    #[cfg(feature = "tracing")]
+    pub fn with_tracing_statement_level(mut self, level: tracing::Level) -> Self {
+        self.tracing_statement_level = level;
+        self
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn connect_span(&self) -> tracing::Span {
+        observe::trace::connect(
+            self.tracing_span_level,
+            &self.host,
+            self.port,
+            &self.database,
+            &self.username,
+            self.log_request_id(None).as_deref(),
+        )
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn acquire_span(&self, rid: Option<&str>) -> tracing::Span {
+        observe::trace::acquire(self.tracing_span_level, &self.database, rid)
+    }
+
+    /// Set the TLS mode used to connect to the database
+    ///
+    /// Use `PgSslMode::VerifyCa` or `PgSslMode::VerifyFull` together with
+    /// [`SqlxPostgresConfig::with_ssl_root_cert`] for providers that ship their own CA.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_ssl_mode(mut self, mode: PgSslMode) -> Self {
+        self.ssl_mode = Some(mode);
+        self
+    }
+
+    /// Set the root certificate bundle used to verify the server
+    ///
+    /// The file must exist when Rocket ignites or launch is aborted.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_ssl_root_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ssl_root_cert = Some(path.into());
+        self
+    }
+
+    /// Set the client certificate used for mutual TLS
+    ///
+    /// Must be combined with [`SqlxPostgresConfig::with_ssl_client_key`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_ssl_client_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ssl_client_cert = Some(path.into());
+        self
+    }
+
+    /// Set the private key of the client certificate used for mutual TLS
+    ///
+    /// Must be combined with [`SqlxPostgresConfig::with_ssl_client_cert`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_ssl_client_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ssl_client_key = Some(path.into());
+        self
+    }
+
+    /// The application name used for connections, falling back to the binary name.
+    pub(crate) fn application_name(&self) -> Cow<'static, str> {
+        if let Some(name) = &self.application_name {
+            return name.clone();
+        }
+
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+            .map(Cow::Owned)
+            .unwrap_or(Cow::Borrowed(env!("CARGO_PKG_NAME")))
+    }
+
+    /// Set whether SCRAM authentication must be bound to the TLS channel
+    ///
+    /// sqlx authenticates with SCRAM-SHA-256 without channel binding, so
+    /// `ChannelBinding::Require` cannot be honored and aborts launch at ignite instead of
+    /// silently connecting without it. `Disable` and `Prefer` connect normally.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_channel_binding(mut self, binding: ChannelBinding) -> Self {
+        self.channel_binding = binding;
+        self
+    }
+
+    /// Set a callback run on every new connection the pool opens
+    ///
+    /// Useful for `SET` statements or registering custom types. An error returned by
+    /// the callback fails that connection attempt instead of handing it out.
+    ///
+    /// ```rust,ignore
+    /// config.with_after_connect(|conn| Box::pin(async move {
+    ///     conn.execute("SET work_mem = '64MB'").await?;
+    ///     Ok(())
+    /// }))
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_after_connect<F>(mut self, hook: F) -> Self
+    where
+        F: for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<(), sqlx::Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.after_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a callback run on an idle connection before the pool hands it out
+    ///
+    /// Returning `Ok(false)` or an error closes the connection and the pool tries another
+    /// one, e.g. to drop connections that sat idle too long. Runs after the
+    /// `test_before_acquire` ping.
+    ///
+    /// ```rust,ignore
+    /// config.with_before_acquire(|_conn, meta| Box::pin(async move {
+    ///     Ok(meta.idle_for < Duration::from_secs(300))
+    /// }))
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_before_acquire<F>(mut self, hook: F) -> Self
+    where
+        F: for<'c> Fn(
+                &'c mut PgConnection,
+                PoolConnectionMetadata,
+            ) -> BoxFuture<'c, Result<bool, sqlx::Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.before_acquire = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a callback run on a connection when it is returned to the pool
+    ///
+    /// Returning `Ok(false)` or an error closes the connection instead of keeping it. See
+    /// [`reset_session_state`] for resetting what a request left set on the session.
+    ///
+    /// ```rust,ignore
+    /// config.with_after_release(rocket_sqlxpostgres::reset_session_state())
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_after_release<F>(mut self, hook: F) -> Self
+    where
+        F: for<'c> Fn(
+                &'c mut PgConnection,
+                PoolConnectionMetadata,
+            ) -> BoxFuture<'c, Result<bool, sqlx::Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.after_release = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a callback adjusting the sqlx pool options before the pool is created
+    ///
+    /// Runs after every pool setting of this config is applied, so it can set options this
+    /// config does not expose or override them. Setting `after_connect`, `before_acquire` or
+    /// `after_release` in it replaces the hook of this config, and setting `after_connect` the
+    /// `target_session_attrs` check too.
+    /// Not used when the fairing is given an existing pool.
+    ///
+    /// ```rust,ignore
+    /// config.with_pool_customizer(|options| {
+    ///     options.before_acquire(|_conn, _meta| Box::pin(async { Ok(true) }))
+    /// })
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_pool_customizer<F>(mut self, customizer: F) -> Self
+    where
+        F: Fn(PgPoolOptions) -> PgPoolOptions + Send + Sync + 'static,
+    {
+        self.pool_customizer = Some(Arc::new(customizer));
+        self
+    }
+
+    /// Use prebuilt sqlx connect options instead of the connection fields of this config
+    ///
+    /// When set, the options are used as is: host, port, socket, credentials, database,
+    /// TLS, logging, search_path, startup options and application name set on this config
+    /// are ignored. Pool settings such as `max_connections`, timeouts and hooks still apply.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_connect_options(mut self, options: PgConnectOptions) -> Self {
+        self.connect_options = Some(options);
+        self
+    }
+
+    /// Set whether Rocket launches without a pool when connecting fails
+    ///
+    /// By default a failed connection aborts launch. When ignored the error is still
+    /// logged, but no pool is managed and database guards fail at request time.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn ignore_connect_errors(mut self, ignore: bool) -> Self {
+        self.ignore_connect_errors = ignore;
+        self
+    }
+
+    /// Set whether connecting is deferred until a connection is first acquired
+    ///
+    /// The pool is created at ignite without contacting the database, so launch never
+    /// fails because of it and connection errors surface on requests instead. Retries are
+    /// not used.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Set whether the bare `PgPool` is managed next to this crate's pool, off by default
+    ///
+    /// Handlers and libraries can then use `&State<PgPool>` as well as [`SQLxPostgres`].
+    /// Only applies to the unnamed fairing, named databases are never managed as `PgPool`.
+    ///
+    /// `State<PgPool>` stays the pool connected at ignite: pools the supervisor or a
+    /// [`crate::PoolHandle`] swap in later are reached through `&State<PgPoolSlot>`, which
+    /// is managed as well. Once swapped out the ignite pool is closed after
+    /// [`SqlxPostgresConfig::with_drain_timeout`], so code that outlives a swap should use
+    /// the slot.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn manage_raw_pool(mut self, manage: bool) -> Self {
+        self.manage_raw_pool = manage;
+        self
+    }
+
+    /// Set whether the database is created when it does not exist, off by default
+    ///
+    /// When connecting at ignite fails because the database is missing, it is created
+    /// through the `postgres` maintenance database with the same credentials and connecting
+    /// is retried. Meant for local development and preview environments. Not used in lazy
+    /// mode or with [`SqlxPostgresConfig::with_connect_options`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn create_database_if_missing(mut self, create: bool) -> Self {
+        self.create_database = create;
+        self
+    }
+
+    /// Set whether the configured schema is created at ignite when it does not exist
+    ///
+    /// The schema is the first entry of [`SqlxPostgresConfig::with_search_path`] other than
+    /// `$user`, and is created before migrations and init SQL run. Launch is aborted when
+    /// the role may not create it, or when no schema is configured.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn ensure_schema(mut self, ensure: bool) -> Self {
+        self.ensure_schema = ensure;
+        self
+    }
+
+    /// Run `SELECT 1` at ignite to confirm the database answers queries
+    ///
+    /// The ping must complete within `timeout` and its round trip time is logged. This
+    /// catches proxies that accept connections but never answer. A failed ping aborts
+    /// launch unless [`SqlxPostgresConfig::ignore_connect_errors`] is set. Skipped in lazy mode.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_startup_ping(mut self, timeout: Duration) -> Self {
+        self.startup_ping = Some(timeout);
+        self
+    }
+
+    /// Set whether the server's role is checked at ignite, `false` by default
+    ///
+    /// Runs `SELECT pg_is_in_recovery()`, on the startup ping when one is set. A primary
+    /// that is in recovery, i.e. a read-only standby, aborts launch, and a replica of
+    /// [`SqlxPostgresFairing::with_replica`] that is not is logged, see
+    /// [`SqlxPostgresFairing::strict_replica_role`]. The log names the configured user, host
+    /// and database. Skipped in lazy mode and for pools passed to the fairing.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn verify_server_role(mut self, verify: bool) -> Self {
+        self.verify_server_role = verify;
+        self
+    }
+
+    /// Refuse to launch against a server older than `major.minor`
+    ///
+    /// The version is read from `server_version_num` after connecting, falling back to
+    /// parsing `server_version` for forks. Not checked unless set, nor in lazy mode.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn require_server_version(mut self, major: u32, minor: u32) -> Self {
+        self.server_version = Some((major, minor));
+        self
+    }
+
+    /// Set whether `max_connections` is compared with the server's limit at ignite, `false`
+    /// by default
+    ///
+    /// Reads the server's `max_connections` and `superuser_reserved_connections` and counts
+    /// the client connections in `pg_stat_activity` other than this pool's, and warns when
+    /// this pool may open more connections than are left, logging every number. Several
+    /// services sized against the same server otherwise fail with sporadic connection errors
+    /// under load. Skipped in lazy mode, in pgbouncer mode, where the counts are the
+    /// pooler's, and for pools passed to the fairing; on servers older than Postgres 10 the
+    /// check is logged as failed.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn check_connection_limit(mut self, check: bool) -> Self {
+        self.check_connection_limit = check;
+        self
+    }
+
+    /// Set whether exceeding the server's connection limit aborts launch, `false` by default
+    ///
+    /// See [`SqlxPostgresConfig::check_connection_limit`]. Failing to read the limit still
+    /// only warns.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn strict_connection_limit(mut self, strict: bool) -> Self {
+        self.strict_connection_limit = strict;
+        self
+    }
+
+    /// Label request transactions in `pg_stat_activity` with a per-request `application_name`
+    ///
+    /// The transaction guards set it with `SET LOCAL` semantics, passing the value as a bind
+    /// parameter, so it ends with the transaction. [`crate::SQLxPostgresConnection`] is not
+    /// labeled. The template may use `{method}`, `{route}` (e.g. `/users/<id>`), `{name}` (the
+    /// handler) and `{request_id}`, read from [`SqlxPostgresConfig::with_request_id_header`]
+    /// or [`SqlxPostgresConfig::with_request_id_extractor`].
+    /// The result is truncated to Postgres' 63 byte limit.
+    ///
+    /// ```rust,ignore
+    /// config.with_request_application_name("{method} {route} rid={request_id}")
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_application_name(mut self, template: impl Into<Cow<'static, str>>) -> Self {
+        self.request_application_name = Some(template.into());
+        self
+    }
+
+    /// Cancel the queries of a request server side once its budget runs out
+    ///
+    /// `extractor` returns the time the request has left, e.g. parsed from a header set by
+    /// the gateway, or `None` to keep the configured `statement_timeout`. The transaction
+    /// guards apply it with `SET LOCAL statement_timeout` right after `BEGIN`, so it ends
+    /// with the transaction and never reaches the next user of the connection.
+    /// [`crate::SQLxPostgresConnection`] runs outside a transaction and is not limited.
+    /// Budgets below [`SqlxPostgresConfig::with_request_deadline_floor`] are raised to it.
+    ///
+    /// ```rust,ignore
+    /// config.with_request_deadline(|request| {
+    ///     let deadline: u64 = request.headers().get_one("X-Request-Deadline")?.parse().ok()?;
+    ///     let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as u64;
+    ///     Some(Duration::from_millis(deadline.saturating_sub(now)))
+    /// })
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_deadline<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&Request<'_>) -> Option<Duration> + Send + Sync + 'static,
+    {
+        self.request_deadline = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Set the lowest `statement_timeout` a request budget is applied as, 100 ms by default
+    ///
+    /// Keeps an almost spent budget from failing every query of the request right away; a
+    /// budget already spent is raised to the floor too.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_deadline_floor(mut self, floor: Duration) -> Self {
+        self.request_deadline_floor = floor;
+        self
+    }
+
+    /// Cancel the query a request left running when it is dropped, e.g. because the client
+    /// went away, off by default
+    ///
+    /// The connection, transaction, read only and RLS guards note the `pg_backend_pid()` of
+    /// their connection when created, one extra query. When such a guard is dropped its
+    /// connection is pinged before going back to the pool; a connection still busy after
+    /// `grace` runs a query nobody waits for, which is cancelled with `pg_cancel_backend` from
+    /// a dedicated connection opened for it, as the pool may be exhausted. sqlx doesn't expose
+    /// the key of the protocol's cancel request, hence the extra connection. The connection
+    /// is held until then, so the cancel can only hit
+    /// the backend the guard used, and at worst a query that was about to finish. Guards
+    /// dropped after their last query answer the ping right away. Connections taken out with
+    /// `into_inner`, the automatic transaction guard and the pool guards are not covered.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_cancel_on_disconnect(mut self, grace: Duration) -> Self {
+        self.cancel_on_disconnect = Some(grace);
+        self
+    }
+
+    /// Set how the [`crate::TenantDb`] guard resolves the schema of a request's tenant
+    ///
+    /// An invalid naming template or shared schema aborts launch at ignite.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_tenant_schemas(mut self, tenants: TenantSchemas) -> Self {
+        self.tenant_schemas = Some(tenants);
+        self
+    }
+
+    /// Set the header the request id of `{request_id}` and the logs is read from,
+    /// `X-Request-Id` by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_id_header(mut self, header: impl Into<Cow<'static, str>>) -> Self {
+        self.request_id_header = header.into();
+        self
+    }
+
+    /// Read the request id with `extractor` instead of from the request id header
+    ///
+    /// For ids the application keeps in [`Request::local_cache`], e.g. generated by a fairing
+    /// when the client sent none. Returning `None` logs `rid=-`.
+    ///
+    /// ```rust,ignore
+    /// config.with_request_id_extractor(|request| {
+    ///     request.local_cache(|| RequestId(None)).0.clone()
+    /// })
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_id_extractor<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.request_id_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Add the request id to the query log events of the crate, off by default
+    ///
+    /// The sampled statement log, the slow query plans, the slow acquire warnings and the
+    /// health probe are prefixed with `rid=<id>` under plain `log`; under the `tracing`
+    /// feature the statement events and the acquire and connect spans get a `rid` field.
+    /// Events outside a request, like connecting at ignite, the health probe or queries of
+    /// [`SQLxPostgres`] used outside a handler, carry `rid=-`, as do requests without an id.
+    /// The id comes from [`SqlxPostgresConfig::with_request_id_header`] or
+    /// [`SqlxPostgresConfig::with_request_id_extractor`]. The statement log of sqlx itself
+    /// knows nothing of the request and is left as is.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_log_request_id(mut self, enabled: bool) -> Self {
+        self.log_request_id = enabled;
+        self
+    }
+
+    /// The id of `request`, from the extractor or else the request id header.
+    pub(crate) fn request_id(&self, request: &Request<'_>) -> Option<String> {
+        match &self.request_id_extractor {
+            Some(extractor) => extractor(request),
+            None => request
+                .headers()
+                .get_one(&self.request_id_header)
+                .map(str::to_owned),
+        }
+    }
+
+    /// The request id the query log events carry, `-` outside a request or without an id,
+    /// `None` unless [`SqlxPostgresConfig::with_log_request_id`] is on.
+    pub(crate) fn log_request_id(&self, request: Option<&Request<'_>>) -> Option<Arc<str>> {
+        if !self.log_request_id {
+            return None;
+        }
+
+        let id = request.and_then(|request| self.request_id(request));
+        Some(Arc::from(id.as_deref().unwrap_or("-")))
+    }
+
+    /// Set how long the connection and transaction guards wait for a connection
+    ///
+    /// When the pool is saturated the guards fail with a 503 and
+    /// [`PoolGuardError::AcquireTimeout`] after `timeout` instead of waiting for the pool's
+    /// acquire timeout, and the fairing adds a `Retry-After: 1` header to the response.
+    /// [`SQLxPostgres`] never acquires and is not affected.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_guard_acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.guard_acquire_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how many connection and transaction guards may wait for a connection at once
+    ///
+    /// When Postgres slows down, further guards fail right away with a 503,
+    /// [`PoolGuardError::TooManyWaiters`] and a `Retry-After: 1` header instead of queueing
+    /// up, so piled up requests can't exhaust the memory of the instance. Guards that time
+    /// out or whose request is dropped free their place. Refusals are counted in
+    /// [`PoolStatus::rejected_waiters`] and the metrics. Unlimited by default.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_max_waiters(mut self, max: usize) -> Self {
+        self.max_waiters = Some(max);
+        self
+    }
+
+    /// Set a circuit breaker failing the database guards fast during an outage
+    ///
+    /// After the policy's number of consecutive connection errors or acquire timeouts, the
+    /// breaker opens and the guards fail with 503 and [`PoolGuardError::CircuitOpen`] without
+    /// waiting for a connection. Once the cooldown passed one request is let through to probe
+    /// the database: it closes the breaker when it succeeds and reopens it when it fails.
+    /// Transitions are logged and the state is reported in [`PoolStatus::circuit_breaker`],
+    /// the metrics and the readiness route. Disabled by default.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_circuit_breaker(mut self, policy: CircuitBreakerPolicy) -> Self {
+        self.circuit_breaker = Some(CircuitBreaker::new(policy));
+        self
+    }
+
+    /// Set a watchdog warning about transactions held open too long, e.g. across a call to
+    /// another service
+    ///
+    /// A background task lists the sessions of this application in `pg_stat_activity` every
+    /// interval and logs those idle in a transaction, or running one statement, for longer
+    /// than the threshold, with their pid, state, duration and the start of their query.
+    /// Sessions match on the application name; with
+    /// [`SqlxPostgresConfig::with_request_application_name`] every session of the same user
+    /// and database does. [`TransactionWatchdog::with_terminate_after`] also terminates them.
+    /// The watchdog holds one connection outside the pool and stops at shutdown. Disabled by
+    /// default.
+    ///
+    /// ```rust,ignore
+    /// config.with_transaction_watchdog(TransactionWatchdog::new(Duration::from_secs(30)))
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_transaction_watchdog(mut self, watchdog: TransactionWatchdog) -> Self {
+        self.transaction_watchdog = Some(watchdog);
+        self
+    }
+
+    /// Log the plan of queries slower than the policy's threshold
+    ///
+    /// Queries run through [`SQLxPostgres`], [`SQLxPostgresRef`] and [`SQLxPostgresReplica`]
+    /// taking longer than the threshold are followed by an `EXPLAIN (FORMAT JSON)` of the same
+    /// statement, logged as a warning with the duration of the query. It runs in the
+    /// background on an idle connection of the same pool, and is skipped when there is none,
+    /// when the policy's per minute budget is spent, and for statements that can't be explained
+    /// such as DDL or several statements. sqlx can't reuse bind parameters, so queries with
+    /// parameters get their generic plan, which needs Postgres 16: older servers reject
+    /// `GENERIC_PLAN`, which is logged once as a warning, and from then on only queries
+    /// without parameters are explained. The statement is not run again unless
+    /// [`crate::ExplainPolicy::with_analyze`] is set. Disabled by default.
+    ///
+    /// ```rust,ignore
+    /// config.with_slow_query_explain(ExplainPolicy::new(Duration::from_millis(500)))
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_slow_query_explain(mut self, policy: ExplainPolicy) -> Self {
+        self.slow_query_explain = Some(Explainer::new(policy));
+        self
+    }
+
+    /// Set the query run by the readiness route of [`crate::health_routes`]
+    ///
+    /// Defaults to `SELECT 1`.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_health_query(mut self, query: impl Into<Cow<'static, str>>) -> Self {
+        self.health_query = query.into();
+        self
+    }
+
+    /// Set how long the readiness route waits for its query before answering 503
+    ///
+    /// Defaults to 2 seconds.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_health_timeout(mut self, timeout: Duration) -> Self {
+        self.health_timeout = timeout;
+        self
+    }
+
+    /// Set how often a background task runs the health query to keep [`crate::DbHealth`]
+    /// up to date
+    ///
+    /// The probe uses the health query and timeout, starts at liftoff and stops with Rocket.
+    /// Disabled by default, in which case no `DbHealth` is managed.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_health_probe_interval(mut self, interval: Duration) -> Self {
+        self.health_probe_interval = Some(interval);
+        self
+    }
+
+    /// Set how often the pool status is logged
+    ///
+    /// From liftoff a line like `pool: size=8 idle=3 in_use=5 waiters=0` is logged at info
+    /// level every `interval`, or at debug level when nothing changed since the last one.
+    /// The task stops with Rocket.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_metrics_log_interval(mut self, interval: Duration) -> Self {
+        self.metrics_log_interval = Some(interval);
+        self
+    }
+
+    /// Set whether [`SQLxPostgres::cached`] caches results
+    ///
+    /// Disabled by default, in which case the closures passed to it run on every call.
+    /// Turning it off in one profile, e.g. `query_cache = false` in Rocket.toml, needs no
+    /// change to the handlers.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn query_cache(mut self, enabled: bool) -> Self {
+        self.query_cache = enabled;
+        self
+    }
+
+    /// Set the number of results the query cache holds, 1000 by default
+    ///
+    /// When full, expired entries are dropped first and then the least recently used one.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_query_cache_max_entries(mut self, max_entries: usize) -> Self {
+        self.query_cache_max_entries = max_entries;
+        self
+    }
+
+    /// Add a secondary pool named `name` to the same database, with its own sizing and
+    /// timeouts
+    ///
+    /// Meant for slow work such as reports: each pool has its own connections and waiters, so
+    /// exhausting the secondary pool never blocks acquiring from the primary one. The pool is
+    /// registered in [`crate::SQLxPostgresPools`] like a named database and handed out by
+    /// `SQLxPostgres<D>` with a [`crate::DatabaseMarker`] named `name`. It gets its own
+    /// metrics, status log, supervisor and is closed at shutdown; migrations and init SQL only
+    /// run on the primary pool. Adding a name again replaces its settings.
+    ///
+    /// ```rust,ignore
+    /// let config = config.with_secondary_pool(
+    ///     "reports",
+    ///     PoolSettings {
+    ///         max_connections: 2,
+    ///         statement_timeout: Some(Duration::from_secs(60)),
+    ///         ..PoolSettings::default()
+    ///     },
+    /// );
+    /// ```
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_secondary_pool(mut self, name: &'static str, settings: PoolSettings) -> Self {
+        self.secondary_pools
+            .retain(|(existing, _)| *existing != name);
+        self.secondary_pools.push((name, settings));
+        self
+    }
+
+    /// Set whether the time each request spent on the database is accounted
+    ///
+    /// The queries run through the pool guards, plus the time the connection and
+    /// transaction guards waited for a connection, are summed per request and logged at
+    /// info level once the response is ready. With `headers` the totals are also sent as
+    /// `X-DB-Time-Ms` and `X-DB-Queries`. Concurrent queries of one handler are summed, so
+    /// the total can exceed the request's latency. Queries run on a connection or
+    /// transaction guard are not timed individually.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_request_db_time(mut self, headers: bool) -> Self {
+        self.request_db_time = Some(headers);
+        self
+    }
+
+    /// Log every statement of requests sending the `header`, e.g. `X-DB-Debug: 1`
+    ///
+    /// The statements such a request runs through the pool guards are recorded in order with
+    /// their duration and row counts, never their bind values, and logged at info level once
+    /// the response is ready. With `count_header` their number is also sent back as
+    /// `X-DB-Captured-Queries`. A value of `0` doesn't capture, and requests without the header
+    /// cost nothing extra. Any client can send the header, so enable this where the SQL text
+    /// may end up in the logs, or strip the header at the proxy. Statements run on a connection
+    /// or transaction guard are not captured.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_query_capture(
+        mut self,
+        header: impl Into<Cow<'static, str>>,
+        count_header: bool,
+    ) -> Self {
+        self.query_capture = Some((header.into(), count_header));
+        self
+    }
+
+    /// Set whether the app starts in [`crate::MaintenanceMode`], refusing database work
+    ///
+    /// Disabled by default. The managed `MaintenanceMode` can switch it at runtime either way.
+    /// The pools still connect, migrations still run.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_maintenance_mode(mut self, enabled: bool) -> Self {
+        self.maintenance_mode = enabled;
+        self
+    }
+
+    /// Set the `Retry-After` sent with responses refused for maintenance
+    ///
+    /// Defaults to 60 seconds, rounded to whole seconds of at least 1.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_maintenance_retry_after(mut self, retry_after: Duration) -> Self {
+        self.maintenance_retry_after = retry_after;
+        self
+    }
+
+    /// Set whether [`SQLxPostgresReplica`] reads from the primary right after the client
+    /// used it, so a redirect following a write sees the write
+    ///
+    /// Any guard of the unnamed primary pool sets a private cookie that routes the client's
+    /// replica guards to the primary for [`SqlxPostgresConfig::with_sticky_reads_window`].
+    /// Clients ignoring cookies keep reading from the replica. [`SQLxPostgresReplica::allow_stale`]
+    /// opts a handler out. Needs Rocket's `secret_key` in release builds. Disabled by default.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    #[cfg(feature = "sticky-reads")]
+    pub fn with_sticky_reads(mut self, sticky_reads: bool) -> Self {
+        self.sticky_reads = sticky_reads;
+        self
+    }
+
+    /// Set how long replica reads stay on the primary with `with_sticky_reads`
+    ///
+    /// Should cover the replication lag. Defaults to 5 seconds.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    #[cfg(feature = "sticky-reads")]
+    pub fn with_sticky_reads_window(mut self, window: Duration) -> Self {
+        self.sticky_reads_window = window;
+        self
+    }
+
+    /// Set the acquire time above which the connection and transaction guards log a warning
+    ///
+    /// The warning names the route, the wait and the pool status, and is logged at most
+    /// once every 10 seconds with a count of the ones held back, so a saturated pool does
+    /// not flood the log.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_slow_acquire_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_acquire_threshold = Some(threshold);
+        self
+    }
+
+    /// Set whether the pool's connections are opened at ignite
+    ///
+    /// Opens `min_connections` connections at once, or `max_connections` when no minimum
+    /// is set, and runs `SELECT 1` on each so the first requests after a deploy don't pay
+    /// the connection latency. Failures are logged, see [`SqlxPostgresConfig::warm_up_required`].
+    /// Skipped in lazy mode.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn warm_up(mut self, warm_up: bool) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Set how long the warm up may take before it is abandoned, defaults to 30 seconds
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_warm_up_timeout(mut self, timeout: Duration) -> Self {
+        self.warm_up_timeout = timeout;
+        self
+    }
+
+    /// Set whether a failed or timed out warm up aborts launch, `false` by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn warm_up_required(mut self, required: bool) -> Self {
+        self.warm_up_required = required;
+        self
+    }
+
+    /// Run a supervisor that checks the pool every `interval` and rebuilds it when needed
+    ///
+    /// Started at liftoff, it runs `SELECT 1` bounded by the connect timeout. After
+    /// three failed checks in a row, or as soon as the pool is closed, a fresh pool is built
+    /// from this config and swapped in; guards created afterwards get the new pool. Rebuilding
+    /// backs off like connect retries and the supervisor stops after 10 failed attempts, see
+    /// [`SqlxPostgresConfig::with_supervisor_attempts`]. Not used when the fairing is given an
+    /// existing pool. The replaced pool is closed after [`SqlxPostgresConfig::with_drain_timeout`].
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn supervise(mut self, interval: Duration) -> Self {
+        self.supervisor_interval = Some(interval);
+        self
+    }
+
+    /// Set how many rebuilds in a row the supervisor attempts before giving up
+    ///
+    /// Defaults to 10 and is clamped to at least 1.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_supervisor_attempts(mut self, attempts: u32) -> Self {
+        self.supervisor_attempts = std::cmp::max(attempts, 1);
+        self
+    }
+
+    /// Set how long shutdown waits for checked out connections to be returned
+    ///
+    /// When Rocket shuts down the pool is closed, idle connections right away and busy
+    /// ones as they are returned. Connections still out after the grace period are
+    /// closed when dropped. Defaults to 5 seconds.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Set how long a replaced pool keeps serving before it is closed
+    ///
+    /// Pools swapped out by the supervisor, a [`crate::PoolHandle`], a statement log change
+    /// or an RDS token refresh stay usable for this long, so requests holding their
+    /// connections finish normally. The pool is then closed: idle connections right away,
+    /// busy ones as they are returned, and `PgPool` clones of it, `State<PgPool>` included,
+    /// fail to acquire. Defaults to 30 seconds.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_drain_timeout(mut self, drain: Duration) -> Self {
+        self.drain_timeout = drain;
+        self
+    }
+
+    /// Applies `connections_per_worker` for a Rocket running `workers` workers.
+    pub(crate) fn scale_to_workers(mut self, workers: usize) -> Result<Self, ConfigError> {
+        let (factor, cap) = match self.connections_per_worker {
+            Some(scaling) => scaling,
+            None => return Ok(self),
+        };
+
+        self.validate()?;
+
+        let mut max = (workers as f32 * factor).ceil() as u32;
+
+        if let Some(cap) = cap {
+            max = std::cmp::min(max, cap);
+        }
+
+        self.max_connections = std::cmp::max(max, 1);
+        log::info!(
+            "SQLxPostgres: {} workers * {} per worker = {} max connections",
+            workers,
+            factor,
+            self.max_connections
+        );
+        Ok(self)
+    }
+
+    /// Prepared statements each connection actually caches, none in PgBouncer mode.
+    fn cached_statements(&self) -> usize {
+        match self.pgbouncer_mode {
+            true => 0,
+            false => self.statement_cache_capacity,
+        }
+    }
+
+    /// Schema objects are created in, the first `search_path` entry other than `$user`.
+    pub(crate) fn schema(&self) -> Option<&str> {
+        self.search_path
+            .iter()
+            .map(|schema| schema.as_ref())
+            .find(|schema| *schema != "$user")
+    }
+
+    /// Builds the sqlx pool options described by this config.
+    pub(crate) fn pool_options(&self) -> PgPoolOptions {
+        let max_connections = self.max_connections();
+        let mut options = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .min_connections(std::cmp::min(self.min_connections, max_connections))
+            .acquire_timeout(self.acquire_timeout.unwrap_or(self.connect_timeout))
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+            .test_before_acquire(self.test_before_acquire);
+
+        let target_session_attrs = self.target_session_attrs;
+        let hook = self.after_connect.clone();
+        let set_role: Option<Arc<str>> = self
+            .role
+            .as_ref()
+            .map(|role| Arc::from(format!("SET ROLE {}", quote_ident(role))));
+
+        if target_session_attrs != TargetSessionAttrs::Any || hook.is_some() || set_role.is_some() {
+            let set_role = set_role.clone();
+
+            options = options.after_connect(move |conn, _meta| {
+                let hook = hook.clone();
+                let set_role = set_role.clone();
+
+                Box::pin(async move {
+                    if target_session_attrs == TargetSessionAttrs::ReadWrite {
+                        connect::check_read_write(conn).await?;
+                    }
+
+                    // Before the hook, so it already runs with the privileges of the role.
+                    if let Some(set_role) = &set_role {
+                        (&mut *conn).execute(&**set_role).await?;
+                    }
+
+                    if let Some(hook) = hook {
+                        hook(conn).await?;
+                    }
+
+                    Ok(())
+                })
+            });
+        }
+
+        if let Some(hook) = self.before_acquire.clone() {
+            options = options.before_acquire(move |conn, meta| hook(conn, meta));
+        }
+
+        match (self.after_release.clone(), set_role) {
+            (Some(hook), Some(set_role)) => {
+                // Reset hooks like `reset_session_state` also reset the role.
+                options = options.after_release(move |conn, meta| {
+                    let hook = hook.clone();
+                    let set_role = set_role.clone();
+
+                    Box::pin(async move {
+                        match hook(&mut *conn, meta).await? {
+                            true => conn.execute(&*set_role).await.map(|_| true),
+                            false => Ok(false),
+                        }
+                    })
+                });
+            }
+            (Some(hook), None) => {
+                options = options.after_release(move |conn, meta| hook(conn, meta));
+            }
+            (None, _) => {}
+        }
+
+        match &self.pool_customizer {
+            Some(customizer) => customizer(options),
+            None => options,
+        }
+    }
+
+    /// Server settings sent as `-c key=value` startup options, already escaped.
+    fn startup_options(&self) -> Vec<(String, String)> {
+        let mut options = Vec::new();
+
+        if !self.search_path.is_empty() {
+            let search_path = self
+                .search_path
+                .iter()
+                .map(|schema| quote_ident(schema))
+                .collect::<Vec<_>>()
+                .join(",");
+            options.push(("search_path".to_owned(), escape_startup_value(&search_path)));
+        }
+
+        if let Some(timeout) = self.statement_timeout {
+            options.push((
+                "statement_timeout".to_owned(),
+                timeout.as_millis().to_string(),
+            ));
+        }
+
+        if let Some(timeout) = self.lock_timeout {
+            options.push(("lock_timeout".to_owned(), timeout.as_millis().to_string()));
+        }
+
+        if let Some(timeout) = self.idle_in_transaction_timeout {
+            options.push((
+                "idle_in_transaction_session_timeout".to_owned(),
+                timeout.as_millis().to_string(),
+            ));
+        }
+
+        if let Some(idle) = self.tcp_keepalive {
+            // Whole seconds, at least one since zero means the system default.
+            let secs = idle.as_secs().max(1).to_string();
+            options.push(("tcp_keepalives_idle".to_owned(), secs.clone()));
+            options.push(("tcp_keepalives_interval".to_owned(), secs));
+        }
+
+        if let Some(timeout) = self.tcp_user_timeout {
+            options.push((
+                "tcp_user_timeout".to_owned(),
+                timeout.as_millis().max(1).to_string(),
+            ));
+        }
+
+        if self.read_only {
+            options.push(("default_transaction_read_only".to_owned(), "on".to_owned()));
+        }
+
+        if let Some(timezone) = &self.timezone {
+            options.push(("TimeZone".to_owned(), escape_startup_value(timezone)));
+        }
+
+        if let Some(level) = self.notice_log_level {
+            let min_messages = match level {
+                LevelFilter::Off | LevelFilter::Error => "error",
+                LevelFilter::Warn => "warning",
+                LevelFilter::Info => "notice",
+                LevelFilter::Debug => "debug1",
+                LevelFilter::Trace => "debug5",
+            };
+            options.push(("client_min_messages".to_owned(), min_messages.to_owned()));
+        }
+
+        for (key, value) in &self.options {
+            options.push((escape_startup_value(key), escape_startup_value(value)));
+        }
+
+        options
+    }
+
+    /// Checks the config for mistakes without touching the database.
+    ///
+    /// This is also run when the fairing ignites, aborting launch on error.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_connections == 0 {
+            return Err(ConfigError::ZeroMaxConnections);
+        }
+
+        if self
+            .acquire_timeout
+            .map_or(false, |timeout| timeout.is_zero())
+        {
+            return Err(ConfigError::ZeroDuration("acquire_timeout"));
+        }
+
+        if self.channel_binding == ChannelBinding::Require {
+            return Err(ConfigError::Unsupported {
+                option: "channel_binding=require",
+                reason: "sqlx's SCRAM authentication does not implement channel binding",
+            });
+        }
+
+        if self.connections_per_worker.is_some() && self.max_connections_set {
+            return Err(ConfigError::Conflict {
+                option: "connections_per_worker",
+                conflicts_with: "max_connections",
+            });
+        }
+
+        if self.role.as_ref().map_or(false, |role| !is_role_name(role)) {
+            return Err(ConfigError::Unsupported {
+                option: "role",
+                reason:
+                    "the role must be a non-empty name of at most 63 bytes without NUL characters",
+            });
+        }
+
+        if self
+            .tenant_schemas
+            .as_ref()
+            .map_or(false, |tenants| !tenants.is_valid())
+        {
+            return Err(ConfigError::Unsupported {
+                option: "tenant_schemas",
+                reason: "the template must contain `{tenant}` once and otherwise, like the shared schemas, only lowercase letters, digits and underscores",
+            });
+        }
+
+        if self.ensure_schema && self.schema().is_none() {
+            return Err(ConfigError::MissingOption {
+                option: "ensure_schema",
+                requires: "search_path",
+            });
+        }
+
+        if !self.hosts.is_empty() {
+            if self.connect_options.is_some() {
+                return Err(ConfigError::Conflict {
+                    option: "hosts",
+                    conflicts_with: "connect_options",
+                });
+            }
+
+            if self.socket.is_some() {
+                return Err(ConfigError::Conflict {
+                    option: "hosts",
+                    conflicts_with: "socket",
+                });
+            }
+
+            if self.hosts.iter().any(|(_, port)| *port == 0) {
+                return Err(ConfigError::ZeroPort);
+            }
+        }
+
+        #[cfg(feature = "rds-iam")]
+        if self.rds_iam.is_some() {
+            let ssl_mode = match &self.connect_options {
+                Some(options) => Some(options.get_ssl_mode()),
+                None => self.ssl_mode,
+            };
+
+            if matches!(
+                ssl_mode,
+                Some(PgSslMode::Disable) | Some(PgSslMode::Allow) | Some(PgSslMode::Prefer)
+            ) {
+                return Err(ConfigError::Unsupported {
+                    option: "rds_iam with ssl_mode disable, allow or prefer",
+                    reason: "RDS only accepts IAM auth tokens over TLS",
+                });
+            }
+
+            if self.socket.is_some() {
+                return Err(ConfigError::Conflict {
+                    option: "rds_iam",
+                    conflicts_with: "socket",
+                });
+            }
+        }
+
+        if self.connect_options.is_some() {
+            return Ok(());
+        }
+
+        if self.database.is_empty() {
+            return Err(ConfigError::EmptyDatabase);
+        }
+
+        if self.username.is_empty() {
+            return Err(ConfigError::EmptyUsername);
+        }
+
+        if self.port == 0 {
+            return Err(ConfigError::ZeroPort);
+        }
+
+        if self.socket.is_some() {
+            let uses_tls = !matches!(
+                self.ssl_mode,
+                None | Some(PgSslMode::Disable) | Some(PgSslMode::Allow) | Some(PgSslMode::Prefer)
+            ) || self.ssl_root_cert.is_some()
+                || self.ssl_client_cert.is_some()
+                || self.ssl_client_key.is_some();
+
+            if uses_tls {
+                return Err(ConfigError::Conflict {
+                    option: "socket",
+                    conflicts_with: "TLS options",
+                });
+            }
+        }
+
+        if let Some(cert) = &self.ssl_root_cert {
+            check_file_exists("ssl_root_cert", cert)?;
+        }
+
+        match (&self.ssl_client_cert, &self.ssl_client_key) {
+            (Some(cert), Some(key)) => {
+                check_file_exists("ssl_client_cert", cert)?;
+                check_file_exists("ssl_client_key", key)?;
+            }
+            (Some(_), None) => {
+                return Err(ConfigError::MissingOption {
+                    option: "ssl_client_cert",
+                    requires: "ssl_client_key",
+                })
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError::MissingOption {
+                    option: "ssl_client_key",
+                    requires: "ssl_client_cert",
+                })
+            }
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Connects a pool outside of Rocket, e.g. for a migration binary or setup code, the
+    /// same way the fairing does at ignite.
+    ///
+    /// ```rust,ignore
+    /// async fn setup() -> rocket_sqlxpostgres::Result<()> {
+    ///     let pool = SqlxPostgresConfig::from_env()?.connect().await?;
+    ///     sqlx::query("VACUUM ANALYZE").execute(&pool).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn connect(&self) -> crate::Result<PgPool> {
+        let opts = self.connect_options()?;
+
+        self.connect_with_retries(opts).await.map_err(|e| match e {
+            sqlx::Error::PoolTimedOut => Error::Timeout,
+            e => Error::Connect(e),
+        })
+    }
+
+    /// Connects a pool, retrying as configured by `with_connect_retries`.
+    pub(crate) async fn connect_with_retries(
+        &self,
+        connect_opts: PgConnectOptions,
+    ) -> Result<PgPool, sqlx::Error> {
+        if self.lazy {
+            #[cfg(feature = "rds-iam")]
+            let connect_opts = match &self.rds_iam {
+                Some(rds_iam) => rds_iam.authenticate(connect_opts).await?,
+                None => connect_opts,
+            };
+
+            return Ok(self.pool_options().connect_lazy_with(connect_opts));
+        }
+
+        let (retries, initial_backoff) = self.connect_retries;
+        let mut attempt = 0;
+        let mut create_database = self.create_database && self.connect_options.is_none();
+
+        loop {
+            match self.connect_with(connect_opts.clone()).await {
+                Ok(pool) => return Ok(pool),
+                Err(e) if create_database && connect::is_missing_database(&e) => {
+                    create_database = false;
+                    log::warn!(
+                        "SQLxPostgres: database `{}` does not exist, creating it",
+                        self.database
+                    );
+                    connect::create_database(&connect_opts, &self.database).await?;
+                    log::info!("SQLxPostgres: created database `{}`", self.database);
+                }
+                Err(e) if attempt < retries => {
+                    let delay = connect::backoff(initial_backoff, attempt);
+                    attempt += 1;
+                    log::warn!(
+                        "SQLxPostgres: connection attempt {} of {} failed: {}, retrying in {:?}",
+                        attempt,
+                        retries + 1,
+                        e,
+                        delay
+                    );
+                    rocket::tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Connects a pool using `connect_opts`, failing over between the hosts of the config.
+    ///
+    /// Every build of a pool goes through here, so rebuilds try the whole list again.
+    pub(crate) async fn connect_with(
+        &self,
+        connect_opts: PgConnectOptions,
+    ) -> Result<PgPool, sqlx::Error> {
+        if self.hosts.is_empty() || self.socket.is_some() || self.connect_options.is_some() {
+            return self.connect_once(connect_opts).await;
+        }
+
+        let mut hosts = self.hosts.clone();
+
+        if self.randomize_hosts {
+            use rand::seq::SliceRandom;
+            hosts.shuffle(&mut rand::thread_rng());
+        }
+
+        let mut last_error = None;
+
+        for (host, port) in hosts {
+            let opts = connect_opts.clone().host(&host).port(port);
+
+            match self.connect_once(opts).await {
+                Ok(pool) => {
+                    log::info!("SQLxPostgres: connected to host {}:{}", host, port);
+                    *self
+                        .connected_host
+                        .write()
+                        .unwrap_or_else(|e| e.into_inner()) = Some((host, port));
+                    return Ok(pool);
+                }
+                Err(e) => {
+                    log::warn!("SQLxPostgres: host {}:{} failed: {}", host, port, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("hosts is not empty"))
+    }
+
+    /// Connects a pool, giving up after the connect timeout.
+    async fn connect_once(&self, connect_opts: PgConnectOptions) -> Result<PgPool, sqlx::Error> {
+        #[cfg(feature = "rds-iam")]
+        let connect_opts = match &self.rds_iam {
+            Some(rds_iam) => rds_iam.authenticate(connect_opts).await?,
+            None => connect_opts,
+        };
+
+        let connect = self.pool_options().connect_with(connect_opts);
+
+        match rocket::tokio::time::timeout(self.connect_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(sqlx::Error::PoolTimedOut),
+        }
+    }
+
+    /// The password to log in with: a rotated password, the password file, the explicit
+    /// password, then pgpass.
+    fn resolve_password(&self) -> Result<Cow<'_, str>, ConfigError> {
+        if let Some(password) = &*self
+            .rotated_password
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+        {
+            return Ok(Cow::Owned(password.clone()));
+        }
+
+        if let Some(path) = &self.password_file {
+            return Ok(Cow::Owned(read_password_file(path)?));
+        }
+
+        if !self.password.is_empty() || !self.pgpass {
+            return Ok(Cow::Borrowed(&self.password[..]));
+        }
+
+        let path = match self.pgpass_file.clone().or_else(pgpass::default_path) {
+            Some(path) => path,
+            None => return Ok(Cow::Borrowed("")),
+        };
+        let host = if self.socket.is_some() {
+            "localhost"
+        } else {
+            &self.host[..]
+        };
+
+        Ok(
+            pgpass::lookup(&path, host, self.port, &self.database, &self.username)
+                .map(Cow::Owned)
+                .unwrap_or(Cow::Borrowed("")),
+        )
+    }
+
+    /// The statement log level set at runtime, if any.
+    fn log_level_override(&self) -> Option<LevelFilter> {
+        statement_log::decode(self.log_level_override.load(Ordering::Relaxed))
+    }
+
+    /// The statement log level of new connections, the runtime one when set.
+    pub(crate) fn statement_log_level(&self) -> LevelFilter {
+        self.log_level_override().unwrap_or(self.log_level)
+    }
+
+    /// Whether statements are sampled, leaving sqlx to log only the slow ones.
+    fn samples_statements(&self) -> bool {
+        self.statement_log_sampling.map_or(false, |rate| rate < 1.0)
+    }
+
+    /// Rate, level and slow threshold of the statements sampled by the guards' executors.
+    ///
+    /// sqlx already logs statements over the threshold, when it is set.
+    pub(crate) fn statement_log_sample(&self) -> Option<(f64, log::Level, Option<Duration>)> {
+        let rate = self
+            .statement_log_sampling
+            .filter(|rate| *rate > 0.0 && *rate < 1.0)?;
+        let level = self.statement_log_level().to_level()?;
+        let slow = match self.slow_statement_log {
+            (LevelFilter::Off, _) => None,
+            (_, threshold) => Some(threshold),
+        };
+
+        Some((rate, level, slow))
+    }
+
+    /// Builds the sqlx connect options described by this config.
+    pub(crate) fn connect_options(&self) -> Result<PgConnectOptions, ConfigError> {
+        self.validate()?;
+
+        if let Some(options) = &self.connect_options {
+            let mut options = match self.pgbouncer_mode {
+                true => options.clone().statement_cache_capacity(0),
+                false => options.clone(),
+            };
+
+            if self.samples_statements() {
+                options.log_statements(LevelFilter::Off);
+            } else if let Some(level) = self.log_level_override() {
+                options.log_statements(level);
+            }
+
+            return Ok(options);
+        }
+
+        let mut connect_opts = PgConnectOptions::new();
+        connect_opts
+            .log_statements(match self.samples_statements() {
+                true => LevelFilter::Off,
+                false => self.statement_log_level(),
+            })
+            .log_slow_statements(self.slow_statement_log.0, self.slow_statement_log.1);
+        connect_opts = connect_opts.database(&self.database[..]);
+        connect_opts = connect_opts.username(&self.username[..]);
+        connect_opts = connect_opts.password(&self.resolve_password()?);
+        let (host, port) = self.target();
+        connect_opts = connect_opts.host(&host);
+        connect_opts = connect_opts.port(port);
+        connect_opts = connect_opts.application_name(&self.application_name());
+        connect_opts = connect_opts.statement_cache_capacity(self.cached_statements());
+
+        let options = self.startup_options();
+
+        if !options.is_empty() {
+            connect_opts = connect_opts.options(options);
+        }
+
+        if let Some(socket) = &self.socket {
+            connect_opts = connect_opts.socket(socket);
+        }
+
+        if let Some(mode) = self.ssl_mode {
+            connect_opts = connect_opts.ssl_mode(mode);
+        }
+
+        #[cfg(feature = "rds-iam")]
+        if self.rds_iam.is_some() && self.ssl_mode.is_none() {
+            connect_opts = connect_opts.ssl_mode(PgSslMode::Require);
+        }
+
+        if let Some(cert) = &self.ssl_root_cert {
+            connect_opts = connect_opts.ssl_root_cert(cert);
+        }
+
+        if let (Some(cert), Some(key)) = (&self.ssl_client_cert, &self.ssl_client_key) {
+            connect_opts = connect_opts.ssl_client_cert(cert).ssl_client_key(key);
+        }
+
+        Ok(connect_opts)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for SqlxPostgresConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: rocket::serde::Deserializer<'de>,
+    {
+        use rocket::serde::de::Error;
+
+        let raw = RawConfig::deserialize(deserializer)?;
+        Self::from_raw(raw).map_err(D::Error::custom)
+    }
+}
+
+/// Serializes the config without the password.
+#[cfg(feature = "serde")]
+impl Serialize for SqlxPostgresConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: rocket::serde::Serializer,
+    {
+        self.to_raw(false).serialize(serializer)
+    }
+}
+
+/// Serializes a config including its password, see
+/// [`SqlxPostgresConfig::with_password_serialized`].
+#[cfg(feature = "serde")]
+pub struct SerializeWithPassword<'a>(&'a SqlxPostgresConfig);
+
+#[cfg(feature = "serde")]
+impl Serialize for SerializeWithPassword<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: rocket::serde::Serializer,
+    {
+        self.0.to_raw(true).serialize(serializer)
+    }
+}
+
+fn check_file_exists(option: &'static str, path: &std::path::Path) -> Result<(), ConfigError> {
+    if path.exists() {
+        Ok(())
+    } else {
+        Err(ConfigError::MissingFile {
+            option,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+fn read_password_file(path: &std::path::Path) -> Result<String, ConfigError> {
+    let mut password = std::fs::read_to_string(path).map_err(|e| ConfigError::UnreadableFile {
+        option: "password_file",
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+
+    if password.ends_with('\n') {
+        password.pop();
+
+        if password.ends_with('\r') {
+            password.pop();
+        }
+    }
+
+    Ok(password)
+}
+
+/// Managed pool, swapped out by the supervisor when it is rebuilt.
+#[derive(Debug)]
+pub struct SQLxPostgresPool {
+    client: Arc<RwLock<PgPool>>,
+    pub(crate) config: SqlxPostgresConfig,
+    pub(crate) waiters: Waiters,
+    pub(crate) slow_acquire_log: RateLimit,
+    pub(crate) query_cache: Option<Arc<QueryCache>>,
+    pub(crate) health: Option<DbHealth>,
+    pub(crate) migrations: KnownMigrations,
+    pub(crate) rotation: Option<PoolHandle>,
+    /// Config the cancel connections of [`SqlxPostgresConfig::with_cancel_on_disconnect`]
+    /// connect with, shared by the guards.
+    pub(crate) cancel_config: Option<Arc<SqlxPostgresConfig>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<DatabaseMetrics>,
+}
 
 impl SQLxPostgresPool {
     pub fn new(client: PgPool) -> Self {
-        Self { client }
+        Self::with_config(client, SqlxPostgresConfig::default())
+    }
+
+    pub(crate) fn with_config(client: PgPool, config: SqlxPostgresConfig) -> Self {
+        Self {
+            client: Arc::new(RwLock::new(client)),
+            query_cache: config
+                .query_cache
+                .then(|| Arc::new(QueryCache::new(config.query_cache_max_entries))),
+            health: config.health_probe_interval.map(|_| DbHealth::new()),
+            migrations: KnownMigrations::default(),
+            rotation: None,
+            cancel_config: config
+                .cancel_on_disconnect
+                .map(|_| Arc::new(config.clone())),
+            config,
+            waiters: Waiters::default(),
+            slow_acquire_log: RateLimit::default(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+
+    /// The current pool.
+    pub fn client(&self) -> PgPool {
+        self.client
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether the pool can hand out connections, `false` once it is closed.
+    ///
+    /// With `Option<&State<SQLxPostgresPool>>` a fallback route can tell a database that was
+    /// never configured (`None`) from one that is down.
+    pub fn is_available(&self) -> bool {
+        !self.client().is_closed()
+    }
+
+    /// Snapshot of the pool's connections, cheap enough to take on every metrics scrape.
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus::of(
+            &self.client(),
+            &self.waiters,
+            self.config.circuit_breaker.as_ref(),
+        )
+    }
+
+    /// Takes [`SQLxPostgresPool::status`] snapshots for background tasks.
+    ///
+    /// Holds the pool slot weakly, so it returns `None` once the store is dropped.
+    pub(crate) fn status_source(&self) -> impl Fn() -> Option<PoolStatus> + Send + 'static {
+        let slot = Arc::downgrade(&self.client);
+        let waiters = self.waiters.clone();
+        let breaker = self.config.circuit_breaker.clone();
+
+        move || {
+            let slot = slot.upgrade()?;
+            let pool = slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+            Some(PoolStatus::of(&pool, &waiters, breaker.as_ref()))
+        }
+    }
+
+    /// Handle following the pools swapped in later, for workers outliving the current one.
+    pub fn slot(&self) -> PgPoolSlot {
+        PgPoolSlot::new(self.handle())
+    }
+
+    /// Shared handle to the pool slot, for the supervisor task.
+    pub(crate) fn handle(&self) -> Arc<RwLock<PgPool>> {
+        self.client.clone()
+    }
+
+    /// Adds the [`PoolHandle`] rotating the credentials of a pool built from its config.
+    pub(crate) fn rotatable(self, name: &'static str) -> Self {
+        let rotation = PoolHandle::new(name, self.handle(), self.config.clone());
+
+        Self {
+            rotation: Some(rotation),
+            ..self
+        }
+    }
+}
+
+/// Request guard handing out the managed pool.
+///
+/// With [`SqlxPostgresConfig::lazy`] the pool connects on first use, so the first
+/// request to acquire a connection pays the connection latency and sees any connection error.
+///
+/// Derefs to `PgPool` and `&SQLxPostgres` is an executor, so queries can run on it
+/// directly, e.g. `sqlx::query("...").fetch_one(&db)`.
+///
+/// `SQLxPostgres<D>` hands out the pool attached under [`DatabaseMarker::NAME`] instead,
+/// failing with a 500 when no such database is attached.
+pub struct SQLxPostgres<D: DatabaseMarker = DefaultDatabase> {
+    #[deprecated(note = "use `pool()` or deref instead")]
+    pub poll: PgPool,
+    observer: QueryObserver,
+    query_cache: Option<Arc<QueryCache>>,
+    database: PhantomData<D>,
+}
+
+impl<D: DatabaseMarker> SQLxPostgres<D> {
+    #[allow(deprecated)]
+    pub(crate) fn new(
+        pool: PgPool,
+        observer: QueryObserver,
+        query_cache: Option<Arc<QueryCache>>,
+    ) -> Self {
+        Self {
+            poll: pool,
+            observer,
+            query_cache,
+            database: PhantomData,
+        }
+    }
+
+    pub(crate) fn from_store(store: &SQLxPostgresPool) -> Self {
+        Self::new(
+            store.client(),
+            QueryObserver::new(&store.config),
+            store.query_cache.clone(),
+        )
+    }
+
+    pub(crate) fn for_request(store: &SQLxPostgresPool, request: &Request<'_>) -> Self {
+        Self::new(
+            store.client(),
+            QueryObserver::for_request(&store.config, request),
+            store.query_cache.clone(),
+        )
+    }
+
+    /// The pool of `D` for code outside a handler, like liftoff and shutdown fairings, or
+    /// `None` when its fairing isn't attached or its pool is closed.
+    ///
+    /// Maintenance mode and the circuit breaker only gate requests and are not checked.
+    ///
+    /// ```rust,ignore
+    /// .attach(AdHoc::on_shutdown("flush audit", |rocket| Box::pin(async move {
+    ///     if let Some(db) = SQLxPostgres::<DefaultDatabase>::from_rocket(rocket) {
+    ///         let _ = sqlx::query("CALL flush_audit()").execute(&db).await;
+    ///     }
+    /// })))
+    /// ```
+    pub fn from_rocket(rocket: &Rocket<Orbit>) -> Option<Self> {
+        D::with_rocket_store(rocket, Self::from_store).and_then(|db| open_pool(db).ok())
+    }
+
+    /// The pool of `D` for code holding only a `&Request`, like catchers, or `None` when the
+    /// [`SQLxPostgres`] guard would fail.
+    ///
+    /// Checks what the guard checks, maintenance mode, the circuit breaker and a closed pool
+    /// included, and reuses the state the request cached, so the queries count towards its
+    /// database time and query capture.
+    ///
+    /// ```rust,ignore
+    /// #[catch(500)]
+    /// async fn internal_error(request: &Request<'_>) -> &'static str {
+    ///     if let Some(db) = SQLxPostgres::<DefaultDatabase>::from_request_cached(request) {
+    ///         let _ = sqlx::query("INSERT INTO failed_requests (method, uri) VALUES ($1, $2)")
+    ///             .bind(request.method().as_str())
+    ///             .bind(request.uri().to_string())
+    ///             .execute(&db)
+    ///             .await;
+    ///     }
+    ///
+    ///     "internal server error"
+    /// }
+    /// ```
+    pub fn from_request_cached(request: &Request<'_>) -> Option<Self> {
+        Self::resolve(request).ok()
+    }
+
+    /// What the guard hands out for `request`.
+    fn resolve(request: &Request<'_>) -> Result<Self, PoolGuardError> {
+        let db = maintenance::check(request).and_then(|_| {
+            D::with_store(request, |store| {
+                guards::check_breaker(store).map(|_| SQLxPostgres::for_request(store, request))
+            })
+        });
+
+        db.and_then(|db| db).and_then(open_pool)
+    }
+
+    fn observer(&self) -> &QueryObserver {
+        &self.observer
+    }
+
+    pub(crate) fn query_cache(&self) -> Option<&QueryCache> {
+        self.query_cache.as_deref()
+    }
+
+    /// The managed pool.
+    #[allow(deprecated)]
+    pub fn pool(&self) -> &PgPool {
+        &self.poll
+    }
+
+    /// Runs `f` inside a transaction, committing when it returns `Ok` and rolling back
+    /// when it returns `Err`.
+    ///
+    /// The transaction is also rolled back when the returned future is dropped before it
+    /// completes, for instance when the client disconnects. The closure borrows the
+    /// transaction, so it returns a boxed future:
+    ///
+    /// ```rust,no_run
+    /// # use rocket::{get, http::Status, post};
+    /// # use rocket_sqlxpostgres::SQLxPostgres;
+    /// #[post("/users/<name>")]
+    /// async fn create(db: SQLxPostgres, name: String) -> Result<String, Status> {
+    ///     db.transaction(|tx| Box::pin(async move {
+    ///         let insert = "INSERT INTO users (name) VALUES ($1) RETURNING id";
+    ///         let id: i32 = sqlx::query_scalar(insert)
+    ///             .bind(&name)
+    ///             .fetch_one(&mut **tx)
+    ///             .await?;
+    ///         sqlx::query("INSERT INTO audit (user_id) VALUES ($1)")
+    ///             .bind(id)
+    ///             .execute(&mut **tx)
+    ///             .await?;
+    ///         Ok::<_, sqlx::Error>(id.to_string())
+    ///     }))
+    ///     .await
+    ///     .map_err(|_| Status::InternalServerError)
+    /// }
+    ///
+    /// #[get("/users/count")]
+    /// async fn count(db: SQLxPostgres) -> Result<String, Status> {
+    ///     db.transaction(|tx| Box::pin(async move {
+    ///         sqlx::query_scalar::<_, i64>("SELECT count(*) FROM users")
+    ///             .fetch_one(&mut **tx)
+    ///             .await
+    ///     }))
+    ///     .await
+    ///     .map(|count| count.to_string())
+    ///     .map_err(|_| Status::InternalServerError)
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub async fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: for<'t> FnOnce(&'t mut Transaction<'static, Postgres>) -> BoxFuture<'t, Result<T, E>>
+            + Send,
+        T: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        let mut tx = self.pool().begin().await?;
+
+        match f(&mut tx).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                if let Err(rollback) = tx.rollback().await {
+                    log::warn!("SQLxPostgres: transaction rollback failed: {}", rollback);
+                }
+
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<D: DatabaseMarker> fmt::Debug for SQLxPostgres<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SQLxPostgres")
+            .field("database", &D::NAME)
+            .field("pool", self.pool())
+            .finish()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, D: DatabaseMarker> FromRequest<'r> for SQLxPostgres<D> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let db = Self::resolve(request);
+
+        #[cfg(feature = "sticky-reads")]
+        if db.is_ok() && D::NAME == DefaultDatabase::NAME {
+            sticky::mark(request);
+        }
+
+        guards::outcome(db)
+    }
+}
+
+/// Aborts launch when the database of `D` isn't attached.
+impl<D: DatabaseMarker> Sentinel for SQLxPostgres<D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, D::NAME, "SQLxPostgres")
+    }
+}
+
+/// Request guard like [`SQLxPostgres`] that forwards instead of failing.
+///
+/// When no pool is managed or it is closed the request is forwarded, so a lower ranked
+/// route can serve a degraded response. Derefs to [`SQLxPostgres`].
+#[derive(Debug)]
+pub struct SQLxPostgresOptional(pub SQLxPostgres);
+
+impl std::ops::Deref for SQLxPostgresOptional {
+    type Target = SQLxPostgres;
+
+    fn deref(&self) -> &SQLxPostgres {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresOptional {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let db = maintenance::check(request)
+            .and_then(|_| guards::managed_store(request))
+            .and_then(|store| guards::check_breaker(store).map(|_| store))
+            .map(|store| SQLxPostgres::for_request(store, request));
+
+        match db.and_then(open_pool) {
+            Ok(db) => Outcome::Success(SQLxPostgresOptional(db)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+/// Rejects a closed pool, the guards handing out pools never acquire.
+fn open_pool<G: std::ops::Deref<Target = PgPool>>(guard: G) -> Result<G, PoolGuardError> {
+    match guard {
+        guard if guard.is_closed() => Err(PoolGuardError::PoolClosed),
+        guard => Ok(guard),
+    }
+}
+
+/// Request guard handing out a read replica pool.
+///
+/// With several replicas one is picked per request by the
+/// [`SqlxPostgresFairing::with_replica_strategy`]. Falls back to the primary pool when no
+/// replica is configured or none is available, so handlers can use it for reads
+/// unconditionally.
+#[derive(Debug)]
+pub struct SQLxPostgresReplica {
+    pool: PgPool,
+    observer: QueryObserver,
+    /// The replica, when sticky reads routed the guard to the primary.
+    #[cfg(feature = "sticky-reads")]
+    replica: Option<PgPool>,
+}
+
+impl SQLxPostgresReplica {
+    fn for_request(store: &SQLxPostgresPool, request: &Request<'_>) -> Self {
+        Self {
+            pool: store.client(),
+            observer: QueryObserver::for_request(&store.config, request),
+            #[cfg(feature = "sticky-reads")]
+            replica: None,
+        }
+    }
+
+    /// The guard reading from the replica even when sticky reads routed it to the primary,
+    /// for reads that tolerate replication lag.
+    #[cfg(feature = "sticky-reads")]
+    pub fn allow_stale(self) -> Self {
+        match self.replica {
+            Some(replica) => Self {
+                pool: replica,
+                replica: None,
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    fn observer(&self) -> &QueryObserver {
+        &self.observer
+    }
+
+    /// The replica pool, or the primary one when there is no replica.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+impl<D: DatabaseMarker> std::ops::Deref for SQLxPostgres<D> {
+    type Target = PgPool;
+
+    fn deref(&self) -> &PgPool {
+        self.pool()
+    }
+}
+
+impl std::ops::Deref for SQLxPostgresReplica {
+    type Target = PgPool;
+
+    fn deref(&self) -> &PgPool {
+        self.pool()
+    }
+}
+
+/// Implements `Executor` for a pool guard by delegating to its `pool()`.
+macro_rules! impl_pool_executor {
+    ([$($generics:tt)*] $guard:ty) => {
+        impl<'p, $($generics)*> Executor<'p> for &'_ $guard {
+            type Database = Postgres;
+
+            fn fetch_many<'e, 'q: 'e, E: 'q>(
+                self,
+                query: E,
+            ) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+            where
+                'p: 'e,
+                E: Execute<'q, Postgres>,
+            {
+                let sql = query.sql();
+                self.observer().fetch_many(sql, self.pool(), self.pool().fetch_many(query))
+            }
+
+            fn fetch_optional<'e, 'q: 'e, E: 'q>(
+                self,
+                query: E,
+            ) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+            where
+                'p: 'e,
+                E: Execute<'q, Postgres>,
+            {
+                let sql = query.sql();
+                self.observer().fetch_optional(sql, self.pool(), self.pool().fetch_optional(query))
+            }
+
+            fn prepare_with<'e, 'q: 'e>(
+                self,
+                sql: &'q str,
+                parameters: &'e [PgTypeInfo],
+            ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+            where
+                'p: 'e,
+            {
+                self.pool().prepare_with(sql, parameters)
+            }
+
+            fn describe<'e, 'q: 'e>(
+                self,
+                sql: &'q str,
+            ) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+            where
+                'p: 'e,
+            {
+                self.pool().describe(sql)
+            }
+        }
+    };
+}
+
+impl_pool_executor!([D: DatabaseMarker] SQLxPostgres<D>);
+impl_pool_executor!([] SQLxPostgresReplica);
+impl_pool_executor!(['r] SQLxPostgresRef<'r>);
+
+/// Request guard borrowing the managed pool instead of cloning it.
+///
+/// Resolving it doesn't clone the pool and `&SQLxPostgresRef` is an executor, looking up the
+/// current pool on every query so it follows [`SqlxPostgresConfig::supervise`]. sqlx still
+/// clones the pool for each query, as it does for `&PgPool`, so this is not meant as an
+/// optimization; prefer [`SQLxPostgres`] unless the borrow is more convenient.
+#[derive(Debug)]
+pub struct SQLxPostgresRef<'r> {
+    store: &'r SQLxPostgresPool,
+    observer: QueryObserver,
+}
+
+impl SQLxPostgresRef<'_> {
+    /// The current pool.
+    pub fn pool(&self) -> PgPool {
+        self.store.client()
+    }
+
+    fn observer(&self) -> &QueryObserver {
+        &self.observer
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresRef<'r> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let store = maintenance::check(request).and_then(|_| {
+            let store = request
+                .rocket()
+                .state::<SQLxPostgresPool>()
+                .ok_or(PoolGuardError::NotManaged)?;
+            guards::check_breaker(store).map(|_| store)
+        });
+
+        #[cfg(feature = "sticky-reads")]
+        if store.is_ok() {
+            sticky::mark(request);
+        }
+
+        guards::outcome(store.map(|store| SQLxPostgresRef {
+            store,
+            observer: QueryObserver::for_request(&store.config, request),
+        }))
+    }
+}
+
+impl Sentinel for SQLxPostgresRef<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresRef")
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SQLxPostgresReplica {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        if let Err(e) = maintenance::check(request) {
+            return guards::outcome(Err(e));
+        }
+
+        let replicas = request.rocket().state::<SQLxPostgresReplicas>();
+
+        #[cfg(feature = "sticky-reads")]
+        if let Some(replicas) = replicas.filter(|_| sticky::is_sticky(request)) {
+            let primary = guards::managed_store(request).map(|store| SQLxPostgresReplica {
+                replica: replicas.pick().map(|replica| replica.client()),
+                ..SQLxPostgresReplica::for_request(store, request)
+            });
+
+            return guards::outcome(primary.and_then(open_pool));
+        }
+
+        let replica = match replicas.and_then(|replicas| replicas.pick()) {
+            Some(replica) => Ok(SQLxPostgresReplica::for_request(replica, request)),
+            None => guards::managed_store(request)
+                .map(|store| SQLxPostgresReplica::for_request(store, request)),
+        };
+
+        guards::outcome(replica.and_then(open_pool))
+    }
+}
+
+impl Sentinel for SQLxPostgresReplica {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        named::abort_unattached(rocket, DefaultDatabase::NAME, "SQLxPostgresReplica")
+    }
+}
+
+/// Fairing struct
+pub struct SqlxPostgresFairing {
+    name: Option<&'static str>,
+    poll: Option<PgPool>,
+    shared_pool: Option<SharedPool>,
+    config: SqlxPostgresConfig,
+    ready: DbReady,
+    replicas: Vec<SqlxPostgresConfig>,
+    replica_strategy: ReplicaStrategy,
+    replica_max_lag: Option<Duration>,
+    strict_replica_role: bool,
+    shards: Vec<Box<dyn ShardRoutes>>,
+    tasks: DbTasks,
+    fail_fast: Option<bool>,
+    on_connected: Option<ConnectedCallback>,
+    on_connect_failed: Option<ConnectFailedCallback>,
+    transaction_policy: Option<TransactionPolicy>,
+    channels: Vec<String>,
+    listen_required: bool,
+    listen_resync: Vec<(String, ResyncCallback)>,
+    from_figment: bool,
+    migrator: Option<Migrator>,
+    migrations_path: Option<PathBuf>,
+    migration_target: Option<i64>,
+    allow_revert: bool,
+    required_migrator: Option<Migrator>,
+    init_sql: Vec<InitSql>,
+    init_sql_transactional: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<PoolMetrics>,
+    /// Fairings attached by [`SqlxPostgresFairing::from_figment_databases`], `None` otherwise.
+    figment_databases: Option<Mutex<Vec<Arc<SqlxPostgresFairing>>>>,
+}
+
+impl Default for SqlxPostgresFairing {
+    fn default() -> Self {
+        Self::new(SqlxPostgresConfig::default(), None)
+    }
+}
+
+impl From<SqlxPostgresConfig> for SqlxPostgresFairing {
+    fn from(config: SqlxPostgresConfig) -> Self {
+        Self::from_config(config)
+    }
+}
+
+impl From<PgPool> for SqlxPostgresFairing {
+    fn from(pool: PgPool) -> Self {
+        Self::from_pool(pool)
+    }
+}
+
+/// Fetches a pool owned by another fairing, see [`SqlxPostgresFairing::from_db_pools`].
+type SharedPool = fn(&Rocket<Build>) -> Option<PgPool>;
+
+/// SQL run once at ignite, see [`SqlxPostgresFairing::with_init_sql`].
+enum InitSql {
+    Statement(Cow<'static, str>),
+    File(PathBuf),
+}
+
+impl SqlxPostgresFairing {
+    /// Creates a New SQLx Postgres Fairing.
+    ///
+    /// When `poll` is `Some` that pool is managed as is, and the pool settings of
+    /// `config` (connection limits, timeouts, `test_before_acquire`...) are not applied to it.
+    /// Attaching a second unnamed fairing aborts launch, use [`SqlxPostgresFairing::named`]
+    /// for additional databases.
+    ///
+    /// Must be Created to pass to `rocket.attach()`
+    pub fn new(config: SqlxPostgresConfig, poll: Option<PgPool>) -> Self {
+        let ready = DbReady::new();
+
+        if let Some(pool) = &poll {
+            ready.resolve(Arc::new(RwLock::new(pool.clone())));
+        }
+
+        Self {
+            name: None,
+            poll,
+            ready,
+            shared_pool: None,
+            config,
+            replicas: Vec::new(),
+            replica_strategy: ReplicaStrategy::default(),
+            replica_max_lag: None,
+            strict_replica_role: false,
+            shards: Vec::new(),
+            tasks: DbTasks::new(),
+            fail_fast: None,
+            on_connected: None,
+            on_connect_failed: None,
+            transaction_policy: None,
+            channels: Vec::new(),
+            listen_required: false,
+            listen_resync: Vec::new(),
+            from_figment: false,
+            migrator: None,
+            migrations_path: None,
+            migration_target: None,
+            allow_revert: false,
+            required_migrator: None,
+            init_sql: Vec::new(),
+            init_sql_transactional: true,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            figment_databases: None,
+        }
+    }
+
+    /// Creates a New SQLx Postgres Fairing that reads its config from Rocket's figment.
+    ///
+    /// The `sqlx_postgres` table is loaded at ignite using [`SqlxPostgresConfig::from_figment`],
+    /// and launch is aborted if it is invalid.
+    pub fn from_rocket_config() -> Self {
+        Self {
+            from_figment: true,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a New SQLx Postgres Fairing attaching every database of Rocket's figment.
+    ///
+    /// Each sub-table of `databases` is loaded at ignite using
+    /// [`SqlxPostgresConfig::from_figment_databases`] and attached as if by
+    /// [`SqlxPostgresFairing::named`] under the table name, so its pool is looked up through
+    /// [`SQLxPostgresPools`]. A `fail_fast` key decides per database whether a failed connection
+    /// aborts launch, [`SqlxPostgresFairing::fail_fast`] overrides it for all of them.
+    ///
+    /// ```toml
+    /// [default.databases.main]
+    /// database = "app"
+    ///
+    /// [default.databases.analytics]
+    /// database = "analytics"
+    /// fail_fast = false
+    /// ```
+    pub fn from_figment_databases() -> Self {
+        Self {
+            figment_databases: Some(Mutex::new(Vec::new())),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a New SQLx Postgres Fairing connecting a pool described by `config`.
+    ///
+    /// Same as `SqlxPostgresFairing::new(config, None)`.
+    pub fn from_config(config: SqlxPostgresConfig) -> Self {
+        Self::new(config, None)
+    }
+
+    /// Creates a New SQLx Postgres Fairing managing an existing pool as is.
+    pub fn from_pool(pool: PgPool) -> Self {
+        Self::new(SqlxPostgresConfig::default(), Some(pool))
+    }
+
+    /// Creates a New SQLx Postgres Fairing sharing the pool of the rocket_db_pools database `D`.
+    ///
+    /// Both crates' guards then use the same connections. Attach `D::init()` first, as the
+    /// pool is fetched at ignite and launch is aborted when it is missing. rocket_db_pools owns
+    /// the pool: it creates and closes it, this fairing never closes nor supervises it.
+    #[cfg(feature = "db_pools")]
+    pub fn from_db_pools<D>() -> Self
+    where
+        D: rocket_db_pools::Database<Pool = PgPool>,
+    {
+        Self {
+            shared_pool: Some(|rocket| D::fetch(rocket).map(|db| (**db).clone())),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a New SQLx Postgres Fairing for the database `name`.
+    ///
+    /// Named pools are managed together in [`SQLxPostgresPools`] instead of as
+    /// [`SQLxPostgres`], so several databases can be attached next to an unnamed one.
+    /// Attaching two fairings with the same name aborts launch.
+    pub fn named(name: &'static str, config: SqlxPostgresConfig) -> Self {
+        Self {
+            name: Some(name),
+            ..Self::new(config, None)
+        }
+    }
+
+    /// The [`DbReady`] this fairing resolves, to hand to code that needs the pool before
+    /// or regardless of attaching.
+    ///
+    /// The unnamed fairing also manages it. A fairing attaching several databases with
+    /// [`SqlxPostgresFairing::from_figment_databases`] resolves it to an error at ignite,
+    /// look those pools up in [`SQLxPostgresPools`] instead.
+    pub fn ready(&self) -> DbReady {
+        self.ready.clone()
+    }
+
+    /// Connects a pool to a read replica, handed out by [`SQLxPostgresReplica`].
+    ///
+    /// Call once per replica to spread reads across them. Failing to reach a replica never
+    /// aborts launch: it is logged and skipped until its health probe succeeds, and the
+    /// replica guard falls back to the primary pool while none is available. Migrations and
+    /// init SQL only run on the primary. Ignored on named fairings.
+    pub fn with_replica(mut self, config: SqlxPostgresConfig) -> Self {
+        self.replicas.push(config);
+        self
+    }
+
+    /// Sets how the replica guard picks one of several replicas, round robin by default.
+    pub fn with_replica_strategy(mut self, strategy: ReplicaStrategy) -> Self {
+        self.replica_strategy = strategy;
+        self
+    }
+
+    /// Measures the replay lag of each replica at every health probe and skips those behind
+    /// by more than `max_lag` until they catch up.
+    ///
+    /// Crossing the threshold either way is logged, and the lag is reported by
+    /// [`SQLxPostgresReplicas::status`] and the `sqlxpostgres_replica_lag_seconds` metric. A
+    /// replica that turns out not to be in recovery is a primary configured by mistake: it is
+    /// logged as an error and stays in the rotation. Off by default.
+    pub fn with_replica_max_lag(mut self, max_lag: Duration) -> Self {
+        self.replica_max_lag = Some(max_lag);
+        self
+    }
+
+    /// Sets whether a replica that is not in recovery at ignite, i.e. a primary, aborts
+    /// launch instead of being logged, `false` by default.
+    ///
+    /// See [`SqlxPostgresConfig::verify_server_role`], which turns the check on per replica.
+    pub fn strict_replica_role(mut self, strict: bool) -> Self {
+        self.strict_replica_role = strict;
+        self
+    }
+
+    /// Registers a router handing out the named pools of a sharded database, for the
+    /// [`crate::Sharded`] guard.
+    ///
+    /// Works on any fairing, the shards being attached with [`SqlxPostgresFairing::named`];
+    /// one router per key type can be registered, a second one aborts launch. Shards that
+    /// aren't attached are logged at liftoff.
+    pub fn with_shards<K: Send + Sync + 'static>(mut self, router: ShardRouter<K>) -> Self {
+        self.shards.push(Box::new(router));
+        self
+    }
+
+    /// Runs `task` in the background once the pool is ready, see [`DbTasks`].
+    ///
+    /// At shutdown the task is signalled through its [`rocket::Shutdown`] and awaited, for at
+    /// most the grace of [`SqlxPostgresFairing::with_tasks`], before the pool is closed.
+    pub fn with_task<F, Fut>(self, name: &'static str, task: F) -> Self
+    where
+        F: FnOnce(PgPool, rocket::Shutdown) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(name, task);
+        self
+    }
+
+    /// Uses `tasks` for the background tasks of this fairing, e.g. to set their shutdown grace
+    /// or to keep a handle adding tasks later.
+    ///
+    /// Replaces the tasks added so far with [`SqlxPostgresFairing::with_task`]. The unnamed
+    /// fairing manages its [`DbTasks`], so routes can add tasks too.
+    pub fn with_tasks(mut self, tasks: DbTasks) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// Sets whether a failed connection at ignite aborts launch, overriding the config.
+    ///
+    /// `fail_fast(false)` is [`SqlxPostgresConfig::ignore_connect_errors`] set to `true`, and
+    /// also applies to configs read by [`SqlxPostgresFairing::from_rocket_config`].
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = Some(fail_fast);
+        self
+    }
+
+    /// Runs `callback` at ignite once the pool is created, or right away for a supplied pool.
+    ///
+    /// It runs before the startup checks, migrations and init SQL. Returning an error aborts
+    /// launch.
+    ///
+    /// ```rust,ignore
+    /// fairing.on_connected(|pool| Box::pin(async move {
+    ///     metrics::increment("db.connected");
+    ///     Ok(())
+    /// }))
+    /// ```
+    pub fn on_connected<F>(mut self, callback: F) -> Self
+    where
+        F: for<'a> Fn(
+                &'a PgPool,
+            )
+                -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_connected = Some(Arc::new(callback));
+        self
+    }
+
+    /// Runs `callback` at ignite when connecting fails, before launch is aborted or continued.
+    pub fn on_connect_failed<F>(mut self, callback: F) -> Self
+    where
+        F: for<'a> Fn(&'a sqlx::Error) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_connect_failed = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets how [`crate::SQLxPostgresAutoTransaction`] transactions end, from the response status.
+    ///
+    /// Defaults to [`TransactionAction::for_status`]. When a commit fails the response is
+    /// replaced by an empty 500.
+    pub fn with_transaction_policy<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(Status) -> TransactionAction + Send + Sync + 'static,
+    {
+        self.transaction_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Listens on the notification channels `channels`, relayed through [`PgNotifications`].
+    ///
+    /// The listener connects at ignite with the pool's connect options and relays from
+    /// liftoff until shutdown. When the connection drops it reconnects with backoff, listens
+    /// on every channel again, runs the [`SqlxPostgresFairing::on_listener_resync`]
+    /// callbacks and sends [`crate::Notification::Reconnected`] to the subscribers. Failing
+    /// to connect it at ignite is logged, see [`SqlxPostgresFairing::listen_required`].
+    /// Ignored on named fairings.
+    pub fn listen(mut self, channels: &[&str]) -> Self {
+        self.channels
+            .extend(channels.iter().map(|channel| channel.to_string()));
+        self
+    }
+
+    /// Sets whether failing to connect the listener aborts launch, `false` by default.
+    pub fn listen_required(mut self, required: bool) -> Self {
+        self.listen_required = required;
+        self
+    }
+
+    /// Runs `callback` with the pool each time the listener reconnected, before the
+    /// notifications of `channel` are relayed again, e.g. to reload what the notifications
+    /// sent while disconnected would have updated.
+    ///
+    /// Callbacks run in the order they were registered and an error is only logged. Ignored
+    /// unless `channel` is passed to [`SqlxPostgresFairing::listen`].
+    ///
+    /// ```rust,ignore
+    /// fairing.on_listener_resync("dashboard", |pool| Box::pin(async move {
+    ///     refresh_dashboard(pool).await?;
+    ///     Ok(())
+    /// }))
+    /// ```
+    pub fn on_listener_resync<F>(mut self, channel: &str, callback: F) -> Self
+    where
+        F: for<'a> Fn(
+                &'a PgPool,
+            )
+                -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.listen_resync
+            .push((channel.to_owned(), Arc::new(callback)));
+        self
+    }
+
+    /// Records the pool's Prometheus metrics into `metrics`, labelled with the database name.
+    ///
+    /// The gauges are refreshed every 5 seconds from liftoff, acquire latency and errors are
+    /// recorded by the connection and transaction guards.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: PoolMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Runs the migrations of `migrator` when Rocket ignites, e.g.
+    /// `sqlx::migrate!("./migrations")`.
+    ///
+    /// Pending migrations are applied after the pool is ready, whether it was created
+    /// by the fairing or supplied. Launch is aborted when a migration fails.
+    pub fn with_migrator(mut self, migrator: Migrator) -> Self {
+        self.migrator = Some(migrator);
+        self
+    }
+
+    /// Runs the migrations found in `path` when Rocket ignites.
+    ///
+    /// Unlike [`SqlxPostgresFairing::with_migrator`] the directory is read at runtime, for
+    /// migrations mounted at deploy time. A missing or empty directory aborts launch, as does
+    /// setting both this and an embedded migrator.
+    pub fn with_migrations_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.migrations_path = Some(path.into());
+        self
+    }
+
+    /// Brings the database to exactly migration `version` when Rocket ignites, `0` for none
+    /// at all.
+    ///
+    /// Pending migrations up to `version` are applied and newer ones left pending. When the
+    /// database is ahead, the newer applied migrations are reverted newest first with their
+    /// down migrations, which needs [`SqlxPostgresFairing::allow_revert`]. Launch is aborted,
+    /// before any DDL runs, naming the migration when one to revert is irreversible or
+    /// reverting isn't allowed, and when `version` isn't a migration of the migrator. Each
+    /// revert is logged with its version and description. Requires a migrator.
+    pub fn with_migration_target(mut self, version: i64) -> Self {
+        self.migration_target = Some(version);
+        self
+    }
+
+    /// Sets whether [`SqlxPostgresFairing::with_migration_target`] may revert migrations,
+    /// `false` by default so a stale target never drops a production schema by accident.
+    pub fn allow_revert(mut self, allow: bool) -> Self {
+        self.allow_revert = allow;
+        self
+    }
+
+    /// Refuses to launch unless every migration of `migrator` is already applied.
+    ///
+    /// Nothing is ever applied, for deployments where migrations run from a separate job.
+    /// Launch is aborted listing the pending versions, and separately the versions whose
+    /// file was edited after being applied.
+    pub fn require_migrations_applied(mut self, migrator: Migrator) -> Self {
+        self.required_migrator = Some(migrator);
+        self
+    }
+
+    /// Runs the given statements when Rocket ignites, after migrations.
+    ///
+    /// Meant for idempotent bootstrap statements such as `CREATE EXTENSION IF NOT EXISTS`.
+    /// They run in order on one connection inside a single transaction, see
+    /// [`SqlxPostgresFairing::init_sql_transactional`]. A failing statement aborts launch.
+    pub fn with_init_sql(mut self, statements: Vec<Cow<'static, str>>) -> Self {
+        self.init_sql
+            .extend(statements.into_iter().map(InitSql::Statement));
+        self
+    }
+
+    /// Runs the SQL in `path` when Rocket ignites, after the statements added before it.
+    ///
+    /// The file is read at ignite and may contain several statements.
+    pub fn with_init_sql_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.init_sql.push(InitSql::File(path.into()));
+        self
+    }
+
+    /// Sets whether the init SQL runs inside a transaction, `true` by default.
+    ///
+    /// Turn it off for statements that cannot run in a transaction like
+    /// `CREATE INDEX CONCURRENTLY`.
+    pub fn init_sql_transactional(mut self, transactional: bool) -> Self {
+        self.init_sql_transactional = transactional;
+        self
+    }
+}
+
+impl SqlxPostgresFairing {
+    /// Label of the database in metrics and logs, `default` for the unnamed fairing.
+    fn database_name(&self) -> &'static str {
+        self.name.unwrap_or(DefaultDatabase::NAME)
+    }
+
+    /// Ends the transaction of [`crate::SQLxPostgresAutoTransaction`] according to the policy.
+    async fn end_request_transaction<'r>(
+        &self,
+        request: &'r Request<'_>,
+        response: &mut Response<'r>,
+    ) {
+        let tx = match request.local_cache(AutoTransactionSlot::default).take() {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let action = match &self.transaction_policy {
+            Some(policy) => policy(response.status()),
+            None => TransactionAction::for_status(response.status()),
+        };
+
+        let result = match action {
+            TransactionAction::Commit => tx.commit().await,
+            TransactionAction::Rollback => tx.rollback().await,
+        };
+
+        if let Err(e) = result {
+            log::error!("SQLxPostgres: ending the request transaction failed: {}", e);
+
+            if action == TransactionAction::Commit {
+                response.set_status(Status::InternalServerError);
+                response.set_sized_body(0, std::io::Cursor::new(""));
+            }
+        }
+    }
+
+    /// Connects and prepares the pool of this fairing, see [`Fairing::on_ignite`].
+    async fn ignite(
+        &self,
+        rocket: Rocket<Build>,
+    ) -> std::result::Result<Rocket<Build>, Rocket<Build>> {
+        let attached = match self.name {
+            Some(name) => rocket
+                .state::<SQLxPostgresPools>()
+                .map_or(false, |pools| pools.contains(name)),
+            None => rocket.state::<SQLxPostgresPool>().is_some(),
+        };
+
+        if attached {
+            match self.name {
+                Some(name) => {
+                    log::error!("SQLxPostgres: database `{}` attached more than once", name)
+                }
+                None => log::error!("SQLxPostgres: SqlxPostgresFairing attached more than once"),
+            }
+
+            return Err(rocket);
+        }
+
+        let config = if self.from_figment {
+            match SqlxPostgresConfig::from_figment(rocket.figment()) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("SQLxPostgres: invalid configuration: {}", e);
+                    return Err(rocket);
+                }
+            }
+        } else {
+            self.config.clone()
+        };
+
+        let config = match self.fail_fast {
+            Some(fail_fast) => config.ignore_connect_errors(!fail_fast),
+            None => config,
+        };
+
+        let workers = rocket.figment().extract::<rocket::Config>().map_or_else(
+            |_| rocket::Config::default().workers,
+            |config| config.workers,
+        );
+
+        let config = match config.scale_to_workers(workers) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("SQLxPostgres: invalid configuration: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let rocket = maintenance::register(rocket, &config);
+
+        let store = if let Some(poll) = &self.poll {
+            log::info!("SQLxPostgres: using externally provided pool");
+            SQLxPostgresPool::with_config(poll.clone(), config)
+        } else if let Some(shared_pool) = self.shared_pool {
+            match shared_pool(&rocket) {
+                Some(pool) => {
+                    log::info!("SQLxPostgres: using the rocket_db_pools pool");
+                    SQLxPostgresPool::with_config(pool, config)
+                }
+                None => {
+                    log::error!(
+                        "SQLxPostgres: rocket_db_pools database not found, attach it first"
+                    );
+                    return Err(rocket);
+                }
+            }
+        } else {
+            let connect_opts = match config.connect_options() {
+                Ok(opts) => opts,
+                Err(e) => {
+                    log::error!("SQLxPostgres: invalid configuration: {}", e);
+                    return Err(rocket);
+                }
+            };
+
+            let start = std::time::Instant::now();
+            let connecting = config.connect_with_retries(connect_opts);
+            #[cfg(feature = "tracing")]
+            let connecting = tracing::Instrument::instrument(connecting, config.connect_span());
+
+            let pg_pool = match connecting.await {
+                Ok(n) => {
+                    log::info!(
+                        "SQLxPostgres: connecting to {} succeeded in {:?}",
+                        config.summary(),
+                        start.elapsed()
+                    );
+                    n
+                }
+                Err(e) => {
+                    log::error!(
+                        "SQLxPostgres: connecting to {} failed: {}",
+                        config.summary(),
+                        e
+                    );
+
+                    #[cfg(feature = "metrics")]
+                    if let Some(metrics) = &self.metrics {
+                        metrics.database(self.database_name()).connection_error();
+                    }
+
+                    if let Some(callback) = &self.on_connect_failed {
+                        callback(&e).await;
+                    }
+
+                    if config.ignore_connect_errors {
+                        log::warn!("SQLxPostgres: launching without a database pool");
+                        return Ok(rocket);
+                    }
+
+                    return Err(rocket);
+                }
+            };
+
+            SQLxPostgresPool::with_config(pg_pool, config)
+        };
+
+        #[cfg(feature = "metrics")]
+        let store = self.attach_metrics(self.database_name(), store);
+
+        if let Some(callback) = &self.on_connected {
+            if let Err(e) = callback(&store.client()).await {
+                log::error!("SQLxPostgres: on_connected callback failed: {}", e);
+                return Err(rocket);
+            }
+        }
+
+        // A supplied pool was set up by the application, which knows what it points at.
+        let supplied = self.poll.is_some() || self.shared_pool.is_some();
+        let verify_role = store.config.verify_server_role && !store.config.lazy && !supplied;
+        let mut in_recovery = None;
+
+        if let (Some(timeout), false) = (store.config.startup_ping, store.config.lazy) {
+            // With the role check on, the ping itself reads the role.
+            let ping = match verify_role {
+                true => connect::ping_recovery(&store.client(), timeout)
+                    .await
+                    .map(|(latency, recovery)| (latency, Some(recovery))),
+                false => connect::ping(&store.client(), timeout)
+                    .await
+                    .map(|latency| (latency, None)),
+            };
+
+            match ping {
+                Ok((latency, recovery)) => {
+                    log::info!("SQLxPostgres: database answered in {:?}", latency);
+                    in_recovery = recovery;
+                }
+                Err(e) if store.config.ignore_connect_errors => {
+                    log::warn!("SQLxPostgres: startup ping failed: {}", e)
+                }
+                Err(e) => {
+                    log::error!("SQLxPostgres: startup ping failed: {}", e);
+                    return Err(rocket);
+                }
+            }
+        }
+
+        if verify_role {
+            let in_recovery = match in_recovery {
+                Some(in_recovery) => Ok(in_recovery),
+                None => connect::ping_recovery(&store.client(), store.config.connect_timeout)
+                    .await
+                    .map(|(_, in_recovery)| in_recovery),
+            };
+
+            match in_recovery {
+                Ok(false) => {}
+                Ok(true) => {
+                    log::error!(
+                        "SQLxPostgres: primary {} is in recovery, a read-only standby; point the \
+                         primary config at the primary or turn off verify_server_role",
+                        store.config
+                    );
+                    return Err(rocket);
+                }
+                Err(e) => log::warn!(
+                    "SQLxPostgres: checking whether primary {} is in recovery failed: {}",
+                    store.config,
+                    e
+                ),
+            }
+        }
+
+        if let (Some((major, minor)), false) = (store.config.server_version, store.config.lazy) {
+            let required = connect::ServerVersion { major, minor };
+
+            match connect::server_version(&store.client()).await {
+                Ok(version) if version >= required => {
+                    log::info!("SQLxPostgres: server version {}", version)
+                }
+                Ok(version) => {
+                    log::error!(
+                        "SQLxPostgres: server is {}, need at least {}",
+                        version,
+                        required
+                    );
+                    return Err(rocket);
+                }
+                Err(e) => {
+                    log::error!("SQLxPostgres: reading the server version failed: {}", e);
+                    return Err(rocket);
+                }
+            }
+        }
+
+        if store.config.check_connection_limit
+            && !store.config.lazy
+            && !store.config.pgbouncer_mode
+            && !supplied
+        {
+            let max_connections = store.config.max_connections();
+
+            match connect::connection_limit(&store.client()).await {
+                Ok(limit) if max_connections > limit.headroom() => {
+                    let message = format!(
+                        "max_connections {} exceeds the {} connections left on the server: \
+                         max_connections {}, {} reserved for superusers, {} used by other clients",
+                        max_connections,
+                        limit.headroom(),
+                        limit.max,
+                        limit.reserved,
+                        limit.others
+                    );
+
+                    match store.config.strict_connection_limit {
+                        true => {
+                            log::error!("SQLxPostgres: {}", message);
+                            return Err(rocket);
+                        }
+                        false => log::warn!("SQLxPostgres: {}", message),
+                    }
+                }
+                Ok(limit) => log::info!(
+                    "SQLxPostgres: max_connections {} fits the {} connections left on the server: \
+                     max_connections {}, {} reserved for superusers, {} used by other clients",
+                    max_connections,
+                    limit.headroom(),
+                    limit.max,
+                    limit.reserved,
+                    limit.others
+                ),
+                Err(e) => log::warn!(
+                    "SQLxPostgres: reading the server's connection limit failed: {}",
+                    e
+                ),
+            }
+        }
+
+        if store.config.warm_up && !store.config.lazy {
+            let config = &store.config;
+            let count = match config.min_connections {
+                0 => config.max_connections,
+                min => std::cmp::min(min, config.max_connections),
+            };
+
+            match connect::warm_up(&store.client(), count, config.warm_up_timeout).await {
+                Ok(elapsed) => {
+                    log::info!(
+                        "SQLxPostgres: warmed up {} connections in {:?}",
+                        count,
+                        elapsed
+                    )
+                }
+                Err(e) if config.warm_up_required => {
+                    log::error!("SQLxPostgres: warm up failed: {}", e);
+                    return Err(rocket);
+                }
+                Err(e) => log::warn!("SQLxPostgres: warm up failed: {}", e),
+            }
+        }
+
+        let store = match self.prepare_database(&store).await {
+            Ok(migrations) => SQLxPostgresPool {
+                migrations: KnownMigrations(migrations),
+                ..store
+            },
+            Err(e) => {
+                log::error!("SQLxPostgres: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        // Externally provided pools can't be rebuilt from the config.
+        let (rocket, store) = match self.poll.is_none() && self.shared_pool.is_none() {
+            true => (
+                statement_log::register(rocket, self.database_name(), &store),
+                store.rotatable(self.database_name()),
+            ),
+            false => (rocket, store),
+        };
+
+        let rocket = self.connect_secondary_pools(rocket, &store.config).await?;
+
+        let name = match self.name {
+            Some(name) => name,
+            None => {
+                let mut rocket = self.connect_replicas(rocket).await?;
+
+                if !self.channels.is_empty() {
+                    let resync = self
+                        .listen_resync
+                        .iter()
+                        .filter(|(channel, _)| self.channels.contains(channel))
+                        .cloned()
+                        .collect();
+
+                    match PgNotifications::connect(&store, &self.channels, resync).await {
+                        Ok(notifications) => {
+                            log::info!("SQLxPostgres: listening on {:?}", self.channels);
+                            rocket = rocket.manage(notifications);
+                        }
+                        Err(e) if self.listen_required => {
+                            log::error!("SQLxPostgres: listener failed to connect: {}", e);
+                            return Err(rocket);
+                        }
+                        Err(e) => log::warn!("SQLxPostgres: listener failed to connect: {}", e),
+                    }
+                }
+
+                if let Some(health) = &store.health {
+                    rocket = rocket.manage(health.clone());
+                }
+
+                if let Some(rotation) = &store.rotation {
+                    rocket = rocket.manage(rotation.clone());
+                }
+
+                if store.config.manage_raw_pool {
+                    if rocket.state::<PgPool>().is_some() {
+                        log::warn!(
+                            "SQLxPostgres: a PgPool is already managed, not managing the raw pool"
+                        );
+                    } else {
+                        rocket = rocket.manage(store.client());
+                    }
+
+                    if rocket.state::<PgPoolSlot>().is_none() {
+                        rocket = rocket.manage(store.slot());
+                    }
+                }
+
+                return Ok(rocket.manage(store));
+            }
+        };
+
+        if !self.replicas.is_empty() {
+            log::warn!(
+                "SQLxPostgres: replicas of named database `{}` ignored",
+                name
+            );
+        }
+
+        if !self.channels.is_empty() {
+            log::warn!(
+                "SQLxPostgres: channels of named database `{}` ignored",
+                name
+            );
+        }
+
+        match rocket.state::<SQLxPostgresPools>() {
+            Some(pools) => {
+                if !pools.insert(name, store) {
+                    log::error!("SQLxPostgres: database `{}` attached more than once", name);
+                    return Err(rocket);
+                }
+
+                Ok(rocket)
+            }
+            None => {
+                let pools = SQLxPostgresPools::default();
+                pools.insert(name, store);
+                Ok(rocket.manage(pools))
+            }
+        }
+    }
+
+    /// Attaches each database of the `databases` figment table as a named fairing would.
+    async fn ignite_figment_databases(
+        &self,
+        mut rocket: Rocket<Build>,
+        databases: &Mutex<Vec<Arc<SqlxPostgresFairing>>>,
+    ) -> std::result::Result<Rocket<Build>, Rocket<Build>> {
+        let configs = match SqlxPostgresConfig::from_figment_databases(rocket.figment()) {
+            Ok(configs) => configs,
+            Err(e) => {
+                log::error!("SQLxPostgres: invalid configuration: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        for (name, config) in configs {
+            let name = named::intern(name);
+            let fairing = Self {
+                fail_fast: self.fail_fast,
+                #[cfg(feature = "metrics")]
+                metrics: self.metrics.clone(),
+                ..Self::named(name, config)
+            };
+
+            rocket = fairing.on_ignite(rocket).await?;
+            databases
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(Arc::new(fairing));
+        }
+
+        Ok(rocket)
+    }
+
+    fn attached(databases: &Mutex<Vec<Arc<SqlxPostgresFairing>>>) -> Vec<Arc<SqlxPostgresFairing>> {
+        databases.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Spawns the tasks reporting the pool's status until shutdown.
+    fn spawn_status_tasks(
+        &self,
+        name: &'static str,
+        store: &SQLxPostgresPool,
+        rocket: &Rocket<Orbit>,
+    ) {
+        if let (Some(health), Some(interval)) = (&store.health, store.config.health_probe_interval)
+        {
+            let config = store.config.clone();
+            let probe = health
+                .clone()
+                .run(store.handle(), config, interval, rocket.shutdown());
+            rocket::tokio::spawn(probe);
+        }
+
+        if let Some(interval) = store.config.metrics_log_interval {
+            let status = store.status_source();
+            rocket::tokio::spawn(status::log_status(
+                name,
+                interval,
+                status,
+                rocket.shutdown(),
+            ));
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = store.metrics.clone() {
+            rocket::tokio::spawn(metrics.refresh(store.status_source(), rocket.shutdown()));
+        }
+    }
+
+    /// Starts the transaction watchdog of `store` when it has one.
+    fn spawn_watchdog(&self, name: &'static str, store: &SQLxPostgresPool, rocket: &Rocket<Orbit>) {
+        let watchdog = match store.config.transaction_watchdog {
+            Some(watchdog) => watchdog,
+            None => return,
+        };
+
+        // The watchdog connects on its own, which the config of a pool passed in can't do.
+        if self.poll.is_some() || self.shared_pool.is_some() {
+            log::warn!(
+                "SQLxPostgres: `{}` was given an existing pool, its transaction watchdog is not started",
+                name
+            );
+            return;
+        }
+
+        let config = store.config.clone();
+        rocket::tokio::spawn(watchdog.run(name, config, rocket.shutdown()));
+    }
+
+    /// Adds the metrics of database `name` to `store`, also counting its query cache.
+    #[cfg(feature = "metrics")]
+    fn attach_metrics(&self, name: &'static str, store: SQLxPostgresPool) -> SQLxPostgresPool {
+        let metrics = self.metrics.as_ref().map(|m| m.database(name));
+
+        SQLxPostgresPool {
+            query_cache: store.config.query_cache.then(|| {
+                let cache = QueryCache::new(store.config.query_cache_max_entries);
+                Arc::new(cache.with_metrics(metrics.clone()))
+            }),
+            metrics,
+            ..store
+        }
+    }
+
+    /// Connects the secondary pools of `primary` into [`SQLxPostgresPools`].
+    async fn connect_secondary_pools(
+        &self,
+        mut rocket: Rocket<Build>,
+        primary: &SqlxPostgresConfig,
+    ) -> Result<Rocket<Build>, Rocket<Build>> {
+        for (name, settings) in &primary.secondary_pools {
+            let name = *name;
+            let config = settings.apply(primary);
+            let result = match config.connect_options() {
+                Ok(opts) => config
+                    .connect_with_retries(opts)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+
+            let pool = match result {
+                Ok(pool) => pool,
+                Err(e) if config.ignore_connect_errors => {
+                    log::warn!(
+                        "SQLxPostgres: secondary pool `{}` failed to connect: {}",
+                        name,
+                        e
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    log::error!(
+                        "SQLxPostgres: secondary pool `{}` failed to connect: {}",
+                        name,
+                        e
+                    );
+                    return Err(rocket);
+                }
+            };
+
+            log::info!(
+                "SQLxPostgres: connected secondary pool `{}` with {} connections",
+                name,
+                config.max_connections
+            );
+
+            let store = SQLxPostgresPool::with_config(pool, config).rotatable(name);
+            #[cfg(feature = "metrics")]
+            let store = self.attach_metrics(name, store);
+            rocket = statement_log::register(rocket, name, &store);
+
+            rocket = match rocket.state::<SQLxPostgresPools>() {
+                Some(pools) => {
+                    if !pools.insert(name, store) {
+                        log::error!("SQLxPostgres: database `{}` attached more than once", name);
+                        return Err(rocket);
+                    }
+
+                    rocket
+                }
+                None => {
+                    let pools = SQLxPostgresPools::default();
+                    pools.insert(name, store);
+                    rocket.manage(pools)
+                }
+            };
+        }
+
+        Ok(rocket)
+    }
+
+    /// The secondary pools of this fairing's database that connected.
+    fn secondary_stores<P: Phase>(
+        &self,
+        rocket: &Rocket<P>,
+    ) -> Vec<(&'static str, Arc<SQLxPostgresPool>)> {
+        let pools = match rocket.state::<SQLxPostgresPools>() {
+            Some(pools) => pools,
+            None => return Vec::new(),
+        };
+
+        let config = match self.name {
+            None => rocket
+                .state::<SQLxPostgresPool>()
+                .map(|store| store.config.clone()),
+            Some(name) => pools.pool(name).map(|store| store.config.clone()),
+        };
+
+        config
+            .into_iter()
+            .flat_map(|config| config.secondary_pools)
+            .filter_map(|(name, _)| pools.pool(name).map(|store| (name, store)))
+            .collect()
+    }
+
+    /// Connects the replica pools into the managed [`SQLxPostgresReplicas`], unless there is
+    /// no usable replica.
+    ///
+    /// Unreachable replicas get a lazy pool, connected once their health probe succeeds.
+    async fn connect_replicas(
+        &self,
+        rocket: Rocket<Build>,
+    ) -> Result<Rocket<Build>, Rocket<Build>> {
+        let mut replicas = Vec::new();
+
+        for config in &self.replicas {
+            let opts = match config.connect_options() {
+                Ok(opts) => opts,
+                Err(e) => {
+                    log::warn!("SQLxPostgres: replica {} ignored: {}", config, e);
+                    continue;
+                }
+            };
+
+            let (pool, connected) = match config.connect_with_retries(opts.clone()).await {
+                Ok(pool) => {
+                    log::info!("SQLxPostgres: connected to replica {}", config);
+
+                    if config.verify_server_role && !config.lazy {
+                        match connect::ping_recovery(&pool, config.connect_timeout).await {
+                            Ok((_, true)) => {}
+                            Ok((_, false)) if self.strict_replica_role => {
+                                log::error!(
+                                    "SQLxPostgres: replica {} is not in recovery, it is a primary; \
+                                     point the replica config at a standby",
+                                    config
+                                );
+                                return Err(rocket);
+                            }
+                            Ok((_, false)) => log::warn!(
+                                "SQLxPostgres: replica {} is not in recovery, it is a primary; \
+                                 reads sent to it load the primary",
+                                config
+                            ),
+                            Err(e) => log::warn!(
+                                "SQLxPostgres: checking whether replica {} is in recovery failed: {}",
+                                config,
+                                e
+                            ),
+                        }
+                    }
+
+                    (pool, true)
+                }
+                Err(e) => {
+                    log::warn!(
+                        "SQLxPostgres: replica {} unavailable, retrying in the background: {}",
+                        config,
+                        e
+                    );
+
+                    let lazy = config.clone().lazy(true);
+                    match lazy.connect_with_retries(opts).await {
+                        Ok(pool) => (pool, false),
+                        Err(_) => continue,
+                    }
+                }
+            };
+
+            let name = config.endpoint();
+            let store = SQLxPostgresPool::with_config(pool, config.clone());
+            #[cfg(feature = "metrics")]
+            let store = SQLxPostgresPool {
+                metrics: self
+                    .metrics
+                    .as_ref()
+                    .map(|metrics| metrics.database(&format!("replica:{}", name))),
+                ..store
+            };
+
+            replicas.push((name, store, connected));
+        }
+
+        match replicas.is_empty() {
+            true => Ok(rocket),
+            false => Ok(rocket.manage(SQLxPostgresReplicas::new(
+                replicas,
+                self.replica_strategy,
+                self.replica_max_lag,
+            ))),
+        }
+    }
+
+    /// Runs the database setup steps once the pool is ready, in order, returning the
+    /// migrations run or checked.
+    async fn prepare_database(&self, store: &SQLxPostgresPool) -> crate::Result<Vec<Migration>> {
+        ensure_schema(store).await?;
+        let mut migrations = self.run_migrations(&store.client()).await?;
+        self.check_required_migrations(&store.client()).await?;
+        self.run_init_sql(&store.client()).await?;
+        prepare_warmup(&store.config, &store.client()).await?;
+
+        if let (true, Some(migrator)) = (migrations.is_empty(), &self.required_migrator) {
+            migrations = migrator.iter().cloned().collect();
+        }
+
+        Ok(migrations)
+    }
+
+    async fn run_migrations(&self, pool: &PgPool) -> crate::Result<Vec<Migration>> {
+        let loaded;
+        let migrator = match (&self.migrator, &self.migrations_path) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Config(ConfigError::Conflict {
+                    option: "with_migrator",
+                    conflicts_with: "with_migrations_path",
+                }))
+            }
+            (Some(migrator), None) => migrator,
+            (None, Some(path)) => {
+                loaded = migrate::from_path(path).await?;
+                log::info!(
+                    "SQLxPostgres: found {} migrations in `{}`",
+                    loaded.iter().count(),
+                    path.display()
+                );
+                &loaded
+            }
+            (None, None) if self.migration_target.is_some() => {
+                return Err(Error::Config(ConfigError::MissingOption {
+                    option: "with_migration_target",
+                    requires: "with_migrator",
+                }))
+            }
+            (None, None) => return Ok(Vec::new()),
+        };
+        let target = self.migration_target.map(|version| migrate::Target {
+            version,
+            allow_revert: self.allow_revert,
+        });
+
+        match (
+            migrate::run(pool, migrator, target).await?,
+            self.migration_target,
+        ) {
+            ((0, 0), None) => {
+                log::info!("SQLxPostgres: database up to date, 0 migrations pending")
+            }
+            ((_, count), None) => log::info!(
+                "SQLxPostgres: {} migrations applied, 0 migrations pending",
+                count
+            ),
+            ((reverted, applied), Some(version)) => log::info!(
+                "SQLxPostgres: database at migration {}, {} migrations reverted, {} applied",
+                version,
+                reverted,
+                applied
+            ),
+        }
+
+        Ok(migrator.iter().cloned().collect())
+    }
+
+    async fn check_required_migrations(&self, pool: &PgPool) -> crate::Result<()> {
+        let migrator = match &self.required_migrator {
+            Some(migrator) => migrator,
+            None => return Ok(()),
+        };
+        let migrations = migrator.iter().cloned().collect::<Vec<_>>();
+        let status = migrate::status(pool, &migrations).await?;
+
+        if status.is_up_to_date() {
+            log::info!(
+                "SQLxPostgres: all {} migrations applied",
+                status.applied.len()
+            );
+            return Ok(());
+        }
+
+        if let Some(version) = status.dirty {
+            log::error!("SQLxPostgres: migration {} is partially applied", version);
+        }
+
+        if !status.mismatched.is_empty() {
+            log::error!(
+                "SQLxPostgres: migrations edited after being applied: {:?}",
+                status.mismatched
+            );
+        }
+
+        if !status.pending.is_empty() {
+            log::error!("SQLxPostgres: pending migrations: {:?}", status.pending);
+        }
+
+        Err(Error::MigrationsNotApplied(status))
+    }
+
+    async fn run_init_sql(&self, pool: &PgPool) -> crate::Result<()> {
+        if self.init_sql.is_empty() {
+            return Ok(());
+        }
+
+        let mut statements = Vec::with_capacity(self.init_sql.len());
+
+        for init in &self.init_sql {
+            statements.push(match init {
+                InitSql::Statement(sql) => sql.clone(),
+                InitSql::File(path) => {
+                    std::fs::read_to_string(path).map(Cow::Owned).map_err(|e| {
+                        ConfigError::UnreadableFile {
+                            option: "init_sql",
+                            path: path.clone(),
+                            reason: e.to_string(),
+                        }
+                    })?
+                }
+            });
+        }
+
+        let failed = |index: usize, source: sqlx::Error| Error::InitSql { index, source };
+
+        if self.init_sql_transactional {
+            let mut tx = pool.begin().await?;
+
+            for (index, sql) in statements.iter().enumerate() {
+                tx.execute(&**sql).await.map_err(|e| failed(index, e))?;
+            }
+
+            tx.commit().await?;
+        } else {
+            let mut conn = pool.acquire().await?;
+
+            for (index, sql) in statements.iter().enumerate() {
+                conn.execute(&**sql).await.map_err(|e| failed(index, e))?;
+            }
+        }
+
+        log::info!("SQLxPostgres: ran {} init sql statements", statements.len());
+        Ok(())
+    }
+}
+
+async fn ensure_schema(store: &SQLxPostgresPool) -> crate::Result<()> {
+    let schema = match store.config.schema() {
+        Some(schema) if store.config.ensure_schema => schema,
+        _ => return Ok(()),
+    };
+
+    match connect::ensure_schema(&store.client(), schema).await {
+        Ok(true) => {
+            log::info!("SQLxPostgres: created schema `{}`", schema);
+            Ok(())
+        }
+        Ok(false) => Ok(()),
+        Err(source) => {
+            if let sqlx::Error::Database(e) = &source {
+                if e.code().as_deref() == Some("42501") {
+                    let role: String = sqlx::query_scalar("SELECT current_user::text")
+                        .fetch_one(&store.client())
+                        .await
+                        .unwrap_or_else(|_| store.config.username.to_string());
+
+                    log::error!(
+                        "SQLxPostgres: role `{}` is not allowed to create schema `{}`, grant it CREATE on the database or create the schema beforehand",
+                        role,
+                        schema
+                    );
+                }
+            }
+
+            Err(Error::Schema {
+                schema: schema.to_owned(),
+                source,
+            })
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SqlxPostgresFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "SQLxPostgres",
+            kind: fairing::Kind::Ignite
+                | fairing::Kind::Liftoff
+                | fairing::Kind::Response
+                | fairing::Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(
+        &self,
+        rocket: Rocket<Build>,
+    ) -> std::result::Result<Rocket<Build>, Rocket<Build>> {
+        let mut rocket = rocket;
+
+        for shards in &self.shards {
+            rocket = shards.manage(rocket)?;
+        }
+
+        if let Some(databases) = &self.figment_databases {
+            self.ready
+                .fail("several databases attached, look them up in SQLxPostgresPools");
+            return self.ignite_figment_databases(rocket, databases).await;
+        }
+
+        let rocket = named::register_attached(rocket, self.name.unwrap_or(DefaultDatabase::NAME));
+
+        let result = self.ignite(rocket).await;
+
+        let store = |rocket: &Rocket<Build>| match self.name {
+            None => rocket.state::<SQLxPostgresPool>().map(|store| {
+                (
+                    store.handle(),
+                    store.config.lazy,
+                    store.config.connect_timeout,
+                )
+            }),
+            Some(name) => rocket
+                .state::<SQLxPostgresPools>()
+                .and_then(|pools| pools.pool(name))
+                .map(|store| {
+                    (
+                        store.handle(),
+                        store.config.lazy,
+                        store.config.connect_timeout,
+                    )
+                }),
+        };
+
+        match result.as_ref().map(store) {
+            Ok(Some((slot, true, timeout))) => self.ready.resolve_lazy(slot, timeout),
+            Ok(Some((slot, false, _))) => self.ready.resolve(slot),
+            Ok(None) => self
+                .ready
+                .fail("connecting at ignite failed, launched without a pool"),
+            Err(_) => self.ready.fail("ignite failed, see the log"),
+        }
+
+        match (result, self.name) {
+            (Ok(rocket), None) if rocket.state::<DbReady>().is_none() => {
+                let rocket = rocket.manage(self.ready.clone());
+
+                match rocket.state::<DbTasks>() {
+                    Some(_) => Ok(rocket),
+                    None => Ok(rocket.manage(self.tasks.clone())),
+                }
+            }
+            (result, _) => result,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        for shards in &self.shards {
+            shards.check(rocket);
+        }
+
+        self.tasks.start(self.ready.clone(), rocket.shutdown());
+
+        if let Some(databases) = &self.figment_databases {
+            for fairing in Self::attached(databases) {
+                fairing.on_liftoff(rocket).await;
+            }
+
+            return;
+        }
+
+        if self.name.is_none() {
+            if let Some(notifications) = rocket.state::<PgNotifications>() {
+                notifications.spawn(rocket.shutdown());
+            }
+
+            if let Some(replicas) = rocket.state::<SQLxPostgresReplicas>() {
+                replicas.spawn(rocket.shutdown());
+            }
+        }
+
+        match self.name {
+            None => {
+                if let Some(store) = rocket.state::<SQLxPostgresPool>() {
+                    self.spawn_status_tasks(DefaultDatabase::NAME, store, rocket);
+                    self.spawn_watchdog(DefaultDatabase::NAME, store, rocket);
+                }
+            }
+            Some(name) => {
+                let pools = rocket.state::<SQLxPostgresPools>();
+
+                if let Some(store) = pools.and_then(|pools| pools.pool(name)) {
+                    self.spawn_status_tasks(name, &store, rocket);
+                    self.spawn_watchdog(name, &store, rocket);
+                }
+            }
+        }
+
+        // Secondary pools are always connected from the config, so they are supervised too.
+        for (name, store) in self.secondary_stores(rocket) {
+            self.spawn_status_tasks(name, &store, rocket);
+            spawn_supervisor(store.handle(), store.config.clone(), rocket);
+        }
+
+        if self.poll.is_some() || self.shared_pool.is_some() {
+            return;
+        }
+
+        let supervised = match self.name {
+            None => rocket
+                .state::<SQLxPostgresPool>()
+                .map(|store| (store.handle(), store.config.clone())),
+            Some(name) => rocket
+                .state::<SQLxPostgresPools>()
+                .and_then(|pools| pools.pool(name))
+                .map(|store| (store.handle(), store.config.clone())),
+        };
+
+        if let Some((slot, config)) = supervised {
+            spawn_supervisor(slot, config, rocket);
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if self.name.is_none() && self.figment_databases.is_none() {
+            if request.local_cache(|| AcquireTimedOut(false)).0 {
+                response.set_raw_header("Retry-After", "1");
+            }
+
+            self.end_request_transaction(request, response).await;
+        }
+
+        RequestDbTime::report(request, response);
+        QueryCapture::report(request, response);
+        maintenance::report(request, response);
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        // Tasks still use the pool, so they stop before it is closed.
+        self.tasks.drain().await;
+
+        if let Some(databases) = &self.figment_databases {
+            for fairing in Self::attached(databases) {
+                fairing.on_shutdown(rocket).await;
+            }
+
+            return;
+        }
+
+        for (_, store) in self.secondary_stores(rocket) {
+            close_pool(&store).await;
+        }
+
+        if self.shared_pool.is_some() {
+            return;
+        }
+
+        match self.name {
+            None => {
+                if let Some(replicas) = rocket.state::<SQLxPostgresReplicas>() {
+                    for replica in replicas.stores() {
+                        close_pool(replica).await;
+                    }
+                }
+
+                if let Some(store) = rocket.state::<SQLxPostgresPool>() {
+                    close_pool(store).await;
+                }
+            }
+            Some(name) => {
+                if let Some(store) = rocket
+                    .state::<SQLxPostgresPools>()
+                    .and_then(|pools| pools.pool(name))
+                {
+                    close_pool(&store).await;
+                }
+            }
+        }
+    }
+}
+
+/// Spawns the tasks keeping the pool in `slot` connected, as configured.
+fn spawn_supervisor(slot: Arc<RwLock<PgPool>>, config: SqlxPostgresConfig, rocket: &Rocket<Orbit>) {
+    #[cfg(feature = "rds-iam")]
+    if config.rds_iam().is_some() {
+        rocket::tokio::spawn(rds::refresh(
+            slot.clone(),
+            config.clone(),
+            rocket.shutdown(),
+        ));
+    }
+
+    if let Some(interval) = config.supervisor_interval {
+        let max_attempts = config.supervisor_attempts;
+        let shutdown = rocket.shutdown();
+        rocket::tokio::spawn(supervisor::supervise(
+            slot,
+            config,
+            interval,
+            max_attempts,
+            shutdown,
+        ));
+    }
+}
+
+/// Closes the pool, waiting at most the configured shutdown grace.
+async fn close_pool(store: &SQLxPostgresPool) {
+    let grace = store.config.shutdown_grace;
+
+    log::info!("SQLxPostgres: closing database pool");
+
+    if rocket::tokio::time::timeout(grace, store.client().close())
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "SQLxPostgres: connections still checked out after {:?}, closing them on drop",
+            grace
+        );
+    }
+}
+
+/// Prepares the statements of [`SqlxPostgresConfig::with_prepared_warmup`] on up to
+/// `min_connections` connections held at once, so each lands in a different cache.
+async fn prepare_warmup(config: &SqlxPostgresConfig, pool: &PgPool) -> crate::Result<()> {
+    if config.prepared_warmup.is_empty() {
+        return Ok(());
+    }
+
+    let capacity = config.cached_statements();
+
+    if capacity == 0 {
+        log::warn!(
+            "SQLxPostgres: the statement cache is disabled, not preparing the warm up statements"
+        );
+        return Ok(());
+    }
+
+    if config.prepared_warmup.len() > capacity {
+        log::warn!(
+            "SQLxPostgres: only preparing the first {} of {} warm up statements, the statement cache capacity",
+            capacity,
+            config.prepared_warmup.len()
+        );
+    }
+
+    let prepared = std::cmp::min(capacity, config.prepared_warmup.len());
+    let statements = &config.prepared_warmup[..prepared];
+    let count = std::cmp::min(config.min_connections, config.max_connections()).max(1);
+    let start = std::time::Instant::now();
+    let mut connections = Vec::with_capacity(count as usize);
+
+    for _ in 0..count {
+        connections.push(pool.acquire().await?);
+    }
+
+    for conn in &mut connections {
+        for sql in statements {
+            (&mut **conn)
+                .prepare(&**sql)
+                .await
+                .map_err(|source| Error::Prepare {
+                    sql: sql.to_string(),
+                    source,
+                })?;
+        }
+    }
+
+    log::info!(
+        "SQLxPostgres: prepared {} statements on {} connections in {:?}",
+        statements.len(),
+        count,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "hunter2-Sup3rSecret";
+
+    fn assert_hides_password(config: &SqlxPostgresConfig) {
+        let debug = format!("{:?}", config);
+        let pretty = format!("{:#?}", config);
+        let display = config.to_string();
+
+        for shown in [&debug, &pretty, &display] {
+            assert!(!shown.contains(PASSWORD), "password shown in {}", shown);
+        }
+    }
+
+    #[test]
+    fn debug_and_display_hide_the_password() {
+        let config = SqlxPostgresConfig::default()
+            .with_username("app")
+            .with_password(PASSWORD)
+            .with_database("app");
+
+        assert_hides_password(&config);
+        assert!(format!("{:?}", config).contains("<redacted>"));
+        assert_eq!(config.to_string(), "app@localhost:5432/app");
+    }
+
+    #[test]
+    fn debug_hides_the_password_of_connect_options() {
+        let options = PgConnectOptions::new().username("app").password(PASSWORD);
+        let config = SqlxPostgresConfig::default().with_connect_options(options);
+
+        assert_hides_password(&config);
+    }
+
+    fn valid() -> SqlxPostgresConfig {
+        SqlxPostgresConfig::default()
+            .with_database("app")
+            .with_username("app")
+    }
+
+    fn missing_file() -> PathBuf {
+        PathBuf::from("/nonexistent/rocket_sqlxpostgres/cert.pem")
+    }
+
+    fn existing_file() -> PathBuf {
+        PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+    }
+
+    #[test]
+    fn validate_accepts_a_valid_config() {
+        assert_eq!(valid().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_zero_max_connections() {
+        let config = valid().set_max_connections(0);
+
+        assert_eq!(config.validate(), Err(ConfigError::ZeroMaxConnections));
+    }
+
+    #[test]
+    fn validate_rejects_zero_max_connections_from_the_figment() {
+        let figment = Figment::new()
+            .merge(("sqlx_postgres.database", "app"))
+            .merge(("sqlx_postgres.username", "app"))
+            .merge(("sqlx_postgres.max_connections", 0));
+        let config = SqlxPostgresConfig::from_figment(&figment).unwrap();
+
+        assert_eq!(config.validate(), Err(ConfigError::ZeroMaxConnections));
+    }
+
+    #[test]
+    fn validate_rejects_a_zero_acquire_timeout() {
+        let config = valid().with_acquire_timeout(Duration::from_secs(0));
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::ZeroDuration("acquire_timeout"))
+        );
+    }
+
+    #[test]
+    fn validate_rejects_required_channel_binding() {
+        let config = valid().with_channel_binding(ChannelBinding::Require);
+
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Unsupported {
+                option: "channel_binding=require",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_connections_per_worker_with_max_connections() {
+        let config = valid()
+            .set_max_connections(10)
+            .with_connections_per_worker(1.5, 16u32);
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Conflict {
+                option: "connections_per_worker",
+                conflicts_with: "max_connections",
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_an_invalid_role() {
+        let config = valid().with_role("");
+
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Unsupported { option: "role", .. })
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_an_invalid_tenant_template() {
+        let config = valid().with_tenant_schemas(TenantSchemas::new(|_| None, "tenant"));
+
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Unsupported {
+                option: "tenant_schemas",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_ensure_schema_without_a_search_path() {
+        let config = valid().ensure_schema(true);
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingOption {
+                option: "ensure_schema",
+                requires: "search_path",
+            })
+        );
+        assert_eq!(config.with_search_path(&["app"]).validate(), Ok(()));
+    }
+
+    #[cfg(feature = "rds-iam")]
+    #[test]
+    fn validate_rejects_rds_iam_without_tls() {
+        let config = valid()
+            .with_rds_iam("eu-west-1", None)
+            .with_ssl_mode(PgSslMode::Prefer);
+
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Unsupported {
+                option: "rds_iam with ssl_mode disable, allow or prefer",
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "rds-iam")]
+    #[test]
+    fn validate_rejects_rds_iam_over_a_socket() {
+        let config = valid()
+            .with_rds_iam("eu-west-1", None)
+            .with_socket("/var/run/postgresql");
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Conflict {
+                option: "rds_iam",
+                conflicts_with: "socket",
+            })
+        );
+    }
+
+    #[test]
+    fn validate_skips_the_connection_checks_for_connect_options() {
+        let config = SqlxPostgresConfig::default().with_connect_options(PgConnectOptions::new());
+
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_an_empty_database() {
+        let config = valid().with_database("");
+
+        assert_eq!(config.validate(), Err(ConfigError::EmptyDatabase));
+    }
+
+    #[test]
+    fn validate_rejects_an_empty_username() {
+        let config = valid().with_username("");
+
+        assert_eq!(config.validate(), Err(ConfigError::EmptyUsername));
+    }
+
+    #[test]
+    fn validate_rejects_port_zero() {
+        let config = valid().with_port(0);
+
+        assert_eq!(config.validate(), Err(ConfigError::ZeroPort));
+    }
+
+    #[test]
+    fn validate_rejects_tls_over_a_socket() {
+        let config = valid()
+            .with_socket("/var/run/postgresql")
+            .with_ssl_mode(PgSslMode::Require);
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Conflict {
+                option: "socket",
+                conflicts_with: "TLS options",
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_a_missing_root_cert() {
+        let config = valid().with_ssl_root_cert(missing_file());
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingFile {
+                option: "ssl_root_cert",
+                path: missing_file(),
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_a_missing_client_cert() {
+        let config = valid()
+            .with_ssl_client_cert(missing_file())
+            .with_ssl_client_key(existing_file());
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingFile {
+                option: "ssl_client_cert",
+                path: missing_file(),
+            })
+        );
     }
-}
 
-#[derive(Debug)]
-pub struct SQLxPostgres {
-    pub poll: PgPool,
-}
+    #[test]
+    fn validate_rejects_a_missing_client_key() {
+        let config = valid()
+            .with_ssl_client_cert(existing_file())
+            .with_ssl_client_key(missing_file());
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for SQLxPostgres {
-    type Error = ();
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingFile {
+                option: "ssl_client_key",
+                path: missing_file(),
+            })
+        );
+    }
 
-    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
-        let store = try_outcome!(request.guard::<&State<SQLxPostgresPool>>().await);
-        Outcome::Success(SQLxPostgres {
-            poll: store.client.clone(),
-        })
+    #[test]
+    fn validate_rejects_a_client_cert_without_a_key() {
+        let config = valid().with_ssl_client_cert(existing_file());
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingOption {
+                option: "ssl_client_cert",
+                requires: "ssl_client_key",
+            })
+        );
     }
-}
 
-/// Fairing struct
-#[derive(Default)]
-pub struct SqlxPostgresFairing {
-    poll: Option<PgPool>,
-    config: SqlxPostgresConfig,
-}
+    #[test]
+    fn validate_rejects_a_client_key_without_a_cert() {
+        let config = valid().with_ssl_client_key(existing_file());
 
-impl SqlxPostgresFairing {
-    /// Creates a New SQLx Postgres Fairing.
-    ///
-    /// Must be Created to pass to `rocket.attach()`
-    pub fn new(config: SqlxPostgresConfig, poll: Option<PgPool>) -> Self {
-        Self { poll, config }
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::MissingOption {
+                option: "ssl_client_key",
+                requires: "ssl_client_cert",
+            })
+        );
     }
-}
 
-#[rocket::async_trait]
-impl Fairing for SqlxPostgresFairing {
-    fn info(&self) -> Info {
-        Info {
-            name: "SQLxPostgres",
-            kind: fairing::Kind::Ignite,
+    /// The URL of a server requiring client certificates and the pair it accepts.
+    fn client_cert_server() -> (SqlxPostgresConfig, PathBuf, PathBuf) {
+        let var = |name: &str| std::env::var(name).expect(name);
+        let config = SqlxPostgresConfig::from_url(&var("TLS_DATABASE_URL"))
+            .unwrap()
+            .with_ssl_mode(PgSslMode::Require)
+            .with_connect_timeout(Duration::from_secs(5));
+
+        (
+            config,
+            var("SSL_CLIENT_CERT").into(),
+            var("SSL_CLIENT_KEY").into(),
+        )
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TLS_DATABASE_URL, SSL_CLIENT_CERT and SSL_CLIENT_KEY"]
+    async fn connect_authenticates_with_a_client_certificate() {
+        let (config, cert, key) = client_cert_server();
+        let pool = config
+            .with_ssl_client_cert(cert)
+            .with_ssl_client_key(key)
+            .connect()
+            .await
+            .unwrap();
+
+        crate::connect::ping(&pool, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TLS_DATABASE_URL, SSL_CLIENT_CERT and SSL_CLIENT_KEY"]
+    async fn connect_reports_a_server_refusing_a_missing_client_certificate() {
+        let (config, _, _) = client_cert_server();
+
+        let error = config
+            .connect()
+            .await
+            .err()
+            .expect("connected without a client certificate");
+
+        assert!(!error.to_string().is_empty());
+    }
+
+    /// Run once with the default features and once with
+    /// `--no-default-features --features tls-native-tls`.
+    #[rocket::async_test]
+    #[ignore = "needs TLS_DATABASE_URL"]
+    async fn connections_use_tls_with_the_enabled_backend() {
+        let var = |name: &str| std::env::var(name).ok();
+        let url = var("TLS_DATABASE_URL").expect("TLS_DATABASE_URL is not set");
+        let mut config = SqlxPostgresConfig::from_url(&url)
+            .unwrap()
+            .with_ssl_mode(PgSslMode::Require);
+
+        if let Some(root) = var("SSL_ROOT_CERT") {
+            config = config
+                .with_ssl_mode(PgSslMode::VerifyFull)
+                .with_ssl_root_cert(root);
+        }
+
+        if let (Some(cert), Some(key)) = (var("SSL_CLIENT_CERT"), var("SSL_CLIENT_KEY")) {
+            config = config.with_ssl_client_cert(cert).with_ssl_client_key(key);
         }
+
+        let pool = config.connect().await.unwrap();
+        let ssl: bool =
+            sqlx::query_scalar("SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+
+        assert!(ssl);
     }
 
-    async fn on_ignite(
-        &self,
-        rocket: Rocket<Build>,
-    ) -> std::result::Result<Rocket<Build>, Rocket<Build>> {
-        let store = if let Some(poll) = &self.poll {
-            SQLxPostgresPool::new(poll.clone())
-        } else {
-            let mut connect_opts = PgConnectOptions::new();
-            connect_opts.log_statements(self.config.log_level);
-            connect_opts = connect_opts.database(&self.config.database[..]);
-            connect_opts = connect_opts.username(&self.config.username[..]);
-            connect_opts = connect_opts.password(&self.config.password[..]);
-            connect_opts = connect_opts.host(&self.config.host[..]);
-            connect_opts = connect_opts.port(self.config.port);
-
-            let pg_pool = match PgPoolOptions::new()
-                .max_connections(self.config.max_connections)
-                .connect_with(connect_opts)
+    fn option(config: &SqlxPostgresConfig, key: &str) -> Option<String> {
+        config
+            .startup_options()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn startup_options_are_empty_by_default() {
+        assert!(valid().startup_options().is_empty());
+    }
+
+    #[test]
+    fn startup_options_quote_the_search_path() {
+        let config = valid().with_search_path(&["My Schema", "public"]);
+
+        assert_eq!(
+            option(&config, "search_path").as_deref(),
+            Some(r#""My\ Schema","public""#)
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[rocket::get("/items")]
+    async fn count_items(db: SQLxPostgres) -> String {
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM items")
+            .fetch_one(db.pool())
+            .await
+            .map(|count| count.to_string())
+            .unwrap_or_else(|e| e.to_string())
+    }
+
+    #[cfg(feature = "test-util")]
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn search_path_resolves_unqualified_tables_through_the_guard() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let admin = SqlxPostgresConfig::from_url(&url).unwrap();
+        let db = crate::TestDatabase::create(admin).await.unwrap();
+
+        for statement in [
+            r#"CREATE SCHEMA "My App""#,
+            r#"CREATE TABLE "My App".items (id int)"#,
+            r#"INSERT INTO "My App".items VALUES (1), (2)"#,
+        ] {
+            sqlx::query(statement).execute(db.pool()).await.unwrap();
+        }
+
+        let config = db.config().with_search_path(&["My App", "public"]);
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(config, None))
+            .mount("/", rocket::routes![count_items]);
+        let client = rocket::local::asynchronous::Client::tracked(rocket)
+            .await
+            .unwrap();
+        let response = client.get("/items").dispatch().await;
+
+        assert_eq!(response.into_string().await.as_deref(), Some("2"));
+
+        drop(client);
+        db.cleanup().await.unwrap();
+    }
+
+    #[test]
+    fn startup_options_send_timeouts_in_milliseconds() {
+        let config = valid()
+            .with_statement_timeout(Duration::from_secs(5))
+            .with_lock_timeout(Duration::from_millis(1500))
+            .with_idle_in_transaction_timeout(Duration::from_secs(60));
+
+        assert_eq!(
+            option(&config, "statement_timeout").as_deref(),
+            Some("5000")
+        );
+        assert_eq!(option(&config, "lock_timeout").as_deref(), Some("1500"));
+        assert_eq!(
+            option(&config, "idle_in_transaction_session_timeout").as_deref(),
+            Some("60000")
+        );
+    }
+
+    #[test]
+    fn startup_options_skip_a_zero_statement_timeout() {
+        let zero = valid().with_statement_timeout(Duration::from_secs(0));
+        let none = valid()
+            .with_statement_timeout(Duration::from_secs(5))
+            .with_statement_timeout(None);
+
+        assert_eq!(option(&zero, "statement_timeout"), None);
+        assert_eq!(option(&none, "statement_timeout"), None);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn statement_timeout_cancels_queries_on_every_connection() {
+        let pool = server()
+            .set_max_connections(1)
+            .with_statement_timeout(Duration::from_secs(1))
+            .connect()
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let mut conn = pool.acquire().await.unwrap();
+            let error = sqlx::query("SELECT pg_sleep(5)")
+                .execute(&mut *conn)
                 .await
-            {
-                Ok(n) => n,
-                Err(_) => return Ok(rocket),
-            };
+                .unwrap_err();
 
-            SQLxPostgresPool::new(pg_pool)
-        };
+            assert_eq!(
+                error.as_database_error().and_then(|e| e.code()).as_deref(),
+                Some("57014")
+            );
+
+            // The next iteration runs on a connection the pool opened afterwards.
+            sqlx::Connection::close(conn.detach()).await.unwrap();
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn idle_in_transaction_timeout_terminates_an_abandoned_transaction() {
+        let pool = server()
+            .set_max_connections(1)
+            .with_idle_in_transaction_timeout(Duration::from_millis(500))
+            .connect()
+            .await
+            .unwrap();
+
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("SELECT 1").execute(&mut tx).await.unwrap();
+        rocket::tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        assert!(sqlx::query("SELECT 1").execute(&mut tx).await.is_err());
+        drop(tx);
+
+        // The terminated connection is discarded and replaced.
+        let one: i32 = sqlx::query_scalar("SELECT 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(one, 1);
+    }
+
+    #[test]
+    fn startup_options_set_the_tcp_settings() {
+        let config = valid()
+            .with_tcp_keepalive(Duration::from_millis(30_500))
+            .with_tcp_user_timeout(Duration::from_secs(10));
+
+        assert_eq!(
+            option(&config, "tcp_keepalives_idle").as_deref(),
+            Some("30")
+        );
+        assert_eq!(
+            option(&config, "tcp_keepalives_interval").as_deref(),
+            Some("30")
+        );
+        assert_eq!(
+            option(&config, "tcp_user_timeout").as_deref(),
+            Some("10000")
+        );
+    }
+
+    #[test]
+    fn startup_options_round_tcp_keepalive_up_to_a_second() {
+        let config = valid().with_tcp_keepalive(Duration::from_millis(200));
+
+        assert_eq!(option(&config, "tcp_keepalives_idle").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn startup_options_set_the_session_settings() {
+        let config = valid()
+            .read_only(true)
+            .with_timezone("America/New York")
+            .with_notice_log_level(LevelFilter::Warn);
+
+        assert_eq!(
+            option(&config, "default_transaction_read_only").as_deref(),
+            Some("on")
+        );
+        assert_eq!(
+            option(&config, "TimeZone").as_deref(),
+            Some(r"America/New\ York")
+        );
+        assert_eq!(
+            option(&config, "client_min_messages").as_deref(),
+            Some("warning")
+        );
+    }
+
+    /// Dispatches a GET of `uri` to `routes`, served with a pool of `config`.
+    async fn dispatch(
+        config: SqlxPostgresConfig,
+        routes: Vec<rocket::Route>,
+        uri: &str,
+    ) -> Option<String> {
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(config, None))
+            .mount("/", routes);
+        let client = rocket::local::asynchronous::Client::tracked(rocket)
+            .await
+            .unwrap();
+
+        client.get(uri).dispatch().await.into_string().await
+    }
+
+    #[rocket::get("/write")]
+    async fn write(db: SQLxPostgres) -> String {
+        match sqlx::query("CREATE TABLE read_only_probe (id int)")
+            .execute(db.pool())
+            .await
+        {
+            Ok(_) => "written".to_owned(),
+            Err(e) => match e.as_database_error().and_then(|e| e.code()) {
+                Some(code) => code.into_owned(),
+                None => e.to_string(),
+            },
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn read_only_rejects_writes_through_the_guard() {
+        let config = server().read_only(true);
+
+        // SQLSTATE read_only_sql_transaction.
+        assert_eq!(
+            dispatch(config, rocket::routes![write], "/write")
+                .await
+                .as_deref(),
+            Some("25006")
+        );
+    }
+
+    #[test]
+    fn debug_shows_the_timezone() {
+        let config = valid().with_timezone("UTC");
+
+        assert!(format!("{:?}", config).contains(r#"timezone: Some("UTC")"#));
+    }
+
+    #[rocket::get("/timezone")]
+    async fn timezone(db: SQLxPostgres) -> String {
+        sqlx::query_scalar("SHOW TimeZone")
+            .fetch_one(db.pool())
+            .await
+            .unwrap_or_else(|e| e.to_string())
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn timezone_is_set_on_the_connections_of_the_guard() {
+        let config = server().with_timezone("America/New_York");
+
+        assert_eq!(
+            dispatch(config, rocket::routes![timezone], "/timezone")
+                .await
+                .as_deref(),
+            Some("America/New_York")
+        );
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn connect_reports_an_invalid_timezone() {
+        let error = server()
+            .with_timezone("Mars/Olympus_Mons")
+            .connect()
+            .await
+            .err()
+            .expect("connected with an invalid timezone");
+
+        assert!(error.to_string().contains("TimeZone"), "{}", error);
+    }
+
+    #[test]
+    fn startup_options_escape_custom_options() {
+        let config = valid()
+            .with_option("work_mem", "32MB")
+            .with_option("app.note", r"a b\c")
+            .with_option("work_mem", "64MB");
+
+        assert_eq!(
+            config.startup_options(),
+            vec![
+                ("app.note".to_owned(), r"a\ b\\c".to_owned()),
+                ("work_mem".to_owned(), "64MB".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn startup_options_follow_the_dedicated_settings() {
+        let config = valid()
+            .with_option("statement_timeout", "0")
+            .with_statement_timeout(Duration::from_secs(5));
+        let keys: Vec<_> = config
+            .startup_options()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+
+        assert_eq!(keys, ["statement_timeout=5000", "statement_timeout=0"]);
+    }
+
+    #[test]
+    fn startup_options_reach_the_connect_options() {
+        let config = valid()
+            .with_statement_timeout(Duration::from_secs(5))
+            .read_only(true);
+        let options = format!("{:?}", config.connect_options().unwrap());
+
+        assert!(options.contains("-c statement_timeout=5000"));
+        assert!(options.contains("-c default_transaction_read_only=on"));
+    }
+
+    #[test]
+    fn startup_options_round_trip_through_split_startup_options() {
+        let config = valid()
+            .with_search_path(&["My Schema"])
+            .with_option("app.note", r"a b\c");
+        let options = config
+            .startup_options()
+            .into_iter()
+            .map(|(key, value)| format!("-c {}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        assert_eq!(
+            crate::sql::split_startup_options(&options),
+            Some(vec![
+                ("search_path".to_owned(), r#""My Schema""#.to_owned()),
+                ("app.note".to_owned(), r"a b\c".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn pool_options_clamp_min_connections_to_max_connections() {
+        let options = valid()
+            .set_max_connections(4)
+            .with_min_connections(10)
+            .pool_options();
+
+        assert_eq!(options.get_max_connections(), 4);
+        assert_eq!(options.get_min_connections(), 4);
+    }
+
+    #[test]
+    fn debug_shows_min_connections() {
+        let config = valid().with_min_connections(3);
+
+        assert!(format!("{:?}", config).contains("min_connections: 3"));
+    }
+
+    /// The config of `DATABASE_URL`.
+    fn server() -> SqlxPostgresConfig {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        SqlxPostgresConfig::from_url(&url).unwrap()
+    }
+
+    /// Waits up to five seconds for `pool` to hold `count` idle connections.
+    async fn wait_for_idle(pool: &PgPool, count: usize) -> bool {
+        for _ in 0..50 {
+            if pool.num_idle() >= count {
+                return true;
+            }
+
+            rocket::tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        false
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn ignite_opens_min_connections() {
+        use crate::SqlxPostgresRocketExt;
+
+        let config = server().set_max_connections(4).with_min_connections(2);
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(config, None))
+            .ignite()
+            .await
+            .unwrap();
+        let pool = rocket.pg_pool_or_panic();
+
+        assert!(wait_for_idle(&pool, 2).await, "{} idle", pool.num_idle());
+    }
+
+    #[test]
+    fn pool_options_pass_the_idle_timeout_through() {
+        let timeout = Duration::from_millis(500);
+
+        assert_eq!(
+            valid().pool_options().get_idle_timeout(),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            valid()
+                .with_idle_timeout(Some(timeout))
+                .pool_options()
+                .get_idle_timeout(),
+            Some(timeout)
+        );
+        assert_eq!(
+            valid()
+                .with_idle_timeout(None)
+                .pool_options()
+                .get_idle_timeout(),
+            None
+        );
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn idle_connections_are_closed_after_the_idle_timeout() {
+        let pool = server()
+            .set_max_connections(2)
+            .with_idle_timeout(Some(Duration::from_secs(1)))
+            .connect()
+            .await
+            .unwrap();
+
+        let first = pool.acquire().await.unwrap();
+        let second = pool.acquire().await.unwrap();
+        drop((first, second));
+        assert!(wait_for_idle(&pool, 2).await);
+
+        for _ in 0..50 {
+            if pool.num_idle() == 0 {
+                return;
+            }
+
+            rocket::tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("{} connections still idle", pool.num_idle());
+    }
+
+    #[test]
+    fn pool_options_pass_test_before_acquire_through() {
+        assert!(valid().pool_options().get_test_before_acquire());
+        assert!(!valid()
+            .with_test_before_acquire(false)
+            .pool_options()
+            .get_test_before_acquire());
+    }
+
+    /// Runs a query on a pool of one connection after the server terminated that connection.
+    async fn query_after_termination(config: SqlxPostgresConfig) -> Result<i32, sqlx::Error> {
+        let pool = config.set_max_connections(1).connect().await.unwrap();
+        let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(wait_for_idle(&pool, 1).await);
+
+        let admin = server().connect().await.unwrap();
+        sqlx::query("SELECT pg_terminate_backend($1)")
+            .bind(pid)
+            .execute(&admin)
+            .await
+            .unwrap();
+        admin.close().await;
+        rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+
+        sqlx::query_scalar("SELECT 1").fetch_one(&pool).await
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_before_acquire_replaces_a_terminated_connection() {
+        let tested = query_after_termination(server()).await;
+        let untested = query_after_termination(server().with_test_before_acquire(false)).await;
+
+        assert_eq!(tested.unwrap(), 1);
+        assert!(untested.is_err());
+    }
+
+    #[test]
+    fn debug_shows_the_statement_log_settings() {
+        let config = valid()
+            .with_loglevel(LevelFilter::Off)
+            .with_slow_statement_log(LevelFilter::Warn, Duration::from_millis(200));
+        let debug = format!("{:?}", config);
+
+        assert!(debug.contains("log_level: Off"), "{}", debug);
+        assert!(
+            debug.contains("slow_statement_log: (Warn, 200ms)"),
+            "{}",
+            debug
+        );
+    }
+
+    /// Records every message logged at warn or above.
+    struct WarnLog(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for WarnLog {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if self.enabled(record.metadata()) {
+                self.0
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(format!("{}: {}", record.target(), record.args()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static WARN_LOG: WarnLog = WarnLog(std::sync::Mutex::new(Vec::new()));
+
+    /// The warnings logged so far, installing [`WARN_LOG`] as the logger on first use.
+    fn warnings() -> Vec<String> {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+
+        INSTALL.call_once(|| {
+            log::set_logger(&WARN_LOG).unwrap();
+            log::set_max_level(LevelFilter::Warn);
+        });
+
+        WARN_LOG.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn slow_statements_are_logged_at_the_slow_level() {
+        assert!(warnings().iter().all(|line| !line.contains("pg_sleep")));
+
+        let pool = server()
+            .with_loglevel(LevelFilter::Off)
+            .with_slow_statement_log(LevelFilter::Warn, Duration::from_millis(100))
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query("SELECT pg_sleep(0.3)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let slow = warnings();
+        assert!(
+            slow.iter()
+                .any(|line| line.starts_with("sqlx") && line.contains("pg_sleep")),
+            "{:?}",
+            slow
+        );
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn the_guard_executes_queries_on_its_pool() {
+        use sqlx::Statement;
+
+        // One connection, so the temporary table is visible to every query.
+        let config = server().set_max_connections(1);
+        let pool = config.connect().await.unwrap();
+        let db = SQLxPostgres::<DefaultDatabase>::new(pool, QueryObserver::new(&config), None);
+
+        sqlx::query("CREATE TEMPORARY TABLE items (id int)")
+            .execute(&db)
+            .await
+            .unwrap();
+        let inserted = sqlx::query("INSERT INTO items VALUES ($1), ($2)")
+            .bind(1)
+            .bind(2)
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(inserted.rows_affected(), 2);
+
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM items")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let ids: Vec<i32> = sqlx::query_scalar("SELECT id FROM items ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, [1, 2]);
+
+        let statement = (&db)
+            .prepare("SELECT id FROM items WHERE id = $1")
+            .await
+            .unwrap();
+        assert_eq!(statement.columns().len(), 1);
+
+        let id: i32 = statement
+            .query_scalar()
+            .bind(2)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(id, 2);
+    }
+
+    #[test]
+    fn pgbouncer_mode_disables_the_statement_cache() {
+        let config = valid().with_statement_cache_capacity(50);
+
+        assert_eq!(config.cached_statements(), 50);
+        assert_eq!(config.pgbouncer_mode(true).cached_statements(), 0);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs PGBOUNCER_URL of a PgBouncer in transaction mode"]
+    async fn pgbouncer_mode_survives_transaction_pooling() {
+        let url = std::env::var("PGBOUNCER_URL").expect("PGBOUNCER_URL is not set");
+        let config = SqlxPostgresConfig::from_url(&url)
+            .unwrap()
+            .pgbouncer_mode(true)
+            .set_max_connections(4);
+        let pool = config.connect().await.unwrap();
+        let db = SQLxPostgres::<DefaultDatabase>::new(pool, QueryObserver::new(&config), None);
+
+        // The same statement on every client connection, which PgBouncer moves between
+        // server connections.
+        let queries = (0..64).map(|i| {
+            sqlx::query_scalar::<_, i32>("SELECT $1::int")
+                .bind(i)
+                .fetch_one(&db)
+        });
+
+        for (i, result) in futures::future::join_all(queries)
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            assert_eq!(result.unwrap(), i as i32);
+        }
+    }
+
+    /// A config whose pool connects on first use, so ignite never reaches a server.
+    fn lazy() -> SqlxPostgresConfig {
+        valid().lazy(true)
+    }
+
+    fn assert_fairing_failed(rocket: std::result::Result<Rocket<Ignite>, rocket::Error>) {
+        let error = rocket.err().expect("ignite succeeded");
+
+        match error.kind() {
+            rocket::error::ErrorKind::FailedFairings(fairings) => {
+                assert_eq!(fairings.len(), 1);
+                assert_eq!(fairings[0].name, "SQLxPostgres");
+            }
+            kind => panic!("expected a failed fairing, got {:?}", kind),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn attaching_one_fairing_ignites() {
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(lazy(), None))
+            .ignite()
+            .await;
+
+        assert!(rocket.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn attaching_the_fairing_twice_aborts_ignite() {
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(lazy(), None))
+            .attach(SqlxPostgresFairing::new(lazy(), None))
+            .ignite()
+            .await;
+
+        assert_fairing_failed(rocket);
+    }
+
+    #[rocket::async_test]
+    async fn attaching_named_databases_next_to_the_default_ignites() {
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::new(lazy(), None))
+            .attach(SqlxPostgresFairing::named("main", lazy()))
+            .attach(SqlxPostgresFairing::named("analytics", lazy()))
+            .ignite()
+            .await
+            .unwrap();
+        let pools = rocket.state::<SQLxPostgresPools>().unwrap();
+
+        assert!(pools.contains("main"));
+        assert!(pools.contains("analytics"));
+    }
+
+    #[rocket::async_test]
+    async fn attaching_a_named_database_twice_aborts_ignite() {
+        let rocket = rocket::build()
+            .attach(SqlxPostgresFairing::named("analytics", lazy()))
+            .attach(SqlxPostgresFairing::named("analytics", lazy()))
+            .ignite()
+            .await;
+
+        assert_fairing_failed(rocket);
+    }
+
+    #[test]
+    fn validate_rejects_a_host_list_with_a_socket() {
+        let config = valid()
+            .with_hosts(&[("db1", 5432), ("db2", 5432)])
+            .with_socket("/var/run/postgresql");
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Conflict {
+                option: "hosts",
+                conflicts_with: "socket",
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_a_host_list_with_connect_options() {
+        let config = valid()
+            .with_hosts(&[("db1", 5432)])
+            .with_connect_options(PgConnectOptions::new());
+
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::Conflict {
+                option: "hosts",
+                conflicts_with: "connect_options",
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_port_zero_in_a_host_list() {
+        let config = valid().with_hosts(&[("db1", 5432), ("db2", 0)]);
+
+        assert_eq!(config.validate(), Err(ConfigError::ZeroPort));
+    }
+
+    #[test]
+    fn a_host_list_targets_the_host_connected_to() {
+        let config = valid().with_hosts(&[("db1", 5432), ("db2", 5433)]);
+
+        assert_eq!(config.target(), ("db1".into(), 5432));
+
+        *config.connected_host.write().unwrap() = Some(("db2".into(), 5433));
+
+        assert_eq!(config.clone().target(), ("db2".into(), 5433));
+        assert_eq!(config.to_string(), "app@db2:5433/app");
+        assert!(format!("{:?}", config.connect_options().unwrap()).contains("db2"));
+        assert_eq!(
+            config.with_hosts(&[("db3", 5432)]).target(),
+            ("db3".into(), 5432)
+        );
+    }
+
+    #[rocket::async_test]
+    async fn connect_retries_with_backoff_before_giving_up() {
+        // Accepts and drops every connection, so each attempt fails right away.
+        let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicU32::new(0));
+        let counter = accepted.clone();
+        rocket::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let config = valid()
+            .with_host("127.0.0.1")
+            .with_port(port)
+            .with_ssl_mode(PgSslMode::Disable)
+            .with_connect_retries(2, Duration::from_millis(10));
+        let start = std::time::Instant::now();
+
+        assert!(config.connect().await.is_err());
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+        // At least half of each of the 10ms and 20ms delays.
+        assert!(start.elapsed() >= Duration::from_millis(15));
+    }
+
+    /// The migrator of `tests/migrations/<name>`.
+    #[cfg(feature = "test-util")]
+    async fn test_migrator(name: &str) -> Migrator {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/migrations");
+        Migrator::new(std::path::Path::new(dir).join(name))
+            .await
+            .unwrap()
+    }
+
+    #[cfg(feature = "test-util")]
+    async fn applied_versions(pool: &PgPool) -> Vec<i64> {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[cfg(feature = "test-util")]
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn ignite_applies_migrations_in_order() {
+        let db = crate::TestDatabase::create(server()).await.unwrap();
+        let fairing = SqlxPostgresFairing::new(db.config(), None)
+            .with_migrator(test_migrator("ordered").await);
+
+        let rocket = rocket::build().attach(fairing).ignite().await;
+
+        assert!(rocket.is_ok());
+        assert_eq!(applied_versions(db.pool()).await, [1, 2]);
+
+        drop(rocket);
+        db.cleanup().await.unwrap();
+    }
+
+    #[cfg(feature = "test-util")]
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn a_failed_migration_of_a_supplied_pool_aborts_ignite() {
+        let db = crate::TestDatabase::create(server()).await.unwrap();
+        let fairing = db.fairing().with_migrator(test_migrator("failing").await);
+
+        assert_fairing_failed(rocket::build().attach(fairing).ignite().await);
+        assert_eq!(applied_versions(db.pool()).await, [1]);
+
+        db.cleanup().await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn connect_tries_every_host_of_the_list() {
+        let config = valid()
+            .with_hosts(&[("127.0.0.1", 1), ("127.0.0.1", 2)])
+            .with_connect_timeout(Duration::from_millis(500));
+
+        assert!(config.connect().await.is_err());
+        assert!(config.connected_host.read().unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn connect_fails_over_to_the_next_host() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let server = SqlxPostgresConfig::from_url(&url).unwrap();
+        let (host, port) = server.target();
+        let config = server
+            .clone()
+            .with_hosts(&[("127.0.0.1".into(), 1), (host.clone(), port)])
+            .with_connect_timeout(Duration::from_secs(5));
+
+        let pool = config.connect().await.unwrap();
+
+        assert_eq!(config.target(), (host, port));
+        crate::connect::ping(&pool, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "test-util")]
+    #[rocket::async_test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn acquire_times_out_on_a_saturated_pool() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let timeout = Duration::from_millis(500);
+        let admin = SqlxPostgresConfig::from_url(&url)
+            .unwrap()
+            .set_max_connections(1)
+            .with_acquire_timeout(timeout);
+        let db = crate::TestDatabase::create(admin).await.unwrap();
+
+        let held = db.pool().acquire().await.unwrap();
+        let start = std::time::Instant::now();
+        let second = db.pool().acquire().await;
+        let waited = start.elapsed();
+
+        assert!(matches!(second, Err(sqlx::Error::PoolTimedOut)));
+        assert!(waited >= timeout, "gave up after {:?}", waited);
+        assert!(waited < timeout * 3, "waited {:?}", waited);
 
-        Ok(rocket.manage(store))
+        drop(held);
+        db.cleanup().await.unwrap();
     }
 }
@@ -0,0 +1,1576 @@
+use crate::{
+    guards, maintenance,
+    session::SQLxPostgresPool,
+    sql::{quote_ident, quote_table},
+    PoolGuardError,
+};
+use rocket::{
+    fairing::{self, Fairing, Info},
+    http::{Cookie, CookieJar, Header, SameSite, Status},
+    outcome::Outcome,
+    request::{FromRequest, Request},
+    response::Response,
+    serde::{de::DeserializeOwned, Serialize},
+    tokio, Build, Orbit, Rocket, Shutdown,
+};
+use serde_json::{Map, Value};
+use sqlx::{postgres::PgPool, types::Json, Postgres};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Columns of the session table and their `information_schema` data types.
+const COLUMNS: &[(&str, &str)] = &[
+    ("id", "uuid"),
+    ("data", "jsonb"),
+    ("expires", "timestamp with time zone"),
+    ("user_id", "text"),
+];
+
+/// Longest identifier Postgres keeps without truncating it.
+const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// Settings of the session cookie, passed to [`SqlxSessionFairing::from_config`].
+///
+/// The defaults are secure: `SameSite=Lax`, `Secure` and `HttpOnly` on, path `/`, no domain
+/// and sessions living 24 hours after their last write. Browsers accept `Secure` cookies from
+/// `http://localhost`.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    cookie_name: Cow<'static, str>,
+    same_site: SameSite,
+    secure: bool,
+    http_only: bool,
+    domain: Option<Cow<'static, str>>,
+    path: Cow<'static, str>,
+    lifetime: Duration,
+    sliding_expiration: bool,
+    private: bool,
+    sweep_interval: Option<Duration>,
+    sweep_batch_size: i64,
+    max_size: usize,
+    cache: bool,
+    cache_ttl: Duration,
+    cache_max_entries: usize,
+    table_name: Cow<'static, str>,
+    schema: Option<Cow<'static, str>>,
+    create_table: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: "sqlx_session".into(),
+            same_site: SameSite::Lax,
+            secure: true,
+            http_only: true,
+            domain: None,
+            path: "/".into(),
+            lifetime: Duration::from_secs(24 * 60 * 60),
+            sliding_expiration: false,
+            private: false,
+            sweep_interval: Some(Duration::from_secs(10 * 60)),
+            sweep_batch_size: 1000,
+            max_size: 64 * 1024,
+            cache: false,
+            cache_ttl: Duration::from_secs(5),
+            cache_max_entries: 10_000,
+            table_name: "sessions".into(),
+            schema: None,
+            create_table: true,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Set the name of the session cookie, `sqlx_session` by default
+    ///
+    /// Cookies under a previous name are ignored afterwards, so their visitors get a fresh
+    /// session on their next write and the old rows expire on their own.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_cookie_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Set the `SameSite` attribute of the cookie, `Lax` by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Set whether the cookie is only sent over HTTPS, on by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Set whether the cookie is hidden from JavaScript, on by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Set the `Domain` of the cookie, unset by default so it's only sent to this host
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_domain(mut self, domain: impl Into<Cow<'static, str>>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Set the `Path` of the cookie, `/` by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set how long a session lives after its last write, 24 hours by default
+    ///
+    /// Used both as the cookie's `Max-Age` and the `expires` of the stored row.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Set whether each request using the session pushes its expiry back, off by default
+    ///
+    /// Sessions then expire `lifetime` after the visitor's last request instead of the last
+    /// write, at the cost of an `UPDATE` per request using the [`Session`] guard.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn sliding_expiration(mut self, sliding: bool) -> Self {
+        self.sliding_expiration = sliding;
+        self
+    }
+
+    /// Set how often expired sessions are deleted, every 10 minutes by default
+    ///
+    /// The sweeper runs from liftoff until shutdown, deleting rows in batches of
+    /// `with_sweep_batch_size` so it never holds long locks. `None` disables it, e.g. to run
+    /// `DELETE FROM sessions WHERE expires < now()` from cron instead. Expired sessions are
+    /// never loaded either way.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_sweep_interval(mut self, interval: Option<Duration>) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Set how many expired sessions one sweeper `DELETE` removes, 1000 by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_sweep_batch_size(mut self, batch_size: u32) -> Self {
+        self.sweep_batch_size = i64::from(batch_size.max(1));
+        self
+    }
+
+    /// Set the largest session, in bytes of JSON, 64 KiB by default
+    ///
+    /// Writes that would grow a session past it fail with [`SessionError::TooLarge`] and
+    /// leave the session unchanged.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Set whether sessions are cached in memory in front of the table, off by default
+    ///
+    /// Sessions read within `with_cache_ttl` of being loaded or written by this process skip
+    /// the `SELECT`. Writes still go to Postgres and update the cache, regenerate, destroy and
+    /// [`SessionStore::destroy_for_user`] drop the entries. Changes made by other instances
+    /// show up once the entry expires, so leave it off when several instances serve the same
+    /// visitors and stale reads are not acceptable.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn cache(mut self, enabled: bool) -> Self {
+        self.cache = enabled;
+        self
+    }
+
+    /// Set how long a cached session is used before it is read again, 5 seconds by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Set how many sessions the cache holds at most, 10000 by default
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_cache_max_entries(mut self, max_entries: usize) -> Self {
+        self.cache_max_entries = max_entries;
+        self
+    }
+
+    /// Set the name of the session table, `sessions` by default
+    ///
+    /// The name is quoted, so it is used verbatim, case included. Names that are empty,
+    /// contain NUL or are longer than 63 bytes abort launch.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_table_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.table_name = name.into();
+        self
+    }
+
+    /// Set the schema of the session table, the connection's `search_path` by default
+    ///
+    /// Quoted and checked like [`SessionConfig::with_table_name`]. The schema must exist.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn with_schema(mut self, schema: impl Into<Cow<'static, str>>) -> Self {
+        self.schema = Some(schema.into());
+        self
+    }
+
+    /// Set whether the table and its indexes are created at ignite, on by default
+    ///
+    /// When on, the statements of [`SessionStore::migration_sql`] run at ignite and the
+    /// columns of an existing table are checked, aborting launch when they don't match.
+    /// Turn it off to apply the statements out-of-band.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn create_table(mut self, create: bool) -> Self {
+        self.create_table = create;
+        self
+    }
+
+    /// Set whether the session id is stored in a private cookie, off by default
+    ///
+    /// Private cookies are encrypted and authenticated with Rocket's `secret_key`, so set one
+    /// in release builds. Plain cookies hold the random v4 session id as is. Cookies of the
+    /// other kind are ignored, so switching issues fresh sessions.
+    ///
+    /// Call on the fairing before passing it to `rocket.attach()`
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    /// Checks the table and schema names, see [`SessionConfig::with_table_name`].
+    fn validate(&self) -> Result<(), String> {
+        let names = std::iter::once(("table name", &self.table_name))
+            .chain(self.schema.as_ref().map(|schema| ("schema", schema)));
+
+        for (what, name) in names {
+            if name.is_empty() || name.contains('\0') || name.len() > MAX_IDENTIFIER_LEN {
+                return Err(format!(
+                    "invalid session {} `{}`: it must be 1 to {} bytes without NUL",
+                    what,
+                    name.escape_debug(),
+                    MAX_IDENTIFIER_LEN
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The quoted, schema qualified table name.
+    fn table(&self) -> String {
+        quote_table(self.schema.as_deref(), &self.table_name)
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.cookie_name.clone(), value)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(self.http_only)
+            .same_site(self.same_site)
+            .max_age(rocket::time::Duration::seconds(
+                self.lifetime.as_secs().min(i64::MAX as u64) as i64,
+            ))
+            .finish();
+
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        cookie
+    }
+
+    fn session_id(&self, cookies: &CookieJar<'_>) -> Option<Uuid> {
+        let cookie = match self.private {
+            true => cookies.get_private(&self.cookie_name)?,
+            false => cookies.get(&self.cookie_name)?.clone(),
+        };
+
+        Uuid::parse_str(cookie.value()).ok()
+    }
+
+    fn add_cookie(&self, cookies: &CookieJar<'_>, id: Uuid) {
+        let cookie = self.cookie(id.to_string());
+
+        match self.private {
+            true => cookies.add_private(cookie),
+            false => cookies.add(cookie),
+        }
+    }
+
+    fn remove_cookie(&self, cookies: &CookieJar<'_>) {
+        let cookie = self.cookie(String::new());
+
+        match self.private {
+            true => cookies.remove_private(cookie),
+            false => cookies.remove(cookie),
+        }
+    }
+}
+
+/// Fairing storing [`Session`] data in a table of the managed pool, `sessions` by default.
+///
+/// Attach it after the unnamed [`crate::SqlxPostgresFairing`]: the table is created at ignite
+/// and expired rows are deleted. Changes made through the guard are written back once the
+/// response is ready.
+///
+/// ```rust,ignore
+/// rocket::build()
+///     .attach(SqlxPostgresFairing::from_config(config))
+///     .attach(SqlxSessionFairing::from_config(SessionConfig::default().with_cookie_name("sid")))
+/// ```
+#[derive(Debug, Default)]
+pub struct SqlxSessionFairing {
+    config: SessionConfig,
+}
+
+impl SqlxSessionFairing {
+    /// Creates a New SQLx Session Fairing with the default [`SessionConfig`].
+    ///
+    /// Must be Created to pass to `rocket.attach()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a New SQLx Session Fairing setting the cookie as described by `config`.
+    ///
+    /// Must be Created to pass to `rocket.attach()`
+    pub fn from_config(config: SessionConfig) -> Self {
+        Self { config }
+    }
+}
+
+/// The session table, managed by [`SqlxSessionFairing`] once it is ready.
+///
+/// Request it with `&State<SessionStore>`, its functions take any pool so they can run from
+/// handlers and background tasks alike.
+#[derive(Debug)]
+pub struct SessionStore {
+    config: SessionConfig,
+    table: String,
+    cache: Option<SessionCache>,
+}
+
+impl SessionStore {
+    /// The statements creating the session table and its indexes as configured by `config`.
+    ///
+    /// These are what ignite runs with [`SessionConfig::create_table`] on, in order, so they
+    /// can be reviewed or applied out-of-band. All of them are idempotent.
+    pub fn migration_sql(config: &SessionConfig) -> Vec<String> {
+        let table = config.table();
+        let index = |column: &str| quote_ident(&format!("{}_{}", config.table_name, column));
+
+        vec![
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (\n    id uuid PRIMARY KEY,\n    data jsonb NOT NULL,\n    expires timestamptz NOT NULL,\n    user_id text\n)",
+                table
+            ),
+            // Tables created before sessions could be bound to a user.
+            format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS user_id text", table),
+            format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} (user_id) WHERE user_id IS NOT NULL",
+                index("user_id"),
+                table
+            ),
+            format!("CREATE INDEX IF NOT EXISTS {} ON {} (expires)", index("expires"), table),
+        ]
+    }
+
+    /// Deletes every session bound to `user_id` with [`Session::set_user_id`].
+    ///
+    /// For "log out everywhere" or after an account compromise. Returns how many sessions
+    /// were deleted, sessions without a user are never affected. When the current request's
+    /// session is among them, its later writes are dropped and its cookie is cleared.
+    pub async fn destroy_for_user(&self, pool: &PgPool, user_id: &str) -> Result<u64, sqlx::Error> {
+        let sql = format!("DELETE FROM {} WHERE user_id = $1", self.table);
+
+        let removed = sqlx::query(&sql)
+            .bind(user_id)
+            .execute(pool)
+            .await?
+            .rows_affected();
+
+        if let Some(cache) = &self.cache {
+            cache.remove_user(user_id);
+        }
+
+        Ok(removed)
+    }
+
+    /// Compares the columns of the existing table with [`COLUMNS`].
+    async fn verify(&self, pool: &PgPool) -> Result<(), String> {
+        let columns: Vec<(String, String)> = sqlx::query_as(
+            "SELECT column_name::text, data_type::text FROM information_schema.columns
+             WHERE table_schema = COALESCE($1, current_schema()) AND table_name = $2",
+        )
+        .bind(self.config.schema.as_deref())
+        .bind(&*self.config.table_name)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut diff = Vec::new();
+
+        for (name, expected) in COLUMNS {
+            match columns.iter().find(|(column, _)| column == name) {
+                None => diff.push(format!("+ {} {}", name, expected)),
+                Some((_, found)) if found != expected => {
+                    diff.push(format!("- {} {}", name, found));
+                    diff.push(format!("+ {} {}", name, expected));
+                }
+                Some(_) => {}
+            }
+        }
+
+        match diff.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "table {} does not have the expected columns (- found, + expected):\n{}",
+                self.table,
+                diff.join("\n")
+            )),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SqlxSessionFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "SQLxPostgres Sessions",
+            kind: fairing::Kind::Ignite | fairing::Kind::Liftoff | fairing::Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let pool = match rocket.state::<SQLxPostgresPool>() {
+            Some(store) => store.client(),
+            None => {
+                log::error!("SQLxPostgres: attach SqlxPostgresFairing before SqlxSessionFairing");
+                return Err(rocket);
+            }
+        };
+
+        if let Err(e) = self.config.validate() {
+            log::error!("SQLxPostgres: {}", e);
+            return Err(rocket);
+        }
+
+        let cache = match self.config.cache {
+            true => Some(SessionCache::new(
+                self.config.cache_ttl,
+                self.config.cache_max_entries,
+            )),
+            false => None,
+        };
+        let store = SessionStore {
+            config: self.config.clone(),
+            table: self.config.table(),
+            cache,
+        };
+
+        if self.config.create_table {
+            for sql in SessionStore::migration_sql(&self.config) {
+                if let Err(e) = sqlx::query(&sql).execute(&pool).await {
+                    log::error!(
+                        "SQLxPostgres: creating the session table {} failed: {}",
+                        store.table,
+                        e
+                    );
+                    return Err(rocket);
+                }
+            }
+
+            if let Err(e) = store.verify(&pool).await {
+                log::error!("SQLxPostgres: {}", e);
+                return Err(rocket);
+            }
+        }
+
+        Ok(rocket.manage(store))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let interval = match self.config.sweep_interval {
+            // tokio's interval panics on zero.
+            Some(interval) => interval.max(Duration::from_millis(1)),
+            None => return,
+        };
+
+        if let (Some(store), Some(sessions)) = (
+            rocket.state::<SQLxPostgresPool>(),
+            rocket.state::<SessionStore>(),
+        ) {
+            tokio::spawn(sweep(
+                store.handle(),
+                sessions.table.clone(),
+                interval,
+                self.config.sweep_batch_size,
+                rocket.shutdown(),
+            ));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let pending = match request.local_cache(|| -> LoadedSession { Ok(SessionSlot::default()) })
+        {
+            Ok(slot) => slot.take_pending(self.config.sliding_expiration),
+            Err(_) => None,
+        };
+        let pending = match pending {
+            Some(pending) => pending,
+            None => return,
+        };
+        let pool = match guards::managed_store(request) {
+            Ok(store) => store.client(),
+            Err(_) => return,
+        };
+        let store = match request.rocket().state::<SessionStore>() {
+            Some(store) => store,
+            None => return,
+        };
+        let (table, cache) = (&store.table[..], store.cache.as_ref());
+
+        let lifetime = self.config.lifetime;
+        let result = match pending {
+            Pending::Insert(_, record) if record.is_empty() => Ok(true),
+            Pending::Insert(id, record) => {
+                let cached = cache.map(|_| record.clone());
+                let result = insert(&pool, table, id, record, lifetime)
+                    .await
+                    .map(|_| true);
+                cache_result(cache, id, cached, &result);
+                result
+            }
+            Pending::Update(id, record) if record.is_empty() => {
+                let result = delete(&pool, table, id).await.map(|_| true);
+                cache_result(cache, id, None, &result);
+                result
+            }
+            Pending::Update(id, record) => {
+                let cached = cache.map(|_| record.clone());
+                let result = update(&pool, table, id, record, lifetime).await;
+                cache_result(cache, id, cached, &result);
+                result
+            }
+            Pending::Touch(id) => touch(&pool, table, id, lifetime).await,
+        };
+
+        match result {
+            Ok(true) => {}
+            // Destroyed during the request, the cookies of the handler were already sent.
+            Ok(false) => {
+                let mut cookie = self.config.cookie(String::new());
+                cookie.make_removal();
+                response.adjoin_header(Header::new("Set-Cookie", cookie.to_string()));
+            }
+            Err(e) => log::error!("SQLxPostgres: saving the session failed: {}", e),
+        }
+    }
+}
+
+async fn load(pool: &PgPool, table: &str, id: Uuid) -> Result<Option<Record>, sqlx::Error> {
+    let sql = format!(
+        "SELECT data, user_id FROM {} WHERE id = $1 AND expires > now()",
+        table
+    );
+    let row: Option<(Json<Map<String, Value>>, Option<String>)> =
+        sqlx::query_as(&sql).bind(id).fetch_optional(pool).await?;
+
+    Ok(row.map(|(data, user_id)| Record {
+        data: data.0,
+        user_id,
+    }))
+}
+
+fn expires(lifetime: Duration) -> chrono::DateTime<chrono::Utc> {
+    chrono::Utc::now()
+        + chrono::Duration::from_std(lifetime).unwrap_or_else(|_| chrono::Duration::days(1))
+}
+
+async fn insert<'e, E>(
+    executor: E,
+    table: &str,
+    id: Uuid,
+    record: Record,
+    lifetime: Duration,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let sql = format!(
+        "INSERT INTO {} (id, data, expires, user_id) VALUES ($1, $2, $3, $4)",
+        table
+    );
+
+    sqlx::query(&sql)
+        .bind(id)
+        .bind(Json(record.data))
+        .bind(expires(lifetime))
+        .bind(record.user_id)
+        .execute(executor)
+        .await
+        .map(|_| ())
+}
+
+/// Only updates a row that still exists, so a session regenerated or destroyed by a
+/// concurrent request isn't brought back. Returns whether it still existed.
+async fn update(
+    pool: &PgPool,
+    table: &str,
+    id: Uuid,
+    record: Record,
+    lifetime: Duration,
+) -> Result<bool, sqlx::Error> {
+    let sql = format!(
+        "UPDATE {} SET data = $2, expires = $3, user_id = $4 WHERE id = $1",
+        table
+    );
+
+    sqlx::query(&sql)
+        .bind(id)
+        .bind(Json(record.data))
+        .bind(expires(lifetime))
+        .bind(record.user_id)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() > 0)
+}
+
+async fn touch(
+    pool: &PgPool,
+    table: &str,
+    id: Uuid,
+    lifetime: Duration,
+) -> Result<bool, sqlx::Error> {
+    let sql = format!(
+        "UPDATE {} SET expires = $2 WHERE id = $1 AND expires > now()",
+        table
+    );
+
+    sqlx::query(&sql)
+        .bind(id)
+        .bind(expires(lifetime))
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() > 0)
+}
+
+async fn delete<'e, E>(executor: E, table: &str, id: Uuid) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let sql = format!("DELETE FROM {} WHERE id = $1", table);
+
+    sqlx::query(&sql)
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|_| ())
+}
+
+/// Moves the data of `old` to a new row under `new` in one transaction.
+async fn replace(
+    pool: &PgPool,
+    table: &str,
+    old: Option<Uuid>,
+    new: Uuid,
+    record: Record,
+    lifetime: Duration,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(old) = old {
+        delete(&mut tx, table, old).await?;
+    }
+
+    insert(&mut tx, table, new, record, lifetime).await?;
+    tx.commit().await
+}
+
+/// Deletes expired sessions every `interval` until shutdown, `batch_size` rows at a time.
+async fn sweep(
+    slot: Arc<RwLock<PgPool>>,
+    table: String,
+    interval: Duration,
+    batch_size: i64,
+    mut shutdown: Shutdown,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    let sql = format!(
+        "DELETE FROM {table} WHERE id IN (
+             SELECT id FROM {table} WHERE expires < now() LIMIT $1 FOR UPDATE SKIP LOCKED
+         )",
+        table = table
+    );
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = &mut shutdown => return,
+        }
+
+        // The pool is read each time, the supervisor may have swapped it.
+        let pool = slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut removed = 0;
+
+        loop {
+            let deleted = sqlx::query(&sql).bind(batch_size).execute(&pool);
+
+            let deleted = tokio::select! {
+                deleted = deleted => deleted,
+                _ = &mut shutdown => return,
+            };
+
+            match deleted {
+                Ok(result) => {
+                    removed += result.rows_affected();
+
+                    if result.rows_affected() < batch_size as u64 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("SQLxPostgres: deleting expired sessions failed: {}", e);
+                    break;
+                }
+            }
+        }
+
+        log::debug!("SQLxPostgres: deleted {} expired sessions", removed);
+    }
+}
+
+/// Caches `record` under `id` when it was written, forgetting the entry otherwise.
+fn cache_result(
+    cache: Option<&SessionCache>,
+    id: Uuid,
+    record: Option<Record>,
+    result: &Result<bool, sqlx::Error>,
+) {
+    if let Some(cache) = cache {
+        match (record, result) {
+            (Some(record), Ok(true)) => cache.put(id, record),
+            _ => cache.remove(id),
+        }
+    }
+}
+
+/// Sessions recently read or written by this process, see [`SessionConfig::cache`].
+struct SessionCache {
+    entries: Mutex<HashMap<Uuid, (Instant, Record)>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl SessionCache {
+    fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, (Instant, Record)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, id: Uuid) -> Option<Record> {
+        let mut entries = self.lock();
+
+        match entries.get(&id) {
+            Some((cached, record)) if cached.elapsed() < self.ttl => Some(record.clone()),
+            Some(_) => {
+                entries.remove(&id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, id: Uuid, record: Record) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.lock();
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&id) {
+            let ttl = self.ttl;
+            entries.retain(|_, (cached, _)| cached.elapsed() < ttl);
+
+            // Still full of live entries, make room by dropping the oldest.
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (cached, _))| *cached)
+                    .map(|(id, _)| *id);
+
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(id, (Instant::now(), record));
+    }
+
+    fn remove(&self, id: Uuid) {
+        self.lock().remove(&id);
+    }
+
+    fn remove_user(&self, user_id: &str) {
+        self.lock()
+            .retain(|_, (_, record)| record.user_id.as_deref() != Some(user_id));
+    }
+}
+
+impl fmt::Debug for SessionCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionCache")
+            .field("entries", &self.lock().len())
+            .field("ttl", &self.ttl)
+            .field("max_entries", &self.max_entries)
+            .finish()
+    }
+}
+
+/// The stored columns of a session.
+#[derive(Clone)]
+struct Record {
+    data: Map<String, Value>,
+    user_id: Option<String>,
+}
+
+impl Record {
+    /// A session without data nor user is not worth a row.
+    fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.user_id.is_none()
+    }
+}
+
+/// Writes the fairing still has to do for the session of a request.
+enum Pending {
+    /// Store a new session, unless it is empty.
+    Insert(Uuid, Record),
+    /// Store a stored session, or delete it when it is empty.
+    Update(Uuid, Record),
+    /// Push the expiry of an unchanged session back.
+    Touch(Uuid),
+}
+
+/// Session of the request as loaded by the guard, kept in the request local cache.
+type LoadedSession = Result<SessionSlot, PoolGuardError>;
+
+/// Session state of one request.
+#[derive(Default)]
+struct SessionSlot {
+    state: Mutex<SessionState>,
+}
+
+#[derive(Default)]
+struct SessionState {
+    id: Option<Uuid>,
+    data: Map<String, Value>,
+    user_id: Option<String>,
+    dirty: bool,
+    /// Whether `id` has a row, as far as this request knows.
+    stored: bool,
+}
+
+impl SessionSlot {
+    fn lock(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// What to write back: changed sessions are saved, unchanged ones touched when sliding.
+    fn take_pending(&self, sliding: bool) -> Option<Pending> {
+        let mut state = self.lock();
+        let id = state.id?;
+
+        if state.dirty {
+            state.dirty = false;
+            let record = Record {
+                data: std::mem::take(&mut state.data),
+                user_id: state.user_id.take(),
+            };
+
+            return Some(match state.stored {
+                true => Pending::Update(id, record),
+                false => Pending::Insert(id, record),
+            });
+        }
+
+        match sliding && state.stored {
+            true => Some(Pending::Touch(id)),
+            false => None,
+        }
+    }
+}
+
+/// Request guard for the current visitor's session, stored by [`SqlxSessionFairing`].
+///
+/// The session is loaded from the session cookie when the guard is first requested,
+/// using a pool connection only for that `SELECT`, so it works next to the `SQLxPostgres`
+/// guards without holding a second connection. Visitors without a session get one on their
+/// first write. Values are stored as JSON.
+///
+/// ```rust,ignore
+/// #[get("/")]
+/// async fn index(session: Session<'_>) -> String {
+///     let visits = session.get::<u64>("visits").ok().flatten().unwrap_or(0) + 1;
+///     session.set("visits", visits).ok();
+///     format!("{} visits", visits)
+/// }
+/// ```
+pub struct Session<'r> {
+    slot: &'r SessionSlot,
+    store: &'r SessionStore,
+    cookies: &'r CookieJar<'r>,
+    pool: PgPool,
+}
+
+impl<'r> Session<'r> {
+    /// Id of the session, `None` until a new visitor's session is first saved.
+    pub fn id(&self) -> Option<Uuid> {
+        self.slot.lock().id
+    }
+
+    /// Returns the value stored under `key`, `None` when there is none.
+    ///
+    /// Fails when the stored value does not deserialize into `T`. A stored `None` is JSON
+    /// `null`, so `get::<Option<T>>` returns `Some(None)` for it.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SessionError> {
+        let value = match self.slot.lock().data.get(key) {
+            Some(value) => value.clone(),
+            None => return Ok(None),
+        };
+
+        deserialize(key, value).map(Some)
+    }
+
+    /// Stores `value` under `key`, replacing the previous value whatever its type.
+    ///
+    /// Fails without changing the session when `value` doesn't serialize or the session
+    /// would grow past [`SessionConfig::with_max_size`].
+    pub fn set<T: Serialize>(&self, key: impl Into<String>, value: T) -> Result<(), SessionError> {
+        let key = key.into();
+        let value = serde_json::to_value(value).map_err(|source| SessionError::Serialize {
+            key: key.clone(),
+            source,
+        })?;
+        let mut state = self.slot.lock();
+        self.insert(&mut state, key, value)
+    }
+
+    /// Returns the value stored under `key`, first storing the result of `f` when there is none.
+    pub fn get_or_insert_with<T, F>(&self, key: &str, f: F) -> Result<T, SessionError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        let mut state = self.slot.lock();
+
+        if let Some(value) = state.data.get(key) {
+            return deserialize(key, value.clone());
+        }
+
+        let value = f();
+        let json = serde_json::to_value(&value).map_err(|source| SessionError::Serialize {
+            key: key.to_owned(),
+            source,
+        })?;
+        self.insert(&mut state, key.to_owned(), json)?;
+        Ok(value)
+    }
+
+    /// Removes and returns the value stored under `key`.
+    ///
+    /// When it does not deserialize into `T` the error is returned and the value is kept.
+    pub fn take<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SessionError> {
+        let mut state = self.slot.lock();
+        let value = match state.data.get(key) {
+            Some(value) => deserialize(key, value.clone())?,
+            None => return Ok(None),
+        };
+
+        state.data.remove(key);
+        self.touch(&mut state);
+        Ok(Some(value))
+    }
+
+    /// Keys of the values stored in the session, in sorted order.
+    pub fn keys(&self) -> Vec<String> {
+        self.slot.lock().data.keys().cloned().collect()
+    }
+
+    /// Removes the value stored under `key`, returning whether there was one.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut state = self.slot.lock();
+        let removed = state.data.remove(key).is_some();
+
+        if removed {
+            self.touch(&mut state);
+        }
+
+        removed
+    }
+
+    /// Removes every value, deleting the stored session and its cookie.
+    pub fn clear(&self) {
+        let mut state = self.slot.lock();
+        state.data.clear();
+        state.user_id = None;
+        state.dirty = true;
+
+        if state.id.is_some() {
+            self.store.config.remove_cookie(self.cookies);
+        }
+    }
+
+    /// Moves the session to a new id, e.g. at login and logout to prevent session fixation.
+    ///
+    /// The data is stored under a fresh id and the old row deleted in one transaction, then
+    /// the new cookie is set. Concurrent requests still using the old id find no session and
+    /// continue as a new visitor. Returns the new id.
+    pub async fn regenerate(&self) -> Result<Uuid, sqlx::Error> {
+        let (old, record) = {
+            let state = self.slot.lock();
+            let record = Record {
+                data: state.data.clone(),
+                user_id: state.user_id.clone(),
+            };
+            (state.id.filter(|_| state.stored), record)
+        };
+        let new = Uuid::new_v4();
+
+        replace(
+            &self.pool,
+            &self.store.table,
+            old,
+            new,
+            record,
+            self.store.config.lifetime,
+        )
+        .await?;
+
+        if let (Some(cache), Some(old)) = (&self.store.cache, old) {
+            cache.remove(old);
+        }
+
+        let mut state = self.slot.lock();
+        state.id = Some(new);
+        state.stored = true;
+        self.store.config.add_cookie(self.cookies, new);
+        Ok(new)
+    }
+
+    /// Deletes the stored session and removes its cookie.
+    ///
+    /// Later writes in the same request start a new session.
+    pub async fn destroy(&self) -> Result<(), sqlx::Error> {
+        let id = {
+            let state = self.slot.lock();
+            state.id.filter(|_| state.stored)
+        };
+
+        if let Some(id) = id {
+            delete(&self.pool, &self.store.table, id).await?;
+
+            if let Some(cache) = &self.store.cache {
+                cache.remove(id);
+            }
+        }
+
+        let mut state = self.slot.lock();
+
+        if state.id.take().is_some() {
+            self.store.config.remove_cookie(self.cookies);
+        }
+
+        state.data.clear();
+        state.user_id = None;
+        state.dirty = false;
+        state.stored = false;
+        Ok(())
+    }
+
+    /// The user the session is bound to with [`Session::set_user_id`].
+    pub fn user_id(&self) -> Option<String> {
+        self.slot.lock().user_id.clone()
+    }
+
+    /// Binds the session to `user_id`, so [`SessionStore::destroy_for_user`] can end it.
+    ///
+    /// Best combined with [`Session::regenerate`] at login. The binding is kept by regenerate
+    /// and dropped by `clear` and `destroy`.
+    pub fn set_user_id(&self, user_id: impl Into<String>) {
+        let mut state = self.slot.lock();
+        state.user_id = Some(user_id.into());
+        self.touch(&mut state);
+    }
+
+    fn insert(
+        &self,
+        state: &mut SessionState,
+        key: String,
+        value: Value,
+    ) -> Result<(), SessionError> {
+        let previous = state.data.insert(key.clone(), value);
+        // Measured as stored, the document is small next to a round trip to the database.
+        let size = serde_json::to_vec(&state.data).map_or(0, |json| json.len());
+
+        if size > self.store.config.max_size {
+            match previous {
+                Some(previous) => state.data.insert(key, previous),
+                None => state.data.remove(&key),
+            };
+
+            return Err(SessionError::TooLarge {
+                size,
+                limit: self.store.config.max_size,
+            });
+        }
+
+        self.touch(state);
+        Ok(())
+    }
+
+    /// Marks the session for saving, giving a new visitor an id and refreshing the cookie.
+    fn touch(&self, state: &mut SessionState) {
+        let id = *state.id.get_or_insert_with(Uuid::new_v4);
+        state.dirty = true;
+        self.store.config.add_cookie(self.cookies, id);
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(key: &str, value: Value) -> Result<T, SessionError> {
+    serde_json::from_value(value).map_err(|source| SessionError::Deserialize {
+        key: key.to_owned(),
+        source,
+    })
+}
+
+/// Errors returned by the typed accessors of [`Session`].
+#[derive(Debug)]
+pub enum SessionError {
+    /// The value stored under `key` does not deserialize into the requested type.
+    Deserialize {
+        key: String,
+        source: serde_json::Error,
+    },
+    /// The value for `key` could not be serialized.
+    Serialize {
+        key: String,
+        source: serde_json::Error,
+    },
+    /// The session would take `size` bytes of JSON, more than the configured `limit`.
+    TooLarge { size: usize, limit: usize },
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Deserialize { key, source } => {
+                write!(f, "session value `{}` has another type: {}", key, source)
+            }
+            SessionError::Serialize { key, source } => {
+                write!(
+                    f,
+                    "session value `{}` could not be serialized: {}",
+                    key, source
+                )
+            }
+            SessionError::TooLarge { size, limit } => write!(
+                f,
+                "session would take {} bytes, more than the limit of {}",
+                size, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Deserialize { source, .. } | SessionError::Serialize { source, .. } => {
+                Some(source)
+            }
+            SessionError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Debug for Session<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session").field("id", &self.id()).finish()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session<'r> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        let store = match request.rocket().state::<SessionStore>() {
+            Some(store) => store,
+            None => {
+                log::error!("SQLxPostgres: the Session guard requires SqlxSessionFairing");
+                return guards::outcome(Err(PoolGuardError::NotManaged));
+            }
+        };
+
+        if let Err(e) = maintenance::check(request) {
+            return guards::outcome(Err(e));
+        }
+
+        let loaded: &'r LoadedSession = request
+            .local_cache_async(async {
+                let config = &store.config;
+                let id = match config.session_id(request.cookies()) {
+                    Some(id) => id,
+                    None => return Ok(SessionSlot::default()),
+                };
+                let cached = store.cache.as_ref().and_then(|cache| cache.get(id));
+                let record = match cached {
+                    Some(record) => Some(record),
+                    None => {
+                        let pool = guards::managed_store(request)?.client();
+                        let record = load(&pool, &store.table, id).await?;
+
+                        if let (Some(cache), Some(record)) = (&store.cache, &record) {
+                            cache.put(id, record.clone());
+                        }
+
+                        record
+                    }
+                };
+
+                // An expired or deleted session is replaced by a new one on the next write.
+                let state = match record {
+                    Some(record) => {
+                        if config.sliding_expiration {
+                            config.add_cookie(request.cookies(), id);
+                        }
+
+                        SessionState {
+                            id: Some(id),
+                            data: record.data,
+                            user_id: record.user_id,
+                            dirty: false,
+                            stored: true,
+                        }
+                    }
+                    None => SessionState::default(),
+                };
+
+                Ok(SessionSlot {
+                    state: Mutex::new(state),
+                })
+            })
+            .await;
+
+        let slot = match loaded {
+            Ok(slot) => slot,
+            Err(e) => return guards::outcome(Err(e.duplicate())),
+        };
+
+        guards::outcome(guards::managed_store(request).map(|pool| Session {
+            slot,
+            store,
+            cookies: request.cookies(),
+            pool: pool.client(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(user_id: Option<&str>) -> Record {
+        let mut data = Map::new();
+        data.insert("visits".to_owned(), Value::from(1));
+
+        Record {
+            data,
+            user_id: user_id.map(str::to_owned),
+        }
+    }
+
+    fn slot(id: Option<Uuid>, dirty: bool, stored: bool) -> SessionSlot {
+        let state = SessionState {
+            id,
+            data: record(None).data,
+            user_id: Some("alice".to_owned()),
+            dirty,
+            stored,
+        };
+
+        SessionSlot {
+            state: Mutex::new(state),
+        }
+    }
+
+    #[test]
+    fn take_pending_skips_a_session_without_an_id() {
+        let slot = slot(None, true, false);
+
+        assert!(slot.take_pending(true).is_none());
+    }
+
+    #[test]
+    fn take_pending_inserts_a_changed_new_session_once() {
+        let id = Uuid::new_v4();
+        let slot = slot(Some(id), true, false);
+
+        match slot.take_pending(true) {
+            Some(Pending::Insert(pending, record)) => {
+                assert_eq!(pending, id);
+                assert_eq!(record.data.get("visits"), Some(&Value::from(1)));
+                assert_eq!(record.user_id.as_deref(), Some("alice"));
+            }
+            _ => panic!("expected an insert"),
+        }
+
+        // Not stored as far as the request knows, so there is nothing to touch.
+        assert!(slot.take_pending(true).is_none());
+    }
+
+    #[test]
+    fn take_pending_updates_a_changed_stored_session() {
+        let id = Uuid::new_v4();
+        let slot = slot(Some(id), true, true);
+
+        assert!(matches!(
+            slot.take_pending(false),
+            Some(Pending::Update(pending, _)) if pending == id
+        ));
+        assert!(slot.take_pending(false).is_none());
+    }
+
+    #[test]
+    fn take_pending_touches_an_unchanged_session_only_when_sliding() {
+        let id = Uuid::new_v4();
+        let slot = slot(Some(id), false, true);
+
+        assert!(slot.take_pending(false).is_none());
+        assert!(matches!(
+            slot.take_pending(true),
+            Some(Pending::Touch(pending)) if pending == id
+        ));
+    }
+
+    #[test]
+    fn cache_drops_entries_after_the_ttl() {
+        let cache = SessionCache::new(Duration::from_millis(50), 10);
+        let id = Uuid::new_v4();
+        cache.put(id, record(None));
+
+        assert!(cache.get(id).is_some());
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(cache.get(id).is_none());
+        assert_eq!(cache.lock().len(), 0);
+    }
+
+    #[test]
+    fn cache_evicts_the_oldest_entry_when_full() {
+        let cache = SessionCache::new(Duration::from_secs(60), 2);
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+
+        for id in ids {
+            cache.put(id, record(None));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        assert!(cache.get(ids[0]).is_none());
+        assert!(cache.get(ids[1]).is_some());
+        assert!(cache.get(ids[2]).is_some());
+    }
+
+    #[test]
+    fn cache_evicts_expired_entries_before_live_ones() {
+        let cache = SessionCache::new(Duration::from_millis(50), 2);
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+
+        cache.put(ids[0], record(None));
+        std::thread::sleep(Duration::from_millis(80));
+        cache.put(ids[1], record(None));
+        cache.put(ids[2], record(None));
+
+        let cached = cache.lock();
+        assert_eq!(cached.len(), 2);
+        assert!(cached.contains_key(&ids[1]) && cached.contains_key(&ids[2]));
+    }
+
+    #[test]
+    fn cache_without_room_stores_nothing() {
+        let cache = SessionCache::new(Duration::from_secs(60), 0);
+        let id = Uuid::new_v4();
+        cache.put(id, record(None));
+
+        assert!(cache.get(id).is_none());
+    }
+
+    #[test]
+    fn cache_removes_the_sessions_of_a_user() {
+        let cache = SessionCache::new(Duration::from_secs(60), 10);
+        let (alice, bob, anonymous) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache.put(alice, record(Some("alice")));
+        cache.put(bob, record(Some("bob")));
+        cache.put(anonymous, record(None));
+
+        cache.remove_user("alice");
+
+        assert!(cache.get(alice).is_none());
+        assert!(cache.get(bob).is_some());
+        assert!(cache.get(anonymous).is_some());
+    }
+
+    #[cfg(feature = "test-util")]
+    mod database {
+        use super::super::*;
+        use crate::{SqlxPostgresConfig, SqlxPostgresRocketExt, TestDatabase};
+        use rocket::local::asynchronous::Client;
+
+        #[rocket::get("/set/<name>")]
+        fn set(session: Session<'_>, name: &str) {
+            session.set("name", name).unwrap();
+            session.set_user_id(name);
+        }
+
+        #[rocket::get("/get")]
+        fn get(session: Session<'_>) -> String {
+            session.get::<String>("name").unwrap().unwrap_or_default()
+        }
+
+        #[rocket::get("/regenerate")]
+        async fn regenerate(session: Session<'_>) -> String {
+            let old = session.id().unwrap();
+            let new = session.regenerate().await.unwrap();
+            format!("{} {}", old, new)
+        }
+
+        #[rocket::get("/destroy")]
+        async fn destroy(session: Session<'_>) {
+            session.destroy().await.unwrap();
+        }
+
+        async fn database() -> TestDatabase {
+            let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+            TestDatabase::create(SqlxPostgresConfig::from_url(&url).unwrap())
+                .await
+                .unwrap()
+        }
+
+        async fn client(db: &TestDatabase) -> Client {
+            let sessions = SessionConfig::default().secure(false);
+            let rocket = rocket::build()
+                .attach(db.fairing())
+                .attach(SqlxSessionFairing::from_config(sessions))
+                .mount("/", rocket::routes![set, get, regenerate, destroy]);
+
+            Client::tracked(rocket).await.unwrap()
+        }
+
+        async fn body(client: &Client, uri: &str) -> String {
+            let response = client.get(uri).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", uri);
+            response.into_string().await.unwrap_or_default()
+        }
+
+        async fn stored_ids(pool: &PgPool) -> Vec<Uuid> {
+            sqlx::query_scalar("SELECT id FROM sessions")
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        }
+
+        #[rocket::async_test]
+        #[ignore = "needs DATABASE_URL"]
+        async fn regenerate_moves_the_session_to_a_new_id() {
+            let db = database().await;
+            let client = client(&db).await;
+
+            body(&client, "/set/alice").await;
+            let ids = body(&client, "/regenerate").await;
+            let (old, new) = ids.split_once(' ').unwrap();
+            let (old, new) = (Uuid::parse_str(old).unwrap(), Uuid::parse_str(new).unwrap());
+
+            assert_ne!(old, new);
+            assert_eq!(stored_ids(db.pool()).await, [new]);
+            assert_eq!(body(&client, "/get").await, "alice");
+
+            drop(client);
+            db.cleanup().await.unwrap();
+        }
+
+        #[rocket::async_test]
+        #[ignore = "needs DATABASE_URL"]
+        async fn destroy_deletes_the_session() {
+            let db = database().await;
+            let client = client(&db).await;
+
+            body(&client, "/set/alice").await;
+            assert_eq!(stored_ids(db.pool()).await.len(), 1);
+            body(&client, "/destroy").await;
+
+            assert!(stored_ids(db.pool()).await.is_empty());
+            assert_eq!(body(&client, "/get").await, "");
+
+            drop(client);
+            db.cleanup().await.unwrap();
+        }
+
+        #[rocket::async_test]
+        #[ignore = "needs DATABASE_URL"]
+        async fn destroy_for_user_deletes_only_that_users_sessions() {
+            let db = database().await;
+            let alice = client(&db).await;
+            let bob = client(&db).await;
+
+            body(&alice, "/set/alice").await;
+            body(&bob, "/set/bob").await;
+
+            let store = alice.rocket().state::<SessionStore>().unwrap();
+            let pool = alice.rocket().pg_pool_or_panic();
+            assert_eq!(store.destroy_for_user(&pool, "alice").await.unwrap(), 1);
+
+            assert_eq!(body(&alice, "/get").await, "");
+            assert_eq!(body(&bob, "/get").await, "bob");
+
+            drop((alice, bob));
+            db.cleanup().await.unwrap();
+        }
+    }
+}
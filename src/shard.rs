@@ -0,0 +1,283 @@
+use crate::{
+    guards, maintenance,
+    named::{self, SQLxPostgresPools},
+    session::SQLxPostgres,
+    PoolGuardError,
+};
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{FromRequest, Request},
+    Build, Ignite, Orbit, Rocket, Sentinel,
+};
+use std::{collections::HashMap, fmt, hash::Hash, ops::Deref, sync::Arc};
+
+type Resolve<K> = dyn Fn(&Request<'_>) -> Result<K, Status> + Send + Sync;
+type Lookup<K> = dyn Fn(&K) -> Option<&'static str> + Send + Sync;
+
+/// Routes each request to one of several named pools by a shard key, for the [`Sharded`]
+/// guard.
+///
+/// Registered with [`crate::SqlxPostgresFairing::with_shards`]. Every shard is a database
+/// attached with [`crate::SqlxPostgresFairing::named`]; names that aren't attached are
+/// logged at liftoff and fail the guard with a 500.
+///
+/// ```rust,ignore
+/// let shards = ShardRouter::hashed(
+///     |request| {
+///         request
+///             .param::<u64>(1)
+///             .and_then(Result::ok)
+///             .ok_or(Status::BadRequest)
+///     },
+///     &["customers_0", "customers_1", "customers_2", "customers_3"],
+/// );
+///
+/// rocket::build()
+///     .attach(SqlxPostgresFairing::named("customers_0", config_0).with_shards(shards))
+///     ...
+/// ```
+pub struct ShardRouter<K = u64> {
+    resolve: Arc<Resolve<K>>,
+    lookup: Arc<Lookup<K>>,
+    names: Arc<[&'static str]>,
+}
+
+impl<K> Clone for ShardRouter<K> {
+    fn clone(&self) -> Self {
+        Self {
+            resolve: self.resolve.clone(),
+            lookup: self.lookup.clone(),
+            names: self.names.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Send + Sync + 'static> ShardRouter<K> {
+    /// Resolves the key of a request with `resolve` and looks its pool up in `shards`.
+    ///
+    /// `resolve` fails the guard with the status it returns, e.g. `Status::BadRequest` for a
+    /// malformed key. A key missing from `shards` fails it with a 404.
+    pub fn new<F>(resolve: F, shards: impl IntoIterator<Item = (K, &'static str)>) -> Self
+    where
+        F: Fn(&Request<'_>) -> Result<K, Status> + Send + Sync + 'static,
+    {
+        let shards = shards.into_iter().collect::<HashMap<_, _>>();
+        let mut names = shards.values().copied().collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+
+        Self {
+            resolve: Arc::new(resolve),
+            lookup: Arc::new(move |key| shards.get(key).copied()),
+            names: names.into(),
+        }
+    }
+}
+
+impl ShardRouter<u64> {
+    /// Spreads numeric keys over `shards` with a jump consistent hash.
+    ///
+    /// Every key maps to one of the pools, and growing the list from N to N + 1 shards moves
+    /// only about 1 / (N + 1) of the keys, all of them to the new last shard. Keep the order of
+    /// the names stable: reordering them moves keys between shards.
+    pub fn hashed<F>(resolve: F, shards: &[&'static str]) -> Self
+    where
+        F: Fn(&Request<'_>) -> Result<u64, Status> + Send + Sync + 'static,
+    {
+        let names: Arc<[&'static str]> = shards.into();
+        let buckets = names.clone();
+
+        Self {
+            resolve: Arc::new(resolve),
+            lookup: Arc::new(move |key| match buckets.len() {
+                0 => None,
+                count => Some(buckets[jump_hash(*key, count)]),
+            }),
+            names,
+        }
+    }
+}
+
+impl<K> ShardRouter<K> {
+    /// Names of the pools the router can hand out.
+    pub fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+}
+
+impl<K> fmt::Debug for ShardRouter<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardRouter")
+            .field("names", &self.names)
+            .finish()
+    }
+}
+
+/// Jump consistent hash of Lamping and Veach, a bucket in `0..buckets`.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let mut bucket = 0;
+    let mut next = 0u64;
+
+    while next < buckets as u64 {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as u64;
+    }
+
+    bucket as usize
+}
+
+/// A [`ShardRouter`] with its key type erased, held by the fairing until ignite.
+pub(crate) trait ShardRoutes: Send + Sync {
+    /// Manages the router, failing when one with the same key type is managed already.
+    fn manage(&self, rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>;
+
+    /// Logs the shards that aren't attached.
+    fn check(&self, rocket: &Rocket<Orbit>);
+}
+
+impl<K: Send + Sync + 'static> ShardRoutes for ShardRouter<K> {
+    fn manage(&self, rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+        match rocket.state::<ShardRouter<K>>() {
+            Some(_) => {
+                log::error!(
+                    "SQLxPostgres: a shard router with keys of type `{}` is already registered",
+                    std::any::type_name::<K>()
+                );
+                Err(rocket)
+            }
+            None => Ok(rocket.manage(self.clone())),
+        }
+    }
+
+    fn check(&self, rocket: &Rocket<Orbit>) {
+        let pools = rocket.state::<SQLxPostgresPools>();
+
+        if self.names.is_empty() {
+            log::error!("SQLxPostgres: the shard router has no shards, every request fails");
+        }
+
+        for name in self.names.iter() {
+            if !pools.map_or(false, |pools| pools.contains(name)) {
+                log::error!(
+                    "SQLxPostgres: shard '{}' is not attached, requests routed to it fail with 500",
+                    name
+                );
+            }
+        }
+    }
+}
+
+/// Request guard handing out the pool of the shard the request belongs to.
+///
+/// Resolves the key of the request with the [`ShardRouter`] registered for `K`, then derefs
+/// to the [`SQLxPostgres`] guard of its pool, so queries run on it directly and
+/// `db.acquire()` checks out a connection. Fails with the status of the resolver when the
+/// key can't be resolved, a 404 when no shard is mapped to it and a 500 when the shard
+/// isn't attached.
+///
+/// ```rust,ignore
+/// #[get("/customers/<id>")]
+/// async fn customer(db: Sharded, id: u64) -> Result<Json<Customer>, Status> {
+///     sqlx::query_as("SELECT * FROM customers WHERE id = $1")
+///         .bind(id as i64)
+///         .fetch_one(&*db)
+///         .await
+///         .map(Json)
+///         .map_err(|_| Status::NotFound)
+/// }
+/// ```
+pub struct Sharded<K = u64> {
+    db: SQLxPostgres,
+    shard: &'static str,
+    key: K,
+}
+
+impl<K> Sharded<K> {
+    /// Name of the pool the request was routed to.
+    pub fn shard(&self) -> &'static str {
+        self.shard
+    }
+
+    /// Shard key of the request.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the guard of the shard's pool.
+    pub fn into_inner(self) -> SQLxPostgres {
+        self.db
+    }
+}
+
+impl<K> Deref for Sharded<K> {
+    type Target = SQLxPostgres;
+
+    fn deref(&self) -> &SQLxPostgres {
+        &self.db
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for Sharded<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sharded")
+            .field("shard", &self.shard)
+            .field("key", &self.key)
+            .field("pool", self.db.pool())
+            .finish()
+    }
+}
+
+fn resolve<K: Send + Sync + 'static>(request: &Request<'_>) -> Result<Sharded<K>, PoolGuardError> {
+    maintenance::check(request)?;
+
+    let router = request
+        .rocket()
+        .state::<ShardRouter<K>>()
+        .ok_or(PoolGuardError::NoShardRouter)?;
+    let key = (router.resolve)(request).map_err(PoolGuardError::ShardUnresolved)?;
+    let shard = (router.lookup)(&key).ok_or(PoolGuardError::ShardUnresolved(Status::NotFound))?;
+    let store = request
+        .rocket()
+        .state::<SQLxPostgresPools>()
+        .and_then(|pools| pools.pool(shard))
+        .ok_or(PoolGuardError::ShardNotAttached(shard))?;
+
+    guards::check_breaker(&store)?;
+    let db = SQLxPostgres::for_request(&store, request);
+
+    match db.pool().is_closed() {
+        true => Err(PoolGuardError::PoolClosed),
+        false => Ok(Sharded { db, shard, key }),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, K: Send + Sync + 'static> FromRequest<'r> for Sharded<K> {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        guards::outcome(resolve(request))
+    }
+}
+
+/// Aborts launch when no router is registered for `K` or one of its shards isn't attached.
+impl<K: Send + Sync + 'static> Sentinel for Sharded<K> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        let router = match rocket.state::<ShardRouter<K>>() {
+            Some(router) => router,
+            None => {
+                log::error!(
+                    "SQLxPostgres: a route uses `Sharded` but no ShardRouter with keys of type `{}` is registered, add one with `SqlxPostgresFairing::with_shards`",
+                    std::any::type_name::<K>()
+                );
+                return true;
+            }
+        };
+
+        router.names.iter().fold(false, |abort, name| {
+            named::abort_unattached(rocket, *name, "Sharded") || abort
+        })
+    }
+}
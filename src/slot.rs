@@ -0,0 +1,82 @@
+use rocket::tokio;
+use sqlx::postgres::PgPool;
+use std::{
+    mem,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// The swappable pool of a fairing, for code that outlives the pool it started with.
+///
+/// The supervisor, credential rotations and statement log changes swap a fresh pool in;
+/// a `PgPool` cloned earlier keeps using the one it was cloned from until that one is closed,
+/// see [`crate::SqlxPostgresConfig::with_drain_timeout`]. Long-running workers should hold
+/// this handle and call [`PgPoolSlot::current`] for every unit of work.
+///
+/// Managed next to the raw pool with [`crate::SqlxPostgresConfig::manage_raw_pool`] and
+/// returned by [`crate::SqlxPostgresRocketExt::pg_pool_slot`].
+///
+/// ```rust,ignore
+/// async fn run_jobs(slot: PgPoolSlot) {
+///     loop {
+///         let pool = slot.current();
+///         process_next_job(&pool).await;
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PgPoolSlot(Arc<RwLock<PgPool>>);
+
+impl PgPoolSlot {
+    pub(crate) fn new(slot: Arc<RwLock<PgPool>>) -> Self {
+        Self(slot)
+    }
+
+    /// The pool guards are resolved from right now.
+    pub fn current(&self) -> PgPool {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Swaps `pool` into `slot` and closes the replaced pool once `drain` passed.
+///
+/// Until then guards, `State<PgPool>` and workers holding a clone keep working with it.
+/// Closing then ends its idle connections and those checked out as they are returned, and
+/// its clones fail to acquire with `PoolClosed`.
+pub(crate) fn replace(slot: &RwLock<PgPool>, pool: PgPool, drain: Duration) {
+    let old_pool = mem::replace(&mut *slot.write().unwrap_or_else(|e| e.into_inner()), pool);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(drain).await;
+        old_pool.close().await;
+        log::debug!(
+            "SQLxPostgres: replaced pool closed after draining for {:?}",
+            drain
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn lazy_pool() -> PgPool {
+        PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/app")
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn replaced_pool_is_closed_after_draining() {
+        let old_pool = lazy_pool();
+        let slot = RwLock::new(old_pool.clone());
+
+        replace(&slot, lazy_pool(), Duration::from_millis(20));
+        assert!(!old_pool.is_closed());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(old_pool.is_closed());
+        assert!(!slot.read().unwrap().is_closed());
+    }
+}
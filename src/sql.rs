@@ -0,0 +1,156 @@
+/// Quotes an identifier so it can be embedded in SQL text, e.g. `My Schema` -> `"My Schema"`.
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quotes a table name, qualified by `schema` when set. Neither part is split, so names may
+/// contain dots.
+pub(crate) fn quote_table(schema: Option<&str>, table: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", quote_ident(schema), quote_ident(table)),
+        None => quote_ident(table),
+    }
+}
+
+/// Escapes a value for the `options` startup parameter, where whitespace separates
+/// arguments and backslash is the escape character.
+pub(crate) fn escape_startup_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if c == '\\' || c.is_whitespace() {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Splits an `options` startup parameter into its `-c key=value` settings, undoing
+/// [`escape_startup_value`]. Returns `None` for anything but `-c` and `--key=value` arguments.
+pub(crate) fn split_startup_options(options: &str) -> Option<Vec<(String, String)>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut chars = options.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+
+    let mut settings = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let setting = match arg.as_str() {
+            "-c" => args.next()?,
+            _ if arg.starts_with("-c") => arg[2..].to_owned(),
+            _ if arg.starts_with("--") => arg[2..].to_owned(),
+            _ => return None,
+        };
+        let (key, value) = setting.split_once('=')?;
+        settings.push((key.to_owned(), value.to_owned()));
+    }
+
+    Some(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn quote_ident_doubles_quotes() {
+        assert_eq!(quote_ident("My Schema"), r#""My Schema""#);
+        assert_eq!(quote_ident(r#"a"b"#), r#""a""b""#);
+    }
+
+    #[test]
+    fn quote_table_quotes_schema_and_table_apart() {
+        assert_eq!(quote_table(Some("app"), "Jobs"), r#""app"."Jobs""#);
+        assert_eq!(quote_table(None, "Jobs"), r#""Jobs""#);
+    }
+
+    #[test]
+    fn quote_table_keeps_dots_in_names() {
+        assert_eq!(quote_table(None, "v1.jobs"), r#""v1.jobs""#);
+        assert_eq!(quote_table(Some("my.app"), "jobs"), r#""my.app"."jobs""#);
+    }
+
+    #[test]
+    fn escape_startup_value_escapes_whitespace_and_backslashes() {
+        assert_eq!(escape_startup_value("64MB"), "64MB");
+        assert_eq!(escape_startup_value(r"a b\c"), r"a\ b\\c");
+        assert_eq!(escape_startup_value("a\tb"), "a\\\tb");
+    }
+
+    #[test]
+    fn split_startup_options_reads_every_form() {
+        assert_eq!(
+            split_startup_options("-c work_mem=64MB -cgeqo=off --search_path=app"),
+            settings(&[
+                ("work_mem", "64MB"),
+                ("geqo", "off"),
+                ("search_path", "app")
+            ])
+        );
+    }
+
+    #[test]
+    fn split_startup_options_ignores_extra_whitespace() {
+        assert_eq!(
+            split_startup_options("  -c   work_mem=64MB  "),
+            settings(&[("work_mem", "64MB")])
+        );
+        assert_eq!(split_startup_options(""), settings(&[]));
+    }
+
+    #[test]
+    fn split_startup_options_unescapes_values() {
+        assert_eq!(
+            split_startup_options(r"-c app.note=a\ b\\c"),
+            settings(&[("app.note", r"a b\c")])
+        );
+    }
+
+    #[test]
+    fn split_startup_options_undoes_escape_startup_value() {
+        let value = "spaces, tabs\tand \\ backslashes";
+        let options = format!("-c app.note={}", escape_startup_value(value));
+
+        assert_eq!(
+            split_startup_options(&options),
+            settings(&[("app.note", value)])
+        );
+    }
+
+    #[test]
+    fn split_startup_options_rejects_other_arguments() {
+        assert_eq!(split_startup_options("work_mem=64MB"), None);
+        assert_eq!(split_startup_options("-c work_mem"), None);
+        assert_eq!(split_startup_options("-c"), None);
+        assert_eq!(split_startup_options("-d 5"), None);
+    }
+}
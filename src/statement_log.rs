@@ -0,0 +1,192 @@
+use crate::{
+    session::{SQLxPostgresPool, SqlxPostgresConfig},
+    slot,
+};
+use log::LevelFilter;
+use rocket::{tokio, Build, Rocket};
+use sqlx::postgres::PgPool;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+/// Stored in a config's level override when no level was set at runtime.
+const NO_OVERRIDE: u8 = u8::MAX;
+
+pub(crate) fn encode(level: LevelFilter) -> u8 {
+    level as u8
+}
+
+pub(crate) fn decode(level: u8) -> Option<LevelFilter> {
+    match level {
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// Shared between the clones of a config, so every rebuild of its pool logs at the
+/// level set last.
+pub(crate) fn new_override() -> Arc<AtomicU8> {
+    Arc::new(AtomicU8::new(NO_OVERRIDE))
+}
+
+/// A pool whose statement log level follows the control.
+struct Target {
+    name: &'static str,
+    slot: Arc<RwLock<PgPool>>,
+    config: SqlxPostgresConfig,
+}
+
+/// Managed handle changing the statement log level of the pools at runtime.
+///
+/// sqlx fixes the level of a connection when it connects, so [`StatementLogControl::set_level`]
+/// swaps a new, lazily connected pool in for every pool the fairings connected. Its
+/// connections open as guards need them, so the database sees no burst of reconnects.
+/// The old pool is closed after [`crate::SqlxPostgresConfig::with_drain_timeout`]: until then
+/// connections checked out of it finish at the old level, as do `PgPool` clones held elsewhere.
+/// Later rebuilds, such as those of the supervisor, keep the level. Pools passed in with
+/// [`crate::SqlxPostgresFairing::from_pool`] or shared with rocket_db_pools are left as is.
+///
+/// ```rust,ignore
+/// #[post("/admin/statement-log/<level>")]
+/// fn statement_log(control: &State<StatementLogControl>, level: &str) -> Result<String, Status> {
+///     let level = level.parse().map_err(|_| Status::BadRequest)?;
+///     control.set_level(level);
+///     Ok(format!("statements logged at {}", control.current()))
+/// }
+/// ```
+pub struct StatementLogControl {
+    level: AtomicU8,
+    targets: Mutex<Vec<Target>>,
+    /// Rebuilds run one at a time, so the last level set is the one that sticks.
+    rebuilding: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl StatementLogControl {
+    fn new(level: LevelFilter) -> Self {
+        Self {
+            level: AtomicU8::new(encode(level)),
+            targets: Mutex::new(Vec::new()),
+            rebuilding: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// The level set last, or the configured level of the first pool registered.
+    pub fn current(&self) -> LevelFilter {
+        decode(self.level.load(Ordering::Relaxed)).unwrap_or(LevelFilter::Off)
+    }
+
+    /// Logs the statements of every pool at `level` from now on, see the type docs for
+    /// when it takes effect.
+    ///
+    /// Must be called from within Rocket's runtime, e.g. in a handler, since the pools are
+    /// rebuilt in the background.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.level.store(encode(level), Ordering::Relaxed);
+        log::info!("SQLxPostgres: logging statements at {}", level);
+
+        let targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+
+        for target in targets.iter() {
+            target
+                .config
+                .log_level_override
+                .store(encode(level), Ordering::Relaxed);
+
+            let name = target.name;
+            let slot = target.slot.clone();
+            let config = target.config.clone();
+            let rebuilding = self.rebuilding.clone();
+
+            tokio::spawn(async move {
+                let _rebuilding = rebuilding.lock().await;
+
+                // A later call already applied its level, after this one was queued.
+                if config.statement_log_level() != level {
+                    return;
+                }
+
+                rebuild(name, &slot, &config).await;
+            });
+        }
+    }
+
+    fn register(&self, name: &'static str, store: &SQLxPostgresPool) {
+        self.targets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Target {
+                name,
+                slot: store.handle(),
+                config: store.config.clone(),
+            });
+    }
+}
+
+impl fmt::Debug for StatementLogControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let names = targets.iter().map(|target| target.name).collect::<Vec<_>>();
+
+        f.debug_struct("StatementLogControl")
+            .field("level", &self.current())
+            .field("pools", &names)
+            .finish()
+    }
+}
+
+/// Adds the pool of `store` to the managed control, managing one first if needed.
+pub(crate) fn register(
+    rocket: Rocket<Build>,
+    name: &'static str,
+    store: &SQLxPostgresPool,
+) -> Rocket<Build> {
+    match rocket.state::<StatementLogControl>() {
+        Some(control) => {
+            control.register(name, store);
+            rocket
+        }
+        None => {
+            let control = StatementLogControl::new(store.config.statement_log_level());
+            control.register(name, store);
+            rocket.manage(control)
+        }
+    }
+}
+
+async fn rebuild(name: &str, slot: &RwLock<PgPool>, config: &SqlxPostgresConfig) {
+    // Lazy, so the new pool opens its connections as requests move over to it instead of
+    // reconnecting all of them at once.
+    let config = config.clone().lazy(true);
+    let result = match config.connect_options() {
+        Ok(opts) => config
+            .connect_with_retries(opts)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    match result {
+        Ok(new_pool) => {
+            slot::replace(slot, new_pool, config.drain_timeout);
+            log::debug!(
+                "SQLxPostgres: pool of `{}` rebuilt for the new statement log level",
+                name
+            );
+        }
+        // The current pool keeps serving at the old level.
+        Err(e) => log::warn!(
+            "SQLxPostgres: rebuilding the pool of `{}` for the new statement log level failed: {}",
+            name,
+            e
+        ),
+    }
+}
@@ -0,0 +1,180 @@
+use crate::breaker::{BreakerState, CircuitBreaker};
+#[cfg(feature = "serde")]
+use rocket::serde::Serialize;
+use rocket::Shutdown;
+use sqlx::postgres::PgPool;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Snapshot of a pool's connections, see [`crate::SQLxPostgresPool::status`].
+///
+/// Serializable with the `serde` feature, for health endpoints and metrics scrapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "rocket::serde"))]
+pub struct PoolStatus {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    /// Connections waiting in the pool.
+    pub idle: usize,
+    /// Connections checked out.
+    pub in_use: usize,
+    /// Upper bound on `size`.
+    pub max: u32,
+    /// Whether the pool was closed.
+    pub is_closed: bool,
+    /// Request guards currently waiting for a connection.
+    pub waiting: usize,
+    /// Request guards refused since launch because too many were waiting, see
+    /// [`crate::SqlxPostgresConfig::with_max_waiters`].
+    pub rejected_waiters: u64,
+    /// State of the circuit breaker, when one is configured.
+    pub circuit_breaker: Option<BreakerState>,
+}
+
+impl PoolStatus {
+    pub(crate) fn of(pool: &PgPool, waiters: &Waiters, breaker: Option<&CircuitBreaker>) -> Self {
+        let size = pool.size();
+        let idle = pool.num_idle();
+
+        Self {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            max: pool.options().get_max_connections(),
+            is_closed: pool.is_closed(),
+            waiting: waiters.count(),
+            rejected_waiters: waiters.rejected(),
+            circuit_breaker: breaker.map(CircuitBreaker::state),
+        }
+    }
+}
+
+impl fmt::Display for PoolStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "size={} idle={} in_use={} waiters={}",
+            self.size, self.idle, self.in_use, self.waiting
+        )?;
+
+        if self.rejected_waiters > 0 {
+            write!(f, " rejected={}", self.rejected_waiters)?;
+        }
+
+        if let Some(state) = self.circuit_breaker {
+            write!(f, " breaker={}", state)?;
+        }
+
+        if self.is_closed {
+            f.write_str(" closed")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Logs the pool status every `interval` until shutdown or the pool is gone, at debug level
+/// when it did not change.
+pub(crate) async fn log_status<F>(
+    name: impl fmt::Display,
+    interval: Duration,
+    status: F,
+    mut shutdown: Shutdown,
+) where
+    F: Fn() -> Option<PoolStatus>,
+{
+    let mut last = None;
+
+    while let Some(current) = status() {
+        if last == Some(current) {
+            log::debug!("SQLxPostgres: pool {}: {}", name, current);
+        } else {
+            log::info!("SQLxPostgres: pool {}: {}", name, current);
+        }
+
+        last = Some(current);
+
+        rocket::tokio::select! {
+            _ = rocket::tokio::time::sleep(interval) => {}
+            _ = &mut shutdown => return,
+        }
+    }
+}
+
+/// Count of guards waiting on the pool, sqlx doesn't expose its own queue.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Waiters {
+    count: Arc<AtomicUsize>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl Waiters {
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Counts a waiter until the returned token is dropped, or refuses it when `max` are
+    /// waiting already.
+    ///
+    /// The token is released when the waiting future is dropped too, so guards that time
+    /// out or whose request goes away free their place.
+    pub(crate) fn enter(&self, max: Option<usize>) -> Option<Waiting> {
+        let entered = self
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| match max {
+                Some(max) if count >= max => None,
+                _ => Some(count + 1),
+            });
+
+        match entered {
+            Ok(_) => Some(Waiting(self.count.clone())),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+pub(crate) struct Waiting(Arc<AtomicUsize>);
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Lets a log line through at most once per interval, counting the ones held back.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimit {
+    last: Mutex<Option<Instant>>,
+    suppressed: AtomicUsize,
+}
+
+impl RateLimit {
+    /// Returns the number of lines held back since the last one when a line may be logged.
+    pub(crate) fn check(&self, every: Duration) -> Option<usize> {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+
+        match *last {
+            Some(at) if at.elapsed() < every => {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            _ => {
+                *last = Some(Instant::now());
+                Some(self.suppressed.swap(0, Ordering::Relaxed))
+            }
+        }
+    }
+}
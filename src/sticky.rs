@@ -0,0 +1,79 @@
+use crate::{replica::SQLxPostgresReplicas, session::SQLxPostgresPool};
+use rocket::{
+    http::{Cookie, SameSite},
+    request::Request,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Private cookie holding the time, in milliseconds since the epoch, until which the replica
+/// guard of the client is served by the primary.
+const COOKIE_NAME: &str = "sqlxpostgres_primary";
+
+/// Set in the request local cache once the cookie was added, so later guards skip it.
+struct Marked;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The window of the unnamed pool, when it has a replica and sticky reads are enabled.
+fn window(request: &Request<'_>) -> Option<Duration> {
+    let store = request.rocket().state::<SQLxPostgresPool>()?;
+    request.rocket().state::<SQLxPostgresReplicas>()?;
+
+    match store.config.sticky_reads {
+        true => Some(store.config.sticky_reads_window),
+        false => None,
+    }
+}
+
+/// Routes the replica reads of the client to the primary for the configured window, called
+/// by the guards of the primary pool.
+pub(crate) fn mark(request: &Request<'_>) {
+    let window = match window(request) {
+        Some(window) => window,
+        None => return,
+    };
+
+    let mut first = false;
+    request.local_cache(|| {
+        first = true;
+        Marked
+    });
+
+    if !first {
+        return;
+    }
+
+    let until = now_millis().saturating_add(window.as_millis() as u64);
+    let cookie = Cookie::build(COOKIE_NAME, until.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(rocket::time::Duration::milliseconds(
+            window.as_millis().min(i64::MAX as u128) as i64,
+        ))
+        .finish();
+
+    request.cookies().add_private(cookie);
+}
+
+/// Whether the client used the primary within the window, by this request's guards or the
+/// cookie of an earlier response.
+///
+/// Clients that drop cookies are never sticky: they read from the replica as before.
+pub(crate) fn is_sticky(request: &Request<'_>) -> bool {
+    if window(request).is_none() {
+        return false;
+    }
+
+    // The jar also holds cookies added during this request.
+    request
+        .cookies()
+        .get_private(COOKIE_NAME)
+        .and_then(|cookie| cookie.value().parse::<u64>().ok())
+        .map_or(false, |until| until > now_millis())
+}
@@ -0,0 +1,217 @@
+use futures::stream::{self, BoxStream, StreamExt};
+use rocket::{
+    http::ContentType,
+    response::{self, stream::ByteStream, Responder},
+    Request,
+};
+use sqlx::{
+    postgres::{PgPool, Postgres},
+    Column, Executor, Transaction,
+};
+
+/// Name of the cursor, unique per transaction.
+const CURSOR: &str = "rocket_sqlxpostgres_stream";
+
+/// Rows fetched from the cursor per chunk of the body.
+const DEFAULT_BATCH_SIZE: u32 = 1000;
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Ndjson,
+    Csv,
+}
+
+/// Streams the rows of a query as the response body, NDJSON or CSV, without buffering them.
+///
+/// The query runs through a server side cursor in a transaction of its own, which holds
+/// one connection of the pool until the body is complete. When the client goes away the
+/// transaction is rolled back and the connection returns to the pool. Errors once the body
+/// started, e.g. a failing cast in row 100000, are logged and end the body early, as the
+/// status was already sent.
+///
+/// ```rust,ignore
+/// #[get("/users.csv")]
+/// async fn export(db: SQLxPostgres) -> Result<PgStreamResponse, Status> {
+///     PgStreamResponse::csv(db.pool(), "SELECT id, name, email FROM users ORDER BY id")
+///         .await
+///         .map_err(|_| Status::InternalServerError)
+/// }
+/// ```
+pub struct PgStreamResponse {
+    format: Format,
+    tx: Transaction<'static, Postgres>,
+    header: Option<String>,
+    batch_size: u32,
+}
+
+impl PgStreamResponse {
+    /// Streams `query` as newline delimited JSON, one object per row keyed by column name,
+    /// as built by `row_to_json`.
+    ///
+    /// Errors in the query itself, such as a missing table, are returned here while a
+    /// proper status can still be sent.
+    pub async fn ndjson(pool: &PgPool, query: &str) -> Result<Self, sqlx::Error> {
+        let rows = format!("SELECT row_to_json(q)::text FROM ({}) q", query);
+        Self::declare(pool, Format::Ndjson, query, &rows).await
+    }
+
+    /// Streams `query` as CSV with a header line of its column names.
+    ///
+    /// Values are rendered like in JSON, e.g. timestamps in ISO 8601. `NULL` becomes an
+    /// empty field while an empty string is written as `""`.
+    pub async fn csv(pool: &PgPool, query: &str) -> Result<Self, sqlx::Error> {
+        // `json_each_text` yields every value as text in column order, whatever its type.
+        let rows = format!(
+            "SELECT ARRAY(SELECT c.value FROM json_each_text(row_to_json(q)) \
+             WITH ORDINALITY AS c(key, value, n) ORDER BY c.n) FROM ({}) q",
+            query
+        );
+        Self::declare(pool, Format::Csv, query, &rows).await
+    }
+
+    /// Sets the rows fetched per chunk of the body, 1000 by default.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    async fn declare(
+        pool: &PgPool,
+        format: Format,
+        query: &str,
+        rows: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let header = match format {
+            Format::Ndjson => None,
+            Format::Csv => {
+                let describe = (&mut tx).describe(query).await?;
+                let mut line = describe
+                    .columns()
+                    .iter()
+                    .map(|column| csv_field(Some(column.name())))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                line.push_str("\r\n");
+                Some(line)
+            }
+        };
+
+        tx.execute(&*format!(
+            "DECLARE {} NO SCROLL CURSOR FOR {}",
+            CURSOR, rows
+        ))
+        .await?;
+
+        Ok(Self {
+            format,
+            tx,
+            header,
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self.format {
+            Format::Ndjson => ContentType::new("application", "x-ndjson"),
+            Format::Csv => ContentType::CSV,
+        }
+    }
+
+    fn into_stream(self) -> BoxStream<'static, Vec<u8>> {
+        let fetch = format!("FETCH FORWARD {} FROM {}", self.batch_size, CURSOR);
+        let header = stream::iter(self.header.map(String::into_bytes));
+        let state = (Some(self.tx), self.format, self.batch_size, fetch);
+
+        let rows = stream::unfold(state, |(tx, format, batch_size, fetch)| async move {
+            let mut tx = tx?;
+
+            let (chunk, count) = match fetch_chunk(&mut tx, format, &fetch).await {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    log::error!(
+                        "SQLxPostgres: streaming rows failed, ending the body: {}",
+                        e
+                    );
+                    return None;
+                }
+            };
+
+            let tx = match count < batch_size as usize {
+                true => {
+                    // Ends the transaction and closes the cursor before the last chunk is sent.
+                    if let Err(e) = tx.commit().await {
+                        log::warn!("SQLxPostgres: closing the streaming cursor failed: {}", e);
+                    }
+                    None
+                }
+                false => Some(tx),
+            };
+
+            match chunk.is_empty() {
+                true => None,
+                false => Some((chunk, (tx, format, batch_size, fetch))),
+            }
+        });
+
+        header.chain(rows).boxed()
+    }
+}
+
+/// Fetches the next batch from the cursor, rendered, and the number of rows in it.
+async fn fetch_chunk(
+    tx: &mut Transaction<'static, Postgres>,
+    format: Format,
+    fetch: &str,
+) -> Result<(Vec<u8>, usize), sqlx::Error> {
+    let mut chunk = String::new();
+
+    let count = match format {
+        Format::Ndjson => {
+            let lines: Vec<String> = sqlx::query_scalar(fetch).fetch_all(&mut *tx).await?;
+
+            for line in &lines {
+                chunk.push_str(line);
+                chunk.push('\n');
+            }
+
+            lines.len()
+        }
+        Format::Csv => {
+            let rows: Vec<Vec<Option<String>>> =
+                sqlx::query_scalar(fetch).fetch_all(&mut *tx).await?;
+
+            for row in &rows {
+                let fields = row.iter().map(|value| csv_field(value.as_deref()));
+                chunk.push_str(&fields.collect::<Vec<_>>().join(","));
+                chunk.push_str("\r\n");
+            }
+
+            rows.len()
+        }
+    };
+
+    Ok((chunk.into_bytes(), count))
+}
+
+/// Renders one CSV field, quoting it when it holds a separator, quote or line break.
+fn csv_field(value: Option<&str>) -> String {
+    match value {
+        None => String::new(),
+        Some("") => "\"\"".to_owned(),
+        Some(value) if value.contains(&[',', '"', '\r', '\n'][..]) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        Some(value) => value.to_owned(),
+    }
+}
+
+impl<'r> Responder<'r, 'r> for PgStreamResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let content_type = self.content_type();
+        let mut response = ByteStream(self.into_stream()).respond_to(request)?;
+        response.set_header(content_type);
+        Ok(response)
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{connect, session::SqlxPostgresConfig, slot};
+use rocket::{tokio, Shutdown};
+use sqlx::postgres::PgPool;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Consecutive failed health checks after which an open pool is rebuilt.
+const FAILURES_BEFORE_REBUILD: u32 = 3;
+
+/// Periodically checks the pool in `slot` and replaces it when it is closed or keeps failing.
+///
+/// Runs until Rocket shuts down, or until rebuilding failed `max_attempts` times in a row.
+pub(crate) async fn supervise(
+    slot: Arc<RwLock<PgPool>>,
+    config: SqlxPostgresConfig,
+    interval: Duration,
+    max_attempts: u32,
+    mut shutdown: Shutdown,
+) {
+    let current = |slot: &RwLock<PgPool>| slot.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut failures = 0;
+
+    log::info!(
+        "SQLxPostgres: supervisor checking the pool every {:?}",
+        interval
+    );
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = &mut shutdown => return,
+        }
+
+        let pool = current(&slot);
+
+        if !pool.is_closed() {
+            match connect::ping(&pool, config.connect_timeout).await {
+                Ok(_) => {
+                    if failures > 0 {
+                        log::info!(
+                            "SQLxPostgres: pool healthy again after {} failed checks",
+                            failures
+                        );
+                    }
+
+                    failures = 0;
+                    continue;
+                }
+                Err(e) => {
+                    failures += 1;
+                    log::warn!(
+                        "SQLxPostgres: health check {} of {} failed: {}",
+                        failures,
+                        FAILURES_BEFORE_REBUILD,
+                        e
+                    );
+
+                    if failures < FAILURES_BEFORE_REBUILD {
+                        continue;
+                    }
+                }
+            }
+        } else {
+            log::warn!("SQLxPostgres: pool is closed");
+        }
+
+        log::warn!("SQLxPostgres: rebuilding the pool");
+
+        let mut attempt = 0;
+
+        let new_pool = loop {
+            let result = match config.connect_options() {
+                Ok(opts) => config.connect_with(opts).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(new_pool) => break new_pool,
+                Err(e) => {
+                    attempt += 1;
+
+                    if attempt >= max_attempts {
+                        log::error!(
+                            "SQLxPostgres: rebuilding the pool failed {} times, supervisor stopping: {}",
+                            attempt,
+                            e
+                        );
+                        return;
+                    }
+
+                    let delay = connect::backoff(config.connect_retries.1, attempt - 1);
+                    log::warn!(
+                        "SQLxPostgres: rebuild attempt {} of {} failed: {}, retrying in {:?}",
+                        attempt,
+                        max_attempts,
+                        e,
+                        delay
+                    );
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = &mut shutdown => return,
+                    }
+                }
+            }
+        };
+
+        slot::replace(&slot, new_pool, config.drain_timeout);
+        failures = 0;
+        log::info!(
+            "SQLxPostgres: pool replaced after {} failed attempts",
+            attempt
+        );
+    }
+}
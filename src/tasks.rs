@@ -0,0 +1,208 @@
+use crate::ready::DbReady;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    Future,
+};
+use rocket::{
+    tokio::{self, task::JoinHandle},
+    Shutdown,
+};
+use sqlx::postgres::PgPool;
+use std::{
+    fmt, mem,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+type Task = Box<dyn FnOnce(PgPool, Shutdown) -> BoxFuture<'static, ()> + Send>;
+
+#[derive(Default)]
+struct Inner {
+    /// Tasks added before liftoff.
+    pending: Vec<(&'static str, Task)>,
+    /// Set at liftoff, tasks added later start right away.
+    started: Option<(DbReady, Shutdown)>,
+    /// Set at shutdown, tasks added later are not started.
+    stopped: bool,
+    running: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+/// Background tasks bound to the pool of a fairing, started once the pool is ready and
+/// stopped before it is closed.
+///
+/// Each task gets a clone of the pool and Rocket's [`Shutdown`], which resolves when
+/// shutdown starts. At shutdown the fairing waits for the tasks to return, for at most the
+/// grace set with [`DbTasks::with_grace`], aborting those still running, and only then
+/// closes the pool. A task returning, panicking or never starting because the pool did not
+/// connect is logged with its name.
+///
+/// Added with [`crate::SqlxPostgresFairing::with_task`], or through the `DbTasks` the
+/// unnamed fairing manages:
+///
+/// ```rust,ignore
+/// SqlxPostgresFairing::new(config, None).with_task("purge sessions", |pool, mut shutdown| {
+///     async move {
+///         loop {
+///             rocket::tokio::select! {
+///                 _ = rocket::tokio::time::sleep(Duration::from_secs(60)) => {}
+///                 _ = &mut shutdown => return,
+///             }
+///
+///             let _ = sqlx::query("DELETE FROM sessions WHERE expires < now()")
+///                 .execute(&pool)
+///                 .await;
+///         }
+///     }
+/// })
+/// ```
+#[derive(Clone)]
+pub struct DbTasks {
+    inner: Arc<Mutex<Inner>>,
+    grace: Duration,
+}
+
+impl Default for DbTasks {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            grace: Duration::from_secs(10),
+        }
+    }
+}
+
+impl DbTasks {
+    /// No tasks, with a shutdown grace of 10 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long shutdown waits for the tasks before aborting them.
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a task named `name`, started at liftoff once the pool is ready, or right away
+    /// when Rocket already lifted off.
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: FnOnce(PgPool, Shutdown) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task: Task = Box::new(move |pool, shutdown| task(pool, shutdown).boxed());
+        let mut inner = self.lock();
+
+        if inner.stopped {
+            return log::warn!(
+                "SQLxPostgres: task `{}` added during shutdown, not started",
+                name
+            );
+        }
+
+        match inner.started.clone() {
+            Some((ready, shutdown)) => {
+                let handle = launch(name, task, ready, shutdown);
+                inner.running.push((name, handle));
+            }
+            None => inner.pending.push((name, task)),
+        }
+    }
+
+    /// Starts the tasks added so far on the pool of `ready`.
+    pub(crate) fn start(&self, ready: DbReady, shutdown: Shutdown) {
+        let mut inner = self.lock();
+        let pending = mem::take(&mut inner.pending);
+
+        for (name, task) in pending {
+            let handle = launch(name, task, ready.clone(), shutdown.clone());
+            inner.running.push((name, handle));
+        }
+
+        inner.started = Some((ready, shutdown));
+    }
+
+    /// Waits for the tasks to return, aborting those still running after the grace.
+    pub(crate) async fn drain(&self) {
+        let running = {
+            let mut inner = self.lock();
+            inner.stopped = true;
+            mem::take(&mut inner.running)
+        };
+
+        if running.is_empty() {
+            return;
+        }
+
+        log::info!(
+            "SQLxPostgres: waiting up to {:?} for {} tasks",
+            self.grace,
+            running.len()
+        );
+        let deadline = tokio::time::Instant::now() + self.grace;
+
+        for (name, mut handle) in running {
+            if tokio::time::timeout_at(deadline, &mut handle)
+                .await
+                .is_err()
+            {
+                log::warn!(
+                    "SQLxPostgres: task `{}` still running after {:?}, aborting it",
+                    name,
+                    self.grace
+                );
+                handle.abort();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DbTasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+
+        f.debug_struct("DbTasks")
+            .field(
+                "pending",
+                &inner
+                    .pending
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "running",
+                &inner
+                    .running
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("grace", &self.grace)
+            .finish()
+    }
+}
+
+/// Runs `task` once the pool of `ready` is ready, logging how it ends.
+fn launch(name: &'static str, task: Task, ready: DbReady, shutdown: Shutdown) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let pool = tokio::select! {
+            pool = ready.pool() => pool,
+            _ = shutdown.clone() => return,
+        };
+
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(e) => return log::error!("SQLxPostgres: task `{}` not started: {}", name, e),
+        };
+
+        match AssertUnwindSafe(task(pool, shutdown)).catch_unwind().await {
+            Ok(()) => log::info!("SQLxPostgres: task `{}` finished", name),
+            Err(_) => log::error!("SQLxPostgres: task `{}` panicked", name),
+        }
+    })
+}
@@ -0,0 +1,277 @@
+use crate::{
+    cancel::{self, CancelOnDrop},
+    guards,
+    named::{self, DefaultDatabase},
+    session::SQLxPostgresPool,
+    sql::quote_ident,
+    PoolGuardError,
+};
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{FromRequest, Request},
+    Ignite, Rocket, Sentinel,
+};
+use sqlx::{Postgres, Transaction};
+use std::{
+    borrow::Cow,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// Placeholder of the naming template replaced by the tenant.
+const TENANT: &str = "{tenant}";
+
+/// Longest identifier Postgres keeps, longer ones are truncated by the server.
+const MAX_IDENTIFIER: usize = 63;
+
+type Resolve = dyn Fn(&Request<'_>) -> Option<String> + Send + Sync;
+
+/// How [`TenantDb`] finds the schema of a request, set with
+/// [`crate::SqlxPostgresConfig::with_tenant_schemas`].
+///
+/// The resolver returns the tenant of the request, e.g. from its subdomain or a claim, and the
+/// template turns it into a schema name, e.g. `tenant_{tenant}`. The tenant may only hold
+/// lowercase ASCII letters, digits and underscores, and the schema must be a plain lowercase
+/// identifier not starting with `pg_`, so neither can reach anything but a tenant schema.
+///
+/// ```rust,ignore
+/// let tenants = TenantSchemas::new(
+///     |request| {
+///         let host = request.host()?.domain().as_str();
+///         host.split_once('.').map(|(tenant, _)| tenant.to_owned())
+///     },
+///     "tenant_{tenant}",
+/// )
+/// .with_shared_schemas(&["public"]);
+///
+/// let config = SqlxPostgresConfig::default().with_tenant_schemas(tenants);
+/// ```
+#[derive(Clone)]
+pub struct TenantSchemas {
+    resolve: Arc<Resolve>,
+    template: Cow<'static, str>,
+    shared: Vec<Cow<'static, str>>,
+    unresolved: Status,
+}
+
+impl TenantSchemas {
+    /// Resolves the tenant with `resolve` and names its schema with `template`, failing the
+    /// guard with a 404 when there is no valid tenant.
+    ///
+    /// `template` must contain `{tenant}` once, checked when the fairing ignites.
+    pub fn new<F>(resolve: F, template: impl Into<Cow<'static, str>>) -> Self
+    where
+        F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            resolve: Arc::new(resolve),
+            template: template.into(),
+            shared: Vec::new(),
+            unresolved: Status::NotFound,
+        }
+    }
+
+    /// Sets the status of requests without a valid tenant, e.g. `Status::Forbidden` when the
+    /// tenant comes from the user's claims.
+    pub fn with_unresolved_status(mut self, status: Status) -> Self {
+        self.unresolved = status;
+        self
+    }
+
+    /// Appends schemas shared by all tenants to the `search_path`, after the tenant's, e.g.
+    /// `public` for extensions.
+    pub fn with_shared_schemas(mut self, schemas: &[&'static str]) -> Self {
+        self.shared = schemas
+            .iter()
+            .map(|schema| Cow::Borrowed(*schema))
+            .collect();
+        self
+    }
+
+    /// Whether the template and the shared schemas are safe, see [`TenantSchemas`].
+    pub(crate) fn is_valid(&self) -> bool {
+        let fixed = self.template.replacen(TENANT, "", 1);
+
+        self.template.matches(TENANT).count() == 1
+            && fixed.chars().all(is_identifier_char)
+            && self.shared.iter().all(|schema| is_schema(schema))
+    }
+
+    /// The tenant of `request`, its schema and the `search_path` to set.
+    fn resolve(&self, request: &Request<'_>) -> Result<(String, String, String), PoolGuardError> {
+        let unresolved = PoolGuardError::TenantUnresolved(self.unresolved);
+        let tenant = match (self.resolve)(request) {
+            Some(tenant) => tenant,
+            None => return Err(unresolved),
+        };
+
+        if tenant.is_empty() || !tenant.chars().all(is_identifier_char) {
+            log::warn!("SQLxPostgres: rejected the invalid tenant {:?}", tenant);
+            return Err(unresolved);
+        }
+
+        let schema = self.template.replacen(TENANT, &tenant, 1);
+
+        if !is_schema(&schema) {
+            log::warn!(
+                "SQLxPostgres: rejected the invalid tenant schema {:?}",
+                schema
+            );
+            return Err(unresolved);
+        }
+
+        let search_path = std::iter::once(&*schema)
+            .chain(self.shared.iter().map(|schema| schema.as_ref()))
+            .map(quote_ident)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Ok((tenant, schema, search_path))
+    }
+}
+
+impl fmt::Debug for TenantSchemas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantSchemas")
+            .field("template", &self.template)
+            .field("shared", &self.shared)
+            .field("unresolved", &self.unresolved)
+            .finish()
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+}
+
+/// A plain lowercase identifier outside the reserved `pg_` prefix.
+fn is_schema(schema: &str) -> bool {
+    let starts_well = schema
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_lowercase() || c == '_');
+
+    starts_well
+        && schema.len() <= MAX_IDENTIFIER
+        && schema.chars().all(is_identifier_char)
+        && !schema.starts_with("pg_")
+}
+
+/// Request guard running the handler inside a transaction whose `search_path` is the schema of
+/// the request's tenant.
+///
+/// The tenant is resolved with the [`TenantSchemas`] of the config, then the `search_path` is
+/// set with `SELECT set_config('search_path', $1, true)`. The setting is transaction local, so
+/// the connection goes back to the pool without it. Fails with the configured status, 404 by
+/// default, when the request has no valid tenant, and with a 500 when no tenant schemas are
+/// configured. Ends like [`crate::SQLxPostgresTransaction`].
+///
+/// ```rust,ignore
+/// #[get("/invoices")]
+/// async fn invoices(mut db: TenantDb) -> Result<Json<Vec<Invoice>>, Status> {
+///     sqlx::query_as("SELECT * FROM invoices")
+///         .fetch_all(&mut *db)
+///         .await
+///         .map(Json)
+///         .map_err(|_| Status::InternalServerError)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TenantDb {
+    tx: CancelOnDrop<Transaction<'static, Postgres>>,
+    tenant: String,
+    schema: String,
+}
+
+impl TenantDb {
+    /// The tenant of the request.
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// The schema of the tenant, first on the `search_path`.
+    pub fn schema(&self) -> &str {
+        &self.schema
+    }
+
+    /// Commits the transaction.
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().commit().await
+    }
+
+    /// Rolls the transaction back right away instead of when the connection is reused.
+    pub async fn rollback(self) -> Result<(), sqlx::Error> {
+        self.tx.into_inner().rollback().await
+    }
+
+    /// Returns the sqlx transaction, still scoped to the tenant until it ends.
+    pub fn into_inner(self) -> Transaction<'static, Postgres> {
+        self.tx.into_inner()
+    }
+}
+
+impl Deref for TenantDb {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for TenantDb {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+async fn begin(request: &Request<'_>) -> Result<TenantDb, PoolGuardError> {
+    let tenants = guards::managed_store(request)?
+        .config
+        .tenant_schemas
+        .as_ref()
+        .ok_or(PoolGuardError::NoTenantSchemas)?;
+    let (tenant, schema, search_path) = tenants.resolve(request)?;
+
+    let mut tx = guards::begin(request, None).await?;
+
+    sqlx::query("SELECT set_config('search_path', $1, true)")
+        .bind(search_path)
+        .execute(&mut tx)
+        .await?;
+
+    Ok(TenantDb {
+        tx: cancel::arm(request, tx).await?,
+        tenant,
+        schema,
+    })
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TenantDb {
+    type Error = PoolGuardError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, (Status, Self::Error), ()> {
+        guards::outcome(begin(request).await)
+    }
+}
+
+/// Aborts launch when the fairing isn't attached or its config has no tenant schemas.
+impl Sentinel for TenantDb {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        if named::abort_unattached(rocket, DefaultDatabase::NAME, "TenantDb") {
+            return true;
+        }
+
+        match rocket.state::<SQLxPostgresPool>() {
+            Some(store) if store.config.tenant_schemas.is_none() => {
+                log::error!(
+                    "SQLxPostgres: a route uses `TenantDb` but the config has no tenant schemas, set them with `SqlxPostgresConfig::with_tenant_schemas`"
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::session::SqlxPostgresConfig;
+use rocket::{tokio, Shutdown};
+use sqlx::{postgres::PgConnection, Connection};
+use std::time::Duration;
+
+/// Characters of the query text included in a warning.
+const QUERY_PREVIEW_CHARS: i32 = 200;
+
+/// Sessions of the application idle in a transaction, or running one statement, for longer
+/// than `$4` seconds. `$1` is the application name, `$2` whether any name of the same user and
+/// database matches and `$3` the length of the query preview.
+const LONG_SESSIONS: &str = "\
+SELECT pid, state, \
+       EXTRACT(EPOCH FROM now() - CASE WHEN state = 'active' THEN query_start ELSE state_change END)::float8, \
+       left(query, $3) \
+FROM pg_stat_activity \
+WHERE datname = current_database() \
+  AND usename = current_user \
+  AND pid <> pg_backend_pid() \
+  AND (application_name = $1 OR $2) \
+  AND state IN ('active', 'idle in transaction', 'idle in transaction (aborted)') \
+  AND now() - CASE WHEN state = 'active' THEN query_start ELSE state_change END \
+      > make_interval(secs => $4) \
+ORDER BY 3 DESC";
+
+/// Watches `pg_stat_activity` for transactions held open too long, see
+/// [`crate::SqlxPostgresConfig::with_transaction_watchdog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionWatchdog {
+    threshold: Duration,
+    interval: Duration,
+    terminate_after: Option<Duration>,
+}
+
+impl TransactionWatchdog {
+    /// Warns about sessions idle in a transaction or running a statement for longer than
+    /// `threshold`, checking every 30 seconds.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            interval: Duration::from_secs(30),
+            terminate_after: None,
+        }
+    }
+
+    /// Sets how often `pg_stat_activity` is checked.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Terminates sessions with `pg_terminate_backend` once they are over `after`, off by
+    /// default.
+    ///
+    /// The request holding the session sees its connection closed and its transaction rolled
+    /// back. Each termination is logged as an error.
+    pub fn with_terminate_after(mut self, after: Duration) -> Self {
+        self.terminate_after = Some(after);
+        self
+    }
+
+    /// Checks every interval until shutdown, on one connection of its own outside the pool,
+    /// so a pool exhausted by stuck transactions is still watched.
+    pub(crate) async fn run(
+        self,
+        name: &'static str,
+        config: SqlxPostgresConfig,
+        mut shutdown: Shutdown,
+    ) {
+        let application_name = config.application_name();
+        // Request transactions relabel themselves, so any name of the user and database matches.
+        let any_name = config.request_application_name.is_some();
+        let threshold = match self.terminate_after {
+            Some(after) => self.threshold.min(after),
+            None => self.threshold,
+        };
+        let mut conn: Option<PgConnection> = None;
+
+        log::info!(
+            "SQLxPostgres: transaction watchdog of `{}` checking every {:?} for sessions over {:?}",
+            name,
+            self.interval,
+            self.threshold
+        );
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = &mut shutdown => break,
+            }
+
+            if conn.is_none() {
+                conn = match connect(&config).await {
+                    Ok(conn) => Some(conn),
+                    Err(e) => {
+                        log::warn!(
+                            "SQLxPostgres: transaction watchdog of `{}` could not connect: {}",
+                            name,
+                            e
+                        );
+                        continue;
+                    }
+                };
+            }
+
+            let result = match conn.as_mut() {
+                Some(conn) => {
+                    self.check(conn, name, &application_name, any_name, threshold)
+                        .await
+                }
+                None => continue,
+            };
+
+            if let Err(e) = result {
+                log::warn!(
+                    "SQLxPostgres: transaction watchdog of `{}` failed: {}",
+                    name,
+                    e
+                );
+                conn = None;
+            }
+        }
+
+        if let Some(conn) = conn {
+            let _ = conn.close().await;
+        }
+    }
+
+    async fn check(
+        &self,
+        conn: &mut PgConnection,
+        name: &str,
+        application_name: &str,
+        any_name: bool,
+        threshold: Duration,
+    ) -> Result<(), sqlx::Error> {
+        let sessions: Vec<(i32, String, f64, Option<String>)> = sqlx::query_as(LONG_SESSIONS)
+            .bind(application_name)
+            .bind(any_name)
+            .bind(QUERY_PREVIEW_CHARS)
+            .bind(threshold.as_secs_f64())
+            .fetch_all(&mut *conn)
+            .await?;
+
+        for (pid, state, seconds, query) in sessions {
+            let duration = Duration::from_secs_f64(seconds.max(0.0));
+            let query = query.unwrap_or_default();
+
+            match self.terminate_after {
+                Some(after) if duration > after => {
+                    log::error!(
+                        "SQLxPostgres: terminating session {} of `{}`, {} for {:?} (limit {:?}): {}",
+                        pid,
+                        name,
+                        state,
+                        duration,
+                        after,
+                        query
+                    );
+
+                    let terminated: bool = sqlx::query_scalar("SELECT pg_terminate_backend($1)")
+                        .bind(pid)
+                        .fetch_one(&mut *conn)
+                        .await?;
+
+                    if !terminated {
+                        log::warn!(
+                            "SQLxPostgres: session {} of `{}` could not be terminated",
+                            pid,
+                            name
+                        );
+                    }
+                }
+                _ if duration > self.threshold => log::warn!(
+                    "SQLxPostgres: session {} of `{}` {} for {:?}: {}",
+                    pid,
+                    name,
+                    state,
+                    duration,
+                    query
+                ),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn connect(config: &SqlxPostgresConfig) -> Result<PgConnection, String> {
+    let options = config.connect_options().map_err(|e| e.to_string())?;
+
+    match tokio::time::timeout(config.connect_timeout, PgConnection::connect_with(&options)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".to_owned()),
+    }
+}
@@ -0,0 +1 @@
+CREATE TABLE orders (user_id int REFERENCES missing (id));